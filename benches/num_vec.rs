@@ -0,0 +1,61 @@
+#![feature(test)]
+extern crate test;
+
+use gridiron::num_vec::Vector;
+
+const COUNT: usize = 160000;
+const V4: [f64; 4] = [0.0, 1.0, 2.0, 3.0];
+const V8: [f64; 8] = [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0];
+
+
+
+
+// ============================================================================
+#[bench]
+fn add_raw_floats_in_vec(b: &mut test::Bencher) {
+    b.iter(|| {
+        let x: Vec<_> = (0..COUNT).map(|_| 1.0).collect();
+        let y: Vec<_> = (0..COUNT).map(|_| 1.0).collect();
+        let _: Vec<_> = x.into_iter().zip(y).map(|(x, y)| x + y).collect();
+    })
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn add_numeric_vectors4_floats_in_vec(b: &mut test::Bencher) {
+    b.iter(|| {
+        let x: Vec<_> = (0..COUNT / 4).map(|_| Vector::new(V4)).collect();
+        let y: Vec<_> = (0..COUNT / 4).map(|_| Vector::new(V4)).collect();
+        let _: Vec<_> = x.into_iter().zip(y).map(|(x, y)| x + y).collect();
+    })
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn add_numeric_vectors8_floats_in_vec(b: &mut test::Bencher) {
+    b.iter(|| {
+        let x: Vec<_> = (0..COUNT / 8).map(|_| Vector::new(V8)).collect();
+        let y: Vec<_> = (0..COUNT / 8).map(|_| Vector::new(V8)).collect();
+        let _: Vec<_> = x.into_iter().zip(y).map(|(x, y)| x + y).collect();
+    })
+}
+
+
+
+
+// ============================================================================
+#[bench]
+fn axpy_numeric_vectors8_floats_in_vec(b: &mut test::Bencher) {
+    let x: Vec<_> = (0..COUNT / 8).map(|_| Vector::new(V8)).collect();
+    let y = x.clone();
+    b.iter(|| {
+        let z: Vec<_> = x.iter().zip(&y).map(|(&x, &y)| x * 0.5 + y).collect();
+        test::black_box(z)
+    })
+}
\ No newline at end of file
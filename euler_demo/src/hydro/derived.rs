@@ -0,0 +1,113 @@
+use gridiron::index_space::{Axis, IndexSpace};
+use gridiron::patch::Patch;
+
+
+
+
+/**
+ * Returns the vorticity, `dvy/dx - dvx/dy`, in the valid zones of a patch of
+ * primitive fields `[rho, vx, vy, p, ...]` on a Cartesian mesh, where
+ * `spacing` is the zone size at level zero.
+ *
+ * Derivatives are centered differences of the neighboring zones, which is
+ * the average of the two face gradients the solver uses for its diffusive
+ * fluxes. A neighbor outside the valid space (see
+ * [`Patch::valid_index_space`]) is used if the patch's guard zones are
+ * filled and contain it; otherwise the difference is one-sided at that
+ * edge. A patch extended by one filled guard zone therefore gives the same
+ * derivatives as the whole mesh would.
+ */
+pub fn vorticity(primitive: &Patch, spacing: (f64, f64)) -> Patch {
+    let d = Differences::new(primitive, spacing);
+    pointwise(primitive, |index, _| d.derivative(index, 2, Axis::I) - d.derivative(index, 1, Axis::J))
+}
+
+
+
+
+/**
+ * Returns the velocity divergence, `dvx/dx + dvy/dy`, with the same
+ * differences as [`vorticity`].
+ */
+pub fn divergence(primitive: &Patch, spacing: (f64, f64)) -> Patch {
+    let d = Differences::new(primitive, spacing);
+    pointwise(primitive, |index, _| d.derivative(index, 1, Axis::I) + d.derivative(index, 2, Axis::J))
+}
+
+
+
+
+/**
+ * Returns the Mach number, the flow speed over the sound speed of an ideal
+ * gas with the given adiabatic index, in the valid zones of a primitive
+ * patch.
+ */
+pub fn mach_number(primitive: &Patch, gamma_law_index: f64) -> Patch {
+    pointwise(primitive, |_, p| {
+        let cs = (gamma_law_index * p[3] / p[0]).sqrt();
+        p[1].hypot(p[2]) / cs
+    })
+}
+
+
+
+
+/**
+ * Returns the specific entropy `ln(p / rho^gamma) / (gamma - 1)` of an ideal
+ * gas with the given adiabatic index, in the valid zones of a primitive
+ * patch. It is in units of the specific heat at constant volume, and up to a
+ * constant.
+ */
+pub fn entropy(primitive: &Patch, gamma_law_index: f64) -> Patch {
+    pointwise(primitive, |_, p| {
+        (p[3] / p[0].powf(gamma_law_index)).ln() / (gamma_law_index - 1.0)
+    })
+}
+
+
+
+
+// ============================================================================
+struct Differences<'a> {
+    primitive: &'a Patch,
+    readable: IndexSpace,
+    spacing: (f64, f64),
+}
+
+impl<'a> Differences<'a> {
+    fn new(primitive: &'a Patch, spacing: (f64, f64)) -> Self {
+        let scale = (1 << primitive.level()) as f64;
+        let readable = if primitive.guards_filled() {
+            primitive.index_space()
+        } else {
+            primitive.valid_index_space()
+        };
+        Self {
+            primitive,
+            readable,
+            spacing: (spacing.0 * scale, spacing.1 * scale),
+        }
+    }
+
+    fn derivative(&self, (i, j): (i64, i64), field: usize, axis: Axis) -> f64 {
+        let (di, dj, dx) = match axis {
+            Axis::I => (1, 0, self.spacing.0),
+            Axis::J => (0, 1, self.spacing.1),
+        };
+        let value = |index| self.primitive.get_slice(index)[field];
+        let (l, r) = ((i - di, j - dj), (i + di, j + dj));
+
+        match (self.readable.contains(l), self.readable.contains(r)) {
+            (true, true) => (value(r) - value(l)) / (2.0 * dx),
+            (true, false) => (value((i, j)) - value(l)) / dx,
+            (false, true) => (value(r) - value((i, j))) / dx,
+            (false, false) => 0.0,
+        }
+    }
+}
+
+fn pointwise<F: Fn((i64, i64), &[f64]) -> f64>(primitive: &Patch, f: F) -> Patch {
+    Patch::from_scalar_function(primitive.level(), primitive.valid_index_space(), |index| {
+        f(index, primitive.get_slice(index))
+    })
+}
@@ -5,7 +5,7 @@ use std::error;
 
 
 
-#[derive(Debug)]
+#[derive(Clone, Copy, Debug)]
 
 
 /**
@@ -14,6 +14,8 @@ use std::error;
 pub enum Error {
     NegativeGasPressure(f64),
     NegativeMassDensity(f64),
+    SuperluminalVelocity(f64),
+    RootFinderFailed(usize),
 }
 
 
@@ -24,9 +26,34 @@ impl fmt::Display for Error {
         match self {
             NegativeGasPressure(p) => writeln!(fmt, "negative gas pressure: {}", p),
             NegativeMassDensity(d) => writeln!(fmt, "negative mass density: {}", d),
+            SuperluminalVelocity(v) => writeln!(fmt, "superluminal velocity: {}", v),
+            RootFinderFailed(n) => writeln!(fmt, "primitive recovery failed after {} iterations", n),
         }
     }
 }
 
 
 impl error::Error for Error {}
+
+
+
+
+/**
+ * An error in primitive variable recovery, with the zone where it occurred.
+ */
+#[derive(Debug)]
+pub struct ZoneError {
+    pub level: u32,
+    pub index: (i64, i64),
+    pub error: Error,
+}
+
+
+impl fmt::Display for ZoneError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(fmt, "zone {:?} on level {}: {}", self.index, self.level, self.error)
+    }
+}
+
+
+impl error::Error for ZoneError {}
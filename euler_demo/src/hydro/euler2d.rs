@@ -1,6 +1,9 @@
 use std::ops::{Add, Sub, Mul, Div};
 use super::error::Error;
 use super::geometry::{Direction, Vector3d};
+use super::lanes::{Lanes, LANES};
+use super::system::{direction, HydroSystem};
+use gridiron::index_space::Axis;
 
 
 
@@ -73,14 +76,36 @@ impl Conserved {
         let v1 = self.momentum_1() / self.mass_density();
         let v2 = self.momentum_2() / self.mass_density();
 
-        if self.mass_density() < 0.0 {
+        if self.mass_density() <= 0.0 || self.mass_density().is_nan() {
             Err(Error::NegativeMassDensity(self.mass_density()))
-        } else if pg < 0.0 {
+        } else if pg < 0.0 || pg.is_nan() {
             Err(Error::NegativeGasPressure(pg))
         } else {
             Ok(Primitive(self.mass_density(), v1, v2, pg))
         }
     }
+
+    /// Raises the mass density and then the gas pressure to the given
+    /// floors, keeping the momentum. Returns true if either was below its
+    /// floor. Floors of zero are not applied, and states that are not
+    /// finite are left unchanged, so that [`Conserved::to_primitive`] fails
+    /// on them.
+    pub fn apply_floors(&mut self, floors: &Floors, gamma_law_index: f64) -> bool {
+        let mut applied = false;
+
+        if floors.mass_density > 0.0 && self.0 < floors.mass_density {
+            self.0 = floors.mass_density;
+            applied = true;
+        }
+        let ek = 0.5 * self.momentum_squared() / self.0;
+        let pg = (self.3 - ek) * (gamma_law_index - 1.0);
+
+        if floors.gas_pressure > 0.0 && pg < floors.gas_pressure {
+            self.3 = ek + floors.gas_pressure / (gamma_law_index - 1.0);
+            applied = true;
+        }
+        applied
+    }
 }
 
 
@@ -265,3 +290,457 @@ pub fn riemann_hlle(pl: Primitive, pr: Primitive, direction: Direction, gamma_la
 
     (fl * ap - fr * am - (ul - ur) * ap * am) / (ap - am)
 }
+
+
+
+
+// ============================================================================
+/// The local Lax-Friedrichs (Rusanov) flux, which is more diffusive than the
+/// HLLE flux, but robust to strong shocks and rarefactions.
+pub fn riemann_rusanov(pl: Primitive, pr: Primitive, direction: Direction, gamma_law_index: f64) -> Conserved {
+    let ul = pl.to_conserved(gamma_law_index);
+    let ur = pr.to_conserved(gamma_law_index);
+    let fl = pl.flux_vector(direction, gamma_law_index);
+    let fr = pr.flux_vector(direction, gamma_law_index);
+    let a = pl.max_signal_speed(gamma_law_index).max(pr.max_signal_speed(gamma_law_index));
+
+    (fl + fr - (ur - ul) * a) * 0.5
+}
+
+
+
+
+// ============================================================================
+pub fn riemann_roe(pl: Primitive, pr: Primitive, direction: Direction, gamma_law_index: f64) -> Conserved {
+    let ul = pl.to_conserved(gamma_law_index);
+    let ur = pr.to_conserved(gamma_law_index);
+    let fl = pl.flux_vector(direction, gamma_law_index);
+    let fr = pr.flux_vector(direction, gamma_law_index);
+
+    // Roe-averaged state
+    let sl = pl.mass_density().sqrt();
+    let sr = pr.mass_density().sqrt();
+    let hl = (ul.energy_density() + pl.gas_pressure()) / pl.mass_density();
+    let hr = (ur.energy_density() + pr.gas_pressure()) / pr.mass_density();
+    let d = sl * sr;
+    let u = (sl * pl.velocity_1() + sr * pr.velocity_1()) / (sl + sr);
+    let v = (sl * pl.velocity_2() + sr * pr.velocity_2()) / (sl + sr);
+    let h = (sl * hl + sr * hr) / (sl + sr);
+    let a = ((gamma_law_index - 1.0) * (h - 0.5 * (u * u + v * v))).max(0.0).sqrt();
+
+    // Wave strengths; the shear wave carries the jump in the transverse
+    // velocity
+    let (vn, dvn, dvt) = match direction {
+        Direction::I => (u, pr.velocity_1() - pl.velocity_1(), pr.velocity_2() - pl.velocity_2()),
+        Direction::J => (v, pr.velocity_2() - pl.velocity_2(), pr.velocity_1() - pl.velocity_1()),
+        Direction::K => panic!(),
+    };
+    let dd = pr.mass_density() - pl.mass_density();
+    let dp = pr.gas_pressure() - pl.gas_pressure();
+    let a1 = (dp - d * a * dvn) / (2.0 * a * a);
+    let a2 = dd - dp / (a * a);
+    let a3 = d * dvt;
+    let a4 = (dp + d * a * dvn) / (2.0 * a * a);
+
+    // Eigenvalues, with the Harten (1983) entropy fix on the acoustic waves
+    let l1 = harten_entropy_fix(vn - a, a);
+    let l2 = vn.abs();
+    let l4 = harten_entropy_fix(vn + a, a);
+
+    let (r1, r3, r4) = match direction {
+        Direction::I => (
+            Conserved(1.0, u - a, v, h - u * a),
+            Conserved(0.0, 0.0, 1.0, v),
+            Conserved(1.0, u + a, v, h + u * a),
+        ),
+        Direction::J => (
+            Conserved(1.0, u, v - a, h - v * a),
+            Conserved(0.0, 1.0, 0.0, u),
+            Conserved(1.0, u, v + a, h + v * a),
+        ),
+        Direction::K => panic!(),
+    };
+    let r2 = Conserved(1.0, u, v, 0.5 * (u * u + v * v));
+    let dissipation = r1 * (l1 * a1) + r2 * (l2 * a2) + r3 * (l2 * a3) + r4 * (l4 * a4);
+
+    (fl + fr - dissipation) * 0.5
+}
+
+/// Returns the magnitude of a wave speed, smoothed near zero within a tenth
+/// of the sound speed so that transonic rarefactions are not admitted as
+/// expansion shocks.
+fn harten_entropy_fix(lambda: f64, sound_speed: f64) -> f64 {
+    let delta = 0.1 * sound_speed;
+
+    if lambda.abs() < delta {
+        (lambda * lambda + delta * delta) / (2.0 * delta)
+    } else {
+        lambda.abs()
+    }
+}
+
+
+
+
+// ============================================================================
+/// A batch of [`LANES`] primitive states, stored field by field, for the
+/// batched Riemann solvers. The arithmetic is that of [`Primitive`], so each
+/// lane gives the same result as the scalar kernel.
+pub struct PrimitiveLanes(Lanes, Lanes, Lanes, Lanes);
+
+/// A batch of [`LANES`] conserved states or fluxes, stored field by field.
+pub struct ConservedLanes(Lanes, Lanes, Lanes, Lanes);
+
+impl PrimitiveLanes {
+    /// Loads [`LANES`] consecutive states, which are `stride` values apart
+    /// in `states`.
+    pub fn gather(states: &[f64], stride: usize) -> Self {
+        Self(
+            Lanes::gather(states, stride, 0),
+            Lanes::gather(states, stride, 1),
+            Lanes::gather(states, stride, 2),
+            Lanes::gather(states, stride, 3),
+        )
+    }
+
+    pub fn velocity(&self, direction: Direction) -> Lanes {
+        match direction {
+            Direction::I => self.1,
+            Direction::J => self.2,
+            Direction::K => Lanes::splat(0.0),
+        }
+    }
+
+    pub fn velocity_squared(&self) -> Lanes {
+        self.1 * self.1 + self.2 * self.2
+    }
+
+    pub fn sound_speed_squared(&self, gamma_law_index: f64) -> Lanes {
+        Lanes::splat(gamma_law_index) * self.3 / self.0
+    }
+
+    pub fn outer_wavespeeds(&self, direction: Direction, gamma_law_index: f64) -> (Lanes, Lanes) {
+        let cs = self.sound_speed_squared(gamma_law_index).sqrt();
+        let vn = self.velocity(direction);
+        (vn - cs, vn + cs)
+    }
+
+    pub fn max_signal_speed(&self, gamma_law_index: f64) -> Lanes {
+        self.velocity_squared().sqrt() + self.sound_speed_squared(gamma_law_index).sqrt()
+    }
+
+    pub fn to_conserved(&self, gamma_law_index: f64) -> ConservedLanes {
+        let d   = self.0;
+        let p   = self.3;
+        let vsq = self.velocity_squared();
+
+        ConservedLanes(
+            d,
+            d * self.1,
+            d * self.2,
+            d * vsq * 0.5 + p / Lanes::splat(gamma_law_index - 1.0)
+        )
+    }
+
+    pub fn flux_vector(&self, direction: Direction, gamma_law_index: f64) -> ConservedLanes {
+        let pg = self.3;
+        let vn = self.velocity(direction);
+        let u = self.to_conserved(gamma_law_index);
+
+        ConservedLanes(
+             u.0 * vn,
+             u.1 * vn + pg * direction.along(Direction::I),
+             u.2 * vn + pg * direction.along(Direction::J),
+             u.3 * vn + pg * vn)
+    }
+}
+
+impl ConservedLanes {
+    /// Stores the [`LANES`] states into consecutive states of `states`,
+    /// which are `stride` values apart.
+    pub fn scatter(&self, states: &mut [f64], stride: usize) {
+        self.0.scatter(states, stride, 0);
+        self.1.scatter(states, stride, 1);
+        self.2.scatter(states, stride, 2);
+        self.3.scatter(states, stride, 3);
+    }
+}
+
+impl Add<ConservedLanes> for ConservedLanes {
+    type Output = Self;
+    fn add(self, u: Self) -> Self {
+        Self(self.0 + u.0, self.1 + u.1, self.2 + u.2, self.3 + u.3)
+    }
+}
+
+impl Sub<ConservedLanes> for ConservedLanes {
+    type Output = Self;
+    fn sub(self, u: Self) -> Self {
+        Self(self.0 - u.0, self.1 - u.1, self.2 - u.2, self.3 - u.3)
+    }
+}
+
+impl Mul<Lanes> for ConservedLanes {
+    type Output = Self;
+    fn mul(self, a: Lanes) -> Self {
+        Self(self.0 * a, self.1 * a, self.2 * a, self.3 * a)
+    }
+}
+
+impl Div<Lanes> for ConservedLanes {
+    type Output = Self;
+    fn div(self, a: Lanes) -> Self {
+        Self(self.0 / a, self.1 / a, self.2 / a, self.3 / a)
+    }
+}
+
+/// The HLLE flux of [`riemann_hlle`], for a batch of faces.
+pub fn riemann_hlle_lanes(pl: PrimitiveLanes, pr: PrimitiveLanes, direction: Direction, gamma_law_index: f64) -> ConservedLanes {
+    let ul = pl.to_conserved(gamma_law_index);
+    let ur = pr.to_conserved(gamma_law_index);
+    let fl = pl.flux_vector(direction, gamma_law_index);
+    let fr = pr.flux_vector(direction, gamma_law_index);
+
+    let (alm, alp) = pl.outer_wavespeeds(direction, gamma_law_index);
+    let (arm, arp) = pr.outer_wavespeeds(direction, gamma_law_index);
+    let ap = alp.max(arp).max(Lanes::splat(0.0));
+    let am = alm.min(arm).min(Lanes::splat(0.0));
+
+    (fl * ap - fr * am - (ul - ur) * ap * am) / (ap - am)
+}
+
+/// The Rusanov flux of [`riemann_rusanov`], for a batch of faces.
+pub fn riemann_rusanov_lanes(pl: PrimitiveLanes, pr: PrimitiveLanes, direction: Direction, gamma_law_index: f64) -> ConservedLanes {
+    let ul = pl.to_conserved(gamma_law_index);
+    let ur = pr.to_conserved(gamma_law_index);
+    let fl = pl.flux_vector(direction, gamma_law_index);
+    let fr = pr.flux_vector(direction, gamma_law_index);
+    let a = pl.max_signal_speed(gamma_law_index).max(pr.max_signal_speed(gamma_law_index));
+
+    (fl + fr - (ur - ul) * a) * Lanes::splat(0.5)
+}
+
+
+
+
+// ============================================================================
+/// Coefficients of the Navier-Stokes viscous stress and of thermal
+/// conduction. The temperature is `p / rho`, and the bulk viscosity is zero.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Diffusion {
+    pub shear_viscosity: f64,
+    pub thermal_conductivity: f64,
+}
+
+impl Diffusion {
+    /// Returns the diffusive flux through a face normal to the given
+    /// direction, from the velocity `(u, v)` at the face, and the gradients
+    /// of the velocity components and the temperature there. The flux is
+    /// to be added to the inviscid (Riemann solver) flux.
+    pub fn flux(
+        &self,
+        velocity: (f64, f64),
+        grad_u: (f64, f64),
+        grad_v: (f64, f64),
+        grad_t: (f64, f64),
+        direction: Direction,
+    ) -> Conserved {
+        let mu = self.shear_viscosity;
+        let kappa = self.thermal_conductivity;
+        let (u, v) = velocity;
+        let div = grad_u.0 + grad_v.1;
+        let txx = mu * (2.0 * grad_u.0 - 2.0 / 3.0 * div);
+        let tyy = mu * (2.0 * grad_v.1 - 2.0 / 3.0 * div);
+        let txy = mu * (grad_u.1 + grad_v.0);
+
+        match direction {
+            Direction::I => Conserved(0.0, -txx, -txy, -(u * txx + v * txy) - kappa * grad_t.0),
+            Direction::J => Conserved(0.0, -txy, -tyy, -(u * txy + v * tyy) - kappa * grad_t.1),
+            Direction::K => panic!(),
+        }
+    }
+
+    /// Returns the largest diffusion coefficient (in units of area per
+    /// time) of the momentum and energy equations, in the given state.
+    pub fn max_diffusivity(&self, p: &Primitive, gamma_law_index: f64) -> f64 {
+        let nu = 4.0 / 3.0 * self.shear_viscosity / p.mass_density();
+        let chi = (gamma_law_index - 1.0) * self.thermal_conductivity / p.mass_density();
+        nu.max(chi)
+    }
+
+    /// Returns the largest stable time step of an explicit update of the
+    /// diffusion terms on a mesh with the given spacing, in the given state.
+    pub fn max_time_step(&self, p: &Primitive, spacing: (f64, f64), gamma_law_index: f64) -> f64 {
+        let (dx, dy) = spacing;
+        let d = self.max_diffusivity(p, gamma_law_index);
+        0.25 / (d * (1.0 / (dx * dx) + 1.0 / (dy * dy)))
+    }
+}
+
+
+
+
+// ============================================================================
+/// Lower limits on the mass density and gas pressure, imposed before the
+/// primitive variables are recovered. The default floors are zero, which
+/// are not applied.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Floors {
+    pub mass_density: f64,
+    pub gas_pressure: f64,
+}
+
+
+
+
+// ============================================================================
+/// The approximate Riemann solvers available for the 2D Euler equations.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RiemannSolver {
+    Hlle,
+    Roe,
+    Rusanov,
+}
+
+impl RiemannSolver {
+    pub fn flux(self, pl: Primitive, pr: Primitive, direction: Direction, gamma_law_index: f64) -> Conserved {
+        match self {
+            Self::Hlle => riemann_hlle(pl, pr, direction, gamma_law_index),
+            Self::Roe => riemann_roe(pl, pr, direction, gamma_law_index),
+            Self::Rusanov => riemann_rusanov(pl, pr, direction, gamma_law_index),
+        }
+    }
+
+    /// Returns the flux for a batch of faces, if this solver has a batched
+    /// kernel. The Roe solver branches on the wave speeds of each face, so
+    /// it does not.
+    pub fn flux_lanes(
+        self,
+        pl: PrimitiveLanes,
+        pr: PrimitiveLanes,
+        direction: Direction,
+        gamma_law_index: f64,
+    ) -> Option<ConservedLanes> {
+        match self {
+            Self::Hlle => Some(riemann_hlle_lanes(pl, pr, direction, gamma_law_index)),
+            Self::Roe => None,
+            Self::Rusanov => Some(riemann_rusanov_lanes(pl, pr, direction, gamma_law_index)),
+        }
+    }
+}
+
+impl std::str::FromStr for RiemannSolver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hlle" => Ok(Self::Hlle),
+            "roe" => Ok(Self::Roe),
+            "rusanov" => Ok(Self::Rusanov),
+            _ => Err(format!("unknown Riemann solver '{}' (hlle|roe|rusanov)", s)),
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+/// The 2D Euler equations of an ideal gas, with the approximate Riemann
+/// solver, the floors, and the optional viscous and thermal fluxes used by
+/// the solvers. The fallback flux is the Rusanov flux.
+#[derive(Clone, Copy, Debug)]
+pub struct Euler2d {
+    pub gamma_law_index: f64,
+    pub riemann_solver: RiemannSolver,
+    pub floors: Floors,
+    pub diffusion: Option<Diffusion>,
+}
+
+impl HydroSystem for Euler2d {
+    fn num_fields(&self) -> usize {
+        4
+    }
+
+    fn to_conserved(&self, p: &[f64], u: &mut [f64]) {
+        Primitive::from(p).to_conserved(self.gamma_law_index).write_to_slice(u)
+    }
+
+    fn to_primitive(&self, u: &[f64], p: &mut [f64]) -> Result<(), Error> {
+        Conserved::from(u).to_primitive(self.gamma_law_index)?.write_to_slice(p);
+        Ok(())
+    }
+
+    fn riemann_flux(&self, pl: &[f64], pr: &[f64], axis: Axis, f: &mut [f64]) {
+        self.riemann_solver
+            .flux(pl.into(), pr.into(), direction(axis), self.gamma_law_index)
+            .write_to_slice(f)
+    }
+
+    /// Solves the faces in batches of [`LANES`], and the remainder one at a
+    /// time, unless the Riemann solver has no batched kernel.
+    fn riemann_flux_row(&self, pl: &[f64], pr: &[f64], stride: usize, axis: Axis, f: &mut [f64]) {
+        let batch = LANES * stride;
+        let batched = match self.riemann_solver {
+            RiemannSolver::Roe => 0,
+            _ => f.len() / batch * batch,
+        };
+        let (direction, gamma_law_index) = (direction(axis), self.gamma_law_index);
+        let (pl, pl_rest) = pl.split_at(batched);
+        let (pr, pr_rest) = pr.split_at(batched);
+        let (f, f_rest) = f.split_at_mut(batched);
+
+        for ((pl, pr), f) in pl.chunks_exact(batch).zip(pr.chunks_exact(batch)).zip(f.chunks_exact_mut(batch)) {
+            let (pl, pr) = (PrimitiveLanes::gather(pl, stride), PrimitiveLanes::gather(pr, stride));
+
+            if let Some(flux) = self.riemann_solver.flux_lanes(pl, pr, direction, gamma_law_index) {
+                flux.scatter(f, stride)
+            }
+        }
+        for ((pl, pr), f) in pl_rest.chunks(stride).zip(pr_rest.chunks(stride)).zip(f_rest.chunks_mut(stride)) {
+            self.riemann_flux(pl, pr, axis, f)
+        }
+    }
+
+    fn max_signal_speed(&self, p: &[f64]) -> f64 {
+        Primitive::from(p).max_signal_speed(self.gamma_law_index)
+    }
+
+    fn spherical_source(&self, p: &[f64], r: f64, dt: f64, s: &mut [f64]) {
+        s[1] += p[0] * p[2] * p[2] / r * dt;
+        s[2] -= p[0] * p[1] * p[2] / r * dt;
+    }
+
+    fn fallback_flux(&self, pl: &[f64], pr: &[f64], axis: Axis, f: &mut [f64]) {
+        riemann_rusanov(pl.into(), pr.into(), direction(axis), self.gamma_law_index).write_to_slice(f)
+    }
+
+    fn apply_floors(&self, u: &mut [f64]) -> bool {
+        let mut c = Conserved::from(&*u);
+        let applied = c.apply_floors(&self.floors, self.gamma_law_index);
+
+        if applied {
+            c.write_to_slice(u)
+        }
+        applied
+    }
+
+    fn is_diffusive(&self) -> bool {
+        self.diffusion.is_some()
+    }
+
+    fn add_diffusive_flux(&self, velocity: (f64, f64), gradients: [(f64, f64); 3], axis: Axis, f: &mut [f64]) {
+        if let Some(diffusion) = self.diffusion {
+            let [grad_u, grad_v, grad_t] = gradients;
+            let fd = diffusion.flux(velocity, grad_u, grad_v, grad_t, direction(axis));
+            (Conserved::from(&*f) + fd).write_to_slice(f)
+        }
+    }
+
+    fn max_diffusive_time_step(&self, p: &[f64], spacing: (f64, f64)) -> f64 {
+        match self.diffusion {
+            Some(diffusion) => diffusion.max_time_step(&Primitive::from(p), spacing, self.gamma_law_index),
+            None => f64::INFINITY,
+        }
+    }
+}
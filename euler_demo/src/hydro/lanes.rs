@@ -0,0 +1,187 @@
+use std::ops::{Add, Sub, Mul, Div};
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx"))]
+use std::arch::x86_64::*;
+
+
+
+
+/**
+ * The number of zones (or faces) processed together by the batched kernels.
+ */
+pub const LANES: usize = 4;
+
+
+
+
+/**
+ * A batch of [`LANES`] values, with lane-wise arithmetic. Kernels written in
+ * terms of this type process one zone in each lane, so a batch of states is
+ * stored field by field (struct-of-arrays) rather than zone by zone.
+ *
+ * With the `simd` feature, on x86-64 targets with AVX enabled (for example
+ * with `RUSTFLAGS="-C target-cpu=native"`), the lanes are an AVX register and
+ * the operations are explicit intrinsics. Otherwise the lanes are a plain
+ * array, and the operations are loops which the compiler is free to
+ * vectorize. Either way the arithmetic is IEEE lane by lane, so the results
+ * are identical to those of the scalar kernels, except that `max` and `min`
+ * may differ from [`f64::max`] and [`f64::min`] when a lane is NaN.
+ */
+#[derive(Clone, Copy)]
+pub struct Lanes(Repr);
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx"))]
+type Repr = __m256d;
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", target_feature = "avx")))]
+type Repr = [f64; LANES];
+
+
+
+
+// ============================================================================
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx"))]
+impl Lanes {
+    pub fn splat(a: f64) -> Self {
+        unsafe { Self(_mm256_set1_pd(a)) }
+    }
+
+    pub fn from_array(a: [f64; LANES]) -> Self {
+        unsafe { Self(_mm256_loadu_pd(a.as_ptr())) }
+    }
+
+    pub fn to_array(self) -> [f64; LANES] {
+        let mut a = [0.0; LANES];
+        unsafe { _mm256_storeu_pd(a.as_mut_ptr(), self.0) };
+        a
+    }
+
+    pub fn sqrt(self) -> Self {
+        unsafe { Self(_mm256_sqrt_pd(self.0)) }
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        unsafe { Self(_mm256_max_pd(self.0, other.0)) }
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        unsafe { Self(_mm256_min_pd(self.0, other.0)) }
+    }
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx"))]
+macro_rules! lanewise {
+    ($trait:ident, $method:ident, $intrinsic:ident) => {
+        impl $trait for Lanes {
+            type Output = Self;
+            fn $method(self, other: Self) -> Self {
+                unsafe { Self($intrinsic(self.0, other.0)) }
+            }
+        }
+    };
+}
+
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx"))]
+lanewise!(Add, add, _mm256_add_pd);
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx"))]
+lanewise!(Sub, sub, _mm256_sub_pd);
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx"))]
+lanewise!(Mul, mul, _mm256_mul_pd);
+#[cfg(all(feature = "simd", target_arch = "x86_64", target_feature = "avx"))]
+lanewise!(Div, div, _mm256_div_pd);
+
+
+
+
+// ============================================================================
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", target_feature = "avx")))]
+impl Lanes {
+    pub fn splat(a: f64) -> Self {
+        Self([a; LANES])
+    }
+
+    pub fn from_array(a: [f64; LANES]) -> Self {
+        Self(a)
+    }
+
+    pub fn to_array(self) -> [f64; LANES] {
+        self.0
+    }
+
+    pub fn sqrt(self) -> Self {
+        self.map(f64::sqrt)
+    }
+
+    pub fn max(self, other: Self) -> Self {
+        self.zip(other, f64::max)
+    }
+
+    pub fn min(self, other: Self) -> Self {
+        self.zip(other, f64::min)
+    }
+
+    fn map(self, f: impl Fn(f64) -> f64) -> Self {
+        let mut a = self.0;
+        a.iter_mut().for_each(|a| *a = f(*a));
+        Self(a)
+    }
+
+    fn zip(self, other: Self, f: impl Fn(f64, f64) -> f64) -> Self {
+        let mut a = self.0;
+        a.iter_mut().zip(&other.0).for_each(|(a, b)| *a = f(*a, *b));
+        Self(a)
+    }
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", target_feature = "avx")))]
+macro_rules! lanewise {
+    ($trait:ident, $method:ident, $op:tt) => {
+        impl $trait for Lanes {
+            type Output = Self;
+            fn $method(self, other: Self) -> Self {
+                self.zip(other, |a, b| a $op b)
+            }
+        }
+    };
+}
+
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", target_feature = "avx")))]
+lanewise!(Add, add, +);
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", target_feature = "avx")))]
+lanewise!(Sub, sub, -);
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", target_feature = "avx")))]
+lanewise!(Mul, mul, *);
+#[cfg(not(all(feature = "simd", target_arch = "x86_64", target_feature = "avx")))]
+lanewise!(Div, div, /);
+
+
+
+
+// ============================================================================
+impl Lanes {
+    /// Loads one field of [`LANES`] consecutive states, which are `stride`
+    /// values apart in `states`.
+    pub fn gather(states: &[f64], stride: usize, field: usize) -> Self {
+        let mut a = [0.0; LANES];
+
+        for (n, a) in a.iter_mut().enumerate() {
+            *a = states[n * stride + field]
+        }
+        Self::from_array(a)
+    }
+
+    /// Stores the lanes into one field of [`LANES`] consecutive states,
+    /// which are `stride` values apart in `states`.
+    pub fn scatter(self, states: &mut [f64], stride: usize, field: usize) {
+        for (n, a) in self.to_array().iter().enumerate() {
+            states[n * stride + field] = *a
+        }
+    }
+}
+
+impl Mul<f64> for Lanes {
+    type Output = Self;
+    fn mul(self, a: f64) -> Self {
+        self * Self::splat(a)
+    }
+}
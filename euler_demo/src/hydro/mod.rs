@@ -1,4 +1,10 @@
+pub mod derived;
 pub mod euler2d;
 pub mod euler3d;
 pub mod error;
 pub mod geometry;
+pub mod lanes;
+pub mod setups;
+pub mod species;
+pub mod srhd2d;
+pub mod system;
@@ -0,0 +1,248 @@
+use std::f64::consts::PI;
+use std::ops::Range;
+
+
+
+
+/**
+ * The kind of domain a setup is meant to be run on, from which a driver can
+ * choose the boundary conditions.
+ */
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Domain {
+    /// The guard zones are held at the setup's own state.
+    Open,
+
+    /// Both axes are periodic.
+    Periodic,
+
+    /// The gas is stratified by gravity along the second axis: the guard
+    /// zones on that axis are held at the setup's state, and the edges on
+    /// the first axis are reflecting walls.
+    Stratified,
+}
+
+
+
+
+/**
+ * An initial condition for the 2D Euler equations, with primitive fields
+ * `[rho, vx, vy, p]`. Positions are Cartesian coordinates, and the built-in
+ * problems are laid out for the square `[-1, 1] x [-1, 1]`.
+ */
+pub trait Setup: Send + Sync {
+    /// Returns the primitive state at the given position and time. Unless
+    /// the setup [`Setup::is_exact`], only `time = 0` is meaningful, and
+    /// other times return the initial condition.
+    fn primitive_at(&self, position: (f64, f64), time: f64) -> [f64; 4];
+
+    /// Returns the kind of domain the setup is meant for.
+    fn domain(&self) -> Domain {
+        Domain::Open
+    }
+
+    /// Returns true if [`Setup::primitive_at`] is the exact solution at all
+    /// times, so that it can be used to measure the error of a scheme.
+    fn is_exact(&self) -> bool {
+        false
+    }
+}
+
+
+
+
+/**
+ * The shock tube of Sod (1978), with the discontinuity at `x = 0`.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Sod;
+
+impl Setup for Sod {
+    fn primitive_at(&self, (x, _): (f64, f64), _: f64) -> [f64; 4] {
+        if x < 0.0 {
+            [1.0, 0.0, 0.0, 1.0]
+        } else {
+            [0.125, 0.0, 0.0, 0.1]
+        }
+    }
+}
+
+
+
+
+/**
+ * A cylindrical blast wave: a disk of radius 0.24 at ten times the density
+ * and eight times the pressure of the ambient gas.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct CylindricalBlast;
+
+impl Setup for CylindricalBlast {
+    fn primitive_at(&self, (x, y): (f64, f64), _: f64) -> [f64; 4] {
+        if x.hypot(y) < 0.24 {
+            [1.0, 0.0, 0.0, 1.0]
+        } else {
+            [0.1, 0.0, 0.0, 0.125]
+        }
+    }
+}
+
+
+
+
+/**
+ * The Kelvin-Helmholtz instability of two shear layers at `y = +/- 0.5`,
+ * between a dense band moving right and lighter gas moving left, in pressure
+ * equilibrium. The layers are seeded with a single-mode transverse velocity
+ * perturbation.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct KelvinHelmholtz;
+
+impl Setup for KelvinHelmholtz {
+    fn primitive_at(&self, (x, y): (f64, f64), _: f64) -> [f64; 4] {
+        let band = y.abs() < 0.5;
+        let (d, vx) = if band { (2.0, 0.5) } else { (1.0, -0.5) };
+        let layers = (-(y - 0.5).powi(2) / 0.005).exp() + (-(y + 0.5).powi(2) / 0.005).exp();
+        let vy = 0.01 * (2.0 * PI * x).sin() * layers;
+        [d, vx, vy, 2.5]
+    }
+
+    fn domain(&self) -> Domain {
+        Domain::Periodic
+    }
+}
+
+
+
+
+/**
+ * The Rayleigh-Taylor instability: gas of density 2 above gas of density 1,
+ * in hydrostatic equilibrium with a downward gravitational acceleration
+ * (which the driver must apply). The interface at `y = 0` is seeded with a
+ * velocity perturbation which vanishes at the walls.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct RayleighTaylor {
+    pub gravity: f64,
+}
+
+impl Setup for RayleighTaylor {
+    fn primitive_at(&self, (x, y): (f64, f64), _: f64) -> [f64; 4] {
+        let d = if y > 0.0 { 2.0 } else { 1.0 };
+        let p = 2.5 - self.gravity * d * y;
+        let vy = 0.0025 * (1.0 + (PI * x).cos()) * (1.0 + (PI * y).cos());
+        [d, 0.0, vy, p]
+    }
+
+    fn domain(&self) -> Domain {
+        Domain::Stratified
+    }
+}
+
+
+
+
+/**
+ * An isothermal atmosphere with unit sound speed, in hydrostatic equilibrium
+ * with a downward gravitational acceleration (which the driver must apply).
+ * It is at rest, so it is its own exact solution.
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Atmosphere {
+    pub gravity: f64,
+}
+
+impl Setup for Atmosphere {
+    fn primitive_at(&self, (_, y): (f64, f64), _: f64) -> [f64; 4] {
+        let d = (-self.gravity * y).exp();
+        [d, 0.0, 0.0, d]
+    }
+
+    fn domain(&self) -> Domain {
+        Domain::Stratified
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+}
+
+
+
+
+/**
+ * The isentropic vortex of Shu (1998), of the given strength, with lengths
+ * scaled by `radius`, advected diagonally at unit speed on each axis through
+ * a periodic domain. The exact solution is the initial vortex translated,
+ * and its tails are negligible at the domain edges if the radius is at most
+ * a tenth of the domain width.
+ */
+#[derive(Clone, Debug)]
+pub struct IsentropicVortex {
+    pub gamma_law_index: f64,
+    pub strength: f64,
+    pub radius: f64,
+    pub area: (Range<f64>, Range<f64>),
+}
+
+impl Setup for IsentropicVortex {
+    fn primitive_at(&self, (x, y): (f64, f64), time: f64) -> [f64; 4] {
+        let (vx, vy) = (1.0, 1.0);
+        let wrap = |x: f64, range: &Range<f64>| {
+            let width = range.end - range.start;
+            let center = 0.5 * (range.start + range.end);
+            (x - center + 0.5 * width).rem_euclid(width) - 0.5 * width
+        };
+        let x = wrap(x - vx * time, &self.area.0) / self.radius;
+        let y = wrap(y - vy * time, &self.area.1) / self.radius;
+
+        let gm = self.gamma_law_index;
+        let b = self.strength;
+        let r2 = x * x + y * y;
+        let du = b / (2.0 * PI) * (0.5 * (1.0 - r2)).exp();
+        let temperature = 1.0 - (gm - 1.0) * b * b / (8.0 * gm * PI * PI) * (1.0 - r2).exp();
+        let d = temperature.powf(1.0 / (gm - 1.0));
+        [d, vx - du * y, vy + du * x, d * temperature]
+    }
+
+    fn domain(&self) -> Domain {
+        Domain::Periodic
+    }
+
+    fn is_exact(&self) -> bool {
+        true
+    }
+}
+
+
+
+
+/**
+ * Returns the setup with the given name, for the given adiabatic index,
+ * downward gravitational acceleration, and domain.
+ */
+pub fn from_name(
+    name: &str,
+    gamma_law_index: f64,
+    gravity: f64,
+    area: (Range<f64>, Range<f64>),
+) -> Result<Box<dyn Setup>, String> {
+    match name {
+        "sod" => Ok(Box::new(Sod)),
+        "blast" => Ok(Box::new(CylindricalBlast)),
+        "kelvin-helmholtz" => Ok(Box::new(KelvinHelmholtz)),
+        "rayleigh-taylor" => Ok(Box::new(RayleighTaylor { gravity })),
+        "atmosphere" => Ok(Box::new(Atmosphere { gravity })),
+        "vortex" => Ok(Box::new(IsentropicVortex {
+            gamma_law_index,
+            strength: 5.0,
+            radius: 0.1,
+            area,
+        })),
+        _ => Err(format!(
+            "unknown setup '{}' (sod|blast|kelvin-helmholtz|rayleigh-taylor|atmosphere|vortex)",
+            name
+        )),
+    }
+}
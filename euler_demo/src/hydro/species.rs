@@ -0,0 +1,120 @@
+use gridiron::index_space::Axis;
+use super::error::Error;
+use super::system::HydroSystem;
+
+
+
+
+/**
+ * A hydrodynamics system which also carries the mass fractions of a number
+ * of species. The primitive fields are those of the underlying system
+ * followed by the mass fractions, and the conserved fields are followed by
+ * the partial densities (the conserved mass density times each mass
+ * fraction). The species are advected with the mass flux of the underlying
+ * system, taking the mass fractions from the upwind side of each face, so
+ * that a uniform composition stays uniform. Reactions between the species
+ * are applied separately, by [`crate::solvers::reactions::Reactions`].
+ */
+#[derive(Clone, Copy, Debug)]
+pub struct Multispecies<H> {
+    pub hydro: H,
+    pub num_species: usize,
+}
+
+impl<H: HydroSystem> Multispecies<H> {
+    fn upwind_species(&self, pl: &[f64], pr: &[f64], f: &mut [f64]) {
+        let n = self.hydro.num_fields();
+        let x = if f[0] > 0.0 { &pl[n..] } else { &pr[n..] };
+        let mass_flux = f[0];
+
+        for (f, x) in f[n..].iter_mut().zip(x) {
+            *f = mass_flux * x
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+impl<H: HydroSystem> HydroSystem for Multispecies<H> {
+    fn num_fields(&self) -> usize {
+        self.hydro.num_fields() + self.num_species
+    }
+
+    fn to_conserved(&self, p: &[f64], u: &mut [f64]) {
+        let n = self.hydro.num_fields();
+        self.hydro.to_conserved(&p[..n], &mut u[..n]);
+        let d = u[0];
+
+        for (u, x) in u[n..].iter_mut().zip(&p[n..]) {
+            *u = d * x
+        }
+    }
+
+    fn to_primitive(&self, u: &[f64], p: &mut [f64]) -> Result<(), Error> {
+        let n = self.hydro.num_fields();
+        self.hydro.to_primitive(&u[..n], &mut p[..n])?;
+
+        for (x, u_s) in p[n..].iter_mut().zip(&u[n..]) {
+            *x = u_s / u[0]
+        }
+        Ok(())
+    }
+
+    fn riemann_flux(&self, pl: &[f64], pr: &[f64], axis: Axis, f: &mut [f64]) {
+        let n = self.hydro.num_fields();
+        self.hydro.riemann_flux(&pl[..n], &pr[..n], axis, &mut f[..n]);
+        self.upwind_species(pl, pr, f)
+    }
+
+    fn riemann_flux_row(&self, pl: &[f64], pr: &[f64], stride: usize, axis: Axis, f: &mut [f64]) {
+        self.hydro.riemann_flux_row(pl, pr, stride, axis, f);
+
+        for ((pl, pr), f) in pl.chunks(stride).zip(pr.chunks(stride)).zip(f.chunks_mut(stride)) {
+            self.upwind_species(pl, pr, f)
+        }
+    }
+
+    fn max_signal_speed(&self, p: &[f64]) -> f64 {
+        self.hydro.max_signal_speed(&p[..self.hydro.num_fields()])
+    }
+
+    fn spherical_source(&self, p: &[f64], r: f64, dt: f64, s: &mut [f64]) {
+        let n = self.hydro.num_fields();
+        self.hydro.spherical_source(&p[..n], r, dt, &mut s[..n])
+    }
+
+    fn fallback_flux(&self, pl: &[f64], pr: &[f64], axis: Axis, f: &mut [f64]) {
+        let n = self.hydro.num_fields();
+        self.hydro.fallback_flux(&pl[..n], &pr[..n], axis, &mut f[..n]);
+        self.upwind_species(pl, pr, f)
+    }
+
+    /// Applies the floors of the underlying system, keeping the mass
+    /// fractions if the density is raised.
+    fn apply_floors(&self, u: &mut [f64]) -> bool {
+        let n = self.hydro.num_fields();
+        let d = u[0];
+        let applied = self.hydro.apply_floors(&mut u[..n]);
+
+        if applied && d > 0.0 {
+            let scale = u[0] / d;
+            u[n..].iter_mut().for_each(|u| *u *= scale)
+        }
+        applied
+    }
+
+    fn is_diffusive(&self) -> bool {
+        self.hydro.is_diffusive()
+    }
+
+    fn add_diffusive_flux(&self, velocity: (f64, f64), gradients: [(f64, f64); 3], axis: Axis, f: &mut [f64]) {
+        let n = self.hydro.num_fields();
+        self.hydro.add_diffusive_flux(velocity, gradients, axis, &mut f[..n])
+    }
+
+    fn max_diffusive_time_step(&self, p: &[f64], spacing: (f64, f64)) -> f64 {
+        self.hydro.max_diffusive_time_step(&p[..self.hydro.num_fields()], spacing)
+    }
+}
@@ -0,0 +1,484 @@
+use std::ops::{Add, Sub, Mul, Div};
+use super::error::Error;
+use super::geometry::Direction;
+use super::system::{direction, HydroSystem};
+use gridiron::index_space::Axis;
+
+const NEWTON_TOLERANCE: f64 = 1e-10;
+const NEWTON_MAX_ITERATIONS: usize = 50;
+
+
+
+
+/**
+ * Conserved variables of special relativistic hydrodynamics (c = 1): the lab
+ * frame mass density `D = rho W`, the momentum densities `S = rho h W^2 v`,
+ * and the energy density with the rest mass subtracted, `tau = rho h W^2 - p
+ * - D`.
+ */
+#[derive(Clone, Copy)]
+pub struct Conserved(f64, f64, f64, f64);
+
+/**
+ * Primitive variables: the proper mass density, the three-velocity
+ * components, and the gas pressure.
+ */
+#[derive(Clone, Copy)]
+pub struct Primitive(f64, f64, f64, f64);
+
+
+
+
+// ============================================================================
+impl Conserved {
+
+    fn from_slice(cons: &[f64]) -> Self {
+        Self(cons[0], cons[1], cons[2], cons[3])
+    }
+
+    pub fn write_to_slice(&self, cons: &mut [f64]) {
+        cons[0] = self.0;
+        cons[1] = self.1;
+        cons[2] = self.2;
+        cons[3] = self.3;
+    }
+
+    pub fn as_array(&self) -> [f64; 4] {
+        [self.0, self.1, self.2, self.3]
+    }
+
+    pub fn lab_frame_density(&self) -> f64 {
+        self.0
+    }
+
+    pub fn momentum_1(&self) -> f64 {
+        self.1
+    }
+
+    pub fn momentum_2(&self) -> f64 {
+        self.2
+    }
+
+    pub fn energy_density(&self) -> f64 {
+        self.3
+    }
+
+    pub fn momentum(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::I => self.momentum_1(),
+            Direction::J => self.momentum_2(),
+            Direction::K => 0.0,
+        }
+    }
+
+    pub fn momentum_squared(&self) -> f64 {
+        self.1 * self.1 + self.2 * self.2
+    }
+
+    /**
+     * Recovers the primitive variables by Newton iteration on the gas
+     * pressure, starting from the given guess (typically the pressure in the
+     * same zone before the update).
+     */
+    pub fn to_primitive(&self, gamma_law_index: f64, pressure_guess: f64) -> Result<Primitive, Error> {
+        let d = self.lab_frame_density();
+        let tau = self.energy_density();
+        let ssq = self.momentum_squared();
+        let mut p = pressure_guess.max(0.0);
+
+        if d < 0.0 {
+            return Err(Error::NegativeMassDensity(d));
+        }
+
+        for _ in 0..NEWTON_MAX_ITERATIONS {
+            let et = tau + d + p;
+            let vsq = ssq / (et * et);
+
+            if vsq >= 1.0 {
+                return Err(Error::SuperluminalVelocity(vsq.sqrt()));
+            }
+            let w = 1.0 / (1.0 - vsq).sqrt();
+            let rho = d / w;
+            let eps = (tau + d * (1.0 - w) + p * (1.0 - w * w)) / (d * w);
+            let h = 1.0 + eps + p / rho;
+            let csq = gamma_law_index * p / (rho * h);
+            let f = (gamma_law_index - 1.0) * rho * eps - p;
+            let g = vsq * csq - 1.0;
+            let next = (p - f / g).max(0.0);
+
+            if (next - p).abs() <= NEWTON_TOLERANCE * p.max(NEWTON_TOLERANCE) {
+                let et = tau + d + next;
+                let vsq = ssq / (et * et);
+
+                if vsq >= 1.0 {
+                    return Err(Error::SuperluminalVelocity(vsq.sqrt()));
+                }
+                let w = 1.0 / (1.0 - vsq).sqrt();
+                let v1 = self.momentum_1() / et;
+                let v2 = self.momentum_2() / et;
+                return Ok(Primitive(d / w, v1, v2, next));
+            }
+            p = next;
+        }
+        Err(Error::RootFinderFailed(NEWTON_MAX_ITERATIONS))
+    }
+}
+
+
+
+
+// ============================================================================
+impl Primitive {
+
+    fn from_slice(prim: &[f64]) -> Self {
+        Self(prim[0], prim[1], prim[2], prim[3])
+    }
+
+    pub fn write_to_slice(&self, prim: &mut [f64]) {
+        prim[0] = self.0;
+        prim[1] = self.1;
+        prim[2] = self.2;
+        prim[3] = self.3;
+    }
+
+    pub fn new(d0: f64, u0: f64, v0: f64, p0: f64) -> Self {
+        Self(d0, u0, v0, p0)
+    }
+
+    pub fn as_array(&self) -> [f64; 4] {
+        [self.0, self.1, self.2, self.3]
+    }
+
+    pub fn mass_density(&self) -> f64 {
+        self.0
+    }
+
+    pub fn velocity_1(&self) -> f64 {
+        self.1
+    }
+
+    pub fn velocity_2(&self) -> f64 {
+        self.2
+    }
+
+    pub fn gas_pressure(&self) -> f64 {
+        self.3
+    }
+
+    pub fn velocity(&self, direction: Direction) -> f64 {
+        match direction {
+            Direction::I => self.velocity_1(),
+            Direction::J => self.velocity_2(),
+            Direction::K => 0.0,
+        }
+    }
+
+    pub fn velocity_squared(&self) -> f64 {
+        self.1 * self.1 + self.2 * self.2
+    }
+
+    pub fn lorentz_factor(&self) -> f64 {
+        1.0 / (1.0 - self.velocity_squared()).sqrt()
+    }
+
+    pub fn specific_enthalpy(&self, gamma_law_index: f64) -> f64 {
+        1.0 + self.gas_pressure() / self.mass_density() * gamma_law_index / (gamma_law_index - 1.0)
+    }
+
+    pub fn sound_speed_squared(&self, gamma_law_index: f64) -> f64 {
+        gamma_law_index * self.gas_pressure() / (self.mass_density() * self.specific_enthalpy(gamma_law_index))
+    }
+
+    /**
+     * The outermost characteristic speeds along the given direction, from
+     * the relativistic velocity addition of the sound speed to the flow.
+     */
+    pub fn outer_wavespeeds(&self, direction: Direction, gamma_law_index: f64) -> (f64, f64) {
+        let csq = self.sound_speed_squared(gamma_law_index);
+        let vsq = self.velocity_squared();
+        let vn = self.velocity(direction);
+        let a = vn * (1.0 - csq);
+        let b = (csq * (1.0 - vsq) * (1.0 - vsq * csq - vn * vn * (1.0 - csq))).sqrt();
+        let c = 1.0 - vsq * csq;
+        ((a - b) / c, (a + b) / c)
+    }
+
+    pub fn to_conserved(&self, gamma_law_index: f64) -> Conserved {
+        let w = self.lorentz_factor();
+        let d = self.mass_density() * w;
+        let rhw2 = self.mass_density() * self.specific_enthalpy(gamma_law_index) * w * w;
+
+        Conserved(
+            d,
+            rhw2 * self.velocity_1(),
+            rhw2 * self.velocity_2(),
+            rhw2 - self.gas_pressure() - d,
+        )
+    }
+
+    pub fn flux_vector(&self, direction: Direction, gamma_law_index: f64) -> Conserved {
+        let pg = self.gas_pressure();
+        let vn = self.velocity(direction);
+        let u = self.to_conserved(gamma_law_index);
+
+        Conserved(
+             u.0 * vn,
+             u.1 * vn + pg * direction.along(Direction::I),
+             u.2 * vn + pg * direction.along(Direction::J),
+             u.1 * direction.along(Direction::I) + u.2 * direction.along(Direction::J) - u.0 * vn)
+    }
+}
+
+
+
+
+// ============================================================================
+impl From<&[f64]> for Conserved {
+    fn from(cons: &[f64]) -> Self {
+        Self::from_slice(cons)
+    }
+}
+
+impl From<&[f64]> for Primitive {
+    fn from(prim: &[f64]) -> Self {
+        Self::from_slice(prim)
+    }
+}
+
+
+
+
+// ============================================================================
+impl Add<Conserved> for Conserved {
+    type Output = Conserved;
+    fn add(self, u: Self) -> Conserved {
+        Conserved(self.0 + u.0, self.1 + u.1, self.2 + u.2, self.3 + u.3)
+    }
+}
+
+impl Sub<Conserved> for Conserved {
+    type Output = Self;
+    fn sub(self, u: Self) -> Self {
+        Self(self.0 - u.0, self.1 - u.1, self.2 - u.2, self.3 - u.3)
+    }
+}
+
+impl Mul<f64> for Conserved {
+    type Output = Self;
+    fn mul(self, a: f64) -> Self {
+        Self(self.0 * a, self.1 * a, self.2 * a, self.3 * a)
+    }
+}
+
+impl Div<f64> for Conserved {
+    type Output = Self;
+    fn div(self, a: f64) -> Self {
+        Self(self.0 / a, self.1 / a, self.2 / a, self.3 / a)
+    }
+}
+
+
+
+
+// ============================================================================
+pub fn riemann_hlle(pl: Primitive, pr: Primitive, direction: Direction, gamma_law_index: f64) -> Conserved {
+    let ul = pl.to_conserved(gamma_law_index);
+    let ur = pr.to_conserved(gamma_law_index);
+    let fl = pl.flux_vector(direction, gamma_law_index);
+    let fr = pr.flux_vector(direction, gamma_law_index);
+
+    let (alm, alp) = pl.outer_wavespeeds(direction, gamma_law_index);
+    let (arm, arp) = pr.outer_wavespeeds(direction, gamma_law_index);
+    let ap = alp.max(arp).max(0.0);
+    let am = alm.min(arm).min(0.0);
+
+    (fl * ap - fr * am - (ul - ur) * ap * am) / (ap - am)
+}
+
+/**
+ * The HLLC solver of Mignone & Bodo (2005), which restores the contact wave
+ * missing from the HLLE solver.
+ */
+pub fn riemann_hllc(pl: Primitive, pr: Primitive, direction: Direction, gamma_law_index: f64) -> Conserved {
+    let ul = pl.to_conserved(gamma_law_index);
+    let ur = pr.to_conserved(gamma_law_index);
+    let fl = pl.flux_vector(direction, gamma_law_index);
+    let fr = pr.flux_vector(direction, gamma_law_index);
+
+    let (alm, alp) = pl.outer_wavespeeds(direction, gamma_law_index);
+    let (arm, arp) = pr.outer_wavespeeds(direction, gamma_law_index);
+    let ap = alp.max(arp);
+    let am = alm.min(arm);
+
+    if am >= 0.0 {
+        return fl;
+    }
+    if ap <= 0.0 {
+        return fr;
+    }
+
+    // The HLL state and flux, in terms of the total energy E = tau + D
+    let u_hll = (ur * ap - ul * am - (fr - fl)) / (ap - am);
+    let f_hll = (fl * ap - fr * am + (ur - ul) * ap * am) / (ap - am);
+    let e_hll = u_hll.3 + u_hll.0;
+    let fe_hll = f_hll.3 + f_hll.0;
+    let m_hll = u_hll.momentum(direction);
+    let fm_hll = f_hll.momentum(direction);
+
+    // The contact speed is the root of a quadratic with |a*| < 1
+    let a = fe_hll;
+    let b = -(e_hll + fm_hll);
+    let c = m_hll;
+    let a_star = if a.abs() < 1e-12 {
+        -c / b
+    } else {
+        (-b - (b * b - 4.0 * a * c).max(0.0).sqrt()) / (2.0 * a)
+    };
+    let p_star = -fe_hll * a_star + fm_hll;
+
+    let star = |p: &Primitive, u: Conserved, f: Conserved, s: f64| {
+        let vn = p.velocity(direction);
+        let e = u.3 + u.0;
+        let d_star = u.0 * (s - vn) / (s - a_star);
+        let e_star = (e * (s - vn) + p_star * a_star - p.gas_pressure() * vn) / (s - a_star);
+        let mn_star = (u.momentum(direction) * (s - vn) + p_star - p.gas_pressure()) / (s - a_star);
+        let (m1_star, m2_star) = match direction {
+            Direction::I => (mn_star, u.2 * (s - vn) / (s - a_star)),
+            Direction::J => (u.1 * (s - vn) / (s - a_star), mn_star),
+            Direction::K => panic!(),
+        };
+        let u_star = Conserved(d_star, m1_star, m2_star, e_star - d_star);
+        f + (u_star - u) * s
+    };
+
+    if a_star >= 0.0 {
+        star(&pl, ul, fl, am)
+    } else {
+        star(&pr, ur, fr, ap)
+    }
+}
+
+
+
+
+// ============================================================================
+/// The approximate Riemann solvers available for 2D relativistic
+/// hydrodynamics.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RiemannSolver {
+    Hlle,
+    Hllc,
+}
+
+impl RiemannSolver {
+    pub fn flux(self, pl: Primitive, pr: Primitive, direction: Direction, gamma_law_index: f64) -> Conserved {
+        match self {
+            Self::Hlle => riemann_hlle(pl, pr, direction, gamma_law_index),
+            Self::Hllc => riemann_hllc(pl, pr, direction, gamma_law_index),
+        }
+    }
+}
+
+impl std::str::FromStr for RiemannSolver {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hlle" => Ok(Self::Hlle),
+            "hllc" => Ok(Self::Hllc),
+            _ => Err(format!("unknown Riemann solver '{}' (hlle|hllc)", s)),
+        }
+    }
+}
+
+
+
+
+// ============================================================================
+/// The 2D equations of special relativistic hydrodynamics, for an ideal
+/// gas, with the approximate Riemann solver used by the solvers. The
+/// fallback flux is the HLLE flux.
+#[derive(Clone, Copy, Debug)]
+pub struct Srhd2d {
+    pub gamma_law_index: f64,
+    pub riemann_solver: RiemannSolver,
+}
+
+impl HydroSystem for Srhd2d {
+    fn num_fields(&self) -> usize {
+        4
+    }
+
+    fn to_conserved(&self, p: &[f64], u: &mut [f64]) {
+        Primitive::from(p).to_conserved(self.gamma_law_index).write_to_slice(u)
+    }
+
+    fn to_primitive(&self, u: &[f64], p: &mut [f64]) -> Result<(), Error> {
+        Conserved::from(u)
+            .to_primitive(self.gamma_law_index, p[3])?
+            .write_to_slice(p);
+        Ok(())
+    }
+
+    fn riemann_flux(&self, pl: &[f64], pr: &[f64], axis: Axis, f: &mut [f64]) {
+        self.riemann_solver
+            .flux(pl.into(), pr.into(), direction(axis), self.gamma_law_index)
+            .write_to_slice(f)
+    }
+
+    fn max_signal_speed(&self, p: &[f64]) -> f64 {
+        let p = Primitive::from(p);
+        let (ai0, ai1) = p.outer_wavespeeds(Direction::I, self.gamma_law_index);
+        let (aj0, aj1) = p.outer_wavespeeds(Direction::J, self.gamma_law_index);
+        ai0.abs().max(ai1.abs()).max(aj0.abs()).max(aj1.abs())
+    }
+
+    fn spherical_source(&self, p: &[f64], r: f64, dt: f64, s: &mut [f64]) {
+        let prim = Primitive::from(p);
+        let w = prim.lorentz_factor();
+        let rhw2 = prim.mass_density() * prim.specific_enthalpy(self.gamma_law_index) * w * w;
+        s[1] += rhw2 * p[2] * p[2] / r * dt;
+        s[2] -= rhw2 * p[1] * p[2] / r * dt;
+    }
+
+    fn fallback_flux(&self, pl: &[f64], pr: &[f64], axis: Axis, f: &mut [f64]) {
+        riemann_hlle(pl.into(), pr.into(), direction(axis), self.gamma_law_index).write_to_slice(f)
+    }
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn primitive_recovery_inverts_to_conserved() {
+        let gamma_law_index = 4.0 / 3.0;
+
+        for &(d, u, v, p) in &[(1.0, 0.0, 0.0, 1.0), (1.0, 0.5, -0.3, 0.1), (0.1, 0.9, 0.3, 10.0), (10.0, -0.99, 0.0, 1e-3)] {
+            let cons = Primitive::new(d, u, v, p).to_conserved(gamma_law_index);
+
+            for &guess in &[p, 0.5 * p, 2.0 * p] {
+                let prim = cons.to_primitive(gamma_law_index, guess).unwrap();
+                let expected = [d, u, v, p];
+
+                for (x, y) in prim.as_array().iter().zip(&expected) {
+                    assert!((x - y).abs() <= 1e-8 * y.abs().max(1.0), "{:?} != {:?}", prim.as_array(), expected);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn primitive_recovery_rejects_unphysical_states() {
+        let gamma_law_index = 4.0 / 3.0;
+        let superluminal = Conserved(1.0, 10.0, 0.0, 0.1);
+        let negative_density = Conserved(-1.0, 0.0, 0.0, 1.0);
+
+        assert!(matches!(superluminal.to_primitive(gamma_law_index, 0.0), Err(Error::SuperluminalVelocity(_))));
+        assert!(matches!(negative_density.to_primitive(gamma_law_index, 1.0), Err(Error::NegativeMassDensity(_))));
+    }
+}
@@ -0,0 +1,103 @@
+use gridiron::index_space::Axis;
+use super::error::Error;
+use super::geometry::Direction;
+
+
+
+
+/**
+ * The physics of a two-dimensional hydrodynamics system, as needed by the
+ * generic finite volume update in [`crate::solvers::euler2d_pcm`]. States
+ * are passed as slices of `num_fields` values. The primitive fields are
+ * assumed to start with the mass density, the velocity components along the
+ * two mesh axes, and the gas pressure, which the reconstruction, shock
+ * flattening, and source terms rely on; any further fields (for example the
+ * mass fractions of [`super::species::Multispecies`]) are passively
+ * advected. The conserved fields are the corresponding densities, with the
+ * momentum components second and third.
+ */
+pub trait HydroSystem: Clone + Send + Sync + 'static {
+    /// Returns the number of primitive and conserved fields.
+    fn num_fields(&self) -> usize;
+
+    /// Writes the conserved fields of the primitive state `p` to `u`.
+    fn to_conserved(&self, p: &[f64], u: &mut [f64]);
+
+    /// Recovers the primitive state from the conserved fields `u`. On entry
+    /// `p` holds the zone's previous primitive state, which iterative
+    /// recoveries use as the initial guess; it is unchanged on error.
+    fn to_primitive(&self, u: &[f64], p: &mut [f64]) -> Result<(), Error>;
+
+    /// Writes the (approximate Riemann solver) flux through a face normal to
+    /// the given axis, between the primitive states `pl` and `pr`, to `f`.
+    fn riemann_flux(&self, pl: &[f64], pr: &[f64], axis: Axis, f: &mut [f64]);
+
+    /// Writes the Riemann solver fluxes through a row of faces normal to the
+    /// given axis. The states on either side of consecutive faces, and their
+    /// fluxes, are `stride` values apart in `pl`, `pr`, and `f`. Systems may
+    /// override this to solve several faces at once, in SIMD lanes (see
+    /// [`super::lanes`]).
+    fn riemann_flux_row(&self, pl: &[f64], pr: &[f64], stride: usize, axis: Axis, f: &mut [f64]) {
+        for ((pl, pr), f) in pl.chunks(stride).zip(pr.chunks(stride)).zip(f.chunks_mut(stride)) {
+            self.riemann_flux(pl, pr, axis, f)
+        }
+    }
+
+    /// Returns the largest signal speed in the primitive state.
+    fn max_signal_speed(&self, p: &[f64]) -> f64;
+
+    /// Adds the source terms of spherical-polar coordinates `(r, theta)`,
+    /// other than the pressure term, over a time `dt` to `s`, in a zone at
+    /// radius `r` with primitive state `p`.
+    fn spherical_source(&self, p: &[f64], r: f64, dt: f64, s: &mut [f64]);
+
+    /// Writes a robust (diffusive) flux, used to redo the update of zones
+    /// where primitive recovery fails. This is the Riemann solver flux
+    /// unless the system has a more diffusive one.
+    fn fallback_flux(&self, pl: &[f64], pr: &[f64], axis: Axis, f: &mut [f64]) {
+        self.riemann_flux(pl, pr, axis, f)
+    }
+
+    /// Raises the conserved state to the system's floors, if it has any,
+    /// and returns true if it was changed.
+    fn apply_floors(&self, _u: &mut [f64]) -> bool {
+        false
+    }
+
+    /// Returns true if the system has viscous or thermal fluxes. Their
+    /// transverse gradients reach into the corner guard zones.
+    fn is_diffusive(&self) -> bool {
+        false
+    }
+
+    /// Adds the viscous and thermal flux through a face normal to the given
+    /// axis to `f`, from the velocity at the face, and the gradients there
+    /// of the velocity components and of the temperature `p / rho`.
+    fn add_diffusive_flux(
+        &self,
+        _velocity: (f64, f64),
+        _gradients: [(f64, f64); 3],
+        _axis: Axis,
+        _f: &mut [f64],
+    ) {
+    }
+
+    /// Returns the largest stable time step of an explicit update of the
+    /// diffusive terms in a zone with the given spacing and primitive state.
+    fn max_diffusive_time_step(&self, _p: &[f64], _spacing: (f64, f64)) -> f64 {
+        f64::INFINITY
+    }
+}
+
+
+
+
+/**
+ * Returns the direction along a mesh axis.
+ */
+pub fn direction(axis: Axis) -> Direction {
+    match axis {
+        Axis::I => Direction::I,
+        Axis::J => Direction::J,
+    }
+}
@@ -1,22 +1,51 @@
 pub mod hydro;
 pub mod solvers;
 
-use crate::hydro::euler2d::Primitive;
-use crate::solvers::euler2d_pcm::{Mesh, PatchUpdate};
+use crate::hydro::euler2d::{Diffusion, Euler2d, Floors, RiemannSolver};
+use crate::hydro::setups::{self, Domain, Setup};
+use crate::hydro::species::Multispecies;
+use crate::solvers::boundary::{Boundaries, BoundaryCondition, BoundaryFunction};
+use crate::solvers::{advect2d, advect2d_subcycled, burgers2d, euler3d_pcm};
+use crate::solvers::euler1d::{self, InitialCondition};
+use crate::solvers::euler2d_pcm::{Coordinates, Mesh, PatchUpdate, RecoveryFallback, Scheme};
+use crate::solvers::reconstruction::{Flattening, Reconstruction};
+use crate::solvers::cooling::{CoolingTable, RadiativeCooling};
+use crate::solvers::driving::TurbulenceDriving;
+use crate::solvers::point_mass::{BinaryGravity, BinaryOrbit, SinkModel};
+use crate::solvers::reactions::Reactions;
+use crate::solvers::source_terms::{ConstantGravity, HydrostaticGravity, SourceSplitting, SourceTerms};
+use crate::solvers::time_integration::TimeIntegration;
+use crate::solvers::time_series::{Extremum, PatchReduction, RegionIntegral, SurfaceFlux};
 use clap::{AppSettings, Clap};
-use gridiron::automaton::{self, Automaton};
+use gridiron::automaton::{self, Automaton, Metadata};
 use gridiron::coder::Coder;
-use gridiron::index_space::range2d;
-use gridiron::meshing::GraphTopology;
+use gridiron::diagnostics::{combine_over_ranks, volume_totals, Combine, Diagnostics, TimeSeries};
+use gridiron::index_space::{Axis, IndexSpace};
+use gridiron::meshing::{
+    BlockLayout, BoundaryFace, DomainDecomposition, Geometry, GraphTopology, HaloPlan, RefinedRegion, RegionBounds,
+    Side,
+};
 use gridiron::message::{Communicator, NullCommunicator, TcpCommunicator};
-use gridiron::index_space::IndexSpace;
 use gridiron::patch::Patch;
-use gridiron::rect_map::{Rectangle, RectangleMap};
+use gridiron::rect_map::{Brick, BrickMap, Rectangle};
+use gridiron::subcycling::Schedule;
 use gridiron::thread_pool;
+use std::collections::HashMap;
+use std::hash::Hash;
 use std::net::{IpAddr, Ipv4Addr, SocketAddr};
 use std::ops::Range;
+use std::sync::Arc;
 use std::thread;
 
+/// The activation temperature of the fuel burned with `--burning-rate`.
+const ACTIVATION_TEMPERATURE: f64 = 10.0;
+
+/// The energy released per unit mass of fuel burned to ash.
+const HEAT_OF_BURNING: f64 = 5.0;
+
+/// The semi-major axis of the unit-mass binary of `--binary`.
+const BINARY_SEPARATION: f64 = 0.25;
+
 #[derive(Debug, Clone, Clap)]
 #[clap(version = "1.0", author = "J. Zrake <jzrake@clemson.edu>")]
 #[clap(setting = AppSettings::ColoredHelp)]
@@ -32,9 +61,24 @@ struct Opts {
     )]
     strategy: String,
 
+    #[clap(long, default_value = "euler", about = "euler|euler3d|advect|burgers|advect-subcycled")]
+    solver: String,
+
     #[clap(short = 'm', long)]
     multiple_send_threads: bool,
 
+    #[clap(long, about = "spread the stupid pool over NUMA nodes, building each block on its worker")]
+    numa: bool,
+
+    #[clap(long, about = "core ids the stupid pool may pin workers to, e.g. 0-3,8-11")]
+    cores: Option<String>,
+
+    #[clap(long, about = "pin the stupid pool to one hardware thread per physical core")]
+    exclude_siblings: bool,
+
+    #[clap(long, default_value = "0", about = "leave this many cores free of stupid pool workers")]
+    reserve_cores: usize,
+
     #[clap(short = 'n', long, default_value = "1000")]
     grid_resolution: usize,
 
@@ -46,46 +90,155 @@ struct Opts {
 
     #[clap(long, default_value = "0.1")]
     tfinal: f64,
-}
 
-/// The initial model
-struct Model {}
+    #[clap(long, default_value = "0.4", about = "Courant number")]
+    cfl: f64,
 
-impl Model {
-    fn primitive_at(&self, position: (f64, f64)) -> Primitive {
-        let (x, y) = position;
-        let r = (x * x + y * y).sqrt();
+    #[clap(short = 'r', long, default_value = "pcm", about = "pcm|plm|weno5")]
+    reconstruction: Reconstruction,
 
-        if r < 0.24 {
-            Primitive::new(1.0, 0.0, 0.0, 1.0)
-        } else {
-            Primitive::new(0.1, 0.0, 0.0, 0.125)
-        }
+    #[clap(short = 'i', long, about = "fwd|rk2|rk3|ctu (default: fwd with pcm, otherwise rk3)")]
+    time_integration: Option<TimeIntegration>,
+
+    #[clap(long, default_value = "hlle", about = "hlle|roe|rusanov")]
+    riemann_solver: RiemannSolver,
+
+    #[clap(long, default_value = "cartesian", about = "cartesian|cylindrical|spherical")]
+    coordinates: Coordinates,
+
+    #[clap(long, about = "run a 1D problem instead: sod|lax|123|blast|wave")]
+    shock_tube: Option<InitialCondition>,
+
+    #[clap(long, about = "outflow|reflecting|periodic (default: fixed ambient state)")]
+    boundary: Option<BoundaryCondition>,
+
+    #[clap(long, default_value = "0.0")]
+    viscosity: f64,
+
+    #[clap(long, default_value = "0.0")]
+    conductivity: f64,
+
+    #[clap(long, about = "flatten the reconstruction at shocks with this relative pressure jump")]
+    flattening: Option<Flattening>,
+
+    #[clap(long, default_value = "0.0")]
+    density_floor: f64,
+
+    #[clap(long, default_value = "0.0")]
+    pressure_floor: f64,
+
+    #[clap(long, default_value = "fail", about = "fail|diffusive")]
+    recovery_fallback: RecoveryFallback,
+
+    #[clap(long, default_value = "0.0", about = "downward gravitational acceleration")]
+    gravity: f64,
+
+    #[clap(long, about = "apply source terms operator-split rather than unsplit")]
+    split_sources: bool,
+
+    #[clap(long, default_value = "0", about = "steps between conservation and positivity checks")]
+    diagnostics_interval: u64,
+
+    #[clap(long, default_value = "1", about = "levels of refinement for advect-subcycled")]
+    refinement_depth: u32,
+
+    #[clap(
+        long,
+        default_value = "blast",
+        about = "sod|blast|kelvin-helmholtz|rayleigh-taylor|atmosphere|vortex"
+    )]
+    setup: String,
+
+    #[clap(long, about = "balance the gravity against hydrostatic pressure (always unsplit)")]
+    well_balanced: bool,
+
+    #[clap(long, about = "radiative cooling with Lambda(T) from a two-column text file")]
+    cooling_table: Option<String>,
+
+    #[clap(long, default_value = "0.0", about = "rms acceleration of solenoidal turbulence driving")]
+    driving: f64,
+
+    #[clap(long, default_value = "1.0", about = "correlation time of the turbulence driving")]
+    driving_time: f64,
+
+    #[clap(long, about = "directory of the checkpoint files to restart from")]
+    restart: Option<String>,
+
+    #[clap(long, default_value = "0.0", about = "rate of burning fuel to ash (adds two species)")]
+    burning_rate: f64,
+
+    #[clap(long, about = "gravity of a unit-mass binary with this mass ratio (0 for a single mass)")]
+    binary: Option<f64>,
+
+    #[clap(long, default_value = "0.0", about = "eccentricity of the binary orbit")]
+    eccentricity: f64,
+
+    #[clap(long, default_value = "0.02", about = "softening length and sink radius of the binary")]
+    softening: f64,
+
+    #[clap(long, default_value = "0.0", about = "rate at which the binary removes gas")]
+    sink_rate: f64,
+
+    #[clap(long, default_value = "acceleration-free", about = "sink model (acceleration-free|torque-free)")]
+    sink_model: SinkModel,
+
+    #[clap(long, about = "file to append the mass, mass flux, peak Mach number, and luminosity to each step")]
+    time_series: Option<String>,
+}
+
+impl Opts {
+    /// Returns the time integration scheme given with `-i`, or else the
+    /// default for the reconstruction.
+    fn time_integration(&self) -> TimeIntegration {
+        self.time_integration
+            .unwrap_or_else(|| TimeIntegration::default_for(self.reconstruction))
     }
 }
 
-/// The simulation solution state
-#[derive(serde::Serialize)]
+/// The simulation solution state. Each rank writes its own patches, along
+/// with its copy of the turbulence driving (which is the same on every
+/// rank).
+#[derive(serde::Serialize, serde::Deserialize)]
 struct State {
     time: f64,
     iteration: u64,
     primitive: Vec<Patch>,
+    #[serde(default)]
+    driving: Option<TurbulenceDriving>,
 }
 
 impl State {
-    fn new(mesh: &Mesh, bs: usize) -> Self {
-        let model = Model {};
-        let initial_data = |i| model.primitive_at(mesh.cell_center(i)).as_array();
-        let primitive = mesh_rectangles(bs, mesh)
-            .map(|rect| Patch::from_vector_function(0, rect, initial_data))
+    /// Creates the initial state of the given setup, with a uniform
+    /// composition of the given mass fractions (if there are any species).
+    fn new(setup: &dyn Setup, composition: &[f64], mesh: &Mesh, decomposition: &DomainDecomposition) -> Self {
+        let geometry = mesh.geometry();
+        let initial_data = |i, p: &mut [f64]| {
+            let position = mesh.coordinates.to_cartesian(geometry.cell_center(0, i));
+            p[..4].copy_from_slice(&setup.primitive_at(position, 0.0));
+            p[4..].copy_from_slice(composition);
+        };
+        let num_fields = 4 + composition.len();
+        let primitive = decomposition
+            .blocks()
+            .iter()
+            .map(|rect| Patch::from_slice_function(0, rect.clone(), num_fields, initial_data))
             .collect();
 
         Self {
             iteration: 0,
             time: 0.0,
             primitive,
+            driving: None,
         }
     }
+
+    /// Reads the state written by this rank in a previous run, which must
+    /// have had the same mesh, block size, and number of ranks.
+    fn read(directory: &str, rank: usize) -> Result<Self, String> {
+        let path = format!("{}/state.{:04}.cbor", directory, rank);
+        let file = std::fs::File::open(&path).map_err(|e| format!("{}: {}", path, e))?;
+        ciborium::de::from_reader(std::io::BufReader::new(file)).map_err(|e| format!("{}: {}", path, e))
+    }
 }
 
 struct CborCoder<A> {
@@ -119,26 +272,6 @@ where
     }
 }
 
-fn mesh_rectangles(bs: usize, mesh: &Mesh) -> impl Iterator<Item = Rectangle<i64>> {
-    let bs = bs as i64;
-    let ni = mesh.size.0 as i64 / bs;
-    let nj = mesh.size.1 as i64 / bs;
-
-    range2d(0..ni, 0..nj)
-        .into_iter()
-        .map(move |(i, j)| (i * bs..(i + 1) * bs, j * bs..(j + 1) * bs))
-}
-
-fn work_assignment(mesh: &Mesh, comm: &impl Communicator) -> RectangleMap<i64, usize> {
-    mesh.index_space()
-        .tile(comm.size())
-        .into_iter()
-        .map(|space| space.to_rect())
-        .enumerate()
-        .map(|(index, rect)| (rect, index))
-        .collect()
-}
-
 enum Execution {
     Serial,
     Stupid(thread_pool::ThreadPool),
@@ -146,40 +279,303 @@ enum Execution {
     Distributed,
 }
 
+impl Execution {
+    fn new(opts: &Opts) -> Result<Self, String> {
+        if vec!["serial", "mpi"].contains(&opts.strategy.as_str()) && opts.num_threads != 1 {
+            return Err("strategy option requires --num-threads=1".to_string());
+        }
+        match opts.strategy.as_str() {
+            "serial" => Ok(Execution::Serial),
+            "stupid" => {
+                let pool = thread_pool::ThreadPoolBuilder::new(opts.num_threads)
+                    .placement(if opts.numa {
+                        thread_pool::Placement::Numa
+                    } else {
+                        thread_pool::Placement::Compact
+                    })
+                    .core_mask(thread_pool::CoreMask {
+                        cores: opts.cores.as_deref().map(thread_pool::parse_cpu_list),
+                        exclude_siblings: opts.exclude_siblings,
+                        reserved: opts.reserve_cores,
+                    })
+                    .build();
+                if pool.num_threads() != pool.requested_threads() {
+                    eprintln!(
+                        "note: using {} of the {} requested threads{}",
+                        pool.num_threads(),
+                        pool.requested_threads(),
+                        if pool.num_threads() == 0 { " (tasks run in serial)" } else { "" }
+                    );
+                }
+                Ok(Execution::Stupid(pool))
+            }
+            "rayon" => Ok(Execution::Rayon(
+                rayon::ThreadPoolBuilder::new()
+                    .num_threads(opts.num_threads)
+                    .build()
+                    .unwrap(),
+            )),
+            "tcp" | "mpi" => Ok(Execution::Distributed),
+            _ => Err("--strategy options are [serial|stupid|rayon|tcp|mpi]".to_string()),
+        }
+    }
+
+    /// Runs one execution of the task group with this strategy.
+    fn execute<A, K, W>(&self, comm: &mut impl Communicator, work: &W, task_list: Vec<A>) -> Vec<A>
+    where
+        A: 'static + Send + Automaton<Key = K, Value = A>,
+        A::Message: serde::Serialize + serde::Deserialize<'static>,
+        K: 'static + Send + Hash + Eq + serde::Serialize + serde::Deserialize<'static>,
+        W: Fn(&K) -> usize,
+    {
+        match self {
+            Execution::Serial => automaton::execute(task_list).collect(),
+            Execution::Stupid(pool) => automaton::execute_thread_pool(pool, task_list).collect(),
+            Execution::Rayon(pool) => pool
+                .scope(|scope| automaton::execute_rayon(scope, task_list))
+                .collect(),
+            Execution::Distributed => {
+                let code = CborCoder::<A>::new();
+                automaton::execute_comm(comm, &code, work, None, task_list).collect()
+            }
+        }
+    }
+}
+
 fn run(opts: Opts, mut comm: impl Communicator) {
-    let code = CborCoder::<PatchUpdate>::new();
+    if opts.solver == "advect-subcycled" {
+        return run_subcycled(opts, comm);
+    }
+    if opts.solver == "euler3d" {
+        return run_euler3d(opts, comm);
+    }
+    if opts.solver != "euler" {
+        return run_scalar(opts, comm);
+    }
     let mesh = Mesh {
-        area: (-1.0..1.0, -1.0..1.0),
+        area: match opts.coordinates {
+            Coordinates::Cartesian => (-1.0..1.0, -1.0..1.0),
+            Coordinates::Cylindrical => (0.0..1.0, -0.5..0.5),
+            Coordinates::SphericalPolar => (0.05..1.0, 0.0..std::f64::consts::PI),
+        },
         size: (opts.grid_resolution, opts.grid_resolution),
+        coordinates: opts.coordinates,
+    };
+    let diffusion = Diffusion {
+        shear_viscosity: opts.viscosity,
+        thermal_conductivity: opts.conductivity,
+    };
+    let composition = if opts.burning_rate > 0.0 {
+        vec![1.0, 0.0]
+    } else {
+        vec![]
+    };
+    let hydro = Euler2d {
+        gamma_law_index: 5.0 / 3.0,
+        riemann_solver: opts.riemann_solver,
+        floors: Floors {
+            mass_density: opts.density_floor,
+            gas_pressure: opts.pressure_floor,
+        },
+        diffusion: if opts.viscosity != 0.0 || opts.conductivity != 0.0 {
+            Some(diffusion)
+        } else {
+            None
+        },
+    };
+    let system = Multispecies {
+        hydro,
+        num_species: composition.len(),
+    };
+    let scheme = Scheme {
+        reconstruction: opts.reconstruction,
+        time_integration: opts.time_integration(),
+        flattening: opts.flattening,
+        fallback: opts.recovery_fallback,
+    };
+    if scheme.time_integration == TimeIntegration::Ctu
+        && (opts.coordinates != Coordinates::Cartesian || opts.gravity != 0.0 && opts.well_balanced)
+    {
+        if comm.rank() == 0 {
+            eprintln!("Error: ctu is only supported in cartesian coordinates, without --well-balanced");
+        }
+        return;
+    }
+    if opts.binary.is_some() && opts.coordinates != Coordinates::Cartesian {
+        if comm.rank() == 0 {
+            eprintln!("Error: --binary is only supported in cartesian coordinates");
+        }
+        return;
+    }
+    let setup: Arc<dyn Setup> = match setups::from_name(
+        &opts.setup,
+        hydro.gamma_law_index,
+        opts.gravity,
+        mesh.area.clone(),
+    ) {
+        Ok(setup) => Arc::from(setup),
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    let fixed = {
+        let setup = setup.clone();
+        let composition = composition.clone();
+        let coordinates = mesh.coordinates;
+        let function: BoundaryFunction = Arc::new(move |position, time, p: &mut [f64]| {
+            p[..4].copy_from_slice(&setup.primitive_at(coordinates.to_cartesian(position), time));
+            p[4..].copy_from_slice(&composition);
+        });
+        BoundaryCondition::Function(function)
+    };
+    let boundaries = match (&opts.boundary, setup.domain()) {
+        (Some(boundary), _) => Boundaries::uniform(boundary.clone()),
+        (None, Domain::Open) => Boundaries::uniform(fixed),
+        (None, Domain::Periodic) => Boundaries::uniform(BoundaryCondition::Periodic),
+        (None, Domain::Stratified) => Boundaries::uniform(BoundaryCondition::Reflecting)
+            .with_edge(BoundaryFace { axis: Axis::J, side: Side::Lower }, fixed.clone())
+            .with_edge(BoundaryFace { axis: Axis::J, side: Side::Upper }, fixed),
+    };
+    let periodic = boundaries.periodic();
+    let decomposition = match DomainDecomposition::new(
+        mesh.area.clone(),
+        mesh.size,
+        BlockLayout::Size(opts.block_size),
+        comm.size(),
+    ) {
+        Ok(decomposition) => decomposition
+            .with_stencil(scheme.stencil(&system))
+            .with_periodic(periodic),
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
     };
-    let work = work_assignment(&mesh, &comm);
-    let work = |rect: &Rectangle<i64>| {
-        work
-            .query_point(IndexSpace::from(rect.clone()).start())
-            .next()
-            .unwrap()
-            .1
-            .clone()
+    let work = |rect: &Rectangle<i64>| decomposition.rank_of(rect).unwrap();
+    let state = match &opts.restart {
+        Some(directory) => match State::read(directory, comm.rank()) {
+            Ok(state) => state,
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                return;
+            }
+        },
+        None => State::new(&*setup, &composition, &mesh, &decomposition),
     };
     let State {
         mut iteration,
         mut time,
         primitive,
-    } = State::new(&mesh, opts.block_size);
+        driving,
+    } = state;
 
-    let primitive_map: RectangleMap<_, _> = primitive
-        .into_iter()
-        .map(|p| (p.high_resolution_rect(), p))
-        .collect();
-    let dt = mesh.cell_spacing().0 * 0.1;
-    let edge_list = primitive_map.adjacency_list(1);
-    let primitive: Vec<_> = primitive_map.into_iter().map(|(_, prim)| prim).collect();
+    let mut driving = match driving {
+        _ if opts.driving == 0.0 => None,
+        Some(driving) => Some(driving),
+        None => Some(TurbulenceDriving::new(mesh.area.clone(), 3.0, opts.driving, opts.driving_time, 1)),
+    };
 
-    let mut task_list: Vec<_> = primitive
-        .into_iter()
-        .filter(|patch| work(&patch.high_resolution_rect()) == comm.rank())
-        .map(|patch| PatchUpdate::new(patch, mesh.clone(), dt, None, &edge_list))
-        .collect();
+    let mut dt = 0.0;
+    let splitting = if opts.split_sources {
+        SourceSplitting::OperatorSplit
+    } else {
+        SourceSplitting::Unsplit
+    };
+    let cooling = match opts.cooling_table.as_deref().map(CoolingTable::read).transpose() {
+        Ok(table) => table.map(|table| RadiativeCooling {
+            table,
+            gamma_law_index: hydro.gamma_law_index,
+        }),
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    let binary = opts.binary.map(|mass_ratio| BinaryGravity {
+        orbit: BinaryOrbit {
+            total_mass: 1.0,
+            mass_ratio,
+            separation: BINARY_SEPARATION,
+            eccentricity: opts.eccentricity,
+        },
+        softening_length: opts.softening,
+        sink_radius: opts.softening,
+        sink_rate: opts.sink_rate,
+        sink_model: opts.sink_model,
+        gamma_law_index: hydro.gamma_law_index,
+    });
+    let sources = |driving: Option<&TurbulenceDriving>, time: f64| {
+        let mut sources: Vec<Box<dyn SourceTerms>> = Vec::new();
+
+        if opts.gravity != 0.0 && !opts.well_balanced {
+            sources.push(Box::new(ConstantGravity {
+                acceleration: (0.0, -opts.gravity),
+            }))
+        }
+        if let Some(cooling) = &cooling {
+            sources.push(Box::new(cooling.clone()))
+        }
+        if let Some(driving) = driving {
+            sources.push(Box::new(driving.clone()))
+        }
+        if let Some(binary) = &binary {
+            sources.push(Box::new(binary.at_time(time)))
+        }
+        Some(sources)
+            .filter(|sources| !sources.is_empty())
+            .map(|sources| Arc::new(sources) as Arc<dyn SourceTerms>)
+    };
+    let initial_sources = sources(driving.as_ref(), time);
+    let burning_rate = opts.burning_rate;
+    let reactions = Some(()).filter(|_| burning_rate > 0.0).map(|_| {
+        let network = move |_: f64, temperature: f64, x: &[f64], dxdt: &mut [f64]| {
+            let rate = burning_rate * x[0] * (-ACTIVATION_TEMPERATURE / temperature).exp();
+            dxdt[0] = -rate;
+            dxdt[1] = rate;
+            HEAT_OF_BURNING * rate
+        };
+        Arc::new(Reactions {
+            network: Arc::new(network),
+            gamma_law_index: hydro.gamma_law_index,
+        })
+    });
+    let gamma_law_index = hydro.gamma_law_index;
+    let (x, y) = mesh.area.clone();
+    let mut columns: Vec<(&str, Arc<dyn PatchReduction>)> = vec![
+        ("mass", Arc::new(RegionIntegral {
+            region: (x.clone(), y.clone()),
+            quantity: Arc::new(|p| p[0]),
+        })),
+        ("mass_flux", Arc::new(SurfaceFlux {
+            axis: Axis::I,
+            coordinate: 0.5 * (x.start + x.end),
+            span: y.clone(),
+            flux: Arc::new(|p| p[0] * p[1]),
+        })),
+        ("max_mach", Arc::new(Extremum {
+            combine: Combine::Max,
+            quantity: Arc::new(move |p| p[1].hypot(p[2]) / (gamma_law_index * p[3] / p[0]).sqrt()),
+        })),
+    ];
+    if let Some(cooling) = &cooling {
+        let cooling = cooling.clone();
+        columns.push(("luminosity", Arc::new(RegionIntegral {
+            region: (x, y),
+            quantity: Arc::new(move |p| cooling.emissivity(p)),
+        })))
+    }
+    let reductions: Vec<_> = columns.iter().map(|(_, r)| r.clone()).collect();
+    let columns: Vec<_> = columns.iter().map(|(name, r)| (name.to_string(), r.combine())).collect();
+    let edge_list = decomposition.adjacency_list();
+    let diagnostics = Diagnostics::new(opts.diagnostics_interval, vec![0, 3]);
+    let coordinate_system = mesh.coordinate_system();
 
     if opts.grid_resolution % opts.block_size != 0 {
         if comm.rank() == 0 {
@@ -188,63 +584,229 @@ fn run(opts: Opts, mut comm: impl Communicator) {
         return;
     }
 
-    if vec!["serial", "mpi"].contains(&opts.strategy.as_str()) && opts.num_threads != 1 {
-        if comm.rank() == 0 {
-            eprintln!("Error: strategy option requires --num-threads=1");
+    let executor = match Execution::new(&opts) {
+        Ok(executor) => executor,
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
         }
-        return;
-    }
+    };
 
-    let executor = match opts.strategy.as_str() {
-        "serial" => Execution::Serial,
-        "stupid" => Execution::Stupid(thread_pool::ThreadPool::new(opts.num_threads)),
-        "rayon" => Execution::Rayon(
-            rayon::ThreadPoolBuilder::new()
-                .num_threads(opts.num_threads)
-                .build()
-                .unwrap(),
-        ),
-        "tcp" | "mpi" => Execution::Distributed,
-        _ => {
-            eprintln!("Error: --strategy options are [serial|stupid|rayon|tcp|mpi]");
+    // With --numa, each block is assigned to a worker of the stupid pool,
+    // and built on that worker so its buffers are first touched on the
+    // worker's NUMA node.
+    let numa_pool = match &executor {
+        Execution::Stupid(pool) if opts.numa => Some(pool),
+        _ => None,
+    };
+
+    let mut task_list: Vec<_> = primitive
+        .into_iter()
+        .filter(|patch| work(&patch.high_resolution_rect()) == comm.rank())
+        .enumerate()
+        .map(|(n, patch)| {
+            let worker_group = numa_pool.map(|pool| n % pool.num_threads());
+            let build = || {
+                let update = PatchUpdate::new(
+                    patch,
+                    mesh.clone(),
+                    system,
+                    scheme,
+                    dt,
+                    worker_group,
+                    edge_list,
+                );
+                let update = update.with_boundaries(boundaries.clone());
+                let update = match &opts.time_series {
+                    Some(_) => update.with_reductions(reductions.clone()),
+                    None => update,
+                };
+                let update = match &reactions {
+                    Some(reactions) => update.with_reactions(reactions.clone()),
+                    None => update,
+                };
+                let update = if opts.gravity != 0.0 && opts.well_balanced {
+                    update.with_hydrostatic_gravity(HydrostaticGravity {
+                        acceleration: (0.0, -opts.gravity),
+                    })
+                } else {
+                    update
+                };
+                match &initial_sources {
+                    Some(sources) => update.with_sources(sources.clone(), splitting),
+                    None => update,
+                }
+            };
+            match (numa_pool, worker_group) {
+                (Some(pool), Some(worker_id)) => pool.run_pinned(worker_id, build),
+                _ => build(),
+            }
+        })
+        .collect();
+
+    let time_series = opts
+        .time_series
+        .as_ref()
+        .map(|path| TimeSeries::create(&comm, path, columns, opts.restart.is_some()))
+        .transpose();
+    let mut time_series = match time_series {
+        Ok(time_series) => time_series,
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
             return;
         }
     };
 
     println!("rank {} working on {} blocks", comm.rank(), task_list.len());
+    let mut work_seconds = 0.0;
 
-    while time < opts.tfinal {
+    'run: while time < opts.tfinal {
         let start = std::time::Instant::now();
 
         for _ in 0..opts.fold {
-            task_list = match executor {
-                Execution::Serial => automaton::execute(task_list).collect(),
-                Execution::Stupid(ref pool) => {
-                    automaton::execute_thread_pool(&pool, task_list).collect()
-                }
-                Execution::Rayon(ref pool) => pool
-                    .scope(|scope| automaton::execute_rayon(scope, task_list))
-                    .collect(),
-                Execution::Distributed => {
-                    automaton::execute_comm(&mut comm, &code, &work, None, task_list).collect()
+            dt = automaton::max_time_step(&task_list, opts.cfl);
+            dt = comm.all_reduce_min_f64(dt);
+            comm.next_time_stamp();
+
+            for task in &mut task_list {
+                task.set_time_step_size(dt)
+            }
+            if driving.is_some() || binary.is_some() {
+                let sources = sources(driving.as_ref(), time + 0.5 * dt).unwrap();
+
+                for task in &mut task_list {
+                    task.set_sources(sources.clone(), splitting)
                 }
-            };
+            }
+            for _ in 0..opts.time_integration().num_stages() {
+                task_list = executor.execute(&mut comm, &work, task_list);
+                work_seconds += task_list.iter().map(Metadata::cost).sum::<f64>();
+            }
+            debug_assert!(task_list.iter().all(PatchUpdate::is_step_complete));
             iteration += 1;
             time += dt;
+
+            if let Some(driving) = &mut driving {
+                driving.advance(dt)
+            }
+
+            let error = task_list.iter().find_map(PatchUpdate::error);
+
+            if let Some(error) = error {
+                eprintln!("Error: primitive recovery failed on rank {} in {}", comm.rank(), error);
+            }
+            let any_error = comm.all_reduce_max_f64(if error.is_some() { 1.0 } else { 0.0 });
+            comm.next_time_stamp();
+
+            if any_error > 0.0 {
+                break 'run;
+            }
+
+            if let Some(series) = &mut time_series {
+                let mut local = series.identity();
+
+                for task in &task_list {
+                    series.accumulate(&mut local, task.reduced())
+                }
+                if let Err(e) = series.record(&mut comm, time, &local) {
+                    eprintln!("Warning: could not write the time series: {}", e);
+                }
+            }
+
+            if diagnostics.is_due(iteration) {
+                let primitive: Vec<_> = task_list.iter().map(PatchUpdate::primitive).collect();
+                let report = diagnostics.run(
+                    &mut comm,
+                    &*coordinate_system,
+                    task_list.iter().map(PatchUpdate::conserved),
+                    &primitive,
+                );
+                if comm.rank() == 0 {
+                    println!("[{}] totals={:.12?}", iteration, report.totals);
+
+                    if let Some(violation) = report.violation {
+                        println!("[{}] non-positive {}", iteration, violation);
+                    }
+                }
+            }
         }
         let step_seconds = start.elapsed().as_secs_f64() / opts.fold as f64;
         let mzps = mesh.total_zones() as f64 / 1e6 / step_seconds;
 
         if comm.rank() == 0 {
             println! {
-                "[{}] t={:.3} Mzps={:.2}",
+                "[{}] t={:.3} dt={:.2e} Mzps={:.2}",
                 iteration,
                 time,
+                dt,
                 mzps,
             };
         }
     }
 
+    if setup.domain() == Domain::Stratified {
+        let speed = task_list
+            .iter()
+            .map(|task| {
+                let primitive = task.primitive();
+                let speeds = primitive.data().chunks_exact(primitive.num_fields()).map(|p| p[1].hypot(p[2]));
+                speeds.fold(0.0, f64::max)
+            })
+            .fold(0.0, f64::max);
+        let speed = comm.all_reduce_max_f64(speed);
+        comm.next_time_stamp();
+
+        if comm.rank() == 0 {
+            println!("[{}] max speed={:.3e}", iteration, speed);
+        }
+    }
+
+    if setup.is_exact() {
+        let geometry = mesh.geometry();
+        let error: Vec<_> = task_list
+            .iter()
+            .map(|task| {
+                let mut error = task.primitive();
+                error.map_index_mut(|index, p| {
+                    let position = mesh.coordinates.to_cartesian(geometry.cell_center(0, index));
+                    p[0] = (p[0] - setup.primitive_at(position, time)[0]).abs();
+                    p[1] = 1.0;
+                });
+                error
+            })
+            .collect();
+        let totals = volume_totals(&mut comm, &*coordinate_system, &error);
+
+        if comm.rank() == 0 {
+            println!("[{}] L1 density error={:.6e}", iteration, totals[0] / totals[1]);
+        }
+    }
+
+    let slowest = comm.all_reduce_max_f64(work_seconds);
+    comm.next_time_stamp();
+    let fastest = comm.all_reduce_min_f64(work_seconds);
+    comm.next_time_stamp();
+
+    if comm.size() > 1 && comm.rank() == 0 {
+        println!("load imbalance (slowest / fastest rank)={:.2}", slowest / fastest);
+    }
+
+    if let Execution::Stupid(pool) = &executor {
+        for (n, stats) in pool.stats().iter().enumerate() {
+            println!(
+                "worker {}: jobs={} stolen={} busy={:.1}%",
+                n,
+                stats.jobs_executed,
+                stats.jobs_stolen,
+                100.0 * stats.busy_fraction
+            );
+        }
+    }
+
     let primitive = task_list
         .into_iter()
         .map(|block| block.primitive())
@@ -254,6 +816,7 @@ fn run(opts: Opts, mut comm: impl Communicator) {
         iteration,
         time,
         primitive,
+        driving,
     };
 
     let file = std::fs::File::create(format! {"state.{:04}.cbor", comm.rank()}).unwrap();
@@ -261,6 +824,363 @@ fn run(opts: Opts, mut comm: impl Communicator) {
     ciborium::ser::into_writer(&state, &mut buffer).unwrap();
 }
 
+/// The time stepping of a scalar template solver, as used by
+/// [`evolve_scalar`]. Each step executes the task group `ticks_per_step`
+/// times, for solvers which subcycle; the time step is then the size of a
+/// tick.
+struct TimeStepping<A> {
+    max_time_step: fn(&A, f64) -> f64,
+    set_time_step_size: fn(&mut A, f64),
+    ticks_per_step: u64,
+}
+
+/// Advances the tasks of a scalar template solver to the final time, with
+/// the time step given by the smallest of the tasks on all ranks. Returns
+/// the tasks and the number of steps taken.
+fn evolve_scalar<A, K, W>(
+    opts: &Opts,
+    comm: &mut impl Communicator,
+    executor: &Execution,
+    work: &W,
+    mut task_list: Vec<A>,
+    stepping: TimeStepping<A>,
+) -> (Vec<A>, u64)
+where
+    A: 'static + Send + Automaton<Key = K, Value = A>,
+    A::Message: serde::Serialize + serde::Deserialize<'static>,
+    K: 'static + Send + Hash + Eq + serde::Serialize + serde::Deserialize<'static>,
+    W: Fn(&K) -> usize,
+{
+    let TimeStepping {
+        max_time_step,
+        set_time_step_size,
+        ticks_per_step,
+    } = stepping;
+    let mut time = 0.0;
+    let mut iteration = 0;
+
+    while time < opts.tfinal {
+        let dt = task_list
+            .iter()
+            .map(|task| max_time_step(task, opts.cfl))
+            .fold((opts.tfinal - time) / ticks_per_step as f64, f64::min);
+        let dt = comm.all_reduce_min_f64(dt);
+        comm.next_time_stamp();
+
+        for task in &mut task_list {
+            set_time_step_size(task, dt)
+        }
+        for _ in 0..ticks_per_step {
+            task_list = executor.execute(comm, work, task_list);
+        }
+        time += dt * ticks_per_step as f64;
+        iteration += 1;
+    }
+    (task_list, iteration)
+}
+
+/// Runs the advection or Burgers template solver on a periodic mesh, from a
+/// Gaussian profile, and prints the global total of the solution (which
+/// both conserve), and the error of the advection solution. These should
+/// not depend on the execution strategy or the number of ranks.
+fn run_scalar(opts: Opts, mut comm: impl Communicator) {
+    let mesh = Mesh {
+        area: (-1.0..1.0, -1.0..1.0),
+        size: (opts.grid_resolution, opts.grid_resolution),
+        coordinates: Coordinates::Cartesian,
+    };
+    let decomposition = match DomainDecomposition::new(
+        mesh.area.clone(),
+        mesh.size,
+        BlockLayout::Size(opts.block_size),
+        comm.size(),
+    ) {
+        Ok(decomposition) => decomposition
+            .with_stencil(advect2d::STENCIL)
+            .with_periodic((true, true)),
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    let executor = match Execution::new(&opts) {
+        Ok(executor) => executor,
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    let work = |rect: &Rectangle<i64>| decomposition.rank_of(rect).unwrap();
+    let geometry = mesh.geometry();
+    let initial = |(x, y): (f64, f64)| (-8.0 * (x * x + y * y)).exp();
+    let velocity = (1.0, 0.5);
+    let edge_list = decomposition.adjacency_list();
+    let patches = decomposition
+        .blocks()
+        .iter()
+        .filter(|rect| work(rect) == comm.rank())
+        .map(|rect| Patch::from_scalar_function(0, rect.clone(), |i| initial(geometry.cell_center(0, i))));
+
+    let (solution, error, iteration): (Vec<_>, Vec<_>, _) = match opts.solver.as_str() {
+        "advect" => {
+            let task_list = patches
+                .map(|patch| advect2d::PatchUpdate::new(patch, mesh.clone(), velocity, edge_list))
+                .collect();
+            let (task_list, iteration) = evolve_scalar(
+                &opts,
+                &mut comm,
+                &executor,
+                &work,
+                task_list,
+                TimeStepping {
+                    max_time_step: advect2d::PatchUpdate::max_time_step,
+                    set_time_step_size: advect2d::PatchUpdate::set_time_step_size,
+                    ticks_per_step: 1,
+                },
+            );
+            let error = task_list
+                .iter()
+                .map(|task| {
+                    let exact = |position| advect2d::exact(&mesh, velocity, initial, position, task.time());
+                    let mut error = task.solution();
+                    error.map_index_mut(|i, u| u[0] = (u[0] - exact(geometry.cell_center(0, i))).abs());
+                    error
+                })
+                .collect();
+            let solution = task_list.iter().map(advect2d::PatchUpdate::solution).collect();
+            (solution, error, iteration)
+        }
+        "burgers" => {
+            let task_list = patches
+                .map(|patch| burgers2d::PatchUpdate::new(patch, mesh.clone(), edge_list))
+                .collect();
+            let (task_list, iteration) = evolve_scalar(
+                &opts,
+                &mut comm,
+                &executor,
+                &work,
+                task_list,
+                TimeStepping {
+                    max_time_step: burgers2d::PatchUpdate::max_time_step,
+                    set_time_step_size: burgers2d::PatchUpdate::set_time_step_size,
+                    ticks_per_step: 1,
+                },
+            );
+            let solution = task_list.iter().map(burgers2d::PatchUpdate::solution).collect();
+            (solution, Vec::new(), iteration)
+        }
+        _ => {
+            if comm.rank() == 0 {
+                eprintln!("Error: --solver options are [euler|euler3d|advect|burgers|advect-subcycled]");
+            }
+            return;
+        }
+    };
+    let total = volume_totals(&mut comm, &geometry, &solution);
+    let error = volume_totals(&mut comm, &geometry, &error);
+
+    if comm.rank() == 0 {
+        println!("[{}] total={:.12?}", iteration, total);
+
+        if let Some(error) = error.first() {
+            println!("[{}] L1 error={:.6e}", iteration, error / 4.0);
+        }
+    }
+}
+
+/// Runs the subcycled advection solver, from the same Gaussian profile as
+/// [`run_scalar`], with the central part of the domain refined to the given
+/// depth. The base resolution is the grid resolution. Prints the total and
+/// the error of the solution on the finest patches covering each point.
+/// The field is held at zero beyond the edges of the domain, so the final
+/// time should be short enough that the profile stays clear of them.
+fn run_subcycled(opts: Opts, mut comm: impl Communicator) {
+    let area = (-1.0..1.0, -1.0..1.0);
+    let region = RefinedRegion {
+        bounds: RegionBounds::Physical((-0.5..0.5, -0.5..0.5)),
+        depth: opts.refinement_depth,
+    };
+    let decomposition = match DomainDecomposition::new(
+        area.clone(),
+        (opts.grid_resolution, opts.grid_resolution),
+        BlockLayout::Size(opts.block_size),
+        comm.size(),
+    )
+    .and_then(|d| d.with_stencil(advect2d_subcycled::STENCIL).with_refinement(&[region]))
+    {
+        Ok(decomposition) => decomposition,
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    let executor = match Execution::new(&opts) {
+        Ok(executor) => executor,
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    let base_level = decomposition.base_level();
+    let schedule = Schedule::new(base_level);
+    let finest = opts.grid_resolution << base_level;
+    let mesh = Mesh {
+        area: area.clone(),
+        size: (finest, finest),
+        coordinates: Coordinates::Cartesian,
+    };
+    let geometry = mesh.geometry();
+    let level_blocks: Vec<_> = decomposition
+        .level_work()
+        .iter()
+        .flat_map(|(&level, map)| map.iter().map(move |(rect, &rank)| (IndexSpace::from(rect), level, rank)))
+        .collect();
+    let owners: HashMap<_, _> = level_blocks
+        .iter()
+        .map(|(space, level, rank)| (space.refine_by(1 << level).to_rect(), *rank))
+        .collect();
+    let work = |rect: &Rectangle<i64>| owners[rect];
+    let covered = |level: u32, (i, j): (i64, i64)| {
+        let center = (((2 * i + 1) << level) / 2, ((2 * j + 1) << level) / 2);
+        level_blocks
+            .iter()
+            .any(|(space, l, _)| *l < level && space.refine_by(1 << l).contains(center))
+    };
+    let initial = |(x, y): (f64, f64)| (-8.0 * (x * x + y * y)).exp();
+    let velocity = (1.0, 0.5);
+    let edge_list = decomposition.adjacency_list();
+    let plan = HaloPlan::with_stencil(edge_list, &advect2d_subcycled::STENCIL);
+    let task_list = level_blocks
+        .iter()
+        .filter(|(_, _, rank)| *rank == comm.rank())
+        .map(|(space, level, _)| {
+            let patch = Patch::from_scalar_function(*level, space.clone(), |i| initial(geometry.cell_center(*level, i)));
+            advect2d_subcycled::PatchUpdate::new(patch, &geometry, velocity, schedule.clone(), edge_list, &plan)
+        })
+        .collect();
+    let (task_list, iteration) = evolve_scalar(
+        &opts,
+        &mut comm,
+        &executor,
+        &work,
+        task_list,
+        TimeStepping {
+            max_time_step: advect2d_subcycled::PatchUpdate::max_time_step,
+            set_time_step_size: advect2d_subcycled::PatchUpdate::set_time_step_size,
+            ticks_per_step: schedule.ticks_per_cycle(),
+        },
+    );
+    let mut solution = Vec::new();
+    let mut error = Vec::new();
+
+    for task in &task_list {
+        let mut patch = task.solution();
+        let level = patch.level();
+        let exact = |position| advect2d::exact(&mesh, velocity, initial, position, task.time());
+        patch.set_mask(|i| covered(level, i));
+        let mut e = patch.clone();
+        e.map_index_mut(|i, u| u[0] = (u[0] - exact(geometry.cell_center(level, i))).abs());
+        solution.push(patch);
+        error.push(e);
+    }
+    let total = volume_totals(&mut comm, &geometry, &solution);
+    let error = volume_totals(&mut comm, &geometry, &error);
+
+    if comm.rank() == 0 {
+        println!("[{}] levels={} total={:.12?}", iteration, base_level + 1, total);
+        println!("[{}] L1 error={:.6e}", iteration, error[0] / 4.0);
+    }
+}
+
+/// Runs the 3D Euler solver on a cube with `grid_resolution` zones on a side,
+/// divided into bricks of about `block_size` zones on a side, from a
+/// spherical blast wave. The ambient gas matches the fixed state beyond the
+/// edges of the cube, and the totals of the conserved quantities are printed,
+/// so the final time should be short enough that the blast stays clear of
+/// the edges.
+fn run_euler3d(opts: Opts, mut comm: impl Communicator) {
+    let mesh = euler3d_pcm::Mesh {
+        volume: (-1.0..1.0, -1.0..1.0, -1.0..1.0),
+        size: (opts.grid_resolution, opts.grid_resolution, opts.grid_resolution),
+    };
+    if opts.reconstruction != Reconstruction::Pcm {
+        if comm.rank() == 0 {
+            eprintln!("Error: --solver euler3d takes single forward Euler steps, so it requires -r pcm");
+        }
+        return;
+    }
+    let bricks_per_axis = (opts.grid_resolution / opts.block_size.max(1)).max(1);
+    let bricks = mesh.decompose(bricks_per_axis.pow(3));
+
+    if bricks.len() < comm.size() {
+        if comm.rank() == 0 {
+            eprintln!("Error: {} bricks cannot be shared by {} ranks", bricks.len(), comm.size());
+        }
+        return;
+    }
+    let executor = match Execution::new(&opts) {
+        Ok(executor) => executor,
+        Err(e) => {
+            if comm.rank() == 0 {
+                eprintln!("Error: {}", e);
+            }
+            return;
+        }
+    };
+    let owners: HashMap<_, _> = bricks
+        .iter()
+        .enumerate()
+        .map(|(n, brick)| (brick.clone(), n * comm.size() / bricks.len()))
+        .collect();
+    let work = |brick: &Brick<i64>| owners[brick];
+    let map: BrickMap<i64, ()> = bricks.iter().map(|brick| (brick.clone(), ())).collect();
+    let edge_list = map.adjacency_list(opts.reconstruction.num_guard());
+    let initial = |(x, y, z): (f64, f64, f64)| {
+        if x * x + y * y + z * z < 0.0625 {
+            [1.0, 0.0, 0.0, 0.0, 1.0]
+        } else {
+            [0.1, 0.0, 0.0, 0.0, 0.125]
+        }
+    };
+    let task_list = bricks
+        .iter()
+        .filter(|brick| work(brick) == comm.rank())
+        .map(|brick| {
+            let primitive = euler3d_pcm::Block::from_vector_function(brick.clone(), |i| initial(mesh.cell_center(i)));
+            euler3d_pcm::PatchUpdate::new(primitive, mesh.clone(), opts.reconstruction, 0.0, None, &edge_list)
+        })
+        .collect();
+    let (task_list, iteration) = evolve_scalar(
+        &opts,
+        &mut comm,
+        &executor,
+        &work,
+        task_list,
+        TimeStepping {
+            max_time_step: euler3d_pcm::PatchUpdate::max_time_step,
+            set_time_step_size: euler3d_pcm::PatchUpdate::set_time_step_size,
+            ticks_per_step: 1,
+        },
+    );
+    let local = task_list
+        .iter()
+        .map(euler3d_pcm::PatchUpdate::conserved_totals)
+        .fold(vec![0.0; 5], |a, b| a.iter().zip(b).map(|(a, b)| a + b).collect());
+    let total = combine_over_ranks(&mut comm, &[Combine::Sum; 5], &local);
+
+    if comm.rank() == 0 {
+        println!("[{}] total={:.12?}", iteration, total);
+    }
+}
+
 fn peer(rank: usize) -> SocketAddr {
     SocketAddr::new(IpAddr::V4(Ipv4Addr::new(127, 0, 0, 1)), 7070 + rank as u16)
 }
@@ -304,6 +1224,49 @@ fn main_mpi(_opts: Opts) {
     println!("Error: compiled without MPI support");
 }
 
+fn main_1d(opts: Opts, initial: InitialCondition) {
+    let mesh = euler1d::Mesh {
+        domain: 0.0..1.0,
+        size: opts.grid_resolution,
+    };
+    let scheme = euler1d::Scheme {
+        reconstruction: opts.reconstruction,
+        riemann_solver: opts.riemann_solver,
+        time_integration: opts.time_integration(),
+        gamma_law_index: InitialCondition::GAMMA_LAW_INDEX,
+    };
+    let (time, primitive) = match euler1d::solve(initial, &mesh, scheme, opts.block_size, opts.cfl) {
+        Ok(solution) => solution,
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            return;
+        }
+    };
+    let zones: Vec<_> = primitive
+        .iter()
+        .flat_map(|patch| {
+            let (i0, i1) = (patch.index_space().start().0, patch.index_space().end().0);
+            (i0..i1).map(|i| mesh.cell_center(i)).zip(patch.data().chunks_exact(3))
+        })
+        .collect();
+
+    let mut output = String::new();
+    for (x, p) in &zones {
+        output.push_str(&format!("{:.6} {:.12e} {:.12e} {:.12e}\n", x, p[0], p[1], p[2]));
+    }
+    std::fs::write("shock_tube.dat", output).unwrap();
+    println!("{:?} at t={:.3} written to shock_tube.dat", initial, time);
+
+    if zones.iter().all(|(x, _)| initial.exact(*x, time).is_some()) {
+        let l1: f64 = zones
+            .iter()
+            .map(|(x, p)| (p[0] - initial.exact(*x, time).unwrap()[0]).abs())
+            .sum::<f64>()
+            / zones.len() as f64;
+        println!("L1 density error: {:.6e}", l1);
+    }
+}
+
 fn main_mt(opts: Opts) {
     run(opts, NullCommunicator::new())
 }
@@ -311,6 +1274,14 @@ fn main_mt(opts: Opts) {
 fn main() {
     let opts = Opts::parse();
 
+    if !opts.time_integration().is_stable_with(opts.reconstruction) {
+        eprintln!("Error: -i fwd is unstable with -r plm|weno5; use rk2, rk3 or ctu");
+        return;
+    }
+
+    if let Some(initial) = opts.shock_tube {
+        return main_1d(opts, initial);
+    }
     match opts.strategy.as_str() {
         "mpi" => main_mpi(opts),
         "tcp" => main_tcp(opts),
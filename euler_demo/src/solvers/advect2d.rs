@@ -0,0 +1,201 @@
+use gridiron::adjacency_list::AdjacencyList;
+use gridiron::automaton::{Automaton, Status};
+use gridiron::index_space::{Axis, IndexSpace, Stencil};
+use gridiron::meshing::{self, AxisBoundary, BoundaryTopology};
+use gridiron::patch::Patch;
+use gridiron::rect_map::Rectangle;
+use super::euler2d_pcm::Mesh;
+
+const NUM_GUARD: i64 = 1;
+
+/// The guard zones read by [`PatchUpdate`]. The mesh adjacency list must be
+/// built for this stencil, and be periodic on both axes.
+pub const STENCIL: Stencil = Stencil::star(NUM_GUARD);
+
+/// A first-order upwind scheme for the linear advection of a scalar field
+/// `u` at a constant velocity, `du/dt + a . grad(u) = 0`, on a mesh which is
+/// periodic on both axes. The exact solution is the initial field translated
+/// by the velocity times the time.
+///
+/// This is meant as a template for solvers of other equations: the task
+/// sends the zones next to its edges to its neighbors, fills its guard zones
+/// from the zones it receives, computes the fluxes through the faces of its
+/// zones, and updates them. Only [`PatchUpdate::compute_flux`] and
+/// [`PatchUpdate::max_time_step`] depend on the equation being solved.
+pub struct PatchUpdate {
+    extended_scalar: Patch,
+    flux_i: Patch,
+    flux_j: Patch,
+    incoming_count: usize,
+    index_space: IndexSpace,
+    level: u32,
+    mesh: Mesh,
+    neighbor_patches: Vec<Patch>,
+    outgoing_edges: Vec<(Rectangle<i64>, u32)>,
+    time: f64,
+    time_step_size: f64,
+    velocity: (f64, f64),
+}
+
+impl PatchUpdate {
+    pub fn new(
+        scalar: Patch,
+        mesh: Mesh,
+        velocity: (f64, f64),
+        edge_list: &AdjacencyList<(Rectangle<i64>, u32)>,
+    ) -> Self {
+        let key = (scalar.high_resolution_rect(), scalar.level());
+        let lv = scalar.level();
+        let index_space = scalar.index_space();
+        let extended_scalar = Patch::extract_from(&scalar, index_space.extend_all(NUM_GUARD));
+        let flux_i = Patch::zeros(lv, 1, index_space.extend_upper(1, Axis::I));
+        let flux_j = Patch::zeros(lv, 1, index_space.extend_upper(1, Axis::J));
+        let incoming_count = edge_list.incoming_edges(&key).count();
+        let outgoing_edges = edge_list.outgoing_edges(&key).cloned().collect();
+
+        Self {
+            extended_scalar,
+            flux_i,
+            flux_j,
+            incoming_count,
+            index_space,
+            level: lv,
+            mesh,
+            neighbor_patches: Vec::new(),
+            outgoing_edges,
+            time: 0.0,
+            time_step_size: 0.0,
+            velocity,
+        }
+    }
+
+    /// Returns the scalar field, without guard zones.
+    pub fn solution(&self) -> Patch {
+        self.extended_scalar.extract(self.index_space.clone())
+    }
+
+    /// Returns the simulation time reached by this task.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Returns the largest stable time step with the given Courant number.
+    pub fn max_time_step(&self, cfl: f64) -> f64 {
+        let (dx, dy) = self.mesh.geometry().spacing;
+        let (a, b) = self.velocity;
+        cfl / (a.abs() / dx + b.abs() / dy)
+    }
+
+    pub fn set_time_step_size(&mut self, time_step_size: f64) {
+        self.time_step_size = time_step_size
+    }
+
+    /// Computes the upwind flux through each face normal to the given axis.
+    /// The flux at index `n` is through the lower face of zone `n`.
+    pub(super) fn compute_flux(pe: &Patch, axis: Axis, velocity: (f64, f64), flux: &mut Patch) {
+        let ul = pe.select(flux.index_space().translate(-1, axis));
+        let ur = pe.select(flux.index_space());
+        let a = match axis {
+            Axis::I => velocity.0,
+            Axis::J => velocity.1,
+        };
+        for (f, (ul, ur)) in flux.iter_data_mut().zip(ul.zip(ur)) {
+            f[0] = a * if a > 0.0 { ul[0] } else { ur[0] }
+        }
+    }
+}
+
+impl Automaton for PatchUpdate {
+    type Key = Rectangle<i64>;
+    type Message = Patch;
+    type Value = Self;
+
+    fn key(&self) -> Self::Key {
+        self.index_space.refine_by(1 << self.level).to_rect()
+    }
+
+    /// Sends each neighbor the zones of this patch that it needs for its
+    /// guard zones, including those reached across the periodic edges of the
+    /// mesh.
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.outgoing_edges
+            .iter()
+            .cloned()
+            .map(|(rect, level)| {
+                let overlap = IndexSpace::from(rect.clone())
+                    .extend_all(NUM_GUARD * (1 << level))
+                    .coarsen_by(1 << self.level)
+                    .periodic_images(&self.mesh.index_space())
+                    .filter_map(|(image, _)| image.intersect(&self.index_space))
+                    .reduce(|a, b| {
+                        let (a0, a1) = (a.start(), a.end());
+                        let (b0, b1) = (b.start(), b.end());
+                        IndexSpace::new(
+                            a0.0.min(b0.0)..a1.0.max(b1.0),
+                            a0.1.min(b0.1)..a1.1.max(b1.1),
+                        )
+                    })
+                    .expect("patches do not overlap");
+                (rect, self.extended_scalar.extract(overlap))
+            })
+            .collect()
+    }
+
+    fn receive(&mut self, patch: Self::Message) -> Status {
+        self.neighbor_patches.push(patch);
+        Status::eligible_if(self.neighbor_patches.len() == self.incoming_count)
+    }
+
+    fn value(mut self) -> Self::Value {
+        let topology = BoundaryTopology::new(self.mesh.index_space())
+            .with_axis(Axis::I, AxisBoundary::Periodic)
+            .with_axis(Axis::J, AxisBoundary::Periodic);
+
+        meshing::extend_patch_mut(
+            &mut self.extended_scalar,
+            &self.index_space,
+            &topology,
+            |_, _, _| {},
+            &self.neighbor_patches,
+        );
+        self.neighbor_patches.clear();
+
+        let pe = &self.extended_scalar;
+        Self::compute_flux(pe, Axis::I, self.velocity, &mut self.flux_i);
+        Self::compute_flux(pe, Axis::J, self.velocity, &mut self.flux_j);
+
+        let (dx, dy) = self.mesh.geometry().spacing;
+        let dt = self.time_step_size;
+        let index_space = &self.index_space;
+
+        let fim = self.flux_i.select(index_space.clone());
+        let fip = self.flux_i.select(index_space.translate(1, Axis::I));
+        let fjm = self.flux_j.select(index_space.clone());
+        let fjp = self.flux_j.select(index_space.translate(1, Axis::J));
+        let u = self.extended_scalar.select_mut(index_space.clone());
+
+        for (fip, (fim, (fjp, (fjm, u)))) in fip.zip(fim.zip(fjp.zip(fjm.zip(u)))) {
+            u[0] -= (fip[0] - fim[0]) * dt / dx + (fjp[0] - fjm[0]) * dt / dy;
+        }
+        self.time += dt;
+        self
+    }
+
+    fn independent(&self) -> bool {
+        self.incoming_count == 0
+    }
+}
+
+/// Returns the exact solution at a point and time, for the given initial
+/// field on a periodic mesh.
+pub fn exact<F>(mesh: &Mesh, velocity: (f64, f64), initial: F, position: (f64, f64), time: f64) -> f64
+where
+    F: Fn((f64, f64)) -> f64,
+{
+    let (x, y) = &mesh.area;
+    let wrap = |q: f64, r: &std::ops::Range<f64>| r.start + (q - r.start).rem_euclid(r.end - r.start);
+    initial((
+        wrap(position.0 - velocity.0 * time, x),
+        wrap(position.1 - velocity.1 * time, y),
+    ))
+}
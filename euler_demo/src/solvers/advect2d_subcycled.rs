@@ -0,0 +1,212 @@
+use gridiron::adjacency_list::AdjacencyList;
+use gridiron::automaton::{Automaton, Status};
+use gridiron::index_space::{Axis, IndexSpace, Stencil};
+use gridiron::meshing::{Cartesian, HaloPlan, HaloTransfer};
+use gridiron::patch::Patch;
+use gridiron::rect_map::Rectangle;
+use gridiron::subcycling::{Schedule, TimeBracket};
+use super::advect2d;
+
+const NUM_GUARD: i64 = 1;
+
+/// The guard zones read by [`PatchUpdate`]. The mesh adjacency list and halo
+/// plan must be built for this stencil.
+pub const STENCIL: Stencil = Stencil::star(NUM_GUARD);
+
+/// The message sent to a neighbor: the level of the sender, and the data
+/// for each of the recipient's guard zone regions that the sender covers,
+/// already resampled to the recipient's level.
+pub type Message = (u32, Vec<TimeBracket>);
+
+/// The first-order upwind scheme of [`advect2d`], on statically refined
+/// patches which take local time steps: a patch on level `l` takes one step
+/// for every `2^l` ticks of the executor, so that every level runs at the
+/// same Courant number.
+///
+/// Each task sends its neighbors a [`TimeBracket`] of its data before and
+/// after its latest step, for every guard zone region given by the halo
+/// plan, and interpolates the brackets it receives to its own time. Data
+/// from coarser neighbors is placed first, so that finer data takes
+/// precedence where they overlap. The field is held at zero beyond the
+/// edges of the domain. Coarse zones covered by finer patches are advanced
+/// too, but are not overwritten by the finer solution, and the fluxes at
+/// coarse-fine interfaces are not corrected, so the total is only
+/// approximately conserved.
+pub struct PatchUpdate {
+    extended_scalar: Patch,
+    flux_i: Patch,
+    flux_j: Patch,
+    incoming: Vec<Message>,
+    incoming_count: usize,
+    index_space: IndexSpace,
+    level: u32,
+    outgoing: Vec<(Rectangle<i64>, Vec<HaloTransfer>)>,
+    previous: (f64, Patch),
+    schedule: Schedule,
+    spacing: (f64, f64),
+    tick: u64,
+    tick_size: f64,
+    time: f64,
+    velocity: (f64, f64),
+}
+
+impl PatchUpdate {
+    /// Creates a task for the given patch. The geometry has the resolution
+    /// of level 0, and the edge list and halo plan are those of the
+    /// decomposition into patches on every level.
+    pub fn new(
+        scalar: Patch,
+        geometry: &Cartesian,
+        velocity: (f64, f64),
+        schedule: Schedule,
+        edge_list: &AdjacencyList<(Rectangle<i64>, u32)>,
+        plan: &HaloPlan,
+    ) -> Self {
+        let key = (scalar.high_resolution_rect(), scalar.level());
+        let lv = scalar.level();
+        let index_space = scalar.index_space();
+        let mut extended_scalar = Patch::zeros(lv, 1, index_space.extend_all(NUM_GUARD));
+        let flux_i = Patch::zeros(lv, 1, index_space.extend_upper(1, Axis::I));
+        let flux_j = Patch::zeros(lv, 1, index_space.extend_upper(1, Axis::J));
+        let incoming_count = edge_list.incoming_edges(&key).count();
+        let outgoing = edge_list
+            .outgoing_edges(&key)
+            .map(|target| {
+                let transfers = plan
+                    .outgoing(&key)
+                    .iter()
+                    .filter(|transfer| &transfer.target == target)
+                    .cloned()
+                    .collect();
+                (target.0.clone(), transfers)
+            })
+            .collect();
+        let scale = (1 << lv) as f64;
+        scalar.copy_into(&mut extended_scalar);
+
+        Self {
+            extended_scalar,
+            flux_i,
+            flux_j,
+            incoming: Vec::new(),
+            incoming_count,
+            index_space,
+            level: lv,
+            outgoing,
+            previous: (0.0, scalar),
+            schedule,
+            spacing: (geometry.spacing.0 * scale, geometry.spacing.1 * scale),
+            tick: 0,
+            tick_size: 0.0,
+            time: 0.0,
+            velocity,
+        }
+    }
+
+    /// Returns the scalar field, without guard zones.
+    pub fn solution(&self) -> Patch {
+        self.extended_scalar.extract(self.index_space.clone())
+    }
+
+    /// Returns the simulation time reached by this task.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Returns the largest stable tick size with the given Courant number:
+    /// the largest stable step on this patch's level, divided by the number
+    /// of ticks it spans.
+    pub fn max_time_step(&self, cfl: f64) -> f64 {
+        let (dx, dy) = self.spacing;
+        let (a, b) = self.velocity;
+        cfl / (a.abs() / dx + b.abs() / dy) / self.schedule.ticks_per_step(self.level) as f64
+    }
+
+    /// Sets the size of the ticks in the following cycle. This must only be
+    /// changed at the start of a cycle, when every level is synchronized.
+    pub fn set_time_step_size(&mut self, tick_size: f64) {
+        self.tick_size = tick_size
+    }
+}
+
+impl Automaton for PatchUpdate {
+    type Key = Rectangle<i64>;
+    type Message = Message;
+    type Value = Self;
+
+    fn key(&self) -> Self::Key {
+        self.index_space.refine_by(1 << self.level).to_rect()
+    }
+
+    /// Sends each neighbor a bracket of this patch's data for each of its
+    /// guard zone regions, or an empty message if the patches are adjacent
+    /// but do not overlap. Messages are sent at every tick, whether or not
+    /// either patch is due.
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        let current = TimeBracket::new(
+            self.previous.clone(),
+            (self.time, self.solution()),
+        );
+        self.outgoing
+            .iter()
+            .map(|(rect, transfers)| {
+                let brackets = transfers
+                    .iter()
+                    .map(|transfer| {
+                        current.extract(transfer.extract.clone()).map(|data| {
+                            let mut target = Patch::zeros(transfer.target.1, 1, transfer.place.clone());
+                            transfer.place(data, &mut target);
+                            target
+                        })
+                    })
+                    .collect();
+                (rect.clone(), (self.level, brackets))
+            })
+            .collect()
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        self.incoming.push(message);
+        Status::eligible_if(self.incoming.len() == self.incoming_count)
+    }
+
+    fn value(mut self) -> Self::Value {
+        let mut incoming = std::mem::take(&mut self.incoming);
+
+        if !self.schedule.is_due(self.level, self.tick) {
+            self.tick += 1;
+            return self;
+        }
+        incoming.sort_by_key(|(level, _)| std::cmp::Reverse(*level));
+
+        for bracket in incoming.iter().flat_map(|(_, brackets)| brackets) {
+            bracket.interpolate(self.time).copy_into(&mut self.extended_scalar)
+        }
+        self.previous = (self.time, self.solution());
+
+        let pe = &self.extended_scalar;
+        advect2d::PatchUpdate::compute_flux(pe, Axis::I, self.velocity, &mut self.flux_i);
+        advect2d::PatchUpdate::compute_flux(pe, Axis::J, self.velocity, &mut self.flux_j);
+
+        let (dx, dy) = self.spacing;
+        let dt = self.schedule.step_size(self.level, self.tick_size);
+        let index_space = &self.index_space;
+
+        let fim = self.flux_i.select(index_space.clone());
+        let fip = self.flux_i.select(index_space.translate(1, Axis::I));
+        let fjm = self.flux_j.select(index_space.clone());
+        let fjp = self.flux_j.select(index_space.translate(1, Axis::J));
+        let u = self.extended_scalar.select_mut(index_space.clone());
+
+        for (fip, (fim, (fjp, (fjm, u)))) in fip.zip(fim.zip(fjp.zip(fjm.zip(u)))) {
+            u[0] -= (fip[0] - fim[0]) * dt / dx + (fjp[0] - fjm[0]) * dt / dy;
+        }
+        self.time += dt;
+        self.tick += 1;
+        self
+    }
+
+    fn independent(&self) -> bool {
+        self.incoming_count == 0
+    }
+}
@@ -0,0 +1,191 @@
+use std::sync::Arc;
+use gridiron::index_space::{Axis, IndexSpace};
+use gridiron::meshing::{AxisBoundary, BoundaryFace, BoundaryTopology, FieldBoundary, Side};
+
+/// A function of the position of a guard zone and the time, which writes the
+/// primitive fields of the zone.
+pub type BoundaryFunction = Arc<dyn Fn((f64, f64), f64, &mut [f64]) + Send + Sync>;
+
+/// Describes how the guard zones beyond one edge of the domain are filled.
+/// The primitive fields are assumed to be the density, two velocity
+/// components, and the pressure.
+#[derive(Clone)]
+pub enum BoundaryCondition {
+    /// Copy the nearest zone inside the domain (zero gradient).
+    Outflow,
+
+    /// Mirror the zones inside the domain, reversing the velocity component
+    /// normal to the edge.
+    Reflecting,
+
+    /// Wrap around to the opposite edge, which must also be periodic.
+    Periodic,
+
+    /// Use the given primitive state.
+    Inflow(Vec<f64>),
+
+    /// Call the function with the position of each guard zone and the time.
+    Function(BoundaryFunction),
+}
+
+impl BoundaryCondition {
+    fn axis_boundary(&self, axis: Axis) -> AxisBoundary {
+        match self {
+            Self::Outflow => AxisBoundary::Outflow,
+            Self::Periodic => AxisBoundary::Periodic,
+            Self::Reflecting => match axis {
+                Axis::I => AxisBoundary::Reflect(vec![1.0, -1.0, 1.0, 1.0]),
+                Axis::J => AxisBoundary::Reflect(vec![1.0, 1.0, -1.0, 1.0]),
+            },
+            Self::Inflow(state) => {
+                AxisBoundary::PerField(state.iter().cloned().map(FieldBoundary::Fixed).collect())
+            }
+            Self::Function(_) => AxisBoundary::Custom,
+        }
+    }
+}
+
+impl std::str::FromStr for BoundaryCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "outflow" => Ok(Self::Outflow),
+            "reflecting" => Ok(Self::Reflecting),
+            "periodic" => Ok(Self::Periodic),
+            _ => Err(format!(
+                "unknown boundary condition '{}' (outflow|reflecting|periodic)",
+                s
+            )),
+        }
+    }
+}
+
+impl std::fmt::Debug for BoundaryCondition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Outflow => write!(f, "Outflow"),
+            Self::Reflecting => write!(f, "Reflecting"),
+            Self::Periodic => write!(f, "Periodic"),
+            Self::Inflow(state) => write!(f, "Inflow({:?})", state),
+            Self::Function(_) => write!(f, "Function"),
+        }
+    }
+}
+
+/// The boundary conditions on each edge of the domain.
+#[derive(Clone, Debug)]
+pub struct Boundaries {
+    pub lower_i: BoundaryCondition,
+    pub upper_i: BoundaryCondition,
+    pub lower_j: BoundaryCondition,
+    pub upper_j: BoundaryCondition,
+}
+
+impl Boundaries {
+    /// Uses the same boundary condition on every edge.
+    pub fn uniform(condition: BoundaryCondition) -> Self {
+        Self {
+            lower_i: condition.clone(),
+            upper_i: condition.clone(),
+            lower_j: condition.clone(),
+            upper_j: condition,
+        }
+    }
+
+    /// Returns these boundaries, with the given condition on one edge.
+    pub fn with_edge(mut self, face: BoundaryFace, condition: BoundaryCondition) -> Self {
+        *self.edge_mut(face) = condition;
+        self
+    }
+
+    pub fn edge(&self, face: BoundaryFace) -> &BoundaryCondition {
+        match (face.axis, face.side) {
+            (Axis::I, Side::Lower) => &self.lower_i,
+            (Axis::I, Side::Upper) => &self.upper_i,
+            (Axis::J, Side::Lower) => &self.lower_j,
+            (Axis::J, Side::Upper) => &self.upper_j,
+        }
+    }
+
+    fn edge_mut(&mut self, face: BoundaryFace) -> &mut BoundaryCondition {
+        match (face.axis, face.side) {
+            (Axis::I, Side::Lower) => &mut self.lower_i,
+            (Axis::I, Side::Upper) => &mut self.upper_i,
+            (Axis::J, Side::Lower) => &mut self.lower_j,
+            (Axis::J, Side::Upper) => &mut self.upper_j,
+        }
+    }
+
+    /// Returns whether the domain is periodic on the `I` and `J` axes. The
+    /// mesh adjacency list must be built for these periodic axes. This
+    /// function panics if an axis is periodic on only one edge.
+    pub fn periodic(&self) -> (bool, bool) {
+        let axis = |lower: &BoundaryCondition, upper: &BoundaryCondition| {
+            match (lower, upper) {
+                (BoundaryCondition::Periodic, BoundaryCondition::Periodic) => true,
+                (BoundaryCondition::Periodic, _) | (_, BoundaryCondition::Periodic) => {
+                    panic!("a periodic axis must be periodic on both edges")
+                }
+                _ => false,
+            }
+        };
+        (
+            axis(&self.lower_i, &self.upper_i),
+            axis(&self.lower_j, &self.upper_j),
+        )
+    }
+
+    /// Returns the topology used to fill guard zones in the given domain.
+    /// Zones beyond an edge with a [`BoundaryCondition::Function`] are left
+    /// to [`Boundaries::boundary_value`].
+    pub fn topology(&self, domain: IndexSpace) -> BoundaryTopology {
+        FACES.iter().fold(BoundaryTopology::new(domain), |topology, &face| {
+            topology.with_face(face, self.edge(face).axis_boundary(face.axis))
+        })
+    }
+
+    /// Fills a guard zone beyond an edge with a boundary function, given the
+    /// position of the zone and the time. At the corners of the domain, the
+    /// edge on the `I` axis is tried first. Zones not beyond such an edge are
+    /// left unchanged; these are the corner guard zones, which are not
+    /// received from neighbors if the stencil does not need them.
+    pub fn boundary_value(
+        &self,
+        topology: &BoundaryTopology,
+        index: (i64, i64),
+        position: (f64, f64),
+        time: f64,
+        p: &mut [f64],
+    ) {
+        let (i0, j0) = topology.domain.start();
+        let (i1, j1) = topology.domain.end();
+        let side = |n: i64, lower: i64, upper: i64| {
+            if n < lower {
+                Some(Side::Lower)
+            } else if n >= upper {
+                Some(Side::Upper)
+            } else {
+                None
+            }
+        };
+        let faces = [
+            side(index.0, i0, i1).map(|side| BoundaryFace { axis: Axis::I, side }),
+            side(index.1, j0, j1).map(|side| BoundaryFace { axis: Axis::J, side }),
+        ];
+        let function = faces.iter().flatten().find_map(|&face| match self.edge(face) {
+            BoundaryCondition::Function(f) => Some(f),
+            _ => None,
+        });
+        if let Some(f) = function {
+            f(position, time, p)
+        }
+    }
+}
+
+const FACES: [BoundaryFace; 4] = [
+    BoundaryFace { axis: Axis::I, side: Side::Lower },
+    BoundaryFace { axis: Axis::I, side: Side::Upper },
+    BoundaryFace { axis: Axis::J, side: Side::Lower },
+    BoundaryFace { axis: Axis::J, side: Side::Upper },
+];
@@ -0,0 +1,140 @@
+use super::source_terms::SourceTerms;
+
+/// The largest fraction of the local cooling time taken by one substep.
+const SUBSTEP_FRACTION: f64 = 0.1;
+
+/// The number of substeps after which the rest of the time step is taken in
+/// a single (linearized implicit) substep.
+const MAX_SUBSTEPS: usize = 100;
+
+/// A cooling function `Lambda(T)`, tabulated at increasing temperatures and
+/// interpolated linearly in `log T` and `log Lambda`. Above the table it is
+/// extrapolated as the power law of the last interval; below the table it is
+/// zero, so that the lowest tabulated temperature acts as a cooling floor.
+#[derive(Clone, Debug)]
+pub struct CoolingTable {
+    log_temperature: Vec<f64>,
+    log_lambda: Vec<f64>,
+}
+
+impl CoolingTable {
+    /// Creates a table from `(T, Lambda)` pairs. There must be at least two,
+    /// with positive and strictly increasing temperatures, and positive
+    /// values of `Lambda`.
+    pub fn new(points: &[(f64, f64)]) -> Result<Self, String> {
+        if points.len() < 2 {
+            return Err("a cooling table needs at least two points".to_string());
+        }
+        if points.iter().any(|&(t, lambda)| t <= 0.0 || lambda <= 0.0) {
+            return Err("cooling table entries must be positive".to_string());
+        }
+        if points.windows(2).any(|w| w[1].0 <= w[0].0) {
+            return Err("cooling table temperatures must be increasing".to_string());
+        }
+        Ok(Self {
+            log_temperature: points.iter().map(|(t, _)| t.ln()).collect(),
+            log_lambda: points.iter().map(|(_, lambda)| lambda.ln()).collect(),
+        })
+    }
+
+    /// Reads a table from a text file with columns `T` and `Lambda`. Blank
+    /// lines and lines starting with `#` are skipped.
+    pub fn read(path: &str) -> Result<Self, String> {
+        let text = std::fs::read_to_string(path).map_err(|e| format!("{}: {}", path, e))?;
+        let mut points = Vec::new();
+
+        for line in text.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let columns: Vec<f64> = line
+                .split_whitespace()
+                .map(str::parse)
+                .collect::<Result<_, _>>()
+                .map_err(|e| format!("{}: invalid line '{}': {}", path, line, e))?;
+
+            match columns[..] {
+                [t, lambda] => points.push((t, lambda)),
+                _ => return Err(format!("{}: expected two columns in '{}'", path, line)),
+            }
+        }
+        Self::new(&points).map_err(|e| format!("{}: {}", path, e))
+    }
+
+    /// Returns the lowest tabulated temperature.
+    pub fn min_temperature(&self) -> f64 {
+        self.log_temperature[0].exp()
+    }
+
+    /// Returns `Lambda(T)`, and its logarithmic slope `d log Lambda / d log
+    /// T`.
+    pub fn evaluate(&self, temperature: f64) -> (f64, f64) {
+        let x = temperature.ln();
+        let (xs, ys) = (&self.log_temperature, &self.log_lambda);
+
+        if x < xs[0] {
+            return (0.0, 0.0);
+        }
+        let n = xs.partition_point(|&xn| xn <= x).clamp(1, xs.len() - 1);
+        let slope = (ys[n] - ys[n - 1]) / (xs[n] - xs[n - 1]);
+        ((ys[n - 1] + slope * (x - xs[n - 1])).exp(), slope)
+    }
+}
+
+/// Optically thin radiative cooling, which removes internal energy at the
+/// rate `rho^2 Lambda(T)` per unit volume, where the temperature is `T = p /
+/// rho`. The cooling time can be far shorter than the hydrodynamic time
+/// step, so the internal energy of each zone is integrated over the whole
+/// time step it is given, at fixed density, in substeps of at most a
+/// fraction of the local cooling time. Each substep is linearized implicit
+/// where `Lambda` increases with temperature, and the gas is not cooled
+/// below the lowest tabulated temperature, so the internal energy stays
+/// positive whatever the time step. It therefore adds no time step
+/// restriction, and can be applied either unsplit (over the time step of
+/// each stage) or operator-split (over the whole step).
+#[derive(Clone, Debug)]
+pub struct RadiativeCooling {
+    pub table: CoolingTable,
+    pub gamma_law_index: f64,
+}
+
+impl RadiativeCooling {
+    /// Returns the energy radiated per unit volume and time, `rho^2
+    /// Lambda(T)`, by gas with the given primitive state.
+    pub fn emissivity(&self, p: &[f64]) -> f64 {
+        p[0] * p[0] * self.table.evaluate(p[3] / p[0]).0
+    }
+
+    /// Returns the internal energy density after cooling for a time `dt`,
+    /// from the internal energy density `e` at the mass density `d`.
+    pub fn cool(&self, d: f64, e: f64, dt: f64) -> f64 {
+        let gm1 = self.gamma_law_index - 1.0;
+        let e_floor = d * self.table.min_temperature() / gm1;
+        let mut e = e;
+        let mut t = 0.0;
+
+        for n in 0..MAX_SUBSTEPS {
+            if t >= dt || e <= e_floor {
+                break;
+            }
+            let (lambda, slope) = self.table.evaluate(gm1 * e / d);
+            let rate = d * d * lambda;
+
+            let h = if n + 1 == MAX_SUBSTEPS {
+                dt - t
+            } else {
+                (dt - t).min(SUBSTEP_FRACTION * e / rate)
+            };
+            e = (e - h * rate / (1.0 + h * slope.max(0.0) * rate / e)).max(e_floor);
+            t += h;
+        }
+        e
+    }
+}
+
+impl SourceTerms for RadiativeCooling {
+    fn apply(&self, _: (f64, f64), p: &[f64], dt: f64, du: &mut [f64]) {
+        let e = p[3] / (self.gamma_law_index - 1.0);
+        du[3] += self.cool(p[0], e, dt) - e;
+    }
+}
@@ -0,0 +1,146 @@
+use std::ops::Range;
+use super::source_terms::SourceTerms;
+
+/// A small pseudo-random number generator (SplitMix64), whose state is a
+/// single integer that can be written to a checkpoint.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct Random {
+    state: u64,
+}
+
+impl Random {
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Returns a number drawn uniformly from `(0, 1]`.
+    fn uniform(&mut self) -> f64 {
+        ((self.next_u64() >> 11) + 1) as f64 / (1u64 << 53) as f64
+    }
+
+    /// Returns a number drawn from the standard normal distribution.
+    fn normal(&mut self) -> f64 {
+        let (u1, u2) = (self.uniform(), self.uniform());
+        (-2.0 * u1.ln()).sqrt() * (2.0 * std::f64::consts::PI * u2).cos()
+    }
+}
+
+/// One Fourier mode of the driving: its wavevector, and its complex
+/// amplitude along the direction perpendicular to the wavevector.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+struct Mode {
+    wavevector: (f64, f64),
+    amplitude: (f64, f64),
+}
+
+/// Stochastic driving of turbulence by a solenoidal acceleration field. The
+/// field is a sum of Fourier modes of the (periodic) domain, with
+/// wavevectors in a half-plane and magnitudes up to a maximum number of
+/// wavelengths across the domain. Each mode's acceleration is perpendicular
+/// to its wavevector, so the field is divergence-free, and its complex
+/// amplitude follows an Ornstein-Uhlenbeck process with the given
+/// correlation time. The variance is such that the acceleration has the
+/// given root mean square value.
+///
+/// As a [`SourceTerms`] the driving applies the acceleration field of its
+/// current amplitudes, evaluated at each cell center, so the driver must
+/// hand the tasks a fresh copy after each call to
+/// [`TurbulenceDriving::advance`]. The amplitudes and the random state are
+/// serializable, so a run restarted from a checkpoint draws the same random
+/// sequence as one which was not interrupted. The cell centers are assumed
+/// to be Cartesian coordinates.
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct TurbulenceDriving {
+    correlation_time: f64,
+    modes: Vec<Mode>,
+    random: Random,
+    sigma: f64,
+}
+
+impl TurbulenceDriving {
+    /// Creates a driving field for the given domain, with modes up to
+    /// `max_wavenumber` wavelengths across it. The amplitudes are drawn from
+    /// the stationary distribution of the process, so the driving starts at
+    /// full strength. The same seed always yields the same sequence of
+    /// acceleration fields.
+    pub fn new(
+        area: (Range<f64>, Range<f64>),
+        max_wavenumber: f64,
+        rms_acceleration: f64,
+        correlation_time: f64,
+        seed: u64,
+    ) -> Self {
+        let (lx, ly) = (area.0.end - area.0.start, area.1.end - area.1.start);
+        let nmax = max_wavenumber.floor() as i64;
+        let two_pi = 2.0 * std::f64::consts::PI;
+
+        let wavevectors: Vec<_> = (0..=nmax)
+            .flat_map(|nx| (-nmax..=nmax).map(move |ny| (nx, ny)))
+            .filter(|&(nx, ny)| nx > 0 || ny > 0)
+            .filter(|&(nx, ny)| ((nx * nx + ny * ny) as f64).sqrt() <= max_wavenumber)
+            .map(|(nx, ny)| (two_pi * nx as f64 / lx, two_pi * ny as f64 / ly))
+            .collect();
+
+        let sigma = rms_acceleration / (wavevectors.len().max(1) as f64).sqrt();
+        let mut random = Random { state: seed };
+        let modes = wavevectors
+            .into_iter()
+            .map(|wavevector| Mode {
+                wavevector,
+                amplitude: (sigma * random.normal(), sigma * random.normal()),
+            })
+            .collect();
+
+        Self {
+            correlation_time,
+            modes,
+            random,
+            sigma,
+        }
+    }
+
+    /// Advances the amplitudes of the modes over a time `dt`, with the exact
+    /// update of the Ornstein-Uhlenbeck process, which holds for any `dt`.
+    pub fn advance(&mut self, dt: f64) {
+        let decay = (-dt / self.correlation_time).exp();
+        let kick = self.sigma * (1.0 - decay * decay).sqrt();
+
+        for mode in &mut self.modes {
+            let (a, b) = mode.amplitude;
+            mode.amplitude = (
+                a * decay + kick * self.random.normal(),
+                b * decay + kick * self.random.normal(),
+            );
+        }
+    }
+
+    /// Returns the acceleration at the given position.
+    pub fn acceleration(&self, position: (f64, f64)) -> (f64, f64) {
+        let (x, y) = position;
+        let mut acceleration = (0.0, 0.0);
+
+        for mode in &self.modes {
+            let (kx, ky) = mode.wavevector;
+            let (a, b) = mode.amplitude;
+            let k = kx.hypot(ky);
+            let phase = kx * x + ky * y;
+            let f = a * phase.cos() - b * phase.sin();
+            acceleration.0 -= f * ky / k;
+            acceleration.1 += f * kx / k;
+        }
+        acceleration
+    }
+}
+
+impl SourceTerms for TurbulenceDriving {
+    fn apply(&self, position: (f64, f64), p: &[f64], dt: f64, du: &mut [f64]) {
+        let (gx, gy) = self.acceleration(position);
+        du[1] += p[0] * gx * dt;
+        du[2] += p[0] * gy * dt;
+        du[3] += p[0] * (p[1] * gx + p[2] * gy) * dt;
+    }
+}
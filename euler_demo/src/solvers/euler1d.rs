@@ -0,0 +1,463 @@
+use std::f64::consts::PI;
+use std::ops::Range;
+use gridiron::adjacency_list::AdjacencyList;
+use gridiron::automaton::{self, Automaton, Status};
+use gridiron::index_space::{Axis, IndexSpace, Stencil};
+use gridiron::meshing::{self, AxisBoundary, BlockLayout, BoundaryTopology, DomainDecomposition};
+use gridiron::patch::Patch;
+use gridiron::rect_map::Rectangle;
+use crate::hydro::error::ZoneError;
+use crate::hydro::euler2d::{Conserved, Primitive, RiemannSolver};
+use crate::hydro::geometry::Direction;
+use super::reconstruction::Reconstruction;
+use super::time_integration::TimeIntegration;
+
+/// The primitive fields are the density, velocity, and pressure, and the
+/// conserved fields are the density, momentum, and energy.
+const NUM_FIELDS: usize = 3;
+
+/// A uniform mesh of an interval. The zones are stored as a single row of a
+/// 2D index space, with one zone on the `J` axis, so that the patches,
+/// decompositions, and guard zone exchange of the 2D solvers can be reused.
+#[derive(Clone, Debug)]
+pub struct Mesh {
+    pub domain: Range<f64>,
+    pub size: usize,
+}
+
+impl Mesh {
+    pub fn spacing(&self) -> f64 {
+        (self.domain.end - self.domain.start) / self.size as f64
+    }
+
+    pub fn cell_center(&self, i: i64) -> f64 {
+        self.domain.start + (i as f64 + 0.5) * self.spacing()
+    }
+
+    pub fn index_space(&self) -> IndexSpace {
+        IndexSpace::new(0..self.size as i64, 0..1)
+    }
+
+    /// Decomposes the mesh into blocks of the given number of zones, and
+    /// builds their adjacency list for the given scheme. The blocks at either
+    /// end of the interval are connected if it is periodic.
+    pub fn decompose(
+        &self,
+        block_size: usize,
+        scheme: &Scheme,
+        periodic: bool,
+        num_ranks: usize,
+    ) -> Result<DomainDecomposition, meshing::DecompositionError> {
+        DomainDecomposition::new(
+            (self.domain.clone(), 0.0..1.0),
+            (self.size, 1),
+            BlockLayout::Size(block_size),
+            num_ranks,
+        )
+        .map(|decomposition| {
+            decomposition
+                .with_stencil(Stencil::star(scheme.reconstruction.num_guard()))
+                .with_periodic((periodic, false))
+        })
+    }
+}
+
+/// The numerical methods used by [`PatchUpdate`].
+#[derive(Clone, Copy, Debug)]
+pub struct Scheme {
+    pub reconstruction: Reconstruction,
+    pub riemann_solver: RiemannSolver,
+    pub time_integration: TimeIntegration,
+    pub gamma_law_index: f64,
+}
+
+/// Standard initial conditions for the 1D Euler equations, on the unit
+/// interval with an ideal gas of index 1.4. The shock tubes have a
+/// discontinuity at `x = 0.5`, and outflow boundaries.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum InitialCondition {
+    /// The shock tube of Sod (1978).
+    Sod,
+
+    /// The shock tube of Lax (1954).
+    Lax,
+
+    /// The "123 problem" of Einfeldt et al. (1991): two strong
+    /// rarefactions, leaving a near vacuum in the middle.
+    Toro123,
+
+    /// The left half of the blast wave problem of Woodward & Colella (1984),
+    /// with a pressure ratio of 10^5.
+    Blast,
+
+    /// A sinusoidal density wave, advected at unit speed in pressure
+    /// equilibrium, on a periodic domain. The exact solution is the initial
+    /// condition translated, so it can be used for convergence testing.
+    DensityWave,
+}
+
+impl InitialCondition {
+    pub const GAMMA_LAW_INDEX: f64 = 1.4;
+
+    /// Returns the primitive state at the given position.
+    pub fn primitive(self, x: f64) -> [f64; NUM_FIELDS] {
+        let (l, r) = match self {
+            Self::Sod => ([1.0, 0.0, 1.0], [0.125, 0.0, 0.1]),
+            Self::Lax => ([0.445, 0.698, 3.528], [0.5, 0.0, 0.571]),
+            Self::Toro123 => ([1.0, -2.0, 0.4], [1.0, 2.0, 0.4]),
+            Self::Blast => ([1.0, 0.0, 1000.0], [1.0, 0.0, 0.01]),
+            Self::DensityWave => return [1.0 + 0.2 * (2.0 * PI * x).sin(), 1.0, 1.0],
+        };
+        if x < 0.5 {
+            l
+        } else {
+            r
+        }
+    }
+
+    /// Returns the time at which the solution is usually compared.
+    pub fn final_time(self) -> f64 {
+        match self {
+            Self::Sod => 0.2,
+            Self::Lax => 0.14,
+            Self::Toro123 => 0.15,
+            Self::Blast => 0.012,
+            Self::DensityWave => 1.0,
+        }
+    }
+
+    pub fn is_periodic(self) -> bool {
+        self == Self::DensityWave
+    }
+
+    /// Returns the exact solution at the given position and time, if it is
+    /// known in closed form.
+    pub fn exact(self, x: f64, t: f64) -> Option<[f64; NUM_FIELDS]> {
+        match self {
+            Self::DensityWave => Some(self.primitive((x - t).rem_euclid(1.0))),
+            _ => None,
+        }
+    }
+}
+
+impl std::str::FromStr for InitialCondition {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sod" => Ok(Self::Sod),
+            "lax" => Ok(Self::Lax),
+            "123" => Ok(Self::Toro123),
+            "blast" => Ok(Self::Blast),
+            "wave" => Ok(Self::DensityWave),
+            _ => Err(format!(
+                "unknown initial condition '{}' (sod|lax|123|blast|wave)",
+                s
+            )),
+        }
+    }
+}
+
+/// The 1D counterpart of the 2D Euler solver: each execution of the task runs
+/// one stage of the time integration scheme on a block of zones, with guard
+/// zones received from the neighboring blocks. The boundaries are outflow,
+/// unless the mesh is periodic. If the primitive variables cannot be
+/// recovered in a zone, it keeps its previous primitive state, and the error
+/// is available from [`PatchUpdate::error`].
+pub struct PatchUpdate {
+    conserved: Patch,
+    conserved_n: Patch,
+    error: Option<ZoneError>,
+    extended_primitive: Patch,
+    flux: Patch,
+    incoming_count: usize,
+    index_space: IndexSpace,
+    mesh: Mesh,
+    neighbor_patches: Vec<Patch>,
+    outgoing_edges: Vec<(Rectangle<i64>, u32)>,
+    periodic: bool,
+    scheme: Scheme,
+    stage: usize,
+    time: f64,
+    time_step_size: f64,
+}
+
+impl PatchUpdate {
+    pub fn new(
+        primitive: Patch,
+        mesh: Mesh,
+        scheme: Scheme,
+        periodic: bool,
+        edge_list: &AdjacencyList<(Rectangle<i64>, u32)>,
+    ) -> Self {
+        let key = (primitive.high_resolution_rect(), primitive.level());
+        let index_space = primitive.index_space();
+        let mut conserved = Patch::zeros(0, NUM_FIELDS, index_space.clone());
+
+        for (p, u) in primitive.data().chunks_exact(NUM_FIELDS).zip(conserved.iter_data_mut()) {
+            write_conserved(to_primitive(p).to_conserved(scheme.gamma_law_index), u)
+        }
+        let conserved_n = conserved.clone();
+        let g = scheme.reconstruction.num_guard();
+        let extended_primitive = Patch::extract_from(&primitive, index_space.extend(g, Axis::I));
+        let flux = Patch::zeros(0, NUM_FIELDS, index_space.extend_upper(1, Axis::I));
+        let incoming_count = edge_list.incoming_edges(&key).count();
+        let outgoing_edges = edge_list.outgoing_edges(&key).cloned().collect();
+
+        Self {
+            conserved,
+            conserved_n,
+            error: None,
+            extended_primitive,
+            flux,
+            incoming_count,
+            index_space,
+            mesh,
+            neighbor_patches: Vec::new(),
+            outgoing_edges,
+            periodic,
+            scheme,
+            stage: 0,
+            time: 0.0,
+            time_step_size: 0.0,
+        }
+    }
+
+    pub fn primitive(&self) -> Patch {
+        self.extended_primitive.extract(self.index_space.clone())
+    }
+
+    /// Returns the simulation time reached by this task.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Returns true if the task is between time steps, rather than between
+    /// the stages of a step.
+    pub fn is_step_complete(&self) -> bool {
+        self.stage == 0
+    }
+
+    /// Returns the first zone in which the primitive variables could not be
+    /// recovered, if any.
+    pub fn error(&self) -> Option<&ZoneError> {
+        self.error.as_ref()
+    }
+
+    /// Returns the largest time step allowed by the Courant condition with
+    /// the given Courant number.
+    pub fn max_time_step(&self, cfl: f64) -> f64 {
+        let gamma = self.scheme.gamma_law_index;
+        let a = self
+            .primitive()
+            .data()
+            .chunks_exact(NUM_FIELDS)
+            .map(|p| to_primitive(p).max_signal_speed(gamma))
+            .fold(0.0, f64::max);
+        cfl * self.mesh.spacing() / a
+    }
+
+    /// Sets the time step size used by the stages of the next step. This
+    /// must only be called between steps.
+    pub fn set_time_step_size(&mut self, time_step_size: f64) {
+        assert!(self.is_step_complete(), "time step changed mid-step");
+        self.time_step_size = time_step_size
+    }
+
+    fn compute_flux(pe: &Patch, scheme: Scheme, flux: &mut Patch) {
+        let g = scheme.reconstruction.num_guard();
+        let space = flux.index_space();
+        let mut zones = Vec::with_capacity(2 * g as usize);
+        let mut pl = [0.0; NUM_FIELDS];
+        let mut pr = [0.0; NUM_FIELDS];
+
+        for ((i, j), f) in space.iter().zip(flux.iter_data_mut()) {
+            zones.clear();
+            zones.extend((-g..g).map(|n| pe.get_slice((i + n, j))));
+            scheme.reconstruction.face_states(&zones, &mut pl, &mut pr);
+
+            let fc = scheme.riemann_solver.flux(
+                to_primitive(&pl),
+                to_primitive(&pr),
+                Direction::I,
+                scheme.gamma_law_index,
+            );
+            write_conserved(fc, f)
+        }
+    }
+}
+
+impl Automaton for PatchUpdate {
+    type Key = Rectangle<i64>;
+    type Message = Patch;
+    type Value = Self;
+
+    fn key(&self) -> Self::Key {
+        self.index_space.to_rect()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        let g = self.scheme.reconstruction.num_guard();
+
+        self.outgoing_edges
+            .iter()
+            .cloned()
+            .map(|(rect, _)| {
+                let target = IndexSpace::from(rect.clone()).extend(g, Axis::I);
+                let images: Vec<_> = match self.periodic {
+                    true => target
+                        .periodic_images(&self.mesh.index_space())
+                        .filter(|(_, (_, sj))| *sj == 0)
+                        .map(|(image, _)| image)
+                        .collect(),
+                    false => vec![target],
+                };
+                let overlap = images
+                    .into_iter()
+                    .filter_map(|image| image.intersect(&self.index_space))
+                    .reduce(|a, b| {
+                        IndexSpace::new(a.start().0.min(b.start().0)..a.end().0.max(b.end().0), 0..1)
+                    })
+                    .expect("patches do not overlap");
+                (rect, self.extended_primitive.extract(overlap))
+            })
+            .collect()
+    }
+
+    fn receive(&mut self, patch: Self::Message) -> Status {
+        self.neighbor_patches.push(patch);
+        Status::eligible_if(self.neighbor_patches.len() == self.incoming_count)
+    }
+
+    fn value(mut self) -> Self::Value {
+        let boundary = match self.periodic {
+            true => AxisBoundary::Periodic,
+            false => AxisBoundary::Outflow,
+        };
+        let topology = BoundaryTopology::new(self.mesh.index_space()).with_axis(Axis::I, boundary);
+
+        meshing::extend_patch_mut(
+            &mut self.extended_primitive,
+            &self.index_space,
+            &topology,
+            |_, _, _| {},
+            &self.neighbor_patches,
+        );
+        self.neighbor_patches.clear();
+
+        let scheme = self.scheme;
+        Self::compute_flux(&self.extended_primitive, scheme, &mut self.flux);
+
+        if self.stage == 0 {
+            self.conserved.copy_into(&mut self.conserved_n);
+        }
+        let dt = self.time_step_size;
+        let dx = self.mesh.spacing();
+        let w = scheme.time_integration.weight(self.stage);
+        let fm = self.flux.select(self.index_space.clone());
+        let fp = self.flux.select(self.index_space.translate(1, Axis::I));
+        let un = self.conserved_n.data().chunks_exact(NUM_FIELDS);
+        let u = self.conserved.iter_data_mut();
+
+        for (fp, (fm, (u, un))) in fp.zip(fm.zip(u.zip(un))) {
+            for n in 0..NUM_FIELDS {
+                u[n] = w * un[n] + (1.0 - w) * (u[n] - (fp[n] - fm[n]) * dt / dx);
+            }
+        }
+        let p = self.extended_primitive.select_mut(self.index_space.clone());
+        let u = self.conserved.data().chunks_exact(NUM_FIELDS);
+
+        for ((index, p), u) in self.index_space.iter().zip(p).zip(u) {
+            match to_conserved(u).to_primitive(scheme.gamma_law_index) {
+                Ok(prim) => {
+                    let [d, v, _, pg] = prim.as_array();
+                    p.copy_from_slice(&[d, v, pg]);
+                }
+                Err(error) => {
+                    if self.error.is_none() {
+                        self.error = Some(ZoneError { level: 0, index, error })
+                    }
+                }
+            }
+        }
+        self.stage = (self.stage + 1) % scheme.time_integration.num_stages();
+
+        if self.stage == 0 {
+            self.time += dt;
+        }
+        self
+    }
+
+    fn independent(&self) -> bool {
+        self.incoming_count == 0
+    }
+}
+
+/// Runs the given initial condition to its final time, serially, on blocks
+/// of the given size, and returns the primitive patches in order along the
+/// mesh, with the time reached. An error is returned if the mesh cannot be
+/// decomposed, or if primitive variable recovery fails.
+pub fn solve(
+    initial: InitialCondition,
+    mesh: &Mesh,
+    scheme: Scheme,
+    block_size: usize,
+    cfl: f64,
+) -> Result<(f64, Vec<Patch>), String> {
+    if scheme.time_integration == TimeIntegration::Ctu {
+        return Err("the corner transport upwind scheme is not supported in 1D".to_string());
+    }
+    let periodic = initial.is_periodic();
+    let decomposition = mesh
+        .decompose(block_size, &scheme, periodic, 1)
+        .map_err(|e| e.to_string())?;
+    let edge_list = decomposition.adjacency_list();
+
+    let mut task_list: Vec<_> = decomposition
+        .blocks()
+        .iter()
+        .map(|rect| {
+            let primitive = Patch::from_vector_function(0, rect.clone(), |(i, _)| {
+                initial.primitive(mesh.cell_center(i))
+            });
+            PatchUpdate::new(primitive, mesh.clone(), scheme, periodic, edge_list)
+        })
+        .collect();
+
+    let mut time = 0.0;
+    let tfinal = initial.final_time();
+
+    while time < tfinal {
+        let dt = task_list
+            .iter()
+            .map(|task| task.max_time_step(cfl))
+            .fold(tfinal - time, f64::min);
+
+        for task in &mut task_list {
+            task.set_time_step_size(dt)
+        }
+        for _ in 0..scheme.time_integration.num_stages() {
+            task_list = automaton::execute(task_list).collect();
+        }
+        if let Some(error) = task_list.iter().find_map(PatchUpdate::error) {
+            return Err(format!("primitive recovery failed in {}", error));
+        }
+        time += dt;
+    }
+    let mut primitive: Vec<_> = task_list.iter().map(PatchUpdate::primitive).collect();
+    primitive.sort_by_key(|patch| patch.index_space().start().0);
+    Ok((time, primitive))
+}
+
+fn to_primitive(p: &[f64]) -> Primitive {
+    Primitive::new(p[0], p[1], 0.0, p[2])
+}
+
+fn to_conserved(u: &[f64]) -> Conserved {
+    Conserved::from(&[u[0], u[1], 0.0, u[2]][..])
+}
+
+fn write_conserved(c: Conserved, u: &mut [f64]) {
+    let [d, m, _, e] = c.as_array();
+    u.copy_from_slice(&[d, m, e])
+}
@@ -1,33 +1,132 @@
+use std::ops::Range;
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
 use gridiron::adjacency_list::AdjacencyList;
-use gridiron::automaton::{Automaton, Status};
-use gridiron::index_space::{Axis, IndexSpace};
-use gridiron::meshing;
-use gridiron::patch::Patch;
+use gridiron::automaton::{Automaton, Metadata, Status};
+use gridiron::index_space::{Axis, IndexSpace, Stencil};
+use gridiron::meshing::{self, Cartesian, Cylindrical, Geometry, SphericalPolar};
+use gridiron::patch::{Patch, PatchPool};
 use gridiron::rect_map::Rectangle;
-use crate::hydro::{euler2d, euler2d::Conserved, euler2d::Primitive, geometry::Direction};
+use crate::hydro::error::{Error, ZoneError};
+use crate::hydro::system::HydroSystem;
+use super::boundary::{Boundaries, BoundaryCondition};
+use super::reactions::Reactions;
+use super::reconstruction::{Flattening, Reconstruction};
+use super::source_terms::{HydrostaticGravity, SourceSplitting, SourceTerms};
+use super::time_integration::TimeIntegration;
+use super::time_series::PatchReduction;
 
-const NUM_GUARD: i64 = 1;
-const GAMMA_LAW_INDEX: f64 = 5.0 / 3.0;
+/// The coordinate system of a [`Mesh`]. The curvilinear systems are
+/// axisymmetric, and the velocity components are along the two mesh axes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Coordinates {
+    /// Planar coordinates `(x, y)`.
+    Cartesian,
 
-/// A simple rectilinear structured mesh
+    /// Cylindrical coordinates `(r, z)`.
+    Cylindrical,
+
+    /// Spherical-polar coordinates `(r, theta)`, with `theta` the polar
+    /// angle.
+    SphericalPolar,
+}
+
+impl Coordinates {
+    /// Converts a position in these coordinates to the Cartesian coordinates
+    /// of the meridional plane, with the symmetry axis along `y`.
+    pub fn to_cartesian(self, position: (f64, f64)) -> (f64, f64) {
+        match self {
+            Self::Cartesian | Self::Cylindrical => position,
+            Self::SphericalPolar => {
+                let (r, q) = position;
+                (r * q.sin(), r * q.cos())
+            }
+        }
+    }
+
+    /// Adds the geometric source terms in a zone to `s`. The pressure terms
+    /// are given in terms of the difference in the face areas on either side
+    /// of the zone, divided by its volume, on each axis; this form balances
+    /// the pressure flux exactly in a uniform state. The remaining terms
+    /// depend on the system, and are evaluated at the zone center
+    /// `position`.
+    fn geometric_source<H: HydroSystem>(
+        self,
+        system: &H,
+        p: &[f64],
+        position: (f64, f64),
+        area_difference: (f64, f64),
+        dt: f64,
+        s: &mut [f64],
+    ) {
+        s[1] += p[3] * area_difference.0 * dt;
+        s[2] += p[3] * area_difference.1 * dt;
+
+        if let Self::SphericalPolar = self {
+            system.spherical_source(p, position.0, dt, s)
+        }
+    }
+}
+
+impl std::str::FromStr for Coordinates {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "cartesian" => Ok(Self::Cartesian),
+            "cylindrical" => Ok(Self::Cylindrical),
+            "spherical" => Ok(Self::SphericalPolar),
+            _ => Err(format!(
+                "unknown coordinates '{}' (cartesian|cylindrical|spherical)",
+                s
+            )),
+        }
+    }
+}
+
+/// A simple rectilinear structured mesh. The area is given in the mesh's
+/// coordinate system, and the zones are uniformly spaced in those
+/// coordinates.
 #[derive(Clone)]
 pub struct Mesh {
     pub area: Rectangle<f64>,
     pub size: (usize, usize),
+    pub coordinates: Coordinates,
 }
 
 impl Mesh {
-    pub fn cell_spacing(&self) -> (f64, f64) {
-        let d0 = (self.area.0.end - self.area.0.start) / self.size.0 as f64;
-        let d1 = (self.area.1.end - self.area.1.start) / self.size.1 as f64;
-        (d0, d1)
+    /// Returns the mapping of zone indexes to coordinates. Its areas and
+    /// volumes are only those of the mesh if the coordinates are Cartesian;
+    /// see [`Mesh::coordinate_system`].
+    pub fn geometry(&self) -> Cartesian {
+        Cartesian::new(self.area.clone(), self.size)
+    }
+
+    /// Returns the geometry of the mesh's coordinate system, with the face
+    /// areas and zone volumes used in the finite volume update.
+    pub fn coordinate_system(&self) -> Box<dyn Geometry> {
+        let (area, size) = (self.area.clone(), self.size);
+        match self.coordinates {
+            Coordinates::Cartesian => Box::new(Cartesian::new(area, size)),
+            Coordinates::Cylindrical => Box::new(Cylindrical::new(area, size)),
+            Coordinates::SphericalPolar => Box::new(SphericalPolar::new(area, size)),
+        }
     }
 
-    pub fn cell_center(&self, index: (i64, i64)) -> (f64, f64) {
-        let (d0, d1) = self.cell_spacing();
-        let x0 = self.area.0.start + d0 * (index.0 as f64 + 0.5);
-        let x1 = self.area.1.start + d1 * (index.1 as f64 + 0.5);
-        (x0, x1)
+    /// Returns the smallest physical length of a zone in the given index
+    /// space, along either axis.
+    pub fn min_zone_length(&self, level: u32, space: &IndexSpace) -> f64 {
+        let geometry = self.geometry();
+        let (dx, dy) = geometry.spacing;
+        let scale = (1 << level) as f64;
+
+        match self.coordinates {
+            Coordinates::Cartesian | Coordinates::Cylindrical => dx.min(dy) * scale,
+            Coordinates::SphericalPolar => {
+                let r = geometry.cell_center(level, space.start()).0;
+                dx.min(r * dy) * scale
+            }
+        }
     }
 
     pub fn total_zones(&self) -> usize {
@@ -39,26 +138,172 @@ impl Mesh {
     }
 }
 
-/// A basic first-order update scheme, hard-coded for the 2D euler equations.
-pub struct PatchUpdate {
+/// What [`PatchUpdate`] does in zones where the primitive variables cannot
+/// be recovered, even after the floors are applied.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecoveryFallback {
+    /// Record the error, and keep the zone's primitive state from the start
+    /// of the stage.
+    Fail,
+
+    /// Redo the update of the zone with the Rusanov flux of the piecewise
+    /// constant state at the start of the stage, through each of its faces.
+    /// The error is recorded if recovery still fails.
+    DiffusiveFlux,
+}
+
+impl std::str::FromStr for RecoveryFallback {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fail" => Ok(Self::Fail),
+            "diffusive" => Ok(Self::DiffusiveFlux),
+            _ => Err(format!("unknown recovery fallback '{}' (fail|diffusive)", s)),
+        }
+    }
+}
+
+/// The numerical methods used by [`PatchUpdate`]. The Riemann solver is
+/// part of the [`HydroSystem`].
+#[derive(Clone, Copy, Debug)]
+pub struct Scheme {
+    pub reconstruction: Reconstruction,
+    pub time_integration: TimeIntegration,
+    pub flattening: Option<Flattening>,
+    pub fallback: RecoveryFallback,
+}
+
+impl Scheme {
+    /// Returns the number of guard zones read by [`PatchUpdate`] on each
+    /// side of a patch. The corner transport upwind scheme predicts the face
+    /// states of the zones adjacent to the patch, so it reads one zone more
+    /// than the reconstruction.
+    pub fn num_guard(&self) -> i64 {
+        match self.time_integration {
+            TimeIntegration::Ctu => self.reconstruction.num_guard() + 1,
+            _ => self.reconstruction.num_guard(),
+        }
+    }
+
+    /// Returns the guard zones read by [`PatchUpdate`] for the given system.
+    /// The mesh adjacency list must be built for this stencil. The diffusive
+    /// fluxes need the transverse gradients at each face, and the corner
+    /// transport upwind scheme the transverse fluxes of the zones adjacent
+    /// to the patch, so they require the corner guard zones.
+    pub fn stencil<H: HydroSystem>(&self, system: &H) -> Stencil {
+        Stencil {
+            corners: system.is_diffusive() || self.time_integration == TimeIntegration::Ctu,
+            ..Stencil::star(self.num_guard())
+        }
+    }
+}
+
+/// The face states and first-pass fluxes of the corner transport upwind
+/// scheme, at the faces normal to one axis, of the patch and one zone
+/// beyond it.
+struct TransverseFaces {
+    pl: Patch,
+    pr: Patch,
+    flux: Patch,
+}
+
+impl TransverseFaces {
+    fn new(index_space: &IndexSpace, axis: Axis, num_fields: usize, level: u32) -> Self {
+        let space = index_space.extend_upper(1, axis).extend_all(1);
+        Self {
+            pl: Patch::zeros(level, num_fields, space.clone()),
+            pr: Patch::zeros(level, num_fields, space.clone()),
+            flux: Patch::zeros(level, num_fields, space),
+        }
+    }
+}
+
+/// Buffers used within a stage, which are kept by a [`PatchUpdate`] so that
+/// they are not allocated every stage.
+struct Scratch {
+    /// The states on either side of a row of faces.
+    face_states: (Vec<f64>, Vec<f64>),
+
+    /// The faces normal to each axis, if the scheme is
+    /// [`TimeIntegration::Ctu`].
+    transverse: Option<[TransverseFaces; 2]>,
+
+    /// The source terms of one zone.
+    source: Vec<f64>,
+
+    /// The primitive state of one zone.
+    zone: Vec<f64>,
+}
+
+/// A basic update scheme for any two-dimensional [`HydroSystem`], such as
+/// the Euler equations ([`crate::hydro::euler2d::Euler2d`]) or special
+/// relativistic hydrodynamics ([`crate::hydro::srhd2d::Srhd2d`]); the
+/// messaging, guard zones, and time integration are the same for every
+/// system. It is first order unless a higher order reconstruction is
+/// selected; the mesh adjacency list must be built for [`Scheme::stencil`].
+/// Viscous and thermal fluxes are added if the system has them, with
+/// gradients computed as if the mesh were Cartesian. The fluxes are weighted
+/// by the face areas of the mesh coordinates, and geometric source terms are
+/// added. Each execution of
+/// the task runs one stage of the time integration scheme; the state at the
+/// start of the time step is kept in the task until the step is complete.
+/// Source terms, if any, are added with [`PatchUpdate::with_sources`]. The
+/// guard zones beyond the domain are filled according to the task's
+/// [`Boundaries`]. If the primitive variables cannot be recovered in a zone,
+/// the zone keeps its previous primitive state (so no NaNs are sent to
+/// neighbors), and the error is available from [`PatchUpdate::error`]. If
+/// the scheme has [`Flattening`], the high order reconstructions are
+/// flattened near shocks, with coefficients kept in a scratch patch. Gravity
+/// in a well-balanced form is added with
+/// [`PatchUpdate::with_hydrostatic_gravity`]. Reductions registered with
+/// [`PatchUpdate::with_reductions`] are computed at the end of each time
+/// step, from the new primitive state. After each execution the task
+/// reports its signal speed and the wall time it took through [`Metadata`].
+///
+/// The task owns all the arrays it works on (the conserved states, the
+/// extended primitive patch, the fluxes, and smaller scratch buffers), and
+/// they are moved through [`Automaton::value`] rather than allocated each
+/// stage. The guard zone patches it receives are returned to a pool, from
+/// which the patches it sends are taken.
+pub struct PatchUpdate<H: HydroSystem> {
+    boundaries: Boundaries,
     conserved: Patch,
+    conserved_n: Patch,
+    cost: f64,
+    error: Option<ZoneError>,
     extended_primitive: Patch,
     flux_i: Patch,
     flux_j: Patch,
+    gravity: Option<HydrostaticGravity>,
     incoming_count: usize,
     index_space: IndexSpace,
     level: u32,
+    max_signal_speed: f64,
     mesh: Mesh,
+    message_pool: Mutex<PatchPool>,
     neighbor_patches: Vec<Patch>,
     outgoing_edges: Vec<(Rectangle<i64>, u32)>,
+    reactions: Option<Arc<Reactions>>,
+    reduced: Vec<f64>,
+    reductions: Vec<Arc<dyn PatchReduction>>,
+    scheme: Scheme,
+    scratch: Scratch,
+    shock_sensor: Patch,
+    sources: Option<(Arc<dyn SourceTerms>, SourceSplitting)>,
+    stage: usize,
+    system: H,
+    time: f64,
     time_step_size: f64,
     worker_group: Option<usize>,
 }
 
-impl PatchUpdate {
+impl<H: HydroSystem> PatchUpdate<H> {
     pub fn new(
         primitive: Patch,
         mesh: Mesh,
+        system: H,
+        scheme: Scheme,
         time_step_size: f64,
         worker_group: Option<usize>,
         edge_list: &AdjacencyList<(Rectangle<i64>, u32)>,
@@ -67,72 +312,529 @@ impl PatchUpdate {
         let lv = primitive.level();
         let nq = primitive.num_fields();
         let index_space = primitive.index_space();
-        let conserved = primitive.map(Self::prim_to_cons);
-        let extended_primitive = Patch::extract_from(&primitive, index_space.extend_all(NUM_GUARD));
+        let conserved = primitive.map(|p, u| system.to_conserved(p, u));
+        let conserved_n = conserved.clone();
+        let extended_primitive = Patch::extract_from(
+            &primitive,
+            index_space.extend_all(scheme.num_guard()),
+        );
         let flux_i = Patch::zeros(lv, nq, index_space.extend_upper(1, Axis::I));
         let flux_j = Patch::zeros(lv, nq, index_space.extend_upper(1, Axis::J));
+        let margin = scheme.num_guard() - scheme.reconstruction.num_guard();
+        let shock_sensor = Patch::zeros(lv, 2, index_space.extend_all(1 + margin));
         let incoming_count = edge_list.incoming_edges(&key).count();
         let level = primitive.level();
         let neighbor_patches = Vec::new();
         let outgoing_edges = edge_list.outgoing_edges(&key).cloned().collect();
+        let boundaries = Boundaries::uniform(BoundaryCondition::Inflow(vec![0.1, 0.0, 0.0, 0.125]));
+        let max_signal_speed = Self::signal_speed(&system, &extended_primitive, &index_space);
+        let scratch = Scratch {
+            face_states: (Vec::new(), Vec::new()),
+            transverse: match scheme.time_integration {
+                TimeIntegration::Ctu => Some([
+                    TransverseFaces::new(&index_space, Axis::I, nq, lv),
+                    TransverseFaces::new(&index_space, Axis::J, nq, lv),
+                ]),
+                _ => None,
+            },
+            source: vec![0.0; nq],
+            zone: vec![0.0; nq],
+        };
         Self {
+            boundaries,
             conserved,
+            conserved_n,
+            cost: 0.0,
+            error: None,
             extended_primitive,
             flux_i,
             flux_j,
+            gravity: None,
             incoming_count,
             index_space,
             level,
+            max_signal_speed,
             mesh,
+            message_pool: Mutex::new(PatchPool::new()),
             neighbor_patches,
             outgoing_edges,
+            reactions: None,
+            reduced: Vec::new(),
+            reductions: Vec::new(),
+            scheme,
+            scratch,
+            shock_sensor,
+            sources: None,
+            stage: 0,
+            system,
+            time: 0.0,
             time_step_size,
             worker_group,
         }
     }
+
+    /// Fills the guard zones beyond the domain with the given boundary
+    /// conditions, instead of the default fixed state. The mesh adjacency
+    /// list must be built for the periodic axes of the boundaries.
+    pub fn with_boundaries(self, boundaries: Boundaries) -> Self {
+        Self { boundaries, ..self }
+    }
+
+    /// Applies the given source terms in each time step. Unsplit sources are
+    /// evaluated at the start of each stage, and operator-split sources are
+    /// applied over the whole time step after the last stage.
+    pub fn with_sources(self, sources: Arc<dyn SourceTerms>, splitting: SourceSplitting) -> Self {
+        Self {
+            sources: Some((sources, splitting)),
+            ..self
+        }
+    }
+
+    /// Replaces the source terms, for example with a new realization of a
+    /// stochastic forcing. This must only be done between time steps.
+    pub fn set_sources(&mut self, sources: Arc<dyn SourceTerms>, splitting: SourceSplitting) {
+        assert!(self.is_step_complete(), "source terms changed between stages");
+        self.sources = Some((sources, splitting))
+    }
+
+    /// Integrates the given reaction network over each time step, after the
+    /// last stage and any operator-split source terms. The system must carry
+    /// the mass fractions of [`crate::hydro::species::Multispecies`].
+    pub fn with_reactions(self, reactions: Arc<Reactions>) -> Self {
+        Self {
+            reactions: Some(reactions),
+            ..self
+        }
+    }
+
+    /// Computes the given reductions over this patch at the end of each time
+    /// step; the values are available from [`PatchUpdate::reduced`].
+    pub fn with_reductions(self, reductions: Vec<Arc<dyn PatchReduction>>) -> Self {
+        Self { reductions, ..self }
+    }
+
+    /// Applies a uniform gravitational acceleration in the well-balanced
+    /// form of [`HydrostaticGravity`]: the density and pressure are
+    /// reconstructed as deviations from the local hydrostatic equilibrium of
+    /// each zone (for every reconstruction, including PCM), so that
+    /// isothermal atmospheres stay at rest to round-off. This is in addition
+    /// to any other source terms.
+    pub fn with_hydrostatic_gravity(self, gravity: HydrostaticGravity) -> Self {
+        Self {
+            gravity: Some(gravity),
+            ..self
+        }
+    }
 }
 
-impl PatchUpdate {
-    fn compute_flux(pe: &Patch, axis: Axis, flux: &mut Patch) {
-        let pl = pe.select(flux.index_space().translate(-1, axis));
-        let pr = pe.select(flux.index_space());
+impl<H: HydroSystem> PatchUpdate<H> {
+    /// Computes the fluxes through the faces normal to the given axis, on a
+    /// patch with the given zone spacing. If there is gravity, the face
+    /// states are balanced against it. The face states of a row are written
+    /// to the given buffers, which are resized as needed.
+    #[allow(clippy::too_many_arguments)]
+    fn compute_flux(
+        pe: &Patch,
+        shock_sensor: &Patch,
+        axis: Axis,
+        system: &H,
+        scheme: Scheme,
+        spacing: (f64, f64),
+        gravity: Option<HydrostaticGravity>,
+        face_states: &mut (Vec<f64>, Vec<f64>),
+        flux: &mut Patch,
+    ) {
+        let space = flux.index_space();
+        let (rows, columns) = space.to_rect();
+        let nf = pe.num_fields();
+        let row_len = space.dim().1 * nf;
+
+        // The faces are solved a row at a time, so that the system can batch
+        // its Riemann solves (see [`HydroSystem::riemann_flux_row`]).
+        if scheme.reconstruction == Reconstruction::Pcm && gravity.is_none() {
+            let j = columns.start;
+            let l = match axis {
+                Axis::I => (-1, 0),
+                Axis::J => (0, -1),
+            };
+
+            for (i, f) in rows.zip(flux.data_mut().chunks_exact_mut(row_len)) {
+                let sl = pe.index_space().row_major_offset((i + l.0, j + l.1)) * nf;
+                let sr = pe.index_space().row_major_offset((i, j)) * nf;
+                let pl = &pe.data()[sl..sl + row_len];
+                let pr = &pe.data()[sr..sr + row_len];
+                system.riemann_flux_row(pl, pr, nf, axis, f)
+            }
+        } else {
+            let mut zones = Vec::with_capacity(2 * scheme.reconstruction.num_guard() as usize);
+            let (pl_row, pr_row) = face_states;
+            pl_row.resize(row_len, 0.0);
+            pr_row.resize(row_len, 0.0);
+
+            for (i, f) in rows.zip(flux.data_mut().chunks_exact_mut(row_len)) {
+                let row = (pe, shock_sensor, axis, i, columns.clone());
+                Self::reconstruct_row(row, scheme, spacing, gravity, &mut zones, pl_row, pr_row);
+                system.riemann_flux_row(pl_row, pr_row, nf, axis, f)
+            }
+        }
+        if system.is_diffusive() {
+            Self::add_diffusive_flux(pe, axis, system, spacing, flux)
+        }
+    }
+
+    /// Reconstructs the states on either side of the faces `(i, j)` normal
+    /// to the given axis, for `j` in the given range, into `pl_row` and
+    /// `pr_row`. The zones read by the reconstruction of each face are
+    /// collected in `zones`, which is reused between rows.
+    fn reconstruct_row<'a>(
+        (pe, shock_sensor, axis, i, columns): (&'a Patch, &Patch, Axis, i64, Range<i64>),
+        scheme: Scheme,
+        spacing: (f64, f64),
+        gravity: Option<HydrostaticGravity>,
+        zones: &mut Vec<&'a [f64]>,
+        pl_row: &mut [f64],
+        pr_row: &mut [f64],
+    ) {
+        let Scheme {
+            reconstruction,
+            flattening,
+            ..
+        } = scheme;
+
+        let g = reconstruction.num_guard();
+        let nf = pe.num_fields();
+        let faces = pl_row.chunks_exact_mut(nf).zip(pr_row.chunks_exact_mut(nf));
 
-        let dir = match axis {
-            Axis::I => Direction::I,
-            Axis::J => Direction::J,
+        let (l, q, width) = match axis {
+            Axis::I => ((-1, 0), 0, spacing.0),
+            Axis::J => ((0, -1), 1, spacing.1),
         };
 
-        for (f, (pl, pr)) in flux.iter_data_mut().zip(pl.zip(pr)) {
-            euler2d::riemann_hlle(pl.into(), pr.into(), dir, GAMMA_LAW_INDEX).write_to_slice(f)
+        for (j, (pl, pr)) in columns.zip(faces) {
+            zones.clear();
+            zones.extend((-g..g).map(|n| match axis {
+                Axis::I => pe.get_slice((i + n, j)),
+                Axis::J => pe.get_slice((i, j + n)),
+            }));
+            let coefficients = flattening.map(|_| {
+                let fl = shock_sensor.get_slice((i + l.0, j + l.1))[q];
+                let fr = shock_sensor.get_slice((i, j))[q];
+                (fl, fr)
+            });
+            match (gravity, coefficients) {
+                (Some(gravity), coefficients) => {
+                    let equilibrium = |p: &[f64], n: f64| gravity.equilibrium(p, axis, n * width);
+                    let coefficients = coefficients.unwrap_or((0.0, 0.0));
+                    reconstruction.balanced_face_states(zones, coefficients, equilibrium, pl, pr)
+                }
+                (None, Some(coefficients)) => {
+                    reconstruction.flattened_face_states(zones, coefficients, pl, pr)
+                }
+                (None, None) => reconstruction.face_states(zones, pl, pr),
+            }
         }
     }
 
+    /// Computes the fluxes of the corner transport upwind scheme through the
+    /// faces normal to both axes, over a time step `dt`:
+    ///
+    /// 1. The face states of every face of the patch and one zone beyond it
+    ///    are reconstructed, and unless the reconstruction is piecewise
+    ///    constant, those of each zone are advanced by half a time step with
+    ///    the flux difference across the zone (the MUSCL-Hancock
+    ///    predictor).
+    /// 2. The Riemann problems at those faces give the transverse fluxes.
+    /// 3. The face states of the patch faces are corrected by half a time
+    ///    step of the divergence of the transverse fluxes in the adjacent
+    ///    zone, and the Riemann problems are solved again for the fluxes of
+    ///    the update.
+    ///
+    /// States which are not physical after a correction keep their value
+    /// from before it. The zone widths are taken as Cartesian.
+    #[allow(clippy::too_many_arguments)]
+    fn compute_ctu_flux(
+        pe: &Patch,
+        shock_sensor: &Patch,
+        system: &H,
+        scheme: Scheme,
+        spacing: (f64, f64),
+        dt: f64,
+        faces: &mut [TransverseFaces; 2],
+        flux_i: &mut Patch,
+        flux_j: &mut Patch,
+    ) {
+        let nf = pe.num_fields();
+        let mut zones = Vec::with_capacity(2 * scheme.reconstruction.num_guard() as usize);
+        let mut u = vec![0.0; nf];
+        let mut fl = vec![0.0; nf];
+        let mut fr = vec![0.0; nf];
+
+        for (faces, (axis, width)) in faces.iter_mut().zip([(Axis::I, spacing.0), (Axis::J, spacing.1)]) {
+            let TransverseFaces { pl, pr, flux } = faces;
+            let space = flux.index_space();
+            let (rows, columns) = space.to_rect();
+            let row_len = space.dim().1 * nf;
+            let rows = rows.zip(pl.data_mut().chunks_exact_mut(row_len).zip(pr.data_mut().chunks_exact_mut(row_len)));
+
+            for (i, (pl_row, pr_row)) in rows {
+                let row = (pe, shock_sensor, axis, i, columns.clone());
+                Self::reconstruct_row(row, scheme, spacing, None, &mut zones, pl_row, pr_row);
+            }
+            if scheme.reconstruction != Reconstruction::Pcm {
+                let zones = space.trim_upper(1, axis);
+                let dtdx = 0.5 * dt / width;
+
+                for index in zones.iter() {
+                    let next = match axis {
+                        Axis::I => (index.0 + 1, index.1),
+                        Axis::J => (index.0, index.1 + 1),
+                    };
+                    let (p_minus, p_plus) = (pr.get_slice(index), pl.get_slice(next));
+                    system.riemann_flux(p_minus, p_minus, axis, &mut fl);
+                    system.riemann_flux(p_plus, p_plus, axis, &mut fr);
+                    Self::correct(system, pr.get_slice_mut(index), &fl, &fr, dtdx, &mut u);
+                    Self::correct(system, pl.get_slice_mut(next), &fl, &fr, dtdx, &mut u);
+                }
+            }
+            system.riemann_flux_row(pl.data(), pr.data(), nf, axis, flux.data_mut());
+        }
+
+        let [faces_i, faces_j] = faces;
+        let transverse = [
+            (&mut faces_i.pl, &mut faces_i.pr, &faces_j.flux, flux_i.index_space(), Axis::I),
+            (&mut faces_j.pl, &mut faces_j.pr, &faces_i.flux, flux_j.index_space(), Axis::J),
+        ];
+
+        for (pl, pr, transverse_flux, patch_faces, axis) in transverse {
+            let (dtdx, lower, upper) = match axis {
+                Axis::I => (0.5 * dt / spacing.1, (-1, 0), (0, 1)),
+                Axis::J => (0.5 * dt / spacing.0, (0, -1), (1, 0)),
+            };
+            for (i, j) in patch_faces.iter() {
+                for (zone, p) in [((i + lower.0, j + lower.1), &mut *pl), ((i, j), &mut *pr)] {
+                    let fm = transverse_flux.get_slice(zone);
+                    let fp = transverse_flux.get_slice((zone.0 + upper.0, zone.1 + upper.1));
+                    Self::correct(system, p.get_slice_mut((i, j)), fm, fp, dtdx, &mut u);
+                }
+            }
+        }
+
+        for (faces, flux, axis) in [(&*faces_i, flux_i, Axis::I), (&*faces_j, flux_j, Axis::J)] {
+            let space = flux.index_space();
+            let (rows, columns) = space.to_rect();
+            let row_len = space.dim().1 * nf;
+
+            for (i, f) in rows.zip(flux.data_mut().chunks_exact_mut(row_len)) {
+                let s = faces.pl.index_space().row_major_offset((i, columns.start)) * nf;
+                let pl = &faces.pl.data()[s..s + row_len];
+                let pr = &faces.pr.data()[s..s + row_len];
+                system.riemann_flux_row(pl, pr, nf, axis, f)
+            }
+            if system.is_diffusive() {
+                Self::add_diffusive_flux(pe, axis, system, spacing, flux)
+            }
+        }
+    }
+
+    /// Advances the primitive state `p` by `dtdx` times the difference of
+    /// the fluxes `fm` and `fp` on either side of it, in conserved
+    /// variables, using `u` as scratch. The state is unchanged if the
+    /// result is not physical.
+    fn correct(system: &H, p: &mut [f64], fm: &[f64], fp: &[f64], dtdx: f64, u: &mut [f64]) {
+        system.to_conserved(p, u);
+
+        for (u, (fm, fp)) in u.iter_mut().zip(fm.iter().zip(fp)) {
+            *u -= dtdx * (fp - fm)
+        }
+        system.to_primitive(u, p).ok();
+    }
+
+    /// Writes the flattening coefficient of each zone adjacent to a face into
+    /// the shock sensor, with one field for each axis. Only the zones on
+    /// either side of the faces normal to an axis are written for that axis.
+    fn detect_shocks(pe: &Patch, flattening: Flattening, index_space: &IndexSpace, shock_sensor: &mut Patch) {
+        for &(axis, q) in &[(Axis::I, 0), (Axis::J, 1)] {
+            let (di, dj) = match axis {
+                Axis::I => (1, 0),
+                Axis::J => (0, 1),
+            };
+            for (i, j) in index_space.extend(1, axis).iter() {
+                let zl = pe.get_slice((i - di, j - dj));
+                let zr = pe.get_slice((i + di, j + dj));
+                shock_sensor.get_slice_mut((i, j))[q] = flattening.coefficient(zl, zr, q + 1);
+            }
+        }
+    }
+
+    /// Adds the viscous and thermal fluxes to the given face fluxes. The
+    /// gradients normal to the face are differences of the two adjacent
+    /// zones, and the transverse ones are averaged from the four zones on
+    /// either side of the face, which reaches into the corner guard zones.
+    fn add_diffusive_flux(pe: &Patch, axis: Axis, system: &H, spacing: (f64, f64), flux: &mut Patch) {
+        let (dx, dy) = spacing;
+        let space = flux.index_space();
+        let (di, dj) = match axis {
+            Axis::I => (1, 0),
+            Axis::J => (0, 1),
+        };
+        let fields = |index: (i64, i64)| {
+            let p = pe.get_slice(index);
+            [p[1], p[2], p[3] / p[0]]
+        };
+
+        for ((i, j), f) in space.iter().zip(flux.iter_data_mut()) {
+            let l = fields((i - di, j - dj));
+            let r = fields((i, j));
+            let (lm, lp, rm, rp) = (
+                fields((i - di - dj, j - dj - di)),
+                fields((i - di + dj, j - dj + di)),
+                fields((i - dj, j - di)),
+                fields((i + dj, j + di)),
+            );
+            let mut grad = [(0.0, 0.0); 3];
+
+            for (n, g) in grad.iter_mut().enumerate() {
+                let normal = r[n] - l[n];
+                let transverse = 0.25 * (lp[n] - lm[n] + rp[n] - rm[n]);
+                *g = match axis {
+                    Axis::I => (normal / dx, transverse / dy),
+                    Axis::J => (transverse / dx, normal / dy),
+                };
+            }
+            let velocity = (0.5 * (l[0] + r[0]), 0.5 * (l[1] + r[1]));
+            system.add_diffusive_flux(velocity, grad, axis, f)
+        }
+    }
+
+    /// Applies the system's floors to the conserved state, and returns the
+    /// zones (in order) where the primitive variables cannot be recovered,
+    /// starting from the given primitive state. The recovered states are
+    /// written to `p`, a scratch buffer of one zone.
+    fn apply_floors(system: &H, conserved: &mut Patch, primitive: &Patch, p: &mut [f64]) -> Vec<((i64, i64), Error)> {
+        let space = conserved.index_space();
+        let mut failed = Vec::new();
+
+        for (index, u) in space.iter().zip(conserved.iter_data_mut()) {
+            system.apply_floors(u);
+            p.copy_from_slice(primitive.get_slice(index));
+
+            if let Err(e) = system.to_primitive(u, p) {
+                failed.push((index, e))
+            }
+        }
+        failed
+    }
+
+    /// Writes the primitive state recovered from the conserved state into
+    /// the given patch, leaving it unchanged in zones where recovery fails.
+    fn recover_primitive(system: &H, conserved: &Patch, primitive: &mut Patch) {
+        let space = conserved.index_space();
+        let u = conserved.data().chunks_exact(conserved.num_fields());
+
+        for (p, u) in primitive.select_mut(space).zip(u) {
+            system.to_primitive(u, p).ok();
+        }
+    }
+
+    /// Replaces the flux through a face with the system's fallback flux of
+    /// the adjacent zones of the given primitive state, and calls `correct` with
+    /// the index of each adjacent zone and the change of the flux out of
+    /// it. A face already replaced has no change. Faces on the edge of the
+    /// patch are corrected only on this side, so conservation across
+    /// patches is not exact there.
+    fn replace_flux<F>(system: &H, pe: &Patch, axis: Axis, face: (i64, i64), flux: &mut Patch, mut correct: F)
+    where
+        F: FnMut((i64, i64), &[f64]),
+    {
+        let (i, j) = face;
+        let l = match axis {
+            Axis::I => (i - 1, j),
+            Axis::J => (i, j - 1),
+        };
+        let f0 = flux.get_slice(face).to_vec();
+        let f = flux.get_slice_mut(face);
+        system.fallback_flux(pe.get_slice(l), pe.get_slice(face), axis, f);
+
+        let df: Vec<_> = f.iter().zip(&f0).map(|(f, f0)| f - f0).collect();
+        correct(l, &df);
+        correct(face, &df.iter().map(|x| -x).collect::<Vec<_>>());
+    }
+
+    /// Returns the largest signal speed in the valid zones of the given
+    /// primitive state.
+    fn signal_speed(system: &H, primitive: &Patch, index_space: &IndexSpace) -> f64 {
+        primitive
+            .select(index_space.clone())
+            .map(|p| system.max_signal_speed(p))
+            .fold(0.0, f64::max)
+    }
+
+    /// Sets the time step size. This must only be done between time steps.
+    pub fn set_time_step_size(&mut self, time_step_size: f64) {
+        assert!(self.is_step_complete(), "time step changed between stages");
+        self.time_step_size = time_step_size
+    }
+
+    /// Returns true if the task is between time steps, rather than between
+    /// the stages of a step.
+    pub fn is_step_complete(&self) -> bool {
+        self.stage == 0
+    }
+
     pub fn primitive(&self) -> Patch {
         self.extended_primitive.extract(self.index_space.clone())
     }
 
-    pub fn cons_to_prim(u: &[f64], p: &mut [f64]) {
-        Conserved::from(u)
-            .to_primitive(GAMMA_LAW_INDEX)
-            .unwrap()
-            .write_to_slice(p)
+    /// Returns the conserved fields, without guard zones.
+    pub fn conserved(&self) -> &Patch {
+        &self.conserved
     }
 
-    pub fn prim_to_cons(p: &[f64], u: &mut [f64]) {
-        Primitive::from(p)
-            .to_conserved(GAMMA_LAW_INDEX)
-            .write_to_slice(u)
+    /// Returns the flattening coefficients of the zones of this patch and
+    /// one guard zone, as of the last stage, with one field for each axis.
+    /// They are zero unless the scheme has [`Flattening`].
+    pub fn shock_sensor(&self) -> &Patch {
+        &self.shock_sensor
     }
 
-    fn boundary_value(_: (i64, i64), p: &mut [f64]) {
-        p[0] = 0.1;
-        p[1] = 0.0;
-        p[2] = 0.0;
-        p[3] = 0.125;
+    /// Returns the first zone in which the primitive variables could not be
+    /// recovered, if any. The solution is not valid after such an error.
+    pub fn error(&self) -> Option<&ZoneError> {
+        self.error.as_ref()
+    }
+
+    /// Returns this patch's contribution to each of its reductions, as of
+    /// the end of the last complete time step. It is empty before the first
+    /// step.
+    pub fn reduced(&self) -> &[f64] {
+        &self.reduced
+    }
+
+    /// Returns the hydrodynamics system being solved.
+    pub fn system(&self) -> &H {
+        &self.system
+    }
+
+    /// Returns the simulation time reached by this task.
+    pub fn time(&self) -> f64 {
+        self.time
+    }
+
+    /// Returns the images of the given index space (at this patch's level)
+    /// across the periodic axes of the domain, including the space itself.
+    fn periodic_images(&self, space: IndexSpace) -> Vec<IndexSpace> {
+        match self.boundaries.periodic() {
+            (false, false) => vec![space],
+            (pi, pj) => space
+                .periodic_images(&self.mesh.index_space())
+                .filter(|(_, (si, sj))| (*si == 0 || pi) && (*sj == 0 || pj))
+                .map(|(image, _)| image)
+                .collect(),
+        }
     }
 }
 
-impl Automaton for PatchUpdate {
+impl<H: HydroSystem> Automaton for PatchUpdate<H> {
     type Key = Rectangle<i64>;
     type Message = Patch;
     type Value = Self;
@@ -146,12 +848,28 @@ impl Automaton for PatchUpdate {
             .iter()
             .cloned()
             .map(|(rect, level)| {
-                let overlap = IndexSpace::from(rect.clone())
-                    .extend_all(NUM_GUARD * (1 << level))
-                    .coarsen_by(1 << self.level)
-                    .intersect(&self.index_space)
+                let target = IndexSpace::from(rect.clone())
+                    .extend_all(self.scheme.num_guard() * (1 << level))
+                    .coarsen_by(1 << self.level);
+                let overlap = self
+                    .periodic_images(target)
+                    .into_iter()
+                    .filter_map(|image| image.intersect(&self.index_space))
+                    .reduce(|a, b| {
+                        let (a0, a1) = (a.start(), a.end());
+                        let (b0, b1) = (b.start(), b.end());
+                        IndexSpace::new(
+                            a0.0.min(b0.0)..a1.0.max(b1.0),
+                            a0.1.min(b0.1)..a1.1.max(b1.1),
+                        )
+                    })
                     .expect("patches do not overlap");
-                (rect, self.extended_primitive.extract(overlap))
+                let mut pool = self.message_pool.lock().unwrap();
+                let pe = &self.extended_primitive;
+                let patch = Patch::from_pool(&mut pool, self.level, overlap, pe.num_fields(), |index, p| {
+                    p.copy_from_slice(pe.get_slice(index))
+                });
+                (rect, patch)
             })
             .collect()
     }
@@ -162,59 +880,232 @@ impl Automaton for PatchUpdate {
     }
 
     fn value(self) -> Self::Value {
+        let start = Instant::now();
         let Self {
+            boundaries,
             mut conserved,
+            mut conserved_n,
+            cost: _,
+            mut error,
             mut extended_primitive,
             mut flux_i,
             mut flux_j,
+            gravity,
             incoming_count,
             index_space,
             level,
+            max_signal_speed: _,
             mesh,
+            mut message_pool,
             mut neighbor_patches,
             outgoing_edges,
+            reactions,
+            mut reduced,
+            reductions,
+            scheme,
+            mut scratch,
+            mut shock_sensor,
+            sources,
+            stage,
+            system,
+            mut time,
             time_step_size,
             worker_group,
         } = self;
 
+        let geometry = mesh.geometry();
+        let topology = boundaries.topology(mesh.index_space());
+        let stage_time = time + scheme.time_integration.stage_time(stage) * time_step_size;
+
         meshing::extend_patch_mut(
             &mut extended_primitive,
             &index_space,
-            Self::boundary_value,
+            &topology,
+            |_, index, p| {
+                let position = geometry.cell_center(level, index);
+                boundaries.boundary_value(&topology, index, position, stage_time, p)
+            },
             &neighbor_patches,
         );
-        neighbor_patches.clear();
 
-        Self::compute_flux(&extended_primitive, Axis::I, &mut flux_i);
-        Self::compute_flux(&extended_primitive, Axis::J, &mut flux_j);
+        // Received patches are kept for reuse only up to the number sent
+        // each stage, so the pool does not grow if more are received.
+        let pool = message_pool.get_mut().unwrap();
+
+        for patch in neighbor_patches.drain(..) {
+            if pool.len() < outgoing_edges.len() {
+                pool.reclaim(patch)
+            }
+        }
+
+        if let (Some(flattening), true) = (scheme.flattening, scheme.reconstruction.num_guard() > 1) {
+            let margin = scheme.num_guard() - scheme.reconstruction.num_guard();
+            let space = index_space.extend_all(margin);
+            Self::detect_shocks(&extended_primitive, flattening, &space, &mut shock_sensor);
+        }
+        let scale = (1 << level) as f64;
+        let widths = (geometry.spacing.0 * scale, geometry.spacing.1 * scale);
+        let pe = &extended_primitive;
+        let sensor = &shock_sensor;
+
+        if let Some(faces) = &mut scratch.transverse {
+            let dt = time_step_size;
+            Self::compute_ctu_flux(pe, sensor, &system, scheme, widths, dt, faces, &mut flux_i, &mut flux_j);
+        } else {
+            let face_states = &mut scratch.face_states;
+            Self::compute_flux(pe, sensor, Axis::I, &system, scheme, widths, gravity, face_states, &mut flux_i);
+            Self::compute_flux(pe, sensor, Axis::J, &system, scheme, widths, gravity, face_states, &mut flux_j);
+        }
 
-        let (dx, dy) = mesh.cell_spacing();
+        if stage == 0 {
+            conserved.copy_into(&mut conserved_n);
+        }
+        let coordinate_system = mesh.coordinate_system();
         let dt = time_step_size;
+        let w = scheme.time_integration.weight(stage);
+        let nq = conserved.num_fields();
+        let s = &mut scratch.source;
 
+        let unsplit = match &sources {
+            Some((sources, SourceSplitting::Unsplit)) => Some(sources),
+            _ => None,
+        };
         let fim = flux_i.select(index_space.clone());
         let fip = flux_i.select(index_space.translate(1, Axis::I));
         let fjm = flux_j.select(index_space.clone());
         let fjp = flux_j.select(index_space.translate(1, Axis::J));
-        let u = conserved.iter_data_mut();
+        let p = extended_primitive.select(index_space.clone());
+        let u = conserved.iter_data_mut().zip(conserved_n.data().chunks_exact(nq));
+        let zones = index_space.iter().zip(p.zip(u));
+
+        for (fip, (fim, (fjp, (fjm, (index, (p, (u, un))))))) in
+            fip.zip(fim.zip(fjp.zip(fjm.zip(zones))))
+        {
+            let (i, j) = index;
+            let aim = coordinate_system.face_area(level, Axis::I, index);
+            let aip = coordinate_system.face_area(level, Axis::I, (i + 1, j));
+            let ajm = coordinate_system.face_area(level, Axis::J, index);
+            let ajp = coordinate_system.face_area(level, Axis::J, (i, j + 1));
+            let v = coordinate_system.cell_volume(level, index);
+            let position = coordinate_system.cell_center(level, index);
 
-        for (fip, (fim, (fjp, (fjm, u)))) in fip.zip(fim.zip(fjp.zip(fjm.zip(u)))) {
+            s.iter_mut().for_each(|s| *s = 0.0);
+            let area_difference = ((aip - aim) / v, (ajp - ajm) / v);
+            mesh.coordinates
+                .geometric_source(&system, p, position, area_difference, dt, s);
+
+            if let Some(sources) = unsplit {
+                sources.apply(position, p, dt, s)
+            }
+            if let Some(gravity) = &gravity {
+                gravity.apply(p, widths, [(aim, aip), (ajm, ajp)], v, dt, s)
+            }
             for (n, u) in u.iter_mut().enumerate() {
-                *u -= (fip[n] - fim[n]) * dt / dx + (fjp[n] - fjm[n]) * dt / dy;
+                let df = aip * fip[n] - aim * fim[n] + ajp * fjp[n] - ajm * fjm[n];
+                let du = df * dt / v - s[n];
+                *u = w * un[n] + (1.0 - w) * (*u - du);
+            }
+        }
+        let mut failed = Self::apply_floors(&system, &mut conserved, &extended_primitive, &mut scratch.zone);
+
+        if !failed.is_empty() && scheme.fallback == RecoveryFallback::DiffusiveFlux {
+            let faces = failed.iter().flat_map(|&((i, j), _)| {
+                [
+                    (Axis::I, (i, j)),
+                    (Axis::I, (i + 1, j)),
+                    (Axis::J, (i, j)),
+                    (Axis::J, (i, j + 1)),
+                ]
+            });
+            for (axis, face) in faces {
+                let flux = match axis {
+                    Axis::I => &mut flux_i,
+                    Axis::J => &mut flux_j,
+                };
+                Self::replace_flux(&system, &extended_primitive, axis, face, flux, |index, df| {
+                    let v = coordinate_system.cell_volume(level, index);
+                    let a = coordinate_system.face_area(level, axis, face);
+
+                    if index_space.contains(index) {
+                        for (u, df) in conserved.get_slice_mut(index).iter_mut().zip(df) {
+                            *u -= (1.0 - w) * a * df * dt / v
+                        }
+                    }
+                });
+            }
+            failed = Self::apply_floors(&system, &mut conserved, &extended_primitive, &mut scratch.zone);
+        }
+        if let (None, Some(&(index, e))) = (&error, failed.first()) {
+            error = Some(ZoneError { level, index, error: e });
+        }
+        Self::recover_primitive(&system, &conserved, &mut extended_primitive);
+
+        let next_stage = (stage + 1) % scheme.time_integration.num_stages();
+
+        let mut split: Vec<&dyn SourceTerms> = Vec::new();
+
+        if next_stage == 0 {
+            if let Some((sources, SourceSplitting::OperatorSplit)) = &sources {
+                split.push(sources.as_ref())
+            }
+            if let Some(reactions) = &reactions {
+                split.push(reactions.as_ref())
             }
         }
-        conserved.map_into(&mut extended_primitive, Self::cons_to_prim);
+        for source in split {
+            let p = extended_primitive.select(index_space.clone());
+
+            for (index, (p, u)) in index_space.iter().zip(p.zip(conserved.iter_data_mut())) {
+                s.iter_mut().for_each(|s| *s = 0.0);
+                source.apply(geometry.cell_center(level, index), p, dt, s);
+                u.iter_mut().zip(&*s).for_each(|(u, s)| *u += s);
+            }
+            let failed = Self::apply_floors(&system, &mut conserved, &extended_primitive, &mut scratch.zone);
+
+            if let (None, Some(&(index, e))) = (&error, failed.first()) {
+                error = Some(ZoneError { level, index, error: e });
+            }
+            Self::recover_primitive(&system, &conserved, &mut extended_primitive);
+        }
+        if next_stage == 0 {
+            time += dt;
+            reduced.clear();
+
+            for reduction in &reductions {
+                let value = reduction.reduce(&*coordinate_system, level, &index_space, &extended_primitive);
+                reduced.push(value)
+            }
+        }
+        let max_signal_speed = Self::signal_speed(&system, &extended_primitive, &index_space);
 
         Self {
+            boundaries,
             conserved,
+            conserved_n,
+            cost: start.elapsed().as_secs_f64(),
+            error,
             extended_primitive,
             flux_i,
             flux_j,
+            gravity,
             incoming_count,
             index_space,
             level,
+            max_signal_speed,
             mesh,
+            message_pool,
             neighbor_patches,
             outgoing_edges,
+            reactions,
+            reduced,
+            reductions,
+            scheme,
+            scratch,
+            shock_sensor,
+            sources,
+            stage: next_stage,
+            system,
+            time,
             time_step_size,
             worker_group,
         }
@@ -223,4 +1114,36 @@ impl Automaton for PatchUpdate {
     fn worker_hint(&self) -> Option<usize> {
         self.worker_group
     }
+
+    fn independent(&self) -> bool {
+        self.incoming_count == 0
+    }
+}
+
+impl<H: HydroSystem> Metadata for PatchUpdate<H> {
+    fn max_signal_speed(&self) -> f64 {
+        self.max_signal_speed
+    }
+
+    /// Returns the largest time step allowed on this patch by the given
+    /// Courant number, and by the diffusion terms if there are any. The
+    /// time step of a distributed simulation is the minimum of this over all
+    /// patches and ranks.
+    fn max_time_step(&self, cfl: f64) -> f64 {
+        let (dx, dy) = self.mesh.geometry().spacing;
+        let length = self.mesh.min_zone_length(self.level, &self.index_space);
+        let advective = cfl * length / self.max_signal_speed;
+        let diffusive = self
+            .extended_primitive
+            .select(self.index_space.clone())
+            .map(|p| self.system.max_diffusive_time_step(p, (dx, dy)))
+            .fold(f64::INFINITY, f64::min);
+        advective.min(diffusive)
+    }
+
+    /// Returns the wall time, in seconds, of the latest stage, or zero
+    /// before the first.
+    fn cost(&self) -> f64 {
+        self.cost
+    }
 }
@@ -0,0 +1,468 @@
+use gridiron::adjacency_list::AdjacencyList;
+use gridiron::automaton::{Automaton, Status};
+use gridiron::meshing::decompose_brick;
+use gridiron::rect_map::Brick;
+use crate::hydro::{euler3d, euler3d::Conserved, euler3d::Primitive, geometry::Direction};
+use super::reconstruction::Reconstruction;
+
+const NUM_FIELDS: usize = 5;
+const GAMMA_LAW_INDEX: f64 = 5.0 / 3.0;
+
+/// A simple rectilinear structured mesh in 3D
+#[derive(Clone)]
+pub struct Mesh {
+    pub volume: Brick<f64>,
+    pub size: (usize, usize, usize),
+}
+
+impl Mesh {
+    pub fn spacing(&self) -> (f64, f64, f64) {
+        let (x, y, z) = &self.volume;
+        (
+            (x.end - x.start) / self.size.0 as f64,
+            (y.end - y.start) / self.size.1 as f64,
+            (z.end - z.start) / self.size.2 as f64,
+        )
+    }
+
+    pub fn cell_center(&self, index: (i64, i64, i64)) -> (f64, f64, f64) {
+        let (dx, dy, dz) = self.spacing();
+        (
+            self.volume.0.start + (index.0 as f64 + 0.5) * dx,
+            self.volume.1.start + (index.1 as f64 + 0.5) * dy,
+            self.volume.2.start + (index.2 as f64 + 0.5) * dz,
+        )
+    }
+
+    pub fn total_zones(&self) -> usize {
+        self.size.0 * self.size.1 * self.size.2
+    }
+
+    pub fn index_brick(&self) -> Brick<i64> {
+        (
+            0..self.size.0 as i64,
+            0..self.size.1 as i64,
+            0..self.size.2 as i64,
+        )
+    }
+
+    /// Decomposes the mesh into the given number of bricks.
+    pub fn decompose(&self, count: usize) -> Vec<Brick<i64>> {
+        decompose_brick(self.index_brick(), count)
+    }
+}
+
+/// A 3D array of zones, each holding a fixed number of fields. This is the 3D
+/// analog of a single-level `Patch`.
+#[derive(Clone, serde::Serialize, serde::Deserialize)]
+pub struct Block {
+    brick: Brick<i64>,
+    num_fields: usize,
+    data: Vec<f64>,
+}
+
+impl Block {
+    pub fn zeros(brick: Brick<i64>, num_fields: usize) -> Self {
+        let data = vec![0.0; brick_len(&brick) * num_fields];
+        Self {
+            brick,
+            num_fields,
+            data,
+        }
+    }
+
+    pub fn from_vector_function<F>(brick: Brick<i64>, f: F) -> Self
+    where
+        F: Fn((i64, i64, i64)) -> [f64; NUM_FIELDS],
+    {
+        let mut block = Self::zeros(brick, NUM_FIELDS);
+
+        for index in brick_indexes(&block.brick.clone()) {
+            block.get_slice_mut(index).clone_from_slice(&f(index))
+        }
+        block
+    }
+
+    pub fn brick(&self) -> &Brick<i64> {
+        &self.brick
+    }
+
+    pub fn contains(&self, index: (i64, i64, i64)) -> bool {
+        brick_contains(&self.brick, index)
+    }
+
+    pub fn get_slice(&self, index: (i64, i64, i64)) -> &[f64] {
+        let s = self.offset(index);
+        &self.data[s..s + self.num_fields]
+    }
+
+    pub fn get_slice_mut(&mut self, index: (i64, i64, i64)) -> &mut [f64] {
+        let s = self.offset(index);
+        &mut self.data[s..s + self.num_fields]
+    }
+
+    /// Extracts the part of this block covered by the given brick. This
+    /// method panics if the brick is not contained in this block.
+    pub fn extract(&self, brick: Brick<i64>) -> Self {
+        let mut result = Self::zeros(brick, self.num_fields);
+
+        for index in brick_indexes(&result.brick.clone()) {
+            result.get_slice_mut(index).clone_from_slice(self.get_slice(index))
+        }
+        result
+    }
+
+    fn offset(&self, index: (i64, i64, i64)) -> usize {
+        assert!(self.contains(index), "index is out of bounds");
+        let (i, j, k) = &self.brick;
+        let n = (j.end - j.start) as usize;
+        let l = (k.end - k.start) as usize;
+        let a = (index.0 - i.start) as usize;
+        let b = (index.1 - j.start) as usize;
+        let c = (index.2 - k.start) as usize;
+        ((a * n + b) * l + c) * self.num_fields
+    }
+}
+
+fn brick_len(brick: &Brick<i64>) -> usize {
+    let (i, j, k) = brick;
+    ((i.end - i.start) * (j.end - j.start) * (k.end - k.start)).max(0) as usize
+}
+
+fn brick_contains(brick: &Brick<i64>, index: (i64, i64, i64)) -> bool {
+    brick.0.contains(&index.0) && brick.1.contains(&index.1) && brick.2.contains(&index.2)
+}
+
+fn brick_indexes(brick: &Brick<i64>) -> impl Iterator<Item = (i64, i64, i64)> {
+    let (i, j, k) = brick.clone();
+    i.flat_map(move |i| {
+        let k = k.clone();
+        j.clone().flat_map(move |j| k.clone().map(move |k| (i, j, k)))
+    })
+}
+
+fn extend_brick(brick: &Brick<i64>, n: i64) -> Brick<i64> {
+    let (i, j, k) = brick;
+    (
+        i.start - n..i.end + n,
+        j.start - n..j.end + n,
+        k.start - n..k.end + n,
+    )
+}
+
+fn intersect_brick(a: &Brick<i64>, b: &Brick<i64>) -> Brick<i64> {
+    (
+        a.0.start.max(b.0.start)..a.0.end.min(b.0.end),
+        a.1.start.max(b.1.start)..a.1.end.min(b.1.end),
+        a.2.start.max(b.2.start)..a.2.end.min(b.2.end),
+    )
+}
+
+fn unit(direction: Direction) -> (i64, i64, i64) {
+    match direction {
+        Direction::I => (1, 0, 0),
+        Direction::J => (0, 1, 0),
+        Direction::K => (0, 0, 1),
+    }
+}
+
+/// A basic update scheme, hard-coded for the 3D euler equations. Fluxes along
+/// the x, y, and z axes are all computed from the state at the start of the
+/// step, and applied together in a single unsplit update. It is first order
+/// unless a higher order reconstruction is selected; the brick adjacency list
+/// must be built with [`Reconstruction::num_guard`] guard zones.
+pub struct PatchUpdate {
+    conserved: Block,
+    extended_primitive: Block,
+    incoming_count: usize,
+    brick: Brick<i64>,
+    mesh: Mesh,
+    neighbor_blocks: Vec<Block>,
+    outgoing_edges: Vec<Brick<i64>>,
+    reconstruction: Reconstruction,
+    time_step_size: f64,
+    worker_group: Option<usize>,
+}
+
+impl PatchUpdate {
+    pub fn new(
+        primitive: Block,
+        mesh: Mesh,
+        reconstruction: Reconstruction,
+        time_step_size: f64,
+        worker_group: Option<usize>,
+        edge_list: &AdjacencyList<Brick<i64>>,
+    ) -> Self {
+        let brick = primitive.brick().clone();
+        let mut conserved = Block::zeros(brick.clone(), NUM_FIELDS);
+        let mut extended_primitive = Block::zeros(
+            extend_brick(&brick, reconstruction.num_guard()),
+            NUM_FIELDS,
+        );
+
+        for index in brick_indexes(&brick) {
+            let p = primitive.get_slice(index);
+            Self::prim_to_cons(p, conserved.get_slice_mut(index));
+            extended_primitive.get_slice_mut(index).clone_from_slice(p);
+        }
+        let incoming_count = edge_list.incoming_edges(&brick).count();
+        let outgoing_edges = edge_list.outgoing_edges(&brick).cloned().collect();
+
+        Self {
+            conserved,
+            extended_primitive,
+            incoming_count,
+            brick,
+            mesh,
+            neighbor_blocks: Vec::new(),
+            outgoing_edges,
+            reconstruction,
+            time_step_size,
+            worker_group,
+        }
+    }
+
+    pub fn primitive(&self) -> Block {
+        self.extended_primitive.extract(self.brick.clone())
+    }
+
+    /// Returns the largest stable time step for this block, given the Courant
+    /// number. Since the update is unsplit, the signal crossing times on the
+    /// three axes are added.
+    pub fn max_time_step(&self, cfl: f64) -> f64 {
+        let (dx, dy, dz) = self.mesh.spacing();
+        let a = brick_indexes(&self.brick)
+            .map(|index| Primitive::from(self.extended_primitive.get_slice(index)))
+            .map(|p| p.max_signal_speed(GAMMA_LAW_INDEX))
+            .fold(0.0, f64::max);
+        cfl / (a / dx + a / dy + a / dz)
+    }
+
+    pub fn set_time_step_size(&mut self, time_step_size: f64) {
+        self.time_step_size = time_step_size
+    }
+
+    /// Returns the volume integral over this block of each conserved
+    /// quantity.
+    pub fn conserved_totals(&self) -> Vec<f64> {
+        let (dx, dy, dz) = self.mesh.spacing();
+        let mut totals = vec![0.0; NUM_FIELDS];
+
+        for index in brick_indexes(&self.brick) {
+            for (total, u) in totals.iter_mut().zip(self.conserved.get_slice(index)) {
+                *total += u * dx * dy * dz
+            }
+        }
+        totals
+    }
+
+    pub fn cons_to_prim(u: &[f64], p: &mut [f64]) {
+        Conserved::from(u)
+            .to_primitive(GAMMA_LAW_INDEX)
+            .unwrap()
+            .write_to_slice(p)
+    }
+
+    pub fn prim_to_cons(p: &[f64], u: &mut [f64]) {
+        Primitive::from(p)
+            .to_conserved(GAMMA_LAW_INDEX)
+            .write_to_slice(u)
+    }
+
+    fn boundary_value(_: (i64, i64, i64), p: &mut [f64]) {
+        p[0] = 0.1;
+        p[1] = 0.0;
+        p[2] = 0.0;
+        p[3] = 0.0;
+        p[4] = 0.125;
+    }
+
+    fn extend_primitive(&mut self) {
+        for index in brick_indexes(self.extended_primitive.brick()) {
+            if brick_contains(&self.brick, index) {
+                continue;
+            }
+            let slice = self.extended_primitive.get_slice_mut(index);
+
+            match self.neighbor_blocks.iter().find(|b| b.contains(index)) {
+                Some(neighbor) => slice.clone_from_slice(neighbor.get_slice(index)),
+                None => Self::boundary_value(index, slice),
+            }
+        }
+    }
+
+    /// Computes the fluxes through the faces of this block normal to the
+    /// given direction. The flux at index `n` is through the lower face of
+    /// zone `n`.
+    fn compute_flux(&self, direction: Direction) -> Block {
+        let (di, dj, dk) = unit(direction);
+        let (i, j, k) = &self.brick;
+        let faces = (i.start..i.end + di, j.start..j.end + dj, k.start..k.end + dk);
+        let mut flux = Block::zeros(faces, NUM_FIELDS);
+        let g = self.reconstruction.num_guard();
+        let mut zones = Vec::with_capacity(2 * g as usize);
+        let mut pl = [0.0; NUM_FIELDS];
+        let mut pr = [0.0; NUM_FIELDS];
+
+        for index in brick_indexes(&flux.brick.clone()) {
+            zones.clear();
+            zones.extend((-g..g).map(|n| {
+                self.extended_primitive
+                    .get_slice((index.0 + n * di, index.1 + n * dj, index.2 + n * dk))
+            }));
+            self.reconstruction.face_states(&zones, &mut pl, &mut pr);
+            euler3d::riemann_hlle(pl[..].into(), pr[..].into(), direction, GAMMA_LAW_INDEX)
+                .write_to_slice(flux.get_slice_mut(index))
+        }
+        flux
+    }
+}
+
+impl Automaton for PatchUpdate {
+    type Key = Brick<i64>;
+    type Message = Block;
+    type Value = Self;
+
+    fn key(&self) -> Self::Key {
+        self.brick.clone()
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        self.outgoing_edges
+            .iter()
+            .cloned()
+            .map(|brick| {
+                let overlap = intersect_brick(
+                    &extend_brick(&brick, self.reconstruction.num_guard()),
+                    &self.brick,
+                );
+                (brick, self.extended_primitive.extract(overlap))
+            })
+            .collect()
+    }
+
+    fn receive(&mut self, block: Self::Message) -> Status {
+        self.neighbor_blocks.push(block);
+        Status::eligible_if(self.neighbor_blocks.len() == self.incoming_count)
+    }
+
+    fn value(mut self) -> Self::Value {
+        self.extend_primitive();
+        self.neighbor_blocks.clear();
+
+        let (dx, dy, dz) = self.mesh.spacing();
+        let dt = self.time_step_size;
+        let directions = [
+            (Direction::I, dt / dx),
+            (Direction::J, dt / dy),
+            (Direction::K, dt / dz),
+        ];
+
+        for &(direction, factor) in &directions {
+            let flux = self.compute_flux(direction);
+            let (di, dj, dk) = unit(direction);
+
+            for index in brick_indexes(&self.brick) {
+                let fm = flux.get_slice(index);
+                let fp = flux.get_slice((index.0 + di, index.1 + dj, index.2 + dk));
+
+                for (n, u) in self.conserved.get_slice_mut(index).iter_mut().enumerate() {
+                    *u -= (fp[n] - fm[n]) * factor;
+                }
+            }
+        }
+        for index in brick_indexes(&self.brick) {
+            Self::cons_to_prim(
+                self.conserved.get_slice(index),
+                self.extended_primitive.get_slice_mut(index),
+            )
+        }
+        self
+    }
+
+    fn worker_hint(&self) -> Option<usize> {
+        self.worker_group
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use gridiron::automaton;
+    use gridiron::meshing::GraphTopology;
+    use gridiron::rect_map::BrickMap;
+
+    const AMBIENT: [f64; NUM_FIELDS] = [0.1, 0.0, 0.0, 0.0, 0.125];
+
+    fn evolve<F>(reconstruction: Reconstruction, num_steps: usize, initial: F) -> Vec<PatchUpdate>
+    where
+        F: Fn((f64, f64, f64)) -> [f64; NUM_FIELDS],
+    {
+        let mesh = Mesh {
+            volume: (-1.0..1.0, -1.0..1.0, -1.0..1.0),
+            size: (16, 16, 16),
+        };
+        let bricks = mesh.decompose(8);
+        let map: BrickMap<i64, ()> = bricks.iter().map(|b| (b.clone(), ())).collect();
+        let edge_list = map.adjacency_list(reconstruction.num_guard());
+        let mut task_list: Vec<_> = bricks
+            .into_iter()
+            .map(|brick| {
+                let primitive = Block::from_vector_function(brick, |i| initial(mesh.cell_center(i)));
+                PatchUpdate::new(primitive, mesh.clone(), reconstruction, 0.0, None, &edge_list)
+            })
+            .collect();
+
+        for _ in 0..num_steps {
+            let dt = task_list
+                .iter()
+                .map(|task| task.max_time_step(0.4))
+                .fold(f64::INFINITY, f64::min);
+            task_list.iter_mut().for_each(|task| task.set_time_step_size(dt));
+            task_list = automaton::execute(task_list).collect();
+        }
+        task_list
+    }
+
+    fn totals(task_list: &[PatchUpdate]) -> Vec<f64> {
+        task_list
+            .iter()
+            .map(PatchUpdate::conserved_totals)
+            .fold(vec![0.0; NUM_FIELDS], |a, b| a.iter().zip(b).map(|(a, b)| a + b).collect())
+    }
+
+    #[test]
+    fn uniform_state_stays_uniform() {
+        for &reconstruction in &[Reconstruction::Pcm, Reconstruction::Plm, Reconstruction::Weno5] {
+            for task in evolve(reconstruction, 3, |_| AMBIENT) {
+                let primitive = task.primitive();
+
+                for index in brick_indexes(primitive.brick()) {
+                    for (p, q) in primitive.get_slice(index).iter().zip(&AMBIENT) {
+                        assert!((p - q).abs() < 1e-12);
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn interior_blast_conserves_mass_momentum_and_energy() {
+        let blast = |(x, y, z): (f64, f64, f64)| {
+            if x * x + y * y + z * z < 0.0625 {
+                [1.0, 0.0, 0.0, 0.0, 1.0]
+            } else {
+                AMBIENT
+            }
+        };
+        let initial = totals(&evolve(Reconstruction::Pcm, 0, blast));
+        let task_list = evolve(Reconstruction::Pcm, 3, blast);
+
+        for (u, v) in totals(&task_list).iter().zip(&initial) {
+            assert!((u - v).abs() < 1e-10);
+        }
+        assert!(task_list
+            .iter()
+            .any(|task| brick_indexes(&task.brick).any(|i| task.primitive().get_slice(i)[1] != 0.0)));
+    }
+}
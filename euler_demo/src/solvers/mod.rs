@@ -1 +1,16 @@
+pub mod advect2d;
+pub mod advect2d_subcycled;
+pub mod boundary;
+pub mod burgers2d;
+pub mod cooling;
+pub mod driving;
+pub mod euler1d;
 pub mod euler2d_pcm;
+pub mod euler3d_pcm;
+pub mod point_mass;
+pub mod reactions;
+pub mod reconstruction;
+pub mod source_terms;
+pub mod time_integration;
+pub mod time_series;
+pub mod srhd2d_pcm;
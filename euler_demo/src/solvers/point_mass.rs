@@ -0,0 +1,216 @@
+use std::f64::consts::PI;
+use super::source_terms::SourceTerms;
+
+/// The number of Newton iterations used to solve Kepler's equation. This is
+/// enough to reach round-off for eccentricities up to about 0.9.
+const KEPLER_ITERATIONS: usize = 12;
+
+/// How a point mass removes gas from its sink region.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SinkModel {
+    /// Mass and momentum are removed at the same rate, so the velocity of
+    /// the remaining gas is unchanged.
+    AccelerationFree,
+
+    /// Gas is removed with the velocity of the point mass plus its radial
+    /// velocity relative to the mass, so that the sink exerts no torque on
+    /// the gas about the point mass (Dempsey, Munoz & Lithwick 2020).
+    TorqueFree,
+}
+
+impl std::str::FromStr for SinkModel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "acceleration-free" => Ok(Self::AccelerationFree),
+            "torque-free" => Ok(Self::TorqueFree),
+            _ => Err(format!("unknown sink model '{}' (acceleration-free|torque-free)", s)),
+        }
+    }
+}
+
+/// A gravitating point mass, with units where the gravitational constant is
+/// one. Its potential is Plummer-softened, `-M / sqrt(r^2 + eps^2)`, and it
+/// removes gas at the rate `sink_rate * exp(-(r / sink_radius)^4)` per unit
+/// time, which is negligible beyond a couple of sink radii. A sink rate of
+/// zero disables the sink.
+#[derive(Clone, Copy, Debug)]
+pub struct PointMass {
+    pub mass: f64,
+    pub position: (f64, f64),
+    pub velocity: (f64, f64),
+    pub softening_length: f64,
+    pub sink_radius: f64,
+    pub sink_rate: f64,
+    pub sink_model: SinkModel,
+}
+
+impl PointMass {
+    /// Returns the gravitational acceleration at the given position.
+    pub fn acceleration(&self, position: (f64, f64)) -> (f64, f64) {
+        let (dx, dy) = (position.0 - self.position.0, position.1 - self.position.1);
+        let r2 = dx * dx + dy * dy + self.softening_length * self.softening_length;
+        let f = -self.mass / (r2 * r2.sqrt());
+        (f * dx, f * dy)
+    }
+
+    /// Returns the rate, per unit time, at which gas is removed at the given
+    /// position.
+    pub fn sink_rate_at(&self, position: (f64, f64)) -> f64 {
+        if self.sink_rate == 0.0 {
+            return 0.0;
+        }
+        let (dx, dy) = (position.0 - self.position.0, position.1 - self.position.1);
+        let r = dx.hypot(dy) / self.sink_radius;
+        self.sink_rate * (-r.powi(4)).exp()
+    }
+
+    /// Returns the velocity with which gas moving at `v`, at the given
+    /// position, is removed by the sink.
+    pub fn removal_velocity(&self, position: (f64, f64), v: (f64, f64)) -> (f64, f64) {
+        match self.sink_model {
+            SinkModel::AccelerationFree => v,
+            SinkModel::TorqueFree => {
+                let (dx, dy) = (position.0 - self.position.0, position.1 - self.position.1);
+                let r = dx.hypot(dy);
+
+                if r == 0.0 {
+                    return self.velocity;
+                }
+                let (nx, ny) = (dx / r, dy / r);
+                let vr = (v.0 - self.velocity.0) * nx + (v.1 - self.velocity.1) * ny;
+                (self.velocity.0 + vr * nx, self.velocity.1 + vr * ny)
+            }
+        }
+    }
+}
+
+/// The gravity of, and accretion onto, a set of point masses, for an ideal
+/// gas with the given adiabatic index. The gas removed by a sink keeps its
+/// specific internal energy and mass fractions, and the fraction removed in
+/// a time `dt` is `1 - exp(-rate * dt)`, so the density stays positive for
+/// any time step. The point masses do not move during a time step; a driver
+/// moving them should hand the tasks a fresh copy before each step, as
+/// [`BinaryGravity::at_time`] does. The positions are assumed to be
+/// Cartesian coordinates.
+#[derive(Clone, Debug)]
+pub struct PointMassGravity {
+    pub masses: Vec<PointMass>,
+    pub gamma_law_index: f64,
+}
+
+impl SourceTerms for PointMassGravity {
+    fn apply(&self, position: (f64, f64), p: &[f64], dt: f64, du: &mut [f64]) {
+        let (d, vx, vy, pg) = (p[0], p[1], p[2], p[3]);
+
+        for mass in &self.masses {
+            let (ax, ay) = mass.acceleration(position);
+            du[1] += d * ax * dt;
+            du[2] += d * ay * dt;
+            du[3] += d * (vx * ax + vy * ay) * dt;
+
+            let rate = mass.sink_rate_at(position);
+
+            if rate > 0.0 {
+                let removed = d * (1.0 - (-rate * dt).exp());
+                let (wx, wy) = mass.removal_velocity(position, (vx, vy));
+                let e = pg / (self.gamma_law_index - 1.0) / d;
+                du[0] -= removed;
+                du[1] -= removed * wx;
+                du[2] -= removed * wy;
+                du[3] -= removed * (e + vx * wx + vy * wy - 0.5 * (vx * vx + vy * vy));
+
+                for (du, x) in du[4..].iter_mut().zip(&p[4..]) {
+                    *du -= removed * x
+                }
+            }
+        }
+    }
+}
+
+/// A Keplerian binary orbit, with the center of mass at the origin. The
+/// mass ratio is that of the secondary to the primary. At time zero the
+/// binary is at periapsis, with the secondary on the positive `x` axis, and
+/// the orbit is counter-clockwise.
+#[derive(Clone, Copy, Debug)]
+pub struct BinaryOrbit {
+    pub total_mass: f64,
+    pub mass_ratio: f64,
+    pub separation: f64,
+    pub eccentricity: f64,
+}
+
+impl BinaryOrbit {
+    /// Returns the orbital period.
+    pub fn period(&self) -> f64 {
+        2.0 * PI * (self.separation.powi(3) / self.total_mass).sqrt()
+    }
+
+    /// Returns the mass, position, and velocity of the primary and of the
+    /// secondary at the given time.
+    #[allow(clippy::type_complexity)]
+    pub fn components(&self, time: f64) -> [(f64, (f64, f64), (f64, f64)); 2] {
+        let (a, e) = (self.separation, self.eccentricity);
+        let n = 2.0 * PI / self.period();
+        let mean_anomaly = n * time;
+        let mut k = if e > 0.8 { PI } else { mean_anomaly };
+
+        for _ in 0..KEPLER_ITERATIONS {
+            k -= (k - e * k.sin() - mean_anomaly) / (1.0 - e * k.cos());
+        }
+        let kdot = n / (1.0 - e * k.cos());
+        let b = (1.0 - e * e).sqrt();
+        let (x, y) = (a * (k.cos() - e), a * b * k.sin());
+        let (vx, vy) = (-a * k.sin() * kdot, a * b * k.cos() * kdot);
+
+        let m1 = self.total_mass / (1.0 + self.mass_ratio);
+        let m2 = self.total_mass - m1;
+        let (f1, f2) = (-m2 / self.total_mass, m1 / self.total_mass);
+        [
+            (m1, (f1 * x, f1 * y), (f1 * vx, f1 * vy)),
+            (m2, (f2 * x, f2 * y), (f2 * vx, f2 * vy)),
+        ]
+    }
+}
+
+/// A binary of point masses on a prescribed [`BinaryOrbit`], all of whose
+/// components share the softening length and sink parameters. Components
+/// of zero mass are left out, so a mass ratio of zero gives a single point
+/// mass at the origin.
+#[derive(Clone, Copy, Debug)]
+pub struct BinaryGravity {
+    pub orbit: BinaryOrbit,
+    pub softening_length: f64,
+    pub sink_radius: f64,
+    pub sink_rate: f64,
+    pub sink_model: SinkModel,
+    pub gamma_law_index: f64,
+}
+
+impl BinaryGravity {
+    /// Returns the point masses at the given time. To second order in the
+    /// time step, a driver should use the time at the middle of each step.
+    pub fn at_time(&self, time: f64) -> PointMassGravity {
+        let masses = self
+            .orbit
+            .components(time)
+            .iter()
+            .filter(|(mass, _, _)| *mass > 0.0)
+            .map(|&(mass, position, velocity)| PointMass {
+                mass,
+                position,
+                velocity,
+                softening_length: self.softening_length,
+                sink_radius: self.sink_radius,
+                sink_rate: self.sink_rate,
+                sink_model: self.sink_model,
+            })
+            .collect();
+
+        PointMassGravity {
+            masses,
+            gamma_law_index: self.gamma_law_index,
+        }
+    }
+}
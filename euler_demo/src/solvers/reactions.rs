@@ -0,0 +1,117 @@
+use std::sync::Arc;
+use super::source_terms::SourceTerms;
+
+/// The field of the first mass fraction, after the four fields of the 2D
+/// Euler equations.
+const FIRST_SPECIES: usize = 4;
+
+/// The largest change of any mass fraction, or relative change of the
+/// temperature, in one substep.
+const MAX_CHANGE: f64 = 0.05;
+
+/// The number of substeps after which the rest of the time step is taken in
+/// a single substep.
+const MAX_SUBSTEPS: usize = 1000;
+
+/// The right-hand side of a reaction network, supplied by the user.
+pub trait ReactionNetwork: Send + Sync {
+    /// Writes the rates of change of the mass fractions `x` to `dxdt`, at
+    /// the given mass density and temperature `p / rho`, and returns the
+    /// rate at which energy is released per unit mass (negative if the
+    /// reactions absorb energy). The rates should sum to zero.
+    fn rates(&self, density: f64, temperature: f64, x: &[f64], dxdt: &mut [f64]) -> f64;
+}
+
+/// Any closure receiving the density, temperature, mass fractions, and the
+/// rates to write, and returning the energy release rate, can be used as a
+/// reaction network.
+impl<F> ReactionNetwork for F
+where
+    F: Fn(f64, f64, &[f64], &mut [f64]) -> f64 + Send + Sync,
+{
+    fn rates(&self, density: f64, temperature: f64, x: &[f64], dxdt: &mut [f64]) -> f64 {
+        self(density, temperature, x, dxdt)
+    }
+}
+
+/// Clips the mass fractions to `[0, 1]`, and scales them to sum to one.
+pub fn renormalize(x: &mut [f64]) {
+    x.iter_mut().for_each(|x| *x = x.clamp(0.0, 1.0));
+    let sum: f64 = x.iter().sum();
+
+    if sum > 0.0 {
+        x.iter_mut().for_each(|x| *x /= sum)
+    }
+}
+
+/// A reaction network, integrated in each zone of an ideal gas with the
+/// mass fractions of [`crate::hydro::species::Multispecies`] over a time
+/// step, at fixed density. Reactions are usually much faster than the flow,
+/// so the integration takes second order (midpoint) substeps, each limited
+/// to a small change of the mass fractions and temperature, and the mass
+/// fractions are renormalized after each substep. The energy released heats
+/// the gas. This is meant to be applied operator-split, after the hydro
+/// update of each time step (see
+/// [`super::euler2d_pcm::PatchUpdate::with_reactions`]); as
+/// [`SourceTerms`] it adds the change of the total energy and of the
+/// partial densities.
+#[derive(Clone)]
+pub struct Reactions {
+    pub network: Arc<dyn ReactionNetwork>,
+    pub gamma_law_index: f64,
+}
+
+impl Reactions {
+    /// Advances the mass fractions `x` at the given density and temperature
+    /// over a time `dt`, and returns the energy released per unit mass.
+    pub fn integrate(&self, density: f64, temperature: f64, x: &mut [f64], dt: f64) -> f64 {
+        let gm1 = self.gamma_law_index - 1.0;
+        let mut k1 = vec![0.0; x.len()];
+        let mut k2 = vec![0.0; x.len()];
+        let mut xm = vec![0.0; x.len()];
+        let mut temperature = temperature;
+        let mut released = 0.0;
+        let mut t = 0.0;
+
+        for n in 0..MAX_SUBSTEPS {
+            if t >= dt {
+                break;
+            }
+            let q1 = self.network.rates(density, temperature, x, &mut k1);
+            let fastest = k1.iter().fold(gm1 * q1.abs() / temperature, |a, k| a.max(k.abs()));
+
+            let h = if n + 1 == MAX_SUBSTEPS || fastest == 0.0 {
+                dt - t
+            } else {
+                (dt - t).min(MAX_CHANGE / fastest)
+            };
+            for ((xm, x), k) in xm.iter_mut().zip(&*x).zip(&k1) {
+                *xm = x + 0.5 * h * k
+            }
+            let tm = temperature + 0.5 * h * gm1 * q1;
+            let q2 = self.network.rates(density, tm, &xm, &mut k2);
+
+            for (x, k) in x.iter_mut().zip(&k2) {
+                *x += h * k
+            }
+            renormalize(x);
+            temperature += h * gm1 * q2;
+            released += h * q2;
+            t += h;
+        }
+        released
+    }
+}
+
+impl SourceTerms for Reactions {
+    fn apply(&self, _: (f64, f64), p: &[f64], dt: f64, du: &mut [f64]) {
+        let d = p[0];
+        let mut x = p[FIRST_SPECIES..].to_vec();
+        let q = self.integrate(d, p[3] / d, &mut x, dt);
+        du[3] += d * q;
+
+        for (du, (x1, x0)) in du[FIRST_SPECIES..].iter_mut().zip(x.iter().zip(&p[FIRST_SPECIES..])) {
+            *du += d * (x1 - x0)
+        }
+    }
+}
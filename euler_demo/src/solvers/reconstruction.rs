@@ -0,0 +1,291 @@
+use gridiron::index_space::Stencil;
+
+/// The field of the gas pressure in the primitive states. Any fields after
+/// it, such as mass fractions, are reconstructed like the velocities.
+const PRESSURE: usize = 3;
+
+/// The scheme used by the solvers to reconstruct primitive states at zone
+/// faces.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Reconstruction {
+    /// Piecewise constant; first order, with one guard zone.
+    Pcm,
+
+    /// Piecewise linear, with slopes limited by the generalized minmod
+    /// function; second order, with two guard zones.
+    Plm,
+
+    /// Fifth-order weighted essentially non-oscillatory reconstruction of
+    /// Jiang & Shu (1996), with three guard zones.
+    Weno5,
+}
+
+impl Reconstruction {
+    /// Returns the number of zones read on either side of a face.
+    pub fn num_guard(self) -> i64 {
+        match self {
+            Self::Pcm => 1,
+            Self::Plm => 2,
+            Self::Weno5 => 3,
+        }
+    }
+
+    /// Returns the guard zones read by a solver using this reconstruction.
+    /// The faces are reconstructed along their normal, so no corner guard
+    /// zones are needed.
+    pub fn stencil(self) -> Stencil {
+        Stencil::star(self.num_guard())
+    }
+
+    /// Reconstructs the primitive states on the left and right of a face.
+    /// The zones are given in order along the face normal, `num_guard` on
+    /// either side of the face. Reconstructed states with non-positive
+    /// density (the first field) or pressure (the fourth) are replaced
+    /// with the adjacent zone.
+    pub fn face_states(self, zones: &[&[f64]], pl: &mut [f64], pr: &mut [f64]) {
+        let g = self.num_guard() as usize;
+
+        self.reconstruct(zones, pl, pr);
+
+        if !is_physical(pl) {
+            pl.clone_from_slice(zones[g - 1])
+        }
+        if !is_physical(pr) {
+            pr.clone_from_slice(zones[g])
+        }
+    }
+
+    /// Reconstructs the face states of each field, without checking them.
+    fn reconstruct(self, zones: &[&[f64]], pl: &mut [f64], pr: &mut [f64]) {
+        match self {
+            Self::Pcm => {
+                pl.clone_from_slice(zones[0]);
+                pr.clone_from_slice(zones[1]);
+            }
+            Self::Plm => {
+                for q in 0..pl.len() {
+                    let v = |n: usize| zones[n][q];
+                    pl[q] = v(1) + 0.5 * plm_slope(v(0), v(1), v(2));
+                    pr[q] = v(2) - 0.5 * plm_slope(v(1), v(2), v(3));
+                }
+            }
+            Self::Weno5 => {
+                for q in 0..pl.len() {
+                    let v = |n: usize| zones[n][q];
+                    pl[q] = weno5([v(0), v(1), v(2), v(3), v(4)]);
+                    pr[q] = weno5([v(5), v(4), v(3), v(2), v(1)]);
+                }
+            }
+        }
+    }
+
+    /// Reconstructs the face states like [`Reconstruction::face_states`],
+    /// and then flattens them toward the adjacent zones: the coefficients
+    /// `(fl, fr)` of the zones on the left and right are the weights of the
+    /// zone values, so a coefficient of one gives a piecewise constant
+    /// state on that side.
+    pub fn flattened_face_states(
+        self,
+        zones: &[&[f64]],
+        flattening: (f64, f64),
+        pl: &mut [f64],
+        pr: &mut [f64],
+    ) {
+        let g = self.num_guard() as usize;
+        let (fl, fr) = flattening;
+
+        self.face_states(zones, pl, pr);
+
+        for q in 0..pl.len() {
+            pl[q] = (1.0 - fl) * pl[q] + fl * zones[g - 1][q];
+            pr[q] = (1.0 - fr) * pr[q] + fr * zones[g][q];
+        }
+    }
+
+    /// Reconstructs the face states like
+    /// [`Reconstruction::flattened_face_states`], but with the density and
+    /// pressure (the first and fourth fields) reconstructed as deviations from
+    /// a hydrostatic equilibrium. The state on each side of the face is
+    /// reconstructed from the deviations of the zones from the equilibrium
+    /// through the zone on that side, and the equilibrium extrapolated to
+    /// the face is then added back. `equilibrium(zone, distance)` returns
+    /// the density and pressure of the equilibrium through the given zone
+    /// state, at a distance along the face normal measured in zones; it must
+    /// return the zone's own density and pressure at a distance of zero. If
+    /// the zones are in equilibrium, the face states are the extrapolated
+    /// equilibrium to round-off, whatever the reconstruction. Flattening
+    /// weights the deviations of the adjacent zone, and states with
+    /// non-positive density or pressure are replaced with the extrapolated
+    /// equilibrium of the adjacent zone.
+    pub fn balanced_face_states<E>(
+        self,
+        zones: &[&[f64]],
+        flattening: (f64, f64),
+        equilibrium: E,
+        pl: &mut [f64],
+        pr: &mut [f64],
+    ) where
+        E: Fn(&[f64], f64) -> (f64, f64),
+    {
+        let g = self.num_guard() as usize;
+        self.balanced_state(zones, g - 1, flattening.0, &equilibrium, pl);
+        self.balanced_state(zones, g, flattening.1, &equilibrium, pr);
+    }
+
+    /// Reconstructs the state on the side of a face of the given adjacent
+    /// zone, for [`Reconstruction::balanced_face_states`].
+    fn balanced_state<E>(
+        self,
+        zones: &[&[f64]],
+        adjacent: usize,
+        flattening: f64,
+        equilibrium: &E,
+        state: &mut [f64],
+    ) where
+        E: Fn(&[f64], f64) -> (f64, f64),
+    {
+        let g = self.num_guard() as usize;
+        let nq = state.len();
+        let zone = zones[adjacent];
+        let mut deviations = Vec::with_capacity(zones.len() * nq);
+
+        for (n, z) in zones.iter().enumerate() {
+            let (d, p) = equilibrium(zone, n as f64 - adjacent as f64);
+            deviations.extend_from_slice(z);
+            deviations[n * nq] -= d;
+            deviations[n * nq + PRESSURE] -= p;
+        }
+        let deviations: Vec<_> = deviations.chunks_exact(nq).collect();
+        let mut other = vec![0.0; nq];
+
+        if adjacent < g {
+            self.reconstruct(&deviations, state, &mut other);
+        } else {
+            self.reconstruct(&deviations, &mut other, state);
+        }
+        let (d, p) = equilibrium(zone, if adjacent < g { 0.5 } else { -0.5 });
+
+        for (s, z) in state.iter_mut().zip(deviations[adjacent]) {
+            *s = (1.0 - flattening) * *s + flattening * z;
+        }
+        state[0] += d;
+        state[PRESSURE] += p;
+
+        if !is_physical(state) {
+            state.clone_from_slice(zone);
+            state[0] = d;
+            state[PRESSURE] = p;
+        }
+    }
+}
+
+impl std::str::FromStr for Reconstruction {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pcm" => Ok(Self::Pcm),
+            "plm" => Ok(Self::Plm),
+            "weno5" => Ok(Self::Weno5),
+            _ => Err(format!("unknown reconstruction '{}' (pcm|plm|weno5)", s)),
+        }
+    }
+}
+
+/// A shock sensor, which flattens the reconstruction in zones that are being
+/// compressed along an axis, and across which the pressure jumps by more
+/// than a threshold. The coefficient ramps from zero at the threshold to one
+/// at twice the threshold. This is a simplified form of the flattening of
+/// Colella & Woodward (1984), which needs only one zone on either side.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Flattening {
+    /// The smallest pressure jump across a zone, relative to the smaller of
+    /// the two pressures, which is treated as a shock.
+    pub pressure_jump: f64,
+}
+
+impl Default for Flattening {
+    fn default() -> Self {
+        Self { pressure_jump: 0.33 }
+    }
+}
+
+impl Flattening {
+    /// Returns the flattening coefficient, between zero and one, of a zone
+    /// from the primitive states of its neighbors `zl` and `zr` along an
+    /// axis. The pressure is the fourth field, and `velocity` is the field
+    /// of the velocity component along the axis.
+    pub fn coefficient(self, zl: &[f64], zr: &[f64], velocity: usize) -> f64 {
+        let (pl, pr) = (zl[PRESSURE], zr[PRESSURE]);
+        let jump = (pr - pl).abs() / pl.min(pr);
+
+        if zr[velocity] < zl[velocity] && jump > self.pressure_jump {
+            (jump / self.pressure_jump - 1.0).min(1.0)
+        } else {
+            0.0
+        }
+    }
+}
+
+impl std::str::FromStr for Flattening {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        s.parse()
+            .map(|pressure_jump| Self { pressure_jump })
+            .map_err(|e| format!("invalid pressure jump '{}': {}", s, e))
+    }
+}
+
+fn is_physical(p: &[f64]) -> bool {
+    p[0] > 0.0 && p[PRESSURE] > 0.0
+}
+
+/// Returns the limited slope (times the zone width) of the middle of three
+/// consecutive zones.
+fn plm_slope(a: f64, b: f64, c: f64) -> f64 {
+    const THETA: f64 = 1.5;
+
+    let (l, m, r) = (THETA * (b - a), 0.5 * (c - a), THETA * (c - b));
+
+    if l > 0.0 && m > 0.0 && r > 0.0 {
+        l.min(m).min(r)
+    } else if l < 0.0 && m < 0.0 && r < 0.0 {
+        l.max(m).max(r)
+    } else {
+        0.0
+    }
+}
+
+/// Returns the value at the upper face of the middle zone, from the average
+/// values in five consecutive zones.
+fn weno5(v: [f64; 5]) -> f64 {
+    const EPSILON: f64 = 1e-6;
+    const LINEAR_WEIGHTS: [f64; 3] = [0.1, 0.6, 0.3];
+
+    let smoothness = [
+        13.0 / 12.0 * (v[0] - 2.0 * v[1] + v[2]).powi(2)
+            + 0.25 * (v[0] - 4.0 * v[1] + 3.0 * v[2]).powi(2),
+        13.0 / 12.0 * (v[1] - 2.0 * v[2] + v[3]).powi(2) + 0.25 * (v[1] - v[3]).powi(2),
+        13.0 / 12.0 * (v[2] - 2.0 * v[3] + v[4]).powi(2)
+            + 0.25 * (3.0 * v[2] - 4.0 * v[3] + v[4]).powi(2),
+    ];
+    let candidates = [
+        (2.0 * v[0] - 7.0 * v[1] + 11.0 * v[2]) / 6.0,
+        (-v[1] + 5.0 * v[2] + 2.0 * v[3]) / 6.0,
+        (2.0 * v[2] + 5.0 * v[3] - v[4]) / 6.0,
+    ];
+    let mut weights = [0.0; 3];
+
+    for ((w, d), b) in weights.iter_mut().zip(&LINEAR_WEIGHTS).zip(&smoothness) {
+        *w = d / (EPSILON + b).powi(2)
+    }
+    let total: f64 = weights.iter().sum();
+
+    weights
+        .iter()
+        .zip(&candidates)
+        .map(|(w, q)| w * q)
+        .sum::<f64>()
+        / total
+}
@@ -0,0 +1,134 @@
+use gridiron::index_space::Axis;
+
+/// Source terms for the 2D Euler equations, with conserved fields `[rho,
+/// rho u, rho v, E]` and primitive fields `[rho, u, v, p]`.
+pub trait SourceTerms: Send + Sync {
+    /// Adds to `du` the change of the conserved fields over a time `dt`, in
+    /// the zone centered at `position` with the given primitive state.
+    fn apply(&self, position: (f64, f64), primitive: &[f64], dt: f64, du: &mut [f64]);
+}
+
+/// Any closure receiving the position, primitive state, and time step, and
+/// adding to the conserved fields, can be used as a source term.
+impl<F> SourceTerms for F
+where
+    F: Fn((f64, f64), &[f64], f64, &mut [f64]) + Send + Sync,
+{
+    fn apply(&self, position: (f64, f64), primitive: &[f64], dt: f64, du: &mut [f64]) {
+        self(position, primitive, dt, du)
+    }
+}
+
+/// Several source terms are applied by adding up their contributions.
+impl SourceTerms for Vec<Box<dyn SourceTerms>> {
+    fn apply(&self, position: (f64, f64), primitive: &[f64], dt: f64, du: &mut [f64]) {
+        for source in self {
+            source.apply(position, primitive, dt, du)
+        }
+    }
+}
+
+/// Determines when the source terms are applied by the solver.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SourceSplitting {
+    /// The sources are evaluated with the state at the start of each stage,
+    /// and added along with the flux differences.
+    Unsplit,
+
+    /// The sources are applied in a separate forward Euler update over the
+    /// whole time step, after the last stage of the step.
+    OperatorSplit,
+}
+
+/// A uniform gravitational acceleration.
+#[derive(Clone, Copy, Debug)]
+pub struct ConstantGravity {
+    pub acceleration: (f64, f64),
+}
+
+impl SourceTerms for ConstantGravity {
+    fn apply(&self, _: (f64, f64), p: &[f64], dt: f64, du: &mut [f64]) {
+        let (gx, gy) = self.acceleration;
+        du[1] += p[0] * gx * dt;
+        du[2] += p[0] * gy * dt;
+        du[3] += p[0] * (p[1] * gx + p[2] * gy) * dt;
+    }
+}
+
+/// A uniform gravitational acceleration, in a well-balanced form which
+/// keeps isothermal hydrostatic equilibria at rest to round-off. It is not a
+/// [`SourceTerms`], since it must be matched by the reconstruction: the
+/// solver reconstructs the density and pressure as deviations from the
+/// local [`HydrostaticGravity::equilibrium`] of each zone (see
+/// [`super::reconstruction::Reconstruction::balanced_face_states`]), and
+/// adds [`HydrostaticGravity::apply`] with the areas of the zone faces. It
+/// is only applied unsplit. The axes are assumed to measure lengths, as in
+/// Cartesian and cylindrical coordinates.
+#[derive(Clone, Copy, Debug)]
+pub struct HydrostaticGravity {
+    pub acceleration: (f64, f64),
+}
+
+impl HydrostaticGravity {
+    /// Returns the density and pressure at a distance along an axis from the
+    /// center of a zone with primitive state `p`, in the isothermal
+    /// hydrostatic equilibrium through the zone.
+    pub fn equilibrium(&self, p: &[f64], axis: Axis, distance: f64) -> (f64, f64) {
+        let g = match axis {
+            Axis::I => self.acceleration.0,
+            Axis::J => self.acceleration.1,
+        };
+        let f = (g * p[0] / p[3] * distance).exp();
+        (p[0] * f, p[3] * f)
+    }
+
+    /// Adds to `du` the change of the conserved fields over a time `dt`, in
+    /// a zone with primitive state `p` and the given widths on each axis.
+    /// The areas are those of the lower and upper faces on each axis, and
+    /// `volume` is the zone volume. The momentum source on each axis is the
+    /// difference of the area-weighted equilibrium pressures at the faces,
+    /// less the geometric pressure term, so that it cancels the pressure
+    /// flux of the balanced face states at equilibrium. The energy source is
+    /// the work done by the momentum sources.
+    pub fn apply(
+        &self,
+        p: &[f64],
+        widths: (f64, f64),
+        areas: [(f64, f64); 2],
+        volume: f64,
+        dt: f64,
+        du: &mut [f64],
+    ) {
+        let mut source = [0.0; 2];
+
+        for (n, &(axis, width)) in [(Axis::I, widths.0), (Axis::J, widths.1)].iter().enumerate() {
+            let (am, ap) = areas[n];
+            let pm = self.equilibrium(p, axis, -0.5 * width).1;
+            let pp = self.equilibrium(p, axis, 0.5 * width).1;
+            source[n] = (ap * (pp - p[3]) - am * (pm - p[3])) / volume * dt;
+        }
+        du[1] += source[0];
+        du[2] += source[1];
+        du[3] += p[1] * source[0] + p[2] * source[1];
+    }
+}
+
+/// The geometric terms of axisymmetric flow in cylindrical coordinates
+/// `(r, z)`, with the fluxes computed as if the mesh were Cartesian. The
+/// first mesh axis is the radius, which must be positive at zone centers.
+#[derive(Clone, Copy, Debug)]
+pub struct CylindricalGeometry {
+    pub gamma_law_index: f64,
+}
+
+impl SourceTerms for CylindricalGeometry {
+    fn apply(&self, position: (f64, f64), p: &[f64], dt: f64, du: &mut [f64]) {
+        let r = position.0;
+        let (d, u, v, pg) = (p[0], p[1], p[2], p[3]);
+        let e = d * 0.5 * (u * u + v * v) + pg / (self.gamma_law_index - 1.0);
+        du[0] -= d * u / r * dt;
+        du[1] -= d * u * u / r * dt;
+        du[2] -= d * u * v / r * dt;
+        du[3] -= (e + pg) * u / r * dt;
+    }
+}
@@ -0,0 +1,7 @@
+use crate::hydro::srhd2d::Srhd2d;
+
+/// The update scheme of [`super::euler2d_pcm`], for the 2D equations of
+/// special relativistic hydrodynamics. Primitive variables are recovered
+/// from the conserved ones after each update, starting from the pressure
+/// before the update.
+pub type PatchUpdate = super::euler2d_pcm::PatchUpdate<Srhd2d>;
@@ -0,0 +1,107 @@
+use super::reconstruction::Reconstruction;
+
+/// The strong stability preserving (SSP) Runge-Kutta scheme used by the
+/// solvers to advance the solution over a time step, or the single-stage
+/// corner transport upwind scheme. Each stage is a forward Euler update of
+/// the current stage state, which is then averaged with the state at the
+/// start of the step. The stages are run as separate executions
+/// of the task group, so guard zones are exchanged between them.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TimeIntegration {
+    /// A single forward Euler stage; first order.
+    ForwardEuler,
+
+    /// The two-stage, second order SSP scheme (Heun's method).
+    Rk2,
+
+    /// The three-stage, third order SSP scheme of Shu & Osher (1988).
+    Rk3,
+
+    /// The single-stage corner transport upwind (CTU) scheme of Colella
+    /// (1990), in which the face states are predicted to the half time step,
+    /// including the fluxes transverse to each face. It is unsplit, and
+    /// second order in time with a piecewise linear reconstruction. It is
+    /// only supported by the 2D Euler solver, in Cartesian coordinates.
+    Ctu,
+}
+
+impl TimeIntegration {
+    /// Returns the number of stages in a time step.
+    pub fn num_stages(self) -> usize {
+        match self {
+            Self::ForwardEuler => 1,
+            Self::Rk2 => 2,
+            Self::Rk3 => 3,
+            Self::Ctu => 1,
+        }
+    }
+
+    /// Returns the weight given to the state at the start of the step, at the
+    /// end of the given stage. The remaining weight is given to the forward
+    /// Euler update of the stage state.
+    pub fn weight(self, stage: usize) -> f64 {
+        match (self, stage) {
+            (Self::Rk2, 1) => 0.5,
+            (Self::Rk3, 1) => 0.75,
+            (Self::Rk3, 2) => 1.0 / 3.0,
+            _ => 0.0,
+        }
+    }
+
+    /// Returns the scheme to use with the given reconstruction when none is
+    /// chosen: forward Euler with piecewise constant states, and otherwise
+    /// the three-stage scheme.
+    pub fn default_for(reconstruction: Reconstruction) -> Self {
+        match reconstruction {
+            Reconstruction::Pcm => Self::ForwardEuler,
+            Reconstruction::Plm | Reconstruction::Weno5 => Self::Rk3,
+        }
+    }
+
+    /// Returns false if this scheme is unstable with the given
+    /// reconstruction at any time step: a single forward Euler stage with
+    /// face states of higher than first order.
+    pub fn is_stable_with(self, reconstruction: Reconstruction) -> bool {
+        self != Self::ForwardEuler || reconstruction == Reconstruction::Pcm
+    }
+
+    /// Returns the time at which the given stage is evaluated, as a fraction
+    /// of the time step.
+    pub fn stage_time(self, stage: usize) -> f64 {
+        match (self, stage) {
+            (Self::Rk2, 1) | (Self::Rk3, 1) => 1.0,
+            (Self::Rk3, 2) => 0.5,
+            _ => 0.0,
+        }
+    }
+}
+
+impl std::str::FromStr for TimeIntegration {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "fwd" => Ok(Self::ForwardEuler),
+            "rk2" => Ok(Self::Rk2),
+            "rk3" => Ok(Self::Rk3),
+            "ctu" => Ok(Self::Ctu),
+            _ => Err(format!("unknown time integration '{}' (fwd|rk2|rk3|ctu)", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn forward_euler_is_only_used_with_piecewise_constant_states() {
+        for &reconstruction in &[Reconstruction::Pcm, Reconstruction::Plm, Reconstruction::Weno5] {
+            assert!(TimeIntegration::default_for(reconstruction).is_stable_with(reconstruction));
+        }
+        assert!(TimeIntegration::ForwardEuler.is_stable_with(Reconstruction::Pcm));
+        assert!(!TimeIntegration::ForwardEuler.is_stable_with(Reconstruction::Plm));
+        assert!(!TimeIntegration::ForwardEuler.is_stable_with(Reconstruction::Weno5));
+        assert!(TimeIntegration::Rk2.is_stable_with(Reconstruction::Weno5));
+    }
+}
@@ -0,0 +1,108 @@
+use std::ops::Range;
+use std::sync::Arc;
+use gridiron::diagnostics::Combine;
+use gridiron::index_space::{Axis, IndexSpace};
+use gridiron::meshing::Geometry;
+use gridiron::patch::Patch;
+
+/// A function of the primitive state of a zone, which a reduction
+/// integrates or takes the extremum of.
+pub type Quantity = Arc<dyn Fn(&[f64]) -> f64 + Send + Sync>;
+
+/// A quantity reduced over the zones of every patch at the end of each time
+/// step, as a column of a [`gridiron::diagnostics::TimeSeries`]. The
+/// contributions of the patches are combined as [`PatchReduction::combine`]
+/// says, first on each rank and then over the ranks.
+pub trait PatchReduction: Send + Sync {
+    /// Returns how the contributions of the patches are combined.
+    fn combine(&self) -> Combine;
+
+    /// Returns the contribution of the zones in `index_space`, at the given
+    /// level, of a patch of primitive states which contains them. The
+    /// geometry is that of the mesh coordinates.
+    fn reduce(&self, geometry: &dyn Geometry, level: u32, index_space: &IndexSpace, primitive: &Patch) -> f64;
+}
+
+/// The volume integral of a quantity over the zones whose centers lie in a
+/// region (in mesh coordinates), for example the luminosity of the gas.
+#[derive(Clone)]
+pub struct RegionIntegral {
+    pub region: (Range<f64>, Range<f64>),
+    pub quantity: Quantity,
+}
+
+impl PatchReduction for RegionIntegral {
+    fn combine(&self) -> Combine {
+        Combine::Sum
+    }
+
+    fn reduce(&self, geometry: &dyn Geometry, level: u32, index_space: &IndexSpace, primitive: &Patch) -> f64 {
+        let (x, y) = &self.region;
+        index_space
+            .iter()
+            .filter(|&index| {
+                let (xc, yc) = geometry.cell_center(level, index);
+                x.contains(&xc) && y.contains(&yc)
+            })
+            .map(|index| (self.quantity)(primitive.get_slice(index)) * geometry.cell_volume(level, index))
+            .sum()
+    }
+}
+
+/// The integral of a flux density over a surface normal to an axis, at the
+/// given coordinate on that axis and spanning a range of the other one, for
+/// example the mass flux `rho vx` through a line `x = x0`. The flux density
+/// is taken from the zones the surface cuts (those whose lower face is at
+/// or below the coordinate and whose upper face is above it), weighted by
+/// the area of their lower face.
+#[derive(Clone)]
+pub struct SurfaceFlux {
+    pub axis: Axis,
+    pub coordinate: f64,
+    pub span: Range<f64>,
+    pub flux: Quantity,
+}
+
+impl PatchReduction for SurfaceFlux {
+    fn combine(&self) -> Combine {
+        Combine::Sum
+    }
+
+    fn reduce(&self, geometry: &dyn Geometry, level: u32, index_space: &IndexSpace, primitive: &Patch) -> f64 {
+        index_space
+            .iter()
+            .filter(|&index| {
+                let (x, y) = geometry.cell_bounds(level, index);
+                let (normal, transverse) = match self.axis {
+                    Axis::I => (x, y),
+                    Axis::J => (y, x),
+                };
+                let center = 0.5 * (transverse.start + transverse.end);
+                normal.contains(&self.coordinate) && self.span.contains(&center)
+            })
+            .map(|index| (self.flux)(primitive.get_slice(index)) * geometry.face_area(level, self.axis, index))
+            .sum()
+    }
+}
+
+/// The smallest or largest value of a quantity over all zones, for example
+/// the largest Mach number. The combination must be [`Combine::Min`] or
+/// [`Combine::Max`].
+#[derive(Clone)]
+pub struct Extremum {
+    pub combine: Combine,
+    pub quantity: Quantity,
+}
+
+impl PatchReduction for Extremum {
+    fn combine(&self) -> Combine {
+        self.combine
+    }
+
+    fn reduce(&self, _: &dyn Geometry, _: u32, index_space: &IndexSpace, primitive: &Patch) -> f64 {
+        index_space
+            .iter()
+            .map(|index| (self.quantity)(primitive.get_slice(index)))
+            .fold(self.combine.identity(), |a, b| self.combine.apply(a, b))
+    }
+}
@@ -1,7 +1,9 @@
+use core::fmt::{Debug, Write};
 use core::hash::Hash;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 
 /// A minimal directed graph structure that stores only edges
+#[derive(Clone)]
 pub struct AdjacencyList<K> {
     outgoing: HashMap<K, Vec<K>>,
     incoming: HashMap<K, Vec<K>>,
@@ -34,7 +36,7 @@ where
     }
 
     /// Determine whether the given edge exists.
-    pub fn contains(&mut self, a: &K, b: &K) -> bool {
+    pub fn contains(&self, a: &K, b: &K) -> bool {
         self.outgoing
             .get(a)
             .and_then(|edges| edges.iter().find(|&k| k == b))
@@ -53,6 +55,14 @@ where
             .and_modify(|edges| edges.retain(|k| k != &a1));
     }
 
+    /// Return an iterator over all the edges in the graph, as `(a, b)` pairs
+    /// of vertices for an edge pointing from a -> b.
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &K)> {
+        self.outgoing
+            .iter()
+            .flat_map(|(a, edges)| edges.iter().map(move |b| (a, b)))
+    }
+
     /// Return an iterator over the vertices with edges emanating from the given
     /// vertex.
     pub fn outgoing_edges(&self, a: &K) -> impl Iterator<Item = &K> {
@@ -70,6 +80,127 @@ where
             .into_iter()
             .flat_map(|edges| edges.iter())
     }
+
+    /// Return the number of edges emanating from the given vertex.
+    pub fn out_degree(&self, a: &K) -> usize {
+        self.outgoing.get(a).map_or(0, |edges| edges.len())
+    }
+
+    /// Return the number of edges pointing to the given vertex. In a message
+    /// graph this is the number of messages a task must receive.
+    pub fn in_degree(&self, b: &K) -> usize {
+        self.incoming.get(b).map_or(0, |edges| edges.len())
+    }
+
+    /// Return an iterator over the vertices connected to the given vertex by
+    /// an edge in either direction. Each neighbor is yielded once.
+    pub fn neighbors<'a>(&'a self, k: &'a K) -> impl Iterator<Item = &'a K> {
+        let mut seen = HashSet::new();
+        self.outgoing_edges(k)
+            .chain(self.incoming_edges(k))
+            .filter(move |&n| seen.insert(n))
+    }
+
+    /// Return an iterator over all the vertices which have ever had an edge
+    /// inserted into or out of them.
+    pub fn vertices(&self) -> impl Iterator<Item = &K> {
+        self.outgoing.keys().chain(
+            self.incoming
+                .keys()
+                .filter(move |k| !self.outgoing.contains_key(k)),
+        )
+    }
+
+    /// Return a copy of this graph with the direction of every edge reversed.
+    /// The edges are cloned, so changes to either graph do not affect the
+    /// other.
+    pub fn reversed(&self) -> Self {
+        Self {
+            outgoing: self.incoming.clone(),
+            incoming: self.outgoing.clone(),
+        }
+    }
+
+    /// Return the vertices in an order such that every edge a -> b has a
+    /// before b, or `None` if the graph contains a cycle.
+    pub fn topological_sort(&self) -> Option<Vec<K>> {
+        let mut degree: HashMap<&K, usize> =
+            self.vertices().map(|k| (k, self.in_degree(k))).collect();
+        let mut ready: Vec<&K> = degree
+            .iter()
+            .filter(|(_, &d)| d == 0)
+            .map(|(&k, _)| k)
+            .collect();
+        let mut result = Vec::with_capacity(degree.len());
+
+        while let Some(a) = ready.pop() {
+            for b in self.outgoing_edges(a) {
+                let d = degree.get_mut(b).unwrap();
+                *d -= 1;
+
+                if *d == 0 {
+                    ready.push(b)
+                }
+            }
+            result.push(a.clone());
+        }
+
+        if result.len() == degree.len() {
+            Some(result)
+        } else {
+            None
+        }
+    }
+
+    /// Return a map from each vertex to the label of its (weakly) connected
+    /// component, along with the number of components. Labels are in the
+    /// range `0..count`.
+    pub fn connected_components(&self) -> (HashMap<K, usize>, usize) {
+        let mut labels = HashMap::new();
+        let mut count = 0;
+
+        for root in self.vertices() {
+            if labels.contains_key(root) {
+                continue;
+            }
+            let mut stack = vec![root];
+            labels.insert(root.clone(), count);
+
+            while let Some(k) = stack.pop() {
+                for n in self.neighbors(k) {
+                    if !labels.contains_key(n) {
+                        labels.insert(n.clone(), count);
+                        stack.push(n)
+                    }
+                }
+            }
+            count += 1;
+        }
+        (labels, count)
+    }
+}
+
+impl<K> AdjacencyList<K>
+where
+    K: Hash + Eq + Clone + Debug,
+{
+    /// Return a description of this graph in the GraphViz DOT language. Each
+    /// vertex is labeled by its `Debug` representation. The result can be
+    /// rendered with e.g. `dot -Tpdf graph.dot -o graph.pdf`.
+    pub fn to_dot(&self) -> String {
+        let ids: HashMap<&K, usize> = self.vertices().enumerate().map(|(n, k)| (k, n)).collect();
+        let mut dot = String::from("digraph {\n");
+
+        for (k, n) in &ids {
+            let label = format!("{:?}", k).replace('"', "\\\"");
+            writeln!(dot, "    {} [label=\"{}\"];", n, label).unwrap();
+        }
+        for (a, b) in self.iter() {
+            writeln!(dot, "    {} -> {};", ids[a], ids[b]).unwrap();
+        }
+        dot.push_str("}\n");
+        dot
+    }
 }
 
 impl<K> Default for AdjacencyList<K> {
@@ -81,6 +212,33 @@ impl<K> Default for AdjacencyList<K> {
     }
 }
 
+/// The graph is serialized as a flat sequence of `(a, b)` edges. The incoming
+/// edge table is redundant, so it is rebuilt on deserialization.
+#[cfg(feature = "serde")]
+impl<K> serde::Serialize for AdjacencyList<K>
+where
+    K: Hash + Eq + Clone + serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, K> serde::Deserialize<'de> for AdjacencyList<K>
+where
+    K: Hash + Eq + Clone + serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let edges: Vec<(K, K)> = serde::Deserialize::deserialize(deserializer)?;
+        let mut result = Self::new();
+        for (a, b) in edges {
+            result.insert(a, b)
+        }
+        Ok(result)
+    }
+}
+
 #[cfg(test)]
 mod test {
     use super::AdjacencyList;
@@ -129,4 +287,95 @@ mod test {
         assert_eq!(edges.outgoing_edges(&0).count(), 3);
         assert_eq!(edges.outgoing_edges(&4).count(), 2);
     }
+
+    #[test]
+    fn graph_can_iterate_all_edges() {
+        let mut edges = AdjacencyList::new();
+        edges.insert(0, 1);
+        edges.insert(0, 2);
+        edges.insert(4, 1);
+        edges.remove(0, 2);
+
+        let mut all: Vec<_> = edges.iter().map(|(a, b)| (*a, *b)).collect();
+        all.sort();
+        assert_eq!(all, vec![(0, 1), (4, 1)]);
+    }
+
+    #[test]
+    fn graph_degrees_and_neighbors_work() {
+        let mut edges = AdjacencyList::new();
+        edges.insert(0, 1);
+        edges.insert(1, 0);
+        edges.insert(1, 2);
+
+        assert_eq!(edges.out_degree(&1), 2);
+        assert_eq!(edges.in_degree(&1), 1);
+        assert_eq!(edges.in_degree(&5), 0);
+        assert_eq!(edges.neighbors(&1).count(), 2);
+        assert_eq!(edges.vertices().count(), 3);
+
+        let reversed = edges.reversed();
+        assert!(reversed.contains(&2, &1));
+        assert!(!reversed.contains(&1, &2));
+    }
+
+    #[test]
+    fn graph_topological_sort_works() {
+        let mut edges = AdjacencyList::new();
+        edges.insert(0, 1);
+        edges.insert(0, 2);
+        edges.insert(1, 3);
+        edges.insert(2, 3);
+
+        let order = edges.topological_sort().unwrap();
+        let position = |k| order.iter().position(|&x| x == k).unwrap();
+        assert_eq!(order.len(), 4);
+        assert!(edges.iter().all(|(a, b)| position(*a) < position(*b)));
+
+        edges.insert(3, 0);
+        assert!(edges.topological_sort().is_none());
+    }
+
+    #[test]
+    fn graph_can_be_written_as_dot() {
+        let mut edges = AdjacencyList::new();
+        edges.insert("a", "b");
+
+        let dot = edges.to_dot();
+        assert!(dot.starts_with("digraph {"));
+        assert!(dot.contains("[label=\"\\\"a\\\"\"];"));
+        assert_eq!(dot.matches("->").count(), 1);
+    }
+
+    #[test]
+    fn graph_connected_components_work() {
+        let mut edges = AdjacencyList::new();
+        edges.insert(0, 1);
+        edges.insert(2, 1);
+        edges.insert(3, 4);
+
+        let (labels, count) = edges.connected_components();
+        assert_eq!(count, 2);
+        assert_eq!(labels[&0], labels[&2]);
+        assert_ne!(labels[&0], labels[&3]);
+        assert_eq!(labels[&3], labels[&4]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn graph_serde_round_trip_preserves_edges() {
+        let mut edges = AdjacencyList::new();
+        edges.insert(0, 1);
+        edges.insert(0, 2);
+        edges.insert(4, 1);
+        edges.insert(1, 1);
+
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&edges, &mut bytes).unwrap();
+        let back: AdjacencyList<i32> = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+
+        assert_eq!(back.len(), edges.len());
+        assert!(edges.iter().all(|(a, b)| back.contains(a, b)));
+        assert_eq!(back.incoming_edges(&1).count(), 3);
+    }
 }
@@ -6,13 +6,15 @@ use crate::overlap::Overlap;
 
 
 /**
- * A node in an augmented binary search tree
+ * A node in an augmented binary search tree. The tree is self-balancing (AVL),
+ * so that queries remain O(log n) regardless of the insertion order.
  */
 #[derive(Clone)]
 pub struct Node<T: Ord + Copy, V> {
     key: Range<T>,
     value: V,
     max: T,
+    height: usize,
     l: Option<Box<Node<T, V>>>,
     r: Option<Box<Node<T, V>>>,
 }
@@ -30,7 +32,7 @@ impl<T: Ord + Copy, V> Node<T, V> {
      * Create an empty sub-tree with the given key.
      */
     pub(crate) fn new(key: Range<T>, value: V) -> Self {
-        Self { max: key.end, key, value, l: None, r: None }
+        Self { max: key.end, height: 1, key, value, l: None, r: None }
     }
 
 
@@ -50,7 +52,8 @@ impl<T: Ord + Copy, V> Node<T, V> {
             let l = Self::from_sorted_slice(&mut slice[..mid]);
             let r = Self::from_sorted_slice(&mut slice[mid + 1..]);
             let max = Self::local_max(key.end, &l, &r);
-            Some(Box::new(Self { key, value, max, l, r }))
+            let height = Self::local_height(&l, &r);
+            Some(Box::new(Self { key, value, max, height, l, r }))
         }
     }
 
@@ -86,8 +89,7 @@ impl<T: Ord + Copy, V> Node<T, V> {
      * Return the height of this sub-tree.
      */
     pub(crate) fn height(&self) -> usize {
-        self.l.as_ref().map_or(0, |l| l.height()).max(
-        self.r.as_ref().map_or(0, |r| r.height())) + 1
+        self.height
     }
 
 
@@ -133,25 +135,12 @@ impl<T: Ord + Copy, V> Node<T, V> {
 
     /**
      * Insert a node with the given key into this sub-tree. If a node with that
-     * key already exists, the value is overwritten.
+     * key already exists, the value is overwritten. The sub-tree is
+     * rebalanced, so the returned reference is obtained by a second lookup.
      */
     pub(crate) fn insert(node: &mut Option<Box<Self>>, key: Range<T>, value: V) -> &mut V {
-        if let Some(n) = node {
-
-            n.max = key.end.max(n.max);
-
-            match Self::compare(&key, &n.key) {
-                Less    => Self::insert(&mut n.l, key, value),
-                Greater => Self::insert(&mut n.r, key, value),
-                Equal   => {
-                    n.value = value;
-                    &mut n.value
-                }
-            }
-        } else {
-            *node = Some(Box::new(Self::new(key, value)));
-            &mut node.as_mut().unwrap().value
-        }
+        Self::insert_balanced(node, key.clone(), value);
+        node.as_mut().and_then(|n| n.get_mut(&key)).unwrap()
     }
 
 
@@ -166,18 +155,32 @@ impl<T: Ord + Copy, V> Node<T, V> {
     where
         V: Default
     {
-        if let Some(n) = node {
+        if !node.as_ref().is_some_and(|n| n.contains(&key)) {
+            Self::insert_balanced(node, key.clone(), V::default());
+        }
+        node.as_mut().and_then(|n| n.get_mut(&key)).unwrap()
+    }
+
 
-            n.max = key.end.max(n.max);
 
+
+    /**
+     * Insert a key-value pair into this sub-tree, overwriting the value if
+     * the key exists, and restore the balance of each node along the path.
+     */
+    fn insert_balanced(node: &mut Option<Box<Self>>, key: Range<T>, value: V) {
+        if let Some(n) = node {
             match Self::compare(&key, &n.key) {
-                Less    => Self::require(&mut n.l, key),
-                Greater => Self::require(&mut n.r, key),
-                Equal   => &mut n.value
+                Less    => Self::insert_balanced(&mut n.l, key, value),
+                Greater => Self::insert_balanced(&mut n.r, key, value),
+                Equal   => {
+                    n.value = value;
+                    return
+                }
             }
+            *node = node.take().map(Self::balance)
         } else {
-            *node = Some(Box::new(Self::new(key, V::default())));
-            &mut node.as_mut().unwrap().value
+            *node = Some(Box::new(Self::new(key, value)))
         }
     }
 
@@ -185,42 +188,40 @@ impl<T: Ord + Copy, V> Node<T, V> {
 
 
     /**
-     * Remove a node with the given key from this sub-tree.
+     * Remove a node with the given key from this sub-tree, and return its
+     * value if it existed. The maximum endpoint is updated on every node
+     * along the path to the removed node.
      */
-    pub(crate) fn remove(node: &mut Option<Box<Self>>, key: &Range<T>) {
-        if let Some(n) = node {
-            match Self::compare(key, &n.key) {
+    pub(crate) fn remove(node: &mut Option<Box<Self>>, key: &Range<T>) -> Option<V> {
+        let removed = match node {
+            None => None,
+            Some(n) => match Self::compare(key, &n.key) {
                 Less    => Self::remove(&mut n.l, key),
                 Greater => Self::remove(&mut n.r, key),
-                Equal   => match (n.l.take(), n.r.take()) {
-                    (None, None) => {
-                        *node = None
-                    }
-                    (Some(l), None) => {
-                        *node = Some(l)
-                    }
-                    (None, Some(r)) => {
-                        *node = Some(r)
-                    }
-                    (Some(l), Some(r)) => {
-                        if r.len() > l.len() {
-                            let (new_r, r_key) = r.take_lmost();
-                            n.key = r_key;
-                            n.l = Some(l);
-                            n.r = new_r;
-                        } else {
-                            let (new_l, l_key) = l.take_rmost();
-                            n.key = l_key;
-                            n.l = new_l;
-                            n.r = Some(r);
+                Equal   => {
+                    let Self { value, l, r, .. } = *node.take().unwrap();
+
+                    *node = match (l, r) {
+                        (None, None) => None,
+                        (Some(l), None) => Some(l),
+                        (None, Some(r)) => Some(r),
+                        (Some(l), Some(r)) => {
+                            let n = if r.height > l.height {
+                                let (r, key, value) = r.take_lmost();
+                                Self { max: key.end, height: 1, key, value, l: Some(l), r }
+                            } else {
+                                let (l, key, value) = l.take_rmost();
+                                Self { max: key.end, height: 1, key, value, l, r: Some(r) }
+                            };
+                            Some(Self::balance(Box::new(n)))
                         }
-                    }
+                    };
+                    Some(value)
                 }
             }
-        }
-        if let Some(n) = node {
-            n.max = Self::local_max(n.key.end, &n.l, &n.r);
-        }
+        };
+        *node = node.take().map(Self::balance);
+        removed
     }
 
 
@@ -228,22 +229,16 @@ impl<T: Ord + Copy, V> Node<T, V> {
 
     /**
      * Return this sub-tree, but with the left-most descendant node removed.
-     * Also return the key of that node.
+     * Also return the key and value of that node.
      */
-    pub(crate) fn take_lmost(mut self: Box<Self>) -> (Option<Box<Self>>, Range<T>) {
-        if let Some(l) = self.l {
-            if l.l.is_none() {
-                self.l = None;
-                self.max = Self::local_max(self.key.end, &self.l, &self.r);
-                (Some(self), l.key)
-            } else {
-                let (new_l, l_key) = l.take_lmost();
-                self.l = new_l;
-                self.max = Self::local_max(self.key.end, &self.l, &self.r);
-                (Some(self), l_key)
-            }
+    pub(crate) fn take_lmost(mut self: Box<Self>) -> (Option<Box<Self>>, Range<T>, V) {
+        if let Some(l) = self.l.take() {
+            let (new_l, key, value) = l.take_lmost();
+            self.l = new_l;
+            (Some(Self::balance(self)), key, value)
         } else {
-            (None, self.key)
+            let Self { key, value, r, .. } = *self;
+            (r, key, value)
         }
     }
 
@@ -252,22 +247,16 @@ impl<T: Ord + Copy, V> Node<T, V> {
 
     /**
      * Return this sub-tree, but with the right-most descendant node removed.
-     * Also return the key of that node.
+     * Also return the key and value of that node.
      */
-    pub(crate) fn take_rmost(mut self: Box<Self>) -> (Option<Box<Self>>, Range<T>) {
-        if let Some(r) = self.r {
-            if r.r.is_none() {
-                self.r = None;
-                self.max = Self::local_max(self.key.end, &self.l, &self.r);
-                (Some(self), r.key)
-            } else {
-                let (new_r, r_key) = r.take_rmost();
-                self.r = new_r;
-                self.max = Self::local_max(self.key.end, &self.l, &self.r);
-                (Some(self), r_key)
-            }
+    pub(crate) fn take_rmost(mut self: Box<Self>) -> (Option<Box<Self>>, Range<T>, V) {
+        if let Some(r) = self.r.take() {
+            let (new_r, key, value) = r.take_rmost();
+            self.r = new_r;
+            (Some(Self::balance(self)), key, value)
         } else {
-            (None, self.key)
+            let Self { key, value, l, .. } = *self;
+            (l, key, value)
         }
     }
 
@@ -332,6 +321,29 @@ impl<T: Ord + Copy, V> Node<T, V> {
 
 
 
+    /**
+     * Panic unless a node and its entire subtree is height-balanced, and the
+     * stored heights are correct. This function is for testing purposes.
+     */
+    #[cfg(test)]
+    pub(crate) fn validate_balance(&self) {
+        let hl = Self::node_height(&self.l);
+        let hr = Self::node_height(&self.r);
+
+        if hl.max(hr) - hl.min(hr) > 1 || self.height != hl.max(hr) + 1 {
+            panic!("unbalanced node")
+        }
+        if let Some(l) = &self.l {
+            l.validate_balance()
+        }
+        if let Some(r) = &self.r {
+            r.validate_balance()
+        }
+    }
+
+
+
+
     /**
      * Return the maximum upper bound on this sub-tree. This *should* be the
      * same as the `max` data member on the node, but this function can be
@@ -350,6 +362,105 @@ impl<T: Ord + Copy, V> Node<T, V> {
 
 
 
+    /**
+     * Restore the AVL balance condition on this node, assuming both of its
+     * sub-trees are balanced and differ in height by at most two. The stored
+     * height and maximum endpoint are brought up-to-date. The returned node
+     * is the new root of this sub-tree.
+     */
+    fn balance(mut self: Box<Self>) -> Box<Self> {
+        let hl = Self::node_height(&self.l);
+        let hr = Self::node_height(&self.r);
+
+        if hl > hr + 1 {
+            let l = self.l.take().unwrap();
+            if Self::node_height(&l.l) < Self::node_height(&l.r) {
+                self.l = Some(l.rotate_l());
+            } else {
+                self.l = Some(l);
+            }
+            self.rotate_r()
+        } else if hr > hl + 1 {
+            let r = self.r.take().unwrap();
+            if Self::node_height(&r.r) < Self::node_height(&r.l) {
+                self.r = Some(r.rotate_r());
+            } else {
+                self.r = Some(r);
+            }
+            self.rotate_l()
+        } else {
+            self.update();
+            self
+        }
+    }
+
+
+
+
+    /**
+     * Rotate this sub-tree to the left, so that the right child becomes the
+     * new root. Panics if there is no right child.
+     */
+    fn rotate_l(mut self: Box<Self>) -> Box<Self> {
+        let mut r = self.r.take().unwrap();
+        self.r = r.l.take();
+        self.update();
+        r.l = Some(self);
+        r.update();
+        r
+    }
+
+
+
+
+    /**
+     * Rotate this sub-tree to the right, so that the left child becomes the
+     * new root. Panics if there is no left child.
+     */
+    fn rotate_r(mut self: Box<Self>) -> Box<Self> {
+        let mut l = self.l.take().unwrap();
+        self.l = l.r.take();
+        self.update();
+        l.r = Some(self);
+        l.update();
+        l
+    }
+
+
+
+
+    /**
+     * Recompute the stored height and maximum endpoint of this node from its
+     * children.
+     */
+    fn update(&mut self) {
+        self.height = Self::local_height(&self.l, &self.r);
+        self.max = Self::local_max(self.key.end, &self.l, &self.r);
+    }
+
+
+
+
+    /**
+     * Return the height of a maybe-node, which is zero for an empty sub-tree.
+     */
+    fn node_height(node: &Option<Box<Self>>) -> usize {
+        node.as_ref().map_or(0, |n| n.height)
+    }
+
+
+
+
+    /**
+     * Determine the height of a node from its two maybe-children.
+     */
+    fn local_height(l: &Option<Box<Self>>, r: &Option<Box<Self>>) -> usize {
+        Self::node_height(l).max(Self::node_height(r)) + 1
+    }
+
+
+
+
     /**
      * Determine the maximum upper bound based on the given endpoint, and two
      * other maybe-nodes. The result is correct as two maybe-nodes have
@@ -671,6 +782,97 @@ impl<'a, T: Ord + Copy, V, R: RangeBounds<T>> Iterator for IterRangeQuery<'a, T,
 
 
 
+/**
+ * Iterator that visits, by mutable reference in pre-order, only those
+ * key-value pairs for which the interval contains the given point.
+ */
+pub (crate) struct IterPointQueryMut<'a, T: Ord + Copy, V> {
+    stack: Vec<&'a mut Node<T, V>>,
+    point: T
+}
+
+impl<'a, T: Ord + Copy, V> IterPointQueryMut<'a, T, V> {
+    pub(crate) fn new(node: &'a mut Option<Box<Node<T, V>>>, point: T) -> Self {
+        Self {
+            stack: node.iter_mut().map(|n| &mut **n).collect(),
+            point,
+        }
+    }
+}
+
+impl<'a, T: Ord + Copy, V> Iterator for IterPointQueryMut<'a, T, V> {
+    type Item = (&'a Range<T>, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = self.stack.pop()?;
+
+            if let Some(r) = &mut node.r {
+                if self.point >= node.key.start {
+                    self.stack.push(r)
+                }
+            }
+            if let Some(l) = &mut node.l {
+                if self.point < node.max {
+                    self.stack.push(l)
+                }
+            }
+            if node.key.contains(&self.point) {
+                return Some((&node.key, &mut node.value))
+            }
+        }
+    }
+}
+
+
+
+
+/**
+ * Iterator that visits, by mutable reference in pre-order, only those
+ * key-value pairs for which the interval intersects the given range bounds
+ * object.
+ */
+pub (crate) struct IterRangeQueryMut<'a, T: Ord + Copy, V, R: RangeBounds<T>> {
+    stack: Vec<&'a mut Node<T, V>>,
+    range: R,
+}
+
+impl<'a, T: Ord + Copy, V, R: RangeBounds<T>> IterRangeQueryMut<'a, T, V, R> {
+    pub(crate) fn new(node: &'a mut Option<Box<Node<T, V>>>, range: R) -> Self {
+        Self {
+            stack: node.iter_mut().map(|n| &mut **n).collect(),
+            range,
+        }
+    }
+}
+
+impl<'a, T: Ord + Copy, V, R: RangeBounds<T>> Iterator for IterRangeQueryMut<'a, T, V, R> {
+    type Item = (&'a Range<T>, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            let node = self.stack.pop()?;
+
+            if let Some(r) = &mut node.r {
+                if self.range.overlaps(&(node.key.start..)) {
+                    self.stack.push(r)
+                }
+            }
+            if let Some(l) = &mut node.l {
+                if self.range.overlaps(&(..node.max)) {
+                    self.stack.push(l)
+                }
+            }
+            if self.range.overlaps(&node.key) {
+                return Some((&node.key, &mut node.value))
+            }
+        }
+    }
+}
+
+
+
+
 // ============================================================================
 #[cfg(test)]
 mod test {
@@ -710,5 +912,37 @@ mod test {
         }
         node.as_ref().unwrap().validate_max();
         node.as_ref().unwrap().validate_order();
+        node.as_ref().unwrap().validate_balance();
+    }
+
+    #[test]
+    fn tree_remains_balanced_for_sorted_insertion() {
+        let mut node = None;
+        for i in 0..4096 {
+            Node::insert(&mut node, i..i + 10, ());
+        }
+        let root = node.as_ref().unwrap();
+        root.validate_max();
+        root.validate_order();
+        root.validate_balance();
+        assert_eq!(root.len(), 4096);
+        assert!(root.height() <= 14);
+    }
+
+    #[test]
+    fn tree_remains_balanced_after_removals() {
+        let intervals = stupid_random_intervals(1000, 42);
+        let mut node = None;
+        for x in &intervals {
+            Node::require(&mut node, x.clone());
+        }
+        for x in intervals.iter().step_by(2) {
+            assert_eq!(Node::remove(&mut node, x), Some(()));
+        }
+        let root = node.as_ref().unwrap();
+        root.validate_max();
+        root.validate_order();
+        root.validate_balance();
+        assert_eq!(root.len(), 500);
     }
 }
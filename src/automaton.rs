@@ -22,11 +22,15 @@
 //! certain tasks are updated at a higher cadence than others, the work on the
 //! time-coarse tasks can be skipped, even though the executor formally
 //! processes the entire task group at each fine stage.
+//! [`crate::subcycling`] provides the schedule for this, and the
+//! time-interpolated guard zone data that finer tasks receive from coarser
+//! ones in between their steps.
 
 use crate::coder::{Coder, NullCoder};
 use crate::message::{Communicator, NullCommunicator};
 use core::hash::Hash;
 use std::collections::hash_map::{Entry, HashMap};
+use std::panic::{self, AssertUnwindSafe};
 
 /// Returned by [`Automaton::receive`] to indicate whether a task is eligible
 /// to be evaluated.
@@ -108,6 +112,14 @@ pub trait Automaton {
         None
     }
 
+    /// This method may be implemented to give the priority with which this
+    /// task runs on a thread pool, for example to run the tasks whose
+    /// messages go to other ranks before the interior ones. The executor is
+    /// allowed to ignore it.
+    fn priority(&self) -> crate::thread_pool::Priority {
+        crate::thread_pool::Priority::Normal
+    }
+
     /// This method may be implemented to indicate that this task is eligible
     /// immediately; it does not receive any messages.
     fn independent(&self) -> bool {
@@ -115,6 +127,51 @@ pub trait Automaton {
     }
 }
 
+/// Information that a task reports about itself after each call to
+/// [`Automaton::value`]. A driver can read it between executions of the task
+/// group, to choose the next time step or to rebalance the work over ranks,
+/// without reaching into the task's internals.
+pub trait Metadata {
+    /// Return the largest signal speed in the task's data.
+    fn max_signal_speed(&self) -> f64;
+
+    /// Return the largest time step the task can stably take with the given
+    /// Courant number.
+    fn max_time_step(&self, cfl: f64) -> f64;
+
+    /// Return an estimate of the cost of the task's latest value, for example
+    /// the wall time it took. The units are arbitrary, but must be the same
+    /// for every task in the group, and the cost must not be negative.
+    fn cost(&self) -> f64;
+}
+
+/// Return the largest time step which is stable on all of the given tasks,
+/// or infinity if there are none. In a distributed setting this is still to
+/// be reduced over the ranks.
+pub fn max_time_step<'a, I, A>(tasks: I, cfl: f64) -> f64
+where
+    I: IntoIterator<Item = &'a A>,
+    A: 'a + Metadata,
+{
+    tasks
+        .into_iter()
+        .map(|task| task.max_time_step(cfl))
+        .fold(f64::INFINITY, f64::min)
+}
+
+/// Return the key and cost of each of the given tasks, for example to
+/// partition them with [`crate::meshing::hilbert_partition_weighted`].
+pub fn costs<'a, I, A>(tasks: I) -> Vec<(A::Key, f64)>
+where
+    I: IntoIterator<Item = &'a A>,
+    A: 'a + Automaton + Metadata,
+{
+    tasks
+        .into_iter()
+        .map(|task| (task.key(), task.cost()))
+        .collect()
+}
+
 /// Execute a group of tasks in serial.
 pub fn execute<I, A, K, V, M>(flow: I) -> impl Iterator<Item = V>
 where
@@ -176,7 +233,9 @@ where
 /// eligible upon receiving a message are spawned onto a worker thread. This
 /// function returns as soon as the input iterator is exhausted. The output
 /// iterator will then yield results until all the tasks have completed in the
-/// pool.
+/// pool. If a task panics, its worker survives, and the output iterator
+/// panics with the task's message when it reaches that result. A pool with
+/// no workers executes the tasks in serial, on the calling thread.
 pub fn execute_thread_pool<I, A, K, V, M>(
     pool: &crate::thread_pool::ThreadPool,
     flow: I,
@@ -192,18 +251,23 @@ where
     let code = NullCoder::<(K, M)>::new();
     let work = |_: &K| 0;
     let sink = |a: A| {
+        if pool.num_threads() == 0 {
+            return eligible_sink.send(catch_value(a)).unwrap();
+        }
         let eligible_sink = eligible_sink.clone();
-        pool.spawn_on(a.worker_hint(), move || {
-            eligible_sink.send(a.value()).unwrap();
+        pool.spawn_on_with_priority(a.worker_hint(), a.priority(), move || {
+            eligible_sink.send(catch_value(a)).ok();
         })
     };
     coordinate(flow, &mut comm, &code, work, sink);
-    eligible_source.into_iter()
+    eligible_source.into_iter().map(resume_panic)
 }
 
 /// Executes a group of compute tasks using a distributed communicator, and an
 /// optional pool of worker threads. If no pool is given, the executions are
-/// done synchronously.
+/// done synchronously, as they are if the pool has no workers. Panics in
+/// tasks run on the pool are resumed by the output iterator, as in
+/// [`execute_thread_pool`].
 pub fn execute_comm<Comm, Code, Work, I, A, K, V, M>(
     comm: &mut Comm,
     code: &Code,
@@ -221,17 +285,17 @@ where
     V: 'static + Send,
 {
     let (eligible_sink, eligible_source) = make_channels();
-    let sink = |a: A| match pool {
+    let sink = |a: A| match pool.filter(|pool| pool.num_threads() > 0) {
         Some(pool) => {
             let eligible_sink = eligible_sink.clone();
-            pool.spawn_on(a.worker_hint(), move || {
-                eligible_sink.send(a.value()).unwrap();
+            pool.spawn_on_with_priority(a.worker_hint(), a.priority(), move || {
+                eligible_sink.send(catch_value(a)).ok();
             })
         }
-        None => eligible_sink.send(a.value()).unwrap(),
+        None => eligible_sink.send(Ok(a.value())).unwrap(),
     };
     coordinate(flow, comm, code, work, sink);
-    eligible_source.into_iter()
+    eligible_source.into_iter().map(resume_panic)
 }
 
 fn coordinate<Comm, Code, Work, Sink, I, A, K, V>(
@@ -315,6 +379,19 @@ fn coordinate<Comm, Code, Work, Sink, I, A, K, V>(
     comm.next_time_stamp();
 }
 
+/// Evaluates a task on a pool worker, catching a panic so that the worker
+/// survives and the panic can be resumed by [`resume_panic`] on the thread
+/// collecting the results. The result is sent only if the output iterator
+/// is still alive, since it is dropped if it resumes an earlier panic.
+fn catch_value<A: Automaton>(a: A) -> Result<A::Value, String> {
+    panic::catch_unwind(AssertUnwindSafe(|| a.value()))
+        .map_err(|payload| crate::thread_pool::panic_message(payload.as_ref()))
+}
+
+fn resume_panic<V>(value: Result<V, String>) -> V {
+    value.unwrap_or_else(|message| panic!("a task panicked on a pool worker: {}", message))
+}
+
 #[cfg(feature = "crossbeam_channel")]
 fn make_channels<T>() -> (crossbeam_channel::Sender<T>, crossbeam_channel::Receiver<T>) {
     crossbeam_channel::unbounded()
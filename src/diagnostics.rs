@@ -0,0 +1,483 @@
+//! Global diagnostics of the solution on distributed patches.
+//!
+//! Conservative schemes should preserve the volume integrals of the
+//! conserved quantities up to boundary fluxes and source terms, and some
+//! fields (density and pressure, typically) must stay positive. The
+//! functions here check these properties over the patches on every rank.
+//! They are collective operations: every rank must call them together, with
+//! its local patches, and each call advances the communicator time stamp.
+//!
+//! A [`TimeSeries`] records quantities reduced over the whole domain, such
+//! as a luminosity or the mass flux through a surface, once per time step.
+//! The solver computes each patch's contribution while it updates the
+//! patch, so no extra pass over the data is needed.
+
+use crate::meshing::Geometry;
+use crate::message::Communicator;
+use crate::patch::Patch;
+use std::fmt;
+use std::fs::{File, OpenOptions};
+use std::io::{self, BufWriter, Write};
+use std::path::Path;
+
+/// A zone in which a field that is required to be positive is not.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Violation {
+    /// The rank which owns the zone.
+    pub rank: usize,
+
+    /// The level of the patch containing the zone.
+    pub level: u32,
+
+    /// The index of the zone, at the patch level.
+    pub index: (i64, i64),
+
+    /// The field which is not positive.
+    pub field: usize,
+
+    /// The value of the field.
+    pub value: f64,
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            fmt,
+            "field {} = {:e} at zone {:?} on level {} (rank {})",
+            self.field, self.value, self.index, self.level, self.rank
+        )
+    }
+}
+
+/// The result of running [`Diagnostics`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct Report {
+    /// The volume integral of each field, summed over all ranks.
+    pub totals: Vec<f64>,
+
+    /// The first zone on the lowest rank where a field required to be
+    /// positive is not, if any.
+    pub violation: Option<Violation>,
+}
+
+/// Runs the conservation and positivity checks every few steps.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Diagnostics {
+    /// The number of steps between checks; zero disables them.
+    pub interval: u64,
+
+    /// The fields required to be positive.
+    pub positive_fields: Vec<usize>,
+}
+
+impl Diagnostics {
+    /// Creates diagnostics which run every `interval` steps, and check that
+    /// the given fields are positive.
+    pub fn new(interval: u64, positive_fields: Vec<usize>) -> Self {
+        Self {
+            interval,
+            positive_fields,
+        }
+    }
+
+    /// Returns true if the checks are due at the given iteration. All ranks
+    /// must agree on this, since the checks are collective.
+    pub fn is_due(&self, iteration: u64) -> bool {
+        self.interval != 0 && iteration.is_multiple_of(self.interval)
+    }
+
+    /// Sums the conserved patches with [`volume_totals`], and checks the
+    /// (usually primitive) `positive` patches with [`first_non_positive`].
+    pub fn run<'a, C, G, I, J>(
+        &self,
+        comm: &mut C,
+        geometry: &G,
+        conserved: I,
+        positive: J,
+    ) -> Report
+    where
+        C: Communicator,
+        G: Geometry + ?Sized,
+        I: IntoIterator<Item = &'a Patch>,
+        J: IntoIterator<Item = &'a Patch>,
+    {
+        Report {
+            totals: volume_totals(comm, geometry, conserved),
+            violation: first_non_positive(comm, positive, &self.positive_fields),
+        }
+    }
+}
+
+/// Returns the volume integral of each field of the given patches, summed
+/// over all ranks. Zone volumes are taken from the geometry at each patch's
+/// level, and masked (solid) zones are skipped. Ranks with no patches
+/// contribute nothing. This is a collective operation.
+pub fn volume_totals<'a, C, G, I>(comm: &mut C, geometry: &G, patches: I) -> Vec<f64>
+where
+    C: Communicator,
+    G: Geometry + ?Sized,
+    I: IntoIterator<Item = &'a Patch>,
+{
+    let mut totals = Vec::new();
+
+    for patch in patches {
+        let nq = patch.num_fields();
+        totals.resize(totals.len().max(nq), 0.0);
+
+        for (index, u) in patch
+            .index_space()
+            .iter()
+            .zip(patch.data().chunks_exact(nq))
+        {
+            if !patch.is_solid(index) {
+                let dv = geometry.cell_volume(patch.level(), index);
+
+                for (total, u) in totals.iter_mut().zip(u) {
+                    *total += u * dv
+                }
+            }
+        }
+    }
+    let bytes = comm.all_reduce(
+        |a, b| {
+            let (a, b) = (decode_f64s(&a), decode_f64s(&b));
+            let (long, short) = if a.len() >= b.len() { (a, b) } else { (b, a) };
+            let sum: Vec<_> = long
+                .iter()
+                .enumerate()
+                .map(|(n, x)| x + short.get(n).unwrap_or(&0.0))
+                .collect();
+            encode_f64s(&sum)
+        },
+        encode_f64s(&totals),
+    );
+    comm.next_time_stamp();
+    decode_f64s(&bytes)
+}
+
+/// Returns the first zone in which one of the given fields is not positive
+/// (or is NaN), on the lowest rank that has one. Masked (solid) zones are
+/// skipped. This is a collective operation.
+pub fn first_non_positive<'a, C, I>(comm: &mut C, patches: I, fields: &[usize]) -> Option<Violation>
+where
+    C: Communicator,
+    I: IntoIterator<Item = &'a Patch>,
+{
+    let rank = comm.rank();
+    let mut local = None;
+
+    'search: for patch in patches {
+        let nq = patch.num_fields();
+
+        for (index, p) in patch
+            .index_space()
+            .iter()
+            .zip(patch.data().chunks_exact(nq))
+        {
+            if patch.is_solid(index) {
+                continue;
+            }
+            if let Some(&field) = fields.iter().find(|&&q| p[q].is_nan() || p[q] <= 0.0) {
+                local = Some(Violation {
+                    rank,
+                    level: patch.level(),
+                    index,
+                    field,
+                    value: p[field],
+                });
+                break 'search;
+            }
+        }
+    }
+    let bytes = comm.all_reduce(
+        |a, b| match (decode_violation(&a), decode_violation(&b)) {
+            (Some(x), Some(y)) if y.rank < x.rank => b,
+            (Some(_), _) => a,
+            (None, _) => b,
+        },
+        encode_violation(local),
+    );
+    comm.next_time_stamp();
+    decode_violation(&bytes)
+}
+
+/// How the contributions of the patches (and ranks) to a column of a
+/// [`TimeSeries`] are combined.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Combine {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Combine {
+    /// Returns the value which leaves any other unchanged when combined
+    /// with it, which is the contribution of a rank with no patches.
+    pub fn identity(self) -> f64 {
+        match self {
+            Combine::Sum => 0.0,
+            Combine::Min => f64::INFINITY,
+            Combine::Max => f64::NEG_INFINITY,
+        }
+    }
+
+    /// Combines two contributions.
+    pub fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            Combine::Sum => a + b,
+            Combine::Min => a.min(b),
+            Combine::Max => a.max(b),
+        }
+    }
+}
+
+/// Combines the local values of several quantities over all ranks, each in
+/// its own way. Every rank must pass the same kinds. This is a collective
+/// operation.
+pub fn combine_over_ranks<C: Communicator>(comm: &mut C, kinds: &[Combine], local: &[f64]) -> Vec<f64> {
+    let bytes = comm.all_reduce(
+        |a, b| {
+            let (a, b) = (decode_f64s(&a), decode_f64s(&b));
+            let combined: Vec<_> = kinds
+                .iter()
+                .zip(a.iter().zip(&b))
+                .map(|(kind, (a, b))| kind.apply(*a, *b))
+                .collect();
+            encode_f64s(&combined)
+        },
+        encode_f64s(local),
+    );
+    comm.next_time_stamp();
+    decode_f64s(&bytes)
+}
+
+/// A time series of quantities reduced over the domain, with one row per
+/// recorded step. Each rank combines the contributions of its patches, and
+/// [`TimeSeries::record`] combines the ranks and has rank 0 write the row:
+/// the time followed by each column, separated by spaces, after a header
+/// line starting with `#` which names the columns. The writer is flushed
+/// after each row, so the file can be followed while the run proceeds.
+pub struct TimeSeries<W: Write> {
+    columns: Vec<(String, Combine)>,
+    writer: Option<W>,
+}
+
+impl TimeSeries<BufWriter<File>> {
+    /// Creates a time series written to the given file by rank 0. If
+    /// `append` is true (on restart, for example) and the file exists, rows
+    /// are appended to it without a new header; otherwise the file is
+    /// truncated. Other ranks do not touch the file.
+    pub fn create<C, P>(comm: &C, path: P, columns: Vec<(String, Combine)>, append: bool) -> io::Result<Self>
+    where
+        C: Communicator,
+        P: AsRef<Path>,
+    {
+        if comm.rank() != 0 {
+            return Ok(Self { columns, writer: None });
+        }
+        let path = path.as_ref();
+        let resume = append && path.exists();
+        let file = OpenOptions::new()
+            .create(true)
+            .write(true)
+            .append(resume)
+            .truncate(!resume)
+            .open(path)?;
+        let series = Self::new(columns, Some(BufWriter::new(file)));
+
+        if resume {
+            Ok(series)
+        } else {
+            series.with_header()
+        }
+    }
+}
+
+impl<W: Write> TimeSeries<W> {
+    /// Creates a time series written to the given writer, which should be
+    /// `None` on every rank but one. No header is written.
+    pub fn new(columns: Vec<(String, Combine)>, writer: Option<W>) -> Self {
+        Self { columns, writer }
+    }
+
+    /// Writes the header line naming the columns.
+    pub fn with_header(mut self) -> io::Result<Self> {
+        if let Some(writer) = &mut self.writer {
+            write!(writer, "# time")?;
+
+            for (name, _) in &self.columns {
+                write!(writer, " {}", name)?;
+            }
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+        Ok(self)
+    }
+
+    /// Returns the names and kinds of the columns.
+    pub fn columns(&self) -> &[(String, Combine)] {
+        &self.columns
+    }
+
+    /// Returns the local value of each column before any contributions,
+    /// to be combined with those of this rank's patches.
+    pub fn identity(&self) -> Vec<f64> {
+        self.columns.iter().map(|(_, kind)| kind.identity()).collect()
+    }
+
+    /// Combines the local values of the columns in place, for example those
+    /// of one patch into the running values of a rank.
+    pub fn accumulate(&self, values: &mut [f64], contribution: &[f64]) {
+        for ((value, b), (_, kind)) in values.iter_mut().zip(contribution).zip(&self.columns) {
+            *value = kind.apply(*value, *b)
+        }
+    }
+
+    /// Combines this rank's values of the columns over all ranks, writes
+    /// the row at the given time, and returns the combined values. This is
+    /// a collective operation.
+    pub fn record<C: Communicator>(&mut self, comm: &mut C, time: f64, local: &[f64]) -> io::Result<Vec<f64>> {
+        let kinds: Vec<_> = self.columns.iter().map(|(_, kind)| *kind).collect();
+        let values = combine_over_ranks(comm, &kinds, local);
+
+        if let Some(writer) = &mut self.writer {
+            write!(writer, "{:.12e}", time)?;
+
+            for value in &values {
+                write!(writer, " {:.12e}", value)?;
+            }
+            writeln!(writer)?;
+            writer.flush()?;
+        }
+        Ok(values)
+    }
+
+    /// Returns the writer, if this rank has one.
+    pub fn writer(&self) -> Option<&W> {
+        self.writer.as_ref()
+    }
+}
+
+fn encode_f64s(values: &[f64]) -> Vec<u8> {
+    values.iter().flat_map(|x| x.to_le_bytes()).collect()
+}
+
+fn decode_f64s(bytes: &[u8]) -> Vec<f64> {
+    bytes
+        .chunks_exact(8)
+        .map(|b| f64::from_le_bytes(to_array(b)))
+        .collect()
+}
+
+fn encode_violation(violation: Option<Violation>) -> Vec<u8> {
+    match violation {
+        Some(v) => [
+            (v.rank as u64).to_le_bytes(),
+            (v.level as u64).to_le_bytes(),
+            v.index.0.to_le_bytes(),
+            v.index.1.to_le_bytes(),
+            (v.field as u64).to_le_bytes(),
+            v.value.to_le_bytes(),
+        ]
+        .concat(),
+        None => Vec::new(),
+    }
+}
+
+fn decode_violation(bytes: &[u8]) -> Option<Violation> {
+    if bytes.is_empty() {
+        return None;
+    }
+    let word = |n: usize| to_array(&bytes[8 * n..8 * (n + 1)]);
+    Some(Violation {
+        rank: u64::from_le_bytes(word(0)) as usize,
+        level: u64::from_le_bytes(word(1)) as u32,
+        index: (i64::from_le_bytes(word(2)), i64::from_le_bytes(word(3))),
+        field: u64::from_le_bytes(word(4)) as usize,
+        value: f64::from_le_bytes(word(5)),
+    })
+}
+
+fn to_array(bytes: &[u8]) -> [u8; 8] {
+    let mut array = [0; 8];
+    array.copy_from_slice(bytes);
+    array
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index_space::IndexSpace;
+    use crate::meshing::Cartesian;
+    use crate::message::NullCommunicator;
+
+    #[test]
+    fn volume_totals_are_weighted_and_skip_masked_zones() {
+        let geometry = Cartesian::new((0.0..1.0, 0.0..1.0), (4, 4));
+        let a = Patch::from_slice_function(0, IndexSpace::new(0..2, 0..4), 2, |_, p| {
+            p[0] = 1.0;
+            p[1] = 2.0;
+        });
+        let mut b = Patch::from_slice_function(1, IndexSpace::new(1..2, 0..2), 1, |_, p| {
+            p[0] = 1.0;
+        });
+        let mut comm = NullCommunicator::new();
+        let totals = volume_totals(&mut comm, &geometry, vec![&a, &b]);
+        assert_eq!(totals, vec![1.0, 1.0]);
+
+        b.set_mask(|(_, j)| j == 1);
+        let totals = volume_totals(&mut comm, &geometry, vec![&a, &b]);
+        assert_eq!(totals, vec![0.75, 1.0]);
+        assert!(volume_totals(&mut comm, &geometry, vec![]).is_empty());
+    }
+
+    #[test]
+    fn first_non_positive_reports_the_offending_zone() {
+        let patch = Patch::from_slice_function(0, IndexSpace::new(0..4, 0..4), 3, |(i, j), p| {
+            p[0] = 1.0;
+            p[1] = -1.0;
+            p[2] = if (i, j) == (2, 3) { f64::NAN } else { 1.0 };
+        });
+        let mut comm = NullCommunicator::new();
+        let violation = first_non_positive(&mut comm, vec![&patch], &[0, 2]).unwrap();
+        assert_eq!(violation.index, (2, 3));
+        assert_eq!(violation.field, 2);
+        assert_eq!(violation.rank, 0);
+        assert_eq!(first_non_positive(&mut comm, vec![&patch], &[0]), None);
+
+        let diagnostics = Diagnostics::new(5, vec![1]);
+        assert!(diagnostics.is_due(10) && !diagnostics.is_due(11));
+
+        let geometry = Cartesian::new((0.0..1.0, 0.0..1.0), (4, 4));
+        let report = diagnostics.run(&mut comm, &geometry, vec![&patch], vec![&patch]);
+        assert_eq!(report.totals[0], 1.0);
+        assert_eq!(
+            report.violation.map(|v| (v.index, v.value)),
+            Some(((0, 0), -1.0))
+        );
+    }
+
+    #[test]
+    fn time_series_combines_columns_and_writes_rows() {
+        let columns = vec![
+            ("mass".to_string(), Combine::Sum),
+            ("min_density".to_string(), Combine::Min),
+            ("max_speed".to_string(), Combine::Max),
+        ];
+        let mut series = TimeSeries::new(columns, Some(Vec::new())).with_header().unwrap();
+        let mut local = series.identity();
+        series.accumulate(&mut local, &[1.0, 0.5, 2.0]);
+        series.accumulate(&mut local, &[2.0, 0.25, 1.0]);
+        assert_eq!(local, vec![3.0, 0.25, 2.0]);
+
+        let mut comm = NullCommunicator::new();
+        let values = series.record(&mut comm, 0.5, &local).unwrap();
+        assert_eq!(values, local);
+
+        let text = String::from_utf8(series.writer().unwrap().clone()).unwrap();
+        let lines: Vec<_> = text.lines().collect();
+        assert_eq!(lines[0], "# time mass min_density max_speed");
+        let row: Vec<f64> = lines[1].split(' ').map(|x| x.parse().unwrap()).collect();
+        assert_eq!(row, vec![0.5, 3.0, 0.25, 2.0]);
+    }
+}
@@ -1,7 +1,7 @@
 use core::ops::Range;
 
 /// Identifier for a Cartesian axis
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
 pub enum Axis {
     I,
     J,
@@ -16,6 +16,134 @@ impl Axis {
     }
 }
 
+/// Describes how far an index space is to be extended on each side of each
+/// axis, for example the number of guard zones needed by a directionally
+/// split solver, or the asymmetric halo near a reflecting boundary.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Extension {
+    /// The number of elements added below the start of the `(I, J)` axes.
+    pub lower: (i64, i64),
+
+    /// The number of elements added above the end of the `(I, J)` axes.
+    pub upper: (i64, i64),
+}
+
+impl Extension {
+    /// Creates an extension by the same amount on both sides of each axis.
+    pub fn uniform(delta: i64) -> Self {
+        Self {
+            lower: (delta, delta),
+            upper: (delta, delta),
+        }
+    }
+
+    /// Creates an extension by a different amount on each axis, the same on
+    /// the lower and upper sides.
+    pub fn per_axis(delta_i: i64, delta_j: i64) -> Self {
+        Self {
+            lower: (delta_i, delta_j),
+            upper: (delta_i, delta_j),
+        }
+    }
+
+    /// Returns the extension on the lower side of the given axis.
+    pub fn lower(&self, axis: Axis) -> i64 {
+        match axis {
+            Axis::I => self.lower.0,
+            Axis::J => self.lower.1,
+        }
+    }
+
+    /// Returns the extension on the upper side of the given axis.
+    pub fn upper(&self, axis: Axis) -> i64 {
+        match axis {
+            Axis::I => self.upper.0,
+            Axis::J => self.upper.1,
+        }
+    }
+
+    /// Returns the extension with lower and upper sides exchanged. If a patch
+    /// needs data from the region `lower` below it, then its neighbors must
+    /// supply data to the region `upper` above them.
+    pub fn reversed(&self) -> Self {
+        Self {
+            lower: self.upper,
+            upper: self.lower,
+        }
+    }
+
+    /// Returns the largest extension on any side of any axis.
+    pub fn max(&self) -> i64 {
+        self.lower.0.max(self.lower.1).max(self.upper.0).max(self.upper.1)
+    }
+}
+
+impl From<i64> for Extension {
+    fn from(delta: i64) -> Self {
+        Self::uniform(delta)
+    }
+}
+
+/// Describes the guard zones a solver reads around each patch: the stencil
+/// half-width on each axis, and whether the diagonal (corner) guard zones
+/// are needed. Solvers declare their stencil, and the adjacency lists and
+/// halo plans derived from it include exactly the neighbors that supply
+/// those guard zones. A directionally split scheme needs no corners, while
+/// an unsplit scheme with transverse terms does.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct Stencil {
+    /// The number of guard zones on each side of the `(I, J)` axes.
+    pub width: (i64, i64),
+
+    /// Whether the guard zones at the corners of the patch are needed.
+    pub corners: bool,
+}
+
+impl Stencil {
+    /// Creates a stencil of the given width on each axis, without corners.
+    pub const fn star(width: i64) -> Self {
+        Self {
+            width: (width, width),
+            corners: false,
+        }
+    }
+
+    /// Creates a stencil of the given width on each axis, with corners.
+    pub const fn square(width: i64) -> Self {
+        Self {
+            width: (width, width),
+            corners: true,
+        }
+    }
+
+    /// Returns the extension of a patch's storage needed for its guard zones.
+    pub fn extension(&self) -> Extension {
+        Extension::per_axis(self.width.0, self.width.1)
+    }
+
+    /// Returns the guard zone regions surrounding the given index space, as
+    /// disjoint index spaces. Without corners these are the strips along each
+    /// face; with corners they cover the whole extended space, less the
+    /// space itself.
+    pub fn guard_regions(&self, space: &IndexSpace) -> Vec<IndexSpace> {
+        if self.corners {
+            space.extend_by(&self.extension()).subtract(space)
+        } else {
+            let mut regions = space.extend(self.width.0, Axis::I).subtract(space);
+            regions.extend(space.extend(self.width.1, Axis::J).subtract(space));
+            regions
+        }
+    }
+}
+
+impl From<Stencil> for Extension {
+    fn from(stencil: Stencil) -> Self {
+        stencil.extension()
+    }
+}
+
 /// Describes a rectangular index space. The index type is signed 64-bit integer.
 #[derive(Clone, Debug)]
 pub struct IndexSpace {
@@ -102,6 +230,32 @@ impl IndexSpace {
         }
     }
 
+    /// Returns the part of this index space which is not covered by another
+    /// one, as a list of at most four disjoint index spaces. The first two
+    /// pieces (if non-empty) span the full extent of this space on the `J`
+    /// axis, below and above the other space on the `I` axis. The remaining
+    /// pieces are bounded on the `I` axis by the overlap. Empty pieces are
+    /// omitted.
+    pub fn subtract(&self, other: &Self) -> Vec<Self> {
+        let overlap = match self.intersect(other) {
+            Some(overlap) if !overlap.is_empty() => overlap,
+            _ if self.is_empty() => return vec![],
+            _ => return vec![self.clone()],
+        };
+        let (i0, j0) = overlap.start();
+        let (i1, j1) = overlap.end();
+
+        vec![
+            Self::new(self.di.start..i0, self.dj.clone()),
+            Self::new(i1..self.di.end, self.dj.clone()),
+            Self::new(i0..i1, self.dj.start..j0),
+            Self::new(i0..i1, j1..self.dj.end),
+        ]
+        .into_iter()
+        .filter(|piece| !piece.is_empty())
+        .collect()
+    }
+
     /// Extends this index space by the given number of elements on both sides
     /// of each axis.
     pub fn extend_all(&self, delta: i64) -> Self {
@@ -111,6 +265,15 @@ impl IndexSpace {
         )
     }
 
+    /// Extends this index space by a possibly different number of elements
+    /// on each side of each axis.
+    pub fn extend_by(&self, extension: &Extension) -> Self {
+        Self::new(
+            self.di.start - extension.lower.0..self.di.end + extension.upper.0,
+            self.dj.start - extension.lower.1..self.dj.end + extension.upper.1,
+        )
+    }
+
     /// Extends the elements at both ends of the given axis by a certain
     /// amount.
     pub fn extend(&self, delta: i64, axis: Axis) -> Self {
@@ -188,6 +351,45 @@ impl IndexSpace {
         }
     }
 
+    /// Wraps an index into this index space, treating it as a periodic
+    /// domain. Indexes already inside the space are returned unchanged. This
+    /// function panics if the space is empty.
+    pub fn wrap(&self, index: (i64, i64)) -> (i64, i64) {
+        assert!(!self.is_empty(), "cannot wrap an index into an empty space");
+        let (ni, nj) = self.dim();
+        let i = (index.0 - self.di.start).rem_euclid(ni as i64) + self.di.start;
+        let j = (index.1 - self.dj.start).rem_euclid(nj as i64) + self.dj.start;
+        (i, j)
+    }
+
+    /// Returns an iterator over the periodic images of this index space
+    /// which overlap the given domain. Each item is the image, along with the
+    /// offset that was added to this space to produce it; the offsets are
+    /// integer multiples of the domain shape on each axis. If this space is
+    /// a patch extended by guard zones, the images with non-zero offset
+    /// locate the guard zone data on the far side of a periodic domain. The
+    /// un-shifted image is included if it overlaps the domain. This function
+    /// panics if the domain is empty.
+    pub fn periodic_images(&self, domain: &Self) -> impl Iterator<Item = (Self, (i64, i64))> {
+        assert!(!domain.is_empty(), "periodic domain must not be empty");
+        let (ni, nj) = domain.dim();
+        let (ni, nj) = (ni as i64, nj as i64);
+        let image_range = |r: &Range<i64>, d: &Range<i64>, n: i64| {
+            let k0 = (d.start - r.end).div_euclid(n) + 1;
+            let k1 = -(r.start - d.end).div_euclid(n) - 1;
+            k0..=k1
+        };
+        let ki = image_range(&self.di, &domain.di, ni);
+        let kj = image_range(&self.dj, &domain.dj, nj);
+        let space = self.clone();
+
+        ki.flat_map(move |ki| kj.clone().map(move |kj| (ki * ni, kj * nj)))
+            .map(move |(si, sj)| {
+                let image = space.translate(si, Axis::I).translate(sj, Axis::J);
+                (image, (si, sj))
+            })
+    }
+
     /// Increases the size of this index space by the given factor.
     pub fn refine_by(&self, factor: u32) -> Self {
         let factor = factor as i64;
@@ -224,6 +426,15 @@ impl IndexSpace {
         i * m + j
     }
 
+    /// Returns the linear offset for the given index, in a column-major memory
+    /// buffer aligned with the start of this index space.
+    pub fn column_major_offset(&self, index: (i64, i64)) -> usize {
+        let i = (index.0 - self.di.start) as usize;
+        let j = (index.1 - self.dj.start) as usize;
+        let l = (self.di.end - self.di.start) as usize;
+        j * l + i
+    }
+
     /// Returns a memory region object for a buffer mapped to this index space.
     pub fn memory_region(&self) -> MemoryRegion {
         let start = (0, 0);
@@ -272,6 +483,35 @@ impl IndexSpace {
             .collect()
     }
 
+    /// Returns an iterator over non-overlapping sub-spaces of the given shape
+    /// which cover this one, in row-major order. Tiles at the upper end of
+    /// each axis are truncated if the tile shape does not divide this space.
+    /// This function panics if either tile dimension is zero.
+    pub fn tiles(&self, tile_shape: (usize, usize)) -> impl Iterator<Item = Self> {
+        assert!(tile_shape.0 > 0 && tile_shape.1 > 0, "tile shape must be non-zero");
+        let (ti, tj) = (tile_shape.0 as i64, tile_shape.1 as i64);
+        let Self { di, dj } = self.clone();
+
+        di.clone().step_by(tile_shape.0).flat_map(move |i| {
+            let i1 = (i + ti).min(di.end);
+            let dj = dj.clone();
+            dj.clone()
+                .step_by(tile_shape.1)
+                .map(move |j| Self::new(i..i1, j..(j + tj).min(dj.end)))
+        })
+    }
+
+    /// Returns an iterator which visits every `step`-th index on each axis,
+    /// starting from the minimum index, in row-major order. This function
+    /// panics if either step is zero.
+    pub fn iter_strided(&self, step: (usize, usize)) -> impl Iterator<Item = (i64, i64)> + '_ {
+        assert!(step.0 > 0 && step.1 > 0, "stride must be non-zero");
+        self.di
+            .clone()
+            .step_by(step.0)
+            .flat_map(move |i| self.dj.clone().step_by(step.1).map(move |j| (i, j)))
+    }
+
     /// Returns a consuming iterator which traverses the index space in
     /// row-major order (C-like; the final index increases fastest).
     #[allow(clippy::should_implement_trait)]
@@ -365,6 +605,101 @@ pub fn range2d(di: Range<i64>, dj: Range<i64>) -> IndexSpace {
     IndexSpace::new(di, dj)
 }
 
+/// Returns an iterator over the 3D indexes in the cartesian product of three
+/// ranges, in row-major order (the final index increases fastest).
+pub fn range3d(
+    di: Range<i64>,
+    dj: Range<i64>,
+    dk: Range<i64>,
+) -> impl Iterator<Item = (i64, i64, i64)> {
+    range_nd([di, dj, dk]).map(|[i, j, k]| (i, j, k))
+}
+
+/// Returns an iterator over the multi-indexes in the cartesian product of
+/// `D` ranges, in row-major order (the final index increases fastest).
+pub fn range_nd<const D: usize>(ranges: [Range<i64>; D]) -> RangeNd<D> {
+    RangeNd::new(ranges)
+}
+
+/// Iterator over the multi-indexes in a cartesian product of ranges. Created
+/// by [`range_nd`].
+#[derive(Clone, Debug)]
+pub struct RangeNd<const D: usize> {
+    ranges: [Range<i64>; D],
+    next: Option<[i64; D]>,
+}
+
+impl<const D: usize> RangeNd<D> {
+    fn new(ranges: [Range<i64>; D]) -> Self {
+        let mut start = [0; D];
+
+        for (s, r) in start.iter_mut().zip(&ranges) {
+            *s = r.start
+        }
+        let next = if ranges.iter().any(|r| r.is_empty()) {
+            None
+        } else {
+            Some(start)
+        };
+        Self { ranges, next }
+    }
+}
+
+impl<const D: usize> Iterator for RangeNd<D> {
+    type Item = [i64; D];
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let current = self.next?;
+        let mut index = current;
+
+        for axis in (0..D).rev() {
+            index[axis] += 1;
+
+            if index[axis] < self.ranges[axis].end {
+                self.next = Some(index);
+                return Some(current);
+            }
+            index[axis] = self.ranges[axis].start
+        }
+        self.next = None;
+        Some(current)
+    }
+}
+
+/// Describes the ordering of a buffer of data mapped to a 2D index space, with
+/// a fixed number of fields per index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub enum MemoryLayout {
+    /// Row-major (C-like) order, with the fields at each index stored
+    /// contiguously. This is the layout used by [`crate::patch::Patch`].
+    RowMajor,
+
+    /// Column-major (Fortran-like) order, with the fields at each index
+    /// stored contiguously.
+    ColumnMajor,
+
+    /// Structure-of-arrays, where each field is a contiguous row-major array.
+    RowMajorPlanar,
+
+    /// Structure-of-arrays, where each field is a contiguous column-major
+    /// array.
+    ColumnMajorPlanar,
+}
+
+impl MemoryLayout {
+    /// Returns the linear offset of the given field at the given index, in a
+    /// buffer with this layout mapped to the given index space.
+    pub fn offset(&self, space: &IndexSpace, index: (i64, i64), field: usize, num_fields: usize) -> usize {
+        match self {
+            Self::RowMajor => space.row_major_offset(index) * num_fields + field,
+            Self::ColumnMajor => space.column_major_offset(index) * num_fields + field,
+            Self::RowMajorPlanar => field * space.len() + space.row_major_offset(index),
+            Self::ColumnMajorPlanar => field * space.len() + space.column_major_offset(index),
+        }
+    }
+}
+
 /// A 2D memory region within a contiguous buffer.
 #[derive(Debug)]
 pub struct MemoryRegion {
@@ -547,6 +882,50 @@ pub fn block_dims(count: usize, num_dims: usize) -> Vec<usize> {
         .collect()
 }
 
+/// Like [`block_dims`], but takes the number of zones on each axis of the
+/// domain (`shape`), and chooses the factorization of `count` which
+/// minimizes the worst per-block surface-to-volume ratio. For example, 4
+/// blocks on a `400 x 100` domain are arranged `4 x 1` rather than `2 x 2`.
+/// Among equally good factorizations, the one with the larger leading
+/// factors is chosen. If no factorization leaves every block with at least
+/// one zone on each axis, this function falls back to [`block_dims`].
+pub fn block_dims_for_shape(count: usize, shape: &[usize]) -> Vec<usize> {
+    assert!(count > 0, "the block count must be positive");
+    let mut best: Option<(f64, Vec<usize>)> = None;
+
+    for dims in factorizations(count, shape.len()) {
+        // The smallest block on each axis has floor(n / d) zones, and its
+        // surface-to-volume ratio is proportional to the sum of the inverse
+        // side lengths.
+        if shape.iter().zip(&dims).any(|(n, d)| n < d) {
+            continue;
+        }
+        let ratio: f64 = shape.iter().zip(&dims).map(|(n, d)| 1.0 / (n / d) as f64).sum();
+
+        if best.as_ref().is_none_or(|(r, _)| ratio < *r) {
+            best = Some((ratio, dims))
+        }
+    }
+    best.map(|(_, dims)| dims).unwrap_or_else(|| block_dims(count, shape.len()))
+}
+
+/// Returns all the ordered ways of writing `count` as a product of
+/// `num_dims` factors, with larger leading factors first.
+fn factorizations(count: usize, num_dims: usize) -> Vec<Vec<usize>> {
+    if num_dims <= 1 {
+        return vec![vec![count]; num_dims];
+    }
+    (1..=count)
+        .rev()
+        .filter(|&d| count.is_multiple_of(d))
+        .flat_map(|d| {
+            factorizations(count / d, num_dims - 1).into_iter().map(move |rest| {
+                std::iter::once(d).chain(rest).collect()
+            })
+        })
+        .collect()
+}
+
 /// Equitably divide the given number of elements (`len`) into `num_parts`
 /// partitions, so that the sum of the partitions is `len`. The number of
 /// partitions must be less than or equal to the number of elements.
@@ -559,6 +938,58 @@ pub fn partition(len: usize, num_parts: usize) -> Vec<usize> {
         .collect()
 }
 
+/// Divide a sequence of elements with the given costs into `num_parts`
+/// contiguous partitions whose total costs are as nearly equal as possible,
+/// and return the number of elements in each partition. Every partition gets
+/// at least one element, so the number of partitions must be less than or
+/// equal to the number of elements. Costs must be non-negative.
+pub fn partition_weighted(costs: &[f64], num_parts: usize) -> Vec<usize> {
+    partition_proportional(costs, &vec![1.0; num_parts])
+}
+
+/// Divide a sequence of elements with the given costs into contiguous
+/// partitions, one for each of the given (positive) shares, so that the total
+/// cost of each partition is as nearly proportional to its share as possible,
+/// and return the number of elements in each partition. Every partition gets
+/// at least one element, so the number of shares must be less than or equal
+/// to the number of elements. Costs must be non-negative.
+pub fn partition_proportional(costs: &[f64], shares: &[f64]) -> Vec<usize> {
+    let num_parts = shares.len();
+    assert!(costs.len() >= num_parts);
+    let share_prefix: Vec<f64> = shares
+        .iter()
+        .scan(0.0, |sum, s| {
+            *sum += s;
+            Some(*sum)
+        })
+        .collect();
+    let total_share = share_prefix.last().cloned().unwrap_or(0.0);
+    let prefix: Vec<f64> = costs
+        .iter()
+        .scan(0.0, |sum, c| {
+            *sum += c;
+            Some(*sum)
+        })
+        .collect();
+    let total = prefix.last().cloned().unwrap_or(0.0);
+    let mut edges = vec![0];
+
+    for p in 1..num_parts {
+        let target = total * share_prefix[p - 1] / total_share;
+        let lower = edges[p - 1] + 1;
+        let upper = costs.len() - (num_parts - p);
+        let n = prefix.partition_point(|&x| x < target) + 1;
+        let n = if n > 1 && target - prefix[n - 2] < prefix[n - 1] - target {
+            n - 1
+        } else {
+            n
+        };
+        edges.push(n.clamp(lower, upper));
+    }
+    edges.push(costs.len());
+    edges.windows(2).map(|w| w[1] - w[0]).collect()
+}
+
 /// Equitably subdivide a range into a sequence of non-overlapping contiguous
 /// ranges. Panics if the range has negative length.
 pub fn subdivide(range: Range<i64>, num_parts: usize) -> Vec<Range<i64>> {
@@ -603,6 +1034,21 @@ mod test {
         );
     }
 
+    #[test]
+    fn partition_weighted_balances_cost() {
+        assert_eq!(partition_weighted(&[1.0; 10], 3), vec![3, 4, 3]);
+        assert_eq!(partition_weighted(&[8.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0, 1.0], 2), vec![1, 8]);
+        assert_eq!(partition_weighted(&[0.0, 0.0, 0.0, 9.0], 3), vec![2, 1, 1]);
+        assert_eq!(partition_weighted(&[1.0, 2.0, 3.0], 3), vec![1, 1, 1]);
+    }
+
+    #[test]
+    fn partition_proportional_follows_shares() {
+        assert_eq!(partition_proportional(&[1.0; 12], &[1.0, 2.0, 3.0]), vec![2, 4, 6]);
+        assert_eq!(partition_proportional(&[1.0; 4], &[100.0, 1.0]), vec![3, 1]);
+        assert_eq!(partition_proportional(&[1.0; 10], &[2.0; 3]), partition_weighted(&[1.0; 10], 3));
+    }
+
     #[test]
     fn integer_square_root_works() {
         assert_eq!(integer_square_root(0), 0);
@@ -640,6 +1086,16 @@ mod test {
         assert_eq!(block_dims(2000, 3), vec![20, 10, 10]);
     }
 
+    #[test]
+    fn block_dims_for_shape_follows_the_domain() {
+        assert_eq!(block_dims_for_shape(1, &[10, 10]), vec![1, 1]);
+        assert_eq!(block_dims_for_shape(4, &[400, 100]), vec![4, 1]);
+        assert_eq!(block_dims_for_shape(4, &[100, 400]), vec![1, 4]);
+        assert_eq!(block_dims_for_shape(200, &[1000, 2000]), vec![10, 20]);
+        assert_eq!(block_dims_for_shape(200, &[100, 100, 100]), vec![8, 5, 5]);
+        assert_eq!(block_dims_for_shape(16, &[2, 2]), block_dims(16, 2));
+    }
+
     #[test]
     fn partition_works() {
         assert_eq!(partition(5, 5), vec![1, 1, 1, 1, 1]);
@@ -654,6 +1110,125 @@ mod test {
         assert_eq!(subdivide(-5..5, 3), vec![-5..-1, -1..2, 2..5]);
     }
 
+    #[test]
+    fn wrap_works() {
+        let domain = IndexSpace::new(0..10, -5..5);
+        assert_eq!(domain.wrap((3, 2)), (3, 2));
+        assert_eq!(domain.wrap((-1, 5)), (9, -5));
+        assert_eq!(domain.wrap((10, -6)), (0, 4));
+        assert_eq!(domain.wrap((25, 17)), (5, -3));
+    }
+
+    #[test]
+    fn periodic_images_works() {
+        let domain = IndexSpace::new(0..10, 0..10);
+        let corner = IndexSpace::new(0..5, 0..5).extend_all(2);
+        let images: Vec<_> = corner.periodic_images(&domain).map(|(_, s)| s).collect();
+        assert_eq!(images, vec![(0, 0), (0, 10), (10, 0), (10, 10)]);
+
+        let interior = IndexSpace::new(3..7, 3..7).extend_all(2);
+        assert_eq!(interior.periodic_images(&domain).count(), 1);
+
+        let wide = IndexSpace::new(-12..1, 4..5);
+        let images: Vec<_> = wide.periodic_images(&domain).collect();
+        assert_eq!(images.len(), 3);
+        assert_eq!(images[1].0, IndexSpace::new(-2..11, 4..5));
+    }
+
+    #[test]
+    fn subtract_works() {
+        let outer = IndexSpace::new(0..10, 0..10);
+        let inner = IndexSpace::new(2..8, 3..7);
+        let pieces = outer.subtract(&inner);
+        assert_eq!(pieces.len(), 4);
+        assert_eq!(pieces.iter().map(|p| p.len()).sum::<usize>(), 100 - 24);
+        for (n, a) in pieces.iter().enumerate() {
+            assert!(a.intersect(&inner).is_none_or(|o| o.is_empty()));
+            for b in &pieces[n + 1..] {
+                assert!(a.intersect(b).is_none_or(|o| o.is_empty()));
+            }
+        }
+        assert_eq!(outer.subtract(&outer), vec![]);
+        assert_eq!(outer.subtract(&IndexSpace::new(20..30, 0..10)), vec![outer.clone()]);
+        assert_eq!(
+            outer.subtract(&IndexSpace::new(-5..5, -5..15)),
+            vec![IndexSpace::new(5..10, 0..10)]
+        );
+    }
+
+    #[test]
+    fn tiles_works() {
+        let space = IndexSpace::new(0..10, 5..12);
+        let tiles: Vec<_> = space.tiles((4, 4)).collect();
+        assert_eq!(
+            tiles,
+            vec![
+                IndexSpace::new(0..4, 5..9),
+                IndexSpace::new(0..4, 9..12),
+                IndexSpace::new(4..8, 5..9),
+                IndexSpace::new(4..8, 9..12),
+                IndexSpace::new(8..10, 5..9),
+                IndexSpace::new(8..10, 9..12),
+            ]
+        );
+        assert_eq!(IndexSpace::new(0..0, 0..4).tiles((2, 2)).count(), 0);
+    }
+
+    #[test]
+    fn iter_strided_works() {
+        let space = IndexSpace::new(1..6, 0..4);
+        assert_eq!(
+            space.iter_strided((2, 3)).collect::<Vec<_>>(),
+            vec![(1, 0), (1, 3), (3, 0), (3, 3), (5, 0), (5, 3)]
+        );
+    }
+
+    #[test]
+    fn range_nd_works() {
+        assert_eq!(
+            range_nd([0..2, 5..7]).collect::<Vec<_>>(),
+            vec![[0, 5], [0, 6], [1, 5], [1, 6]]
+        );
+        assert_eq!(range_nd([0..3, 0..4, 0..5, 0..6]).count(), 360);
+        assert_eq!(range_nd([0..3, 0..0]).count(), 0);
+        assert_eq!(range_nd::<0>([]).count(), 1);
+    }
+
+    #[test]
+    fn range3d_works() {
+        let indexes: Vec<_> = range3d(0..2, 0..2, -1..1).collect();
+        assert_eq!(indexes.len(), 8);
+        assert_eq!(indexes[0], (0, 0, -1));
+        assert_eq!(indexes[1], (0, 0, 0));
+        assert_eq!(indexes[7], (1, 1, 0));
+    }
+
+    #[test]
+    fn extend_by_works() {
+        let space = IndexSpace::new(0..10, 0..10);
+        let extension = Extension {
+            lower: (2, 0),
+            upper: (1, 3),
+        };
+        assert_eq!(space.extend_by(&extension), IndexSpace::new(-2..11, 0..13));
+        assert_eq!(space.extend_by(&Extension::uniform(2)), space.extend_all(2));
+        assert_eq!(
+            space.extend_by(&Extension::per_axis(1, 0)),
+            space.extend(1, Axis::I)
+        );
+        assert_eq!(extension.reversed().lower(Axis::J), 3);
+    }
+
+    #[test]
+    fn stencil_guard_regions_works() {
+        let space = IndexSpace::new(0..4, 0..4);
+        let star: usize = Stencil::star(2).guard_regions(&space).iter().map(IndexSpace::len).sum();
+        let square: usize = Stencil::square(2).guard_regions(&space).iter().map(IndexSpace::len).sum();
+        assert_eq!(star, 4 * 8);
+        assert_eq!(square, 64 - 16);
+        assert_eq!(Extension::from(Stencil::star(2)), Extension::uniform(2));
+    }
+
     #[test]
     fn tile_works() {
         let space = IndexSpace::new(0..10, 0..10);
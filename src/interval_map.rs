@@ -56,10 +56,26 @@ impl<T: Ord + Copy, V> IntervalMap<T, V> {
         Node::require(&mut self.root, key)
     }
 
-    pub fn remove(&mut self, key: &Range<T>) {
+    /**
+     * Remove the interval with exactly the given key, and return its value if
+     * it existed.
+     */
+    pub fn remove(&mut self, key: &Range<T>) -> Option<V> {
         Node::remove(&mut self.root, key)
     }
 
+    /**
+     * Remove every interval which overlaps the given range, and return the
+     * removed key-value pairs.
+     */
+    pub fn remove_overlapping<R: RangeBounds<T>>(&mut self, range: R) -> Vec<(Range<T>, V)> {
+        let keys: Vec<_> = self.query_range(range).map(|(k, _)| k.clone()).collect();
+
+        keys.into_iter()
+            .filter_map(|k| Node::remove(&mut self.root, &k).map(|v| (k, v)))
+            .collect()
+    }
+
     pub fn into_balanced(self) -> Self {
         let mut data: Vec<_> = self.into_sorted().map(Some).collect();
         Self { root: Node::from_sorted_slice(&mut data[..]) }
@@ -88,6 +104,25 @@ impl<T: Ord + Copy, V> IntervalMap<T, V> {
     pub fn query_range<R: RangeBounds<T>>(&self, range: R) -> impl Iterator<Item = (&Range<T>, &V)> {
         aug_node::IterRangeQuery::new(&self.root, range)
     }
+
+    /**
+     * Returns an iterator over the key-value pairs whose interval contains
+     * the given point, with mutable references to the values.
+     */
+    pub fn query_point_mut(&mut self, point: T) -> impl Iterator<Item = (&Range<T>, &mut V)> + '_ {
+        aug_node::IterPointQueryMut::new(&mut self.root, point)
+    }
+
+    /**
+     * Returns an iterator over the key-value pairs whose interval intersects
+     * the given range, with mutable references to the values. This is the
+     * mutable counterpart of `query_range`, useful e.g. for accumulating data
+     * into all the intervals touched by a range without first collecting
+     * their keys.
+     */
+    pub fn query_mut<R: RangeBounds<T>>(&mut self, range: R) -> impl Iterator<Item = (&Range<T>, &mut V)> {
+        aug_node::IterRangeQueryMut::new(&mut self.root, range)
+    }
 }
 
 
@@ -150,3 +185,100 @@ impl<T: Ord + Copy, V> FromIterator<(Range<T>, V)> for IntervalMap<T, V> {
         }
     }
 }
+
+/**
+ * The map is serialized as a flat sequence of key-value pairs, rather than as
+ * a tree, so the representation does not depend on the insertion history. The
+ * deserialized map is balanced.
+ */
+#[cfg(feature = "serde")]
+impl<T, V> serde::Serialize for IntervalMap<T, V>
+where
+    T: Ord + Copy + serde::Serialize,
+    V: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T, V> serde::Deserialize<'de> for IntervalMap<T, V>
+where
+    T: Ord + Copy + serde::Deserialize<'de>,
+    V: serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let items: Vec<(Range<T>, V)> = serde::Deserialize::deserialize(deserializer)?;
+        Ok(items.into_iter().collect())
+    }
+}
+
+
+
+
+// ============================================================================
+#[cfg(test)]
+mod test {
+
+    use super::IntervalMap;
+
+    #[test]
+    fn removal_preserves_remaining_values() {
+        let mut map: IntervalMap<_, _> = (0..100).map(|i| (i..i + 5, i)).collect();
+
+        for i in (0..100).step_by(3) {
+            assert_eq!(map.remove(&(i..i + 5)), Some(i));
+        }
+        assert_eq!(map.remove(&(0..5)), None);
+        assert_eq!(map.len(), 66);
+
+        for (k, v) in map.iter() {
+            assert_eq!(k.start, *v);
+        }
+    }
+
+    #[test]
+    fn can_remove_overlapping_intervals() {
+        let mut map: IntervalMap<_, _> = (0..10).map(|i| (i * 10..i * 10 + 10, i)).collect();
+        let mut removed = map.remove_overlapping(25..45);
+        removed.sort_by_key(|(_, v)| *v);
+
+        assert_eq!(removed, vec![(20..30, 2), (30..40, 3), (40..50, 4)]);
+        assert_eq!(map.len(), 7);
+        assert_eq!(map.query_point(35).count(), 0);
+        assert_eq!(map.query_point(55).count(), 1);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn serde_round_trip_preserves_intervals() {
+        let map: IntervalMap<_, _> = (0..10).map(|i| (i * 10..i * 10 + 15, i)).collect();
+        let mut bytes = Vec::new();
+        ciborium::ser::into_writer(&map, &mut bytes).unwrap();
+        let back: IntervalMap<i64, i64> = ciborium::de::from_reader(bytes.as_slice()).unwrap();
+
+        assert_eq!(back.iter().collect::<Vec<_>>(), map.iter().collect::<Vec<_>>());
+        assert_eq!(back.query_point(22).count(), 2);
+    }
+
+    #[test]
+    fn can_accumulate_into_overlapping_intervals() {
+        let mut map: IntervalMap<_, _> = (0..10).map(|i| (i * 10..i * 10 + 10, 0.0)).collect();
+
+        for (_, v) in map.query_mut(15..35) {
+            *v += 1.0
+        }
+        for (_, v) in map.query_point_mut(30) {
+            *v += 0.5
+        }
+        for (_, v) in map.query_mut(..) {
+            *v *= 2.0
+        }
+        assert_eq!(map.get(&(0..10)), Some(&0.0));
+        assert_eq!(map.get(&(10..20)), Some(&2.0));
+        assert_eq!(map.get(&(20..30)), Some(&2.0));
+        assert_eq!(map.get(&(30..40)), Some(&3.0));
+        assert_eq!(map.get(&(40..50)), Some(&0.0));
+    }
+}
@@ -45,7 +45,7 @@ impl<T: Ord + Copy> IntervalSet<T> {
     }
 
     pub fn remove(&mut self, key: &Range<T>) {
-        Node::remove(&mut self.root, key)
+        Node::remove(&mut self.root, key);
     }
 
     pub fn into_balanced(self) -> Self {
@@ -73,6 +73,84 @@ impl<T: Ord + Copy> IntervalSet<T> {
         aug_node::IterRangeQuery::new(&self.root, range).map(|(k, _)| k)
     }
 
+    /**
+     * Return the set of points covered by either this set or the other one.
+     * The result, like that of the other set operations below, is canonical:
+     * its intervals are non-empty, non-overlapping, and non-adjacent.
+     */
+    pub fn union(&self, other: &Self) -> Self {
+        let mut all: Vec<_> = self.iter().chain(other.iter()).cloned().collect();
+        all.sort_by_key(|r| (r.start, r.end));
+        merge_sorted(all).into_iter().collect()
+    }
+
+    /**
+     * Return the set of points covered by both this set and the other one.
+     */
+    pub fn intersection(&self, other: &Self) -> Self {
+        let a = self.canonical();
+        let b = other.canonical();
+        let (mut i, mut j) = (0, 0);
+        let mut result = Vec::new();
+
+        while i < a.len() && j < b.len() {
+            let start = a[i].start.max(b[j].start);
+            let end = a[i].end.min(b[j].end);
+
+            if start < end {
+                result.push(start..end)
+            }
+            if a[i].end < b[j].end {
+                i += 1
+            } else {
+                j += 1
+            }
+        }
+        result.into_iter().collect()
+    }
+
+    /**
+     * Return the set of points covered by this set but not the other one.
+     */
+    pub fn difference(&self, other: &Self) -> Self {
+        let b = other.canonical();
+        let mut result = Vec::new();
+
+        for r in self.canonical() {
+            let mut start = r.start;
+
+            for s in b.iter().filter(|s| s.start < r.end && s.end > r.start) {
+                if start < s.start {
+                    result.push(start..s.start)
+                }
+                start = start.max(s.end)
+            }
+            if start < r.end {
+                result.push(start..r.end)
+            }
+        }
+        result.into_iter().collect()
+    }
+
+    /**
+     * Return the set of points in the given range which are not covered by
+     * this set. For example, if this set contains the intervals where a patch
+     * boundary is covered by neighbors, then the complement within the
+     * boundary is where the physical boundary condition must be applied.
+     */
+    pub fn complement_within(&self, range: Range<T>) -> Self {
+        core::iter::once(range).collect::<Self>().difference(self)
+    }
+
+    /**
+     * Return the canonical form of this set as a sorted vector of intervals.
+     */
+    fn canonical(&self) -> Vec<Range<T>> {
+        let mut all: Vec<_> = self.iter().cloned().collect();
+        all.sort_by_key(|r| (r.start, r.end));
+        merge_sorted(all)
+    }
+
 
 
 
@@ -95,6 +173,25 @@ impl<T: Ord + Copy> IntervalSet<T> {
 
 
 
+/**
+ * Merge a sorted sequence of intervals into a sequence of non-empty,
+ * non-overlapping, non-adjacent intervals.
+ */
+fn merge_sorted<T: Ord + Copy>(sorted: Vec<Range<T>>) -> Vec<Range<T>> {
+    let mut result: Vec<Range<T>> = Vec::new();
+
+    for r in sorted.into_iter().filter(|r| r.start < r.end) {
+        match result.last_mut() {
+            Some(last) if r.start <= last.end => last.end = last.end.max(r.end),
+            _ => result.push(r),
+        }
+    }
+    result
+}
+
+
+
+
 // ============================================================================
 impl<T: Ord + Copy> Default for IntervalSet<T> {
     fn default() -> Self {
@@ -184,6 +281,7 @@ mod test {
             assert!(set.contains(x));
             set.remove(x);
             assert!(!set.contains(x));
+            assert_eq!(set.len(), intervals.len() - 1);
 
             set.validate_max();
             set.validate_order();
@@ -235,13 +333,18 @@ mod test {
         set.insert(1..17);
         set.insert(6..9);
         set.validate_max();
+        let query = |x| {
+            let mut result: Vec<_> = set.query_point(x).cloned().collect();
+            result.sort_by_key(|r| (r.start, r.end));
+            result
+        };
         assert!(set.query_point(-1).count() == 0);
-        assert_eq!(set.query_point(0).collect::<Vec<_>>(), [&(0..10)]);
-        assert_eq!(set.query_point(1).collect::<Vec<_>>(), [&(0..10), &(1..17)]);
-        assert_eq!(set.query_point(2).collect::<Vec<_>>(), [&(0..10), &(2..3), &(1..17)]);
-        assert_eq!(set.query_point(3).collect::<Vec<_>>(), [&(0..10), &(1..17)]);
-        assert_eq!(set.query_point(4).collect::<Vec<_>>(), [&(0..10), &(4..7), &(1..17)]);
-        assert_eq!(set.query_point(11).collect::<Vec<_>>(), [&(1..17), &(8..12)]);
+        assert_eq!(query(0), vec![0..10]);
+        assert_eq!(query(1), [0..10, 1..17]);
+        assert_eq!(query(2), [0..10, 1..17, 2..3]);
+        assert_eq!(query(3), [0..10, 1..17]);
+        assert_eq!(query(4), [0..10, 1..17, 4..7]);
+        assert_eq!(query(11), [1..17, 8..12]);
     }
 
     #[test]
@@ -253,4 +356,36 @@ mod test {
         set.insert(2..5);
         assert_eq!(set.query_range(5..10).collect::<Vec<_>>(), [&(4..10), &(6..12)]);
     }
+
+    fn sorted(set: IntervalSet<i32>) -> Vec<Range<i32>> {
+        set.into_sorted().collect()
+    }
+
+    #[test]
+    fn set_union_is_canonical() {
+        let a: IntervalSet<_> = vec![0..2, 1..4, 8..10].into_iter().collect();
+        let b: IntervalSet<_> = vec![4..5, 12..12, 9..11].into_iter().collect();
+        assert_eq!(sorted(a.union(&b)), vec![0..5, 8..11]);
+    }
+
+    #[test]
+    fn set_intersection_works() {
+        let a: IntervalSet<_> = vec![0..4, 6..10].into_iter().collect();
+        let b: IntervalSet<_> = vec![2..7, 9..12].into_iter().collect();
+        assert_eq!(sorted(a.intersection(&b)), vec![2..4, 6..7, 9..10]);
+    }
+
+    #[test]
+    fn set_difference_works() {
+        let a: IntervalSet<_> = vec![0..10, 20..30].into_iter().collect();
+        let b: IntervalSet<_> = vec![2..4, 3..5, 8..22].into_iter().collect();
+        assert_eq!(sorted(a.difference(&b)), vec![0..2, 5..8, 22..30]);
+    }
+
+    #[test]
+    fn set_complement_within_works() {
+        let covered: IntervalSet<_> = vec![-2..3, 5..7].into_iter().collect();
+        assert_eq!(sorted(covered.complement_within(0..10)), vec![3..5, 7..10]);
+        assert!(IntervalSet::new().complement_within(0..0).is_empty());
+    }
 }
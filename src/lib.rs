@@ -49,6 +49,8 @@ pub mod adjacency_list;
 pub mod aug_node;
 pub mod automaton;
 pub mod coder;
+pub mod diagnostics;
+pub mod flux_register;
 pub mod index_space;
 pub mod interval_map;
 pub mod interval_set;
@@ -59,4 +61,6 @@ pub mod num_vec;
 pub mod overlap;
 pub mod patch;
 pub mod rect_map;
+pub mod refinement;
+pub mod subcycling;
 pub mod thread_pool;
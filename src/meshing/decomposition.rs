@@ -0,0 +1,834 @@
+//! Exports the [`DomainDecomposition`] builder, which divides a domain into
+//! blocks, connects them and assigns them to ranks.
+
+use super::{
+    coarsen_inward, edges_to_rectangles, hilbert_partition, level_adjacency, partition_edges,
+    partition_levels, read_f64, read_i64, read_rect, read_u64, write_f64s, write_i64s,
+    write_rect, write_u64, Cartesian, MovingCartesian,
+};
+use crate::adjacency_list::AdjacencyList;
+use crate::index_space::{self, Extension, IndexSpace, Stencil};
+use crate::rect_map::{Rectangle, RectangleMap};
+use std::collections::BTreeMap;
+use std::io::{self, Read, Write};
+use std::{error, fmt};
+
+/// Describes how a [`DomainDecomposition`] divides its index space into
+/// blocks.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlockLayout {
+    /// Square blocks with the given number of zones on a side. Blocks at the
+    /// upper end of each axis are truncated if the size does not divide the
+    /// resolution.
+    Size(usize),
+
+    /// The given number of blocks, arranged on a grid whose dimensions are
+    /// found by [`block_dims`](index_space::block_dims). Block sizes on each
+    /// axis differ by at most one zone.
+    Count(usize),
+
+    /// Like [`BlockLayout::Count`], but the grid dimensions are found by
+    /// [`block_dims_for_shape`](index_space::block_dims_for_shape) from the
+    /// resolution, so that blocks are as close to square as the count allows.
+    CountForShape(usize),
+}
+
+/// The location of a [`RefinedRegion`]: a rectangle in physical
+/// coordinates, or an index space measured at the base resolution of a
+/// [`DomainDecomposition`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum RegionBounds {
+    Physical(Rectangle<f64>),
+    Index(IndexSpace),
+}
+
+/// A region of static mesh refinement. Zones in the region are refined
+/// `depth` times, by a factor of two each time, relative to the base
+/// resolution of a [`DomainDecomposition`]. A physical rectangle is widened
+/// to the smallest range of base zones which covers it.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RefinedRegion {
+    pub bounds: RegionBounds,
+    pub depth: u32,
+}
+
+/// Error returned when a [`DomainDecomposition`] cannot be constructed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum DecompositionError {
+    /// The resolution, block size, block count, or number of ranks is zero.
+    Empty,
+
+    /// The block grid has more blocks along an axis than there are zones.
+    TooManyBlocks(usize, usize),
+
+    /// There are fewer blocks than ranks, so some ranks would be idle.
+    TooFewBlocks(usize, usize),
+
+    /// The refined region at the given position in the list does not overlap
+    /// the domain.
+    RegionOutsideDomain(usize),
+
+    /// The rank speeds are not one positive number for each rank.
+    InvalidSpeeds,
+}
+
+impl fmt::Display for DecompositionError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            DecompositionError::Empty => {
+                write!(fmt, "resolution, blocks, and ranks must all be non-zero")
+            }
+            DecompositionError::TooManyBlocks(blocks, zones) => write!(
+                fmt,
+                "cannot fit {} blocks along an axis with {} zones",
+                blocks, zones
+            ),
+            DecompositionError::TooFewBlocks(blocks, ranks) => {
+                write!(fmt, "{} blocks cannot be shared by {} ranks", blocks, ranks)
+            }
+            DecompositionError::RegionOutsideDomain(n) => {
+                write!(fmt, "refined region {} does not overlap the domain", n)
+            }
+            DecompositionError::InvalidSpeeds => {
+                write!(fmt, "there must be one positive speed for each rank")
+            }
+        }
+    }
+}
+
+impl error::Error for DecompositionError {}
+
+/// A uniform-resolution (level 0) decomposition of a rectangular physical
+/// domain into blocks, together with the adjacency list of the blocks and
+/// their assignment to ranks. The rank assignment is made by
+/// [`hilbert_partition`], and the adjacency list uses one guard zone unless
+/// another extension is given with [`DomainDecomposition::with_extension`].
+/// Adjacency list keys have the same form as those of
+/// [`GraphTopology::adjacency_list`](super::GraphTopology::adjacency_list)
+/// for patches, so they can be used with patch-based solvers directly.
+/// Static refinement may be added with
+/// [`DomainDecomposition::with_refinement`], in which case the base blocks
+/// are no longer on level 0.
+#[derive(Clone)]
+pub struct DomainDecomposition {
+    extent: Rectangle<f64>,
+    resolution: (usize, usize),
+    num_ranks: usize,
+    blocks: Vec<Rectangle<i64>>,
+    base_level: u32,
+    extension: Extension,
+    corners: bool,
+    periodic: (bool, bool),
+    rank_speeds: Option<Vec<f64>>,
+    adjacency: AdjacencyList<(Rectangle<i64>, u32)>,
+    work: BTreeMap<u32, RectangleMap<i64, usize>>,
+    motion: MovingCartesian,
+}
+
+impl DomainDecomposition {
+    /// Decomposes the physical `extent`, with `resolution` zones on each
+    /// axis, into blocks given by `layout`, and assigns them to `num_ranks`
+    /// ranks.
+    pub fn new(
+        extent: Rectangle<f64>,
+        resolution: (usize, usize),
+        layout: BlockLayout,
+        num_ranks: usize,
+    ) -> Result<Self, DecompositionError> {
+        let space = IndexSpace::new(0..resolution.0 as i64, 0..resolution.1 as i64);
+
+        if space.is_empty() || num_ranks == 0 {
+            return Err(DecompositionError::Empty);
+        }
+        let blocks: Vec<_> = match layout {
+            BlockLayout::Size(0) | BlockLayout::Count(0) | BlockLayout::CountForShape(0) => {
+                return Err(DecompositionError::Empty);
+            }
+            BlockLayout::Size(size) => space.tiles((size, size)).map(|s| s.to_rect()).collect(),
+            BlockLayout::Count(count) | BlockLayout::CountForShape(count) => {
+                let dims = match layout {
+                    BlockLayout::Count(_) => index_space::block_dims(count, 2),
+                    _ => index_space::block_dims_for_shape(count, &[resolution.0, resolution.1]),
+                };
+
+                for (&n, &zones) in dims.iter().zip(&[resolution.0, resolution.1]) {
+                    if n > zones {
+                        return Err(DecompositionError::TooManyBlocks(n, zones));
+                    }
+                }
+                edges_to_rectangles(
+                    &partition_edges(resolution.0, dims[0]),
+                    &partition_edges(resolution.1, dims[1]),
+                    (0, 0),
+                )
+            }
+        };
+        if blocks.len() < num_ranks {
+            return Err(DecompositionError::TooFewBlocks(blocks.len(), num_ranks));
+        }
+        let work = hilbert_partition(blocks.iter().cloned(), num_ranks);
+        let motion = MovingCartesian::stationary(Cartesian::new(extent.clone(), resolution));
+
+        let result = Self {
+            extent,
+            resolution,
+            num_ranks,
+            blocks,
+            base_level: 0,
+            extension: Extension::uniform(1),
+            corners: true,
+            periodic: (false, false),
+            rank_speeds: None,
+            adjacency: AdjacencyList::new(),
+            work: std::iter::once((0, work)).collect(),
+            motion,
+        };
+        Ok(result.with_extension(Extension::uniform(1)))
+    }
+
+    /// Rebuilds the adjacency list for the given guard zone extension. An
+    /// edge `A -> B` is inserted if `A` overlaps `B` when `B` is extended.
+    /// The extension is measured at the level of `B`, and blocks on
+    /// different levels are connected where they overlap.
+    pub fn with_extension(mut self, extension: Extension) -> Self {
+        self.extension = extension;
+        self.corners = true;
+        self.rebuild_adjacency();
+        self
+    }
+
+    /// Rebuilds the adjacency list for the guard zones of the given stencil.
+    /// This is like [`DomainDecomposition::with_extension`], except that if
+    /// the stencil does not need corners, blocks which only touch `B`
+    /// diagonally are not connected to it.
+    pub fn with_stencil(mut self, stencil: Stencil) -> Self {
+        self.extension = stencil.extension();
+        self.corners = stencil.corners;
+        self.rebuild_adjacency();
+        self
+    }
+
+    /// Rebuilds the adjacency list for a domain which is periodic on the
+    /// `I` and/or `J` axes, so that blocks at opposite ends of a periodic
+    /// axis are connected. The guard zones are then filled with a
+    /// [`BoundaryTopology`](super::BoundaryTopology) that is periodic on the
+    /// same axes.
+    pub fn with_periodic(mut self, periodic: (bool, bool)) -> Self {
+        self.periodic = periodic;
+        self.rebuild_adjacency();
+        self
+    }
+
+    /// Returns whether the domain is periodic on the `I` and `J` axes.
+    pub fn periodic(&self) -> (bool, bool) {
+        self.periodic
+    }
+
+    /// Returns the per-rank speeds given to
+    /// [`DomainDecomposition::with_rank_speeds`], if any.
+    pub fn rank_speeds(&self) -> Option<&[f64]> {
+        self.rank_speeds.as_deref()
+    }
+
+    fn rebuild_adjacency(&mut self) {
+        let domain = self.index_space().refine_by(1 << self.base_level);
+        let periodic = Some((&domain, self.periodic)).filter(|(_, p)| p.0 || p.1);
+        self.adjacency = level_adjacency(&self.work, &self.extension, self.corners, periodic, &[]);
+    }
+
+    /// Adds blocks of static refinement covering the given regions, replacing
+    /// any added previously, and reassigns all the blocks to ranks. Recall
+    /// that level 0 is the finest: the base blocks are moved to a level equal
+    /// to the greatest region depth, and a region of depth `d` is covered by
+    /// blocks `d` levels finer than that. Each refined level covers the union
+    /// of the regions at least as deep, so the levels are properly nested.
+    /// Refined zones are tiled into blocks no larger than the largest base
+    /// block. Ranks are assigned along a Hilbert curve through the blocks of
+    /// every level, weighted by their number of zones; without refinement the
+    /// assignment is the same as from [`DomainDecomposition::new`]. Regions
+    /// of depth zero have no effect. The adjacency list is rebuilt with the
+    /// current extension or stencil. An error is returned if a region does
+    /// not overlap the domain.
+    pub fn with_refinement(
+        mut self,
+        regions: &[RefinedRegion],
+    ) -> Result<Self, DecompositionError> {
+        let domain = self.index_space();
+        let mut spaces = Vec::new();
+
+        for (n, region) in regions.iter().enumerate() {
+            let space = match &region.bounds {
+                RegionBounds::Index(space) => space.intersect(&domain),
+                RegionBounds::Physical(rect) => self.covering_space(rect).intersect(&domain),
+            };
+            match space.filter(|space| !space.is_empty()) {
+                Some(space) if region.depth > 0 => spaces.push((space, region.depth)),
+                Some(_) => {}
+                None => return Err(DecompositionError::RegionOutsideDomain(n)),
+            }
+        }
+
+        let base_level = spaces.iter().map(|(_, depth)| *depth).max().unwrap_or(0);
+        let tile_shape = self.blocks.iter().fold((1, 1), |shape, (di, dj)| {
+            (
+                shape.0.max((di.end - di.start) as usize),
+                shape.1.max((dj.end - dj.start) as usize),
+            )
+        });
+        let mut blocks: Vec<_> = self
+            .blocks
+            .iter()
+            .map(|block| (IndexSpace::from(block.clone()), base_level))
+            .collect();
+
+        for depth in 1..=base_level {
+            let mut covered: Vec<IndexSpace> = Vec::new();
+
+            for (space, _) in spaces.iter().filter(|(_, d)| *d >= depth) {
+                let mut pieces = vec![space.clone()];
+
+                for c in &covered {
+                    pieces = pieces.iter().flat_map(|p| p.subtract(c)).collect();
+                }
+                covered.extend(pieces);
+            }
+            for space in covered {
+                for tile in space.refine_by(1 << depth).tiles(tile_shape) {
+                    blocks.push((tile, base_level - depth))
+                }
+            }
+        }
+
+        self.base_level = base_level;
+        self.assign_ranks(blocks);
+        self.rebuild_adjacency();
+        Ok(self)
+    }
+
+    /// Reassigns the blocks of every level to ranks in proportion to the
+    /// given per-rank speeds (for example measured in a calibration step), so
+    /// that ranks on slower nodes receive proportionally fewer zones. Ranks
+    /// are assigned along a Hilbert curve through the blocks, weighted by
+    /// their number of zones, and every rank gets at least one block. The
+    /// speeds are kept if static refinement is added afterwards. An error is
+    /// returned unless there is one positive speed for each rank.
+    pub fn with_rank_speeds(mut self, speeds: &[f64]) -> Result<Self, DecompositionError> {
+        if speeds.len() != self.num_ranks || !speeds.iter().all(|&s| s > 0.0) {
+            return Err(DecompositionError::InvalidSpeeds);
+        }
+        let blocks = self
+            .work
+            .iter()
+            .flat_map(|(&level, map)| map.keys().map(move |rect| (IndexSpace::from(rect), level)))
+            .collect();
+        self.rank_speeds = Some(speeds.to_vec());
+        self.assign_ranks(blocks);
+        Ok(self)
+    }
+
+    fn assign_ranks(&mut self, blocks: Vec<(IndexSpace, u32)>) {
+        self.work = if self.base_level == 0 && self.rank_speeds.is_none() {
+            let mut work = BTreeMap::new();
+            work.insert(
+                0,
+                hilbert_partition(self.blocks.iter().cloned(), self.num_ranks),
+            );
+            work
+        } else {
+            let shares = match &self.rank_speeds {
+                Some(speeds) => speeds.clone(),
+                None => vec![1.0; self.num_ranks],
+            };
+            partition_levels(blocks, &shares, &[])
+        };
+    }
+
+    /// Returns the physical extent of the domain.
+    pub fn extent(&self) -> &Rectangle<f64> {
+        &self.extent
+    }
+
+    /// Returns the number of zones on each axis.
+    pub fn resolution(&self) -> (usize, usize) {
+        self.resolution
+    }
+
+    /// Returns the number of ranks the blocks are assigned to.
+    pub fn num_ranks(&self) -> usize {
+        self.num_ranks
+    }
+
+    /// Returns the index space of the whole domain.
+    pub fn index_space(&self) -> IndexSpace {
+        IndexSpace::new(0..self.resolution.0 as i64, 0..self.resolution.1 as i64)
+    }
+
+    /// Returns the physical size of a zone on each axis.
+    pub fn cell_spacing(&self) -> (f64, f64) {
+        let (x, y) = &self.extent;
+        (
+            (x.end - x.start) / self.resolution.0 as f64,
+            (y.end - y.start) / self.resolution.1 as f64,
+        )
+    }
+
+    /// Returns the physical coordinates of the center of a zone.
+    pub fn cell_center(&self, index: (i64, i64)) -> (f64, f64) {
+        let (dx, dy) = self.cell_spacing();
+        (
+            self.extent.0.start + dx * (index.0 as f64 + 0.5),
+            self.extent.1.start + dy * (index.1 as f64 + 0.5),
+        )
+    }
+
+    /// Returns the smallest index space (at the base level) whose zones cover
+    /// the given physical rectangle.
+    fn covering_space(&self, rect: &Rectangle<f64>) -> IndexSpace {
+        let (dx, dy) = self.cell_spacing();
+        let (x, y) = &self.extent;
+        let lower = |a: f64, a0: f64, da: f64| ((a - a0) / da).floor() as i64;
+        let upper = |a: f64, a0: f64, da: f64| ((a - a0) / da).ceil() as i64;
+        IndexSpace::new(
+            lower(rect.0.start, x.start, dx)..upper(rect.0.end, x.start, dx),
+            lower(rect.1.start, y.start, dy)..upper(rect.1.end, y.start, dy),
+        )
+    }
+
+    /// Returns the Cartesian geometry of the domain, at time zero.
+    pub fn geometry(&self) -> Cartesian {
+        Cartesian::new(self.extent.clone(), self.resolution)
+    }
+
+    /// Makes the mesh move with the given origin velocity, and expand at the
+    /// given rate on each axis (see [`MovingCartesian`]). The extent given
+    /// to [`DomainDecomposition::new`] is the mesh position at time zero.
+    pub fn with_motion(mut self, velocity: (f64, f64), expansion_rate: (f64, f64)) -> Self {
+        self.motion.velocity = velocity;
+        self.motion.expansion_rate = expansion_rate;
+        self
+    }
+
+    /// Returns the time-dependent mapping of the mesh.
+    pub fn motion(&self) -> &MovingCartesian {
+        &self.motion
+    }
+
+    /// Returns the Cartesian geometry of the domain at the given time.
+    pub fn geometry_at(&self, time: f64) -> Cartesian {
+        self.motion.at(time)
+    }
+
+    /// Returns the level of the base blocks. This is zero unless static
+    /// refinement has been added.
+    pub fn base_level(&self) -> u32 {
+        self.base_level
+    }
+
+    /// Returns the base block rectangles, measured at the base level, in
+    /// row-major order.
+    pub fn blocks(&self) -> &[Rectangle<i64>] {
+        &self.blocks
+    }
+
+    /// Returns the index space (measured at its own level) and level of each
+    /// block of static refinement, from finest to coarsest. Patches for these
+    /// blocks are created with the given level and index space.
+    pub fn refined_blocks(&self) -> impl Iterator<Item = (IndexSpace, u32)> + '_ {
+        let base_level = self.base_level;
+        self.work
+            .range(..base_level)
+            .flat_map(|(&level, map)| map.keys().map(move |rect| (IndexSpace::from(rect), level)))
+    }
+
+    /// Returns the adjacency list of the blocks.
+    pub fn adjacency_list(&self) -> &AdjacencyList<(Rectangle<i64>, u32)> {
+        &self.adjacency
+    }
+
+    /// Returns a map from base block rectangles to the ranks that own them.
+    pub fn work(&self) -> &RectangleMap<i64, usize> {
+        &self.work[&self.base_level]
+    }
+
+    /// Returns the maps from block rectangles to the ranks that own them, on
+    /// every level, keyed by level. This is the layout consumed and produced
+    /// by [`regrid`](super::regrid).
+    pub fn level_work(&self) -> &BTreeMap<u32, RectangleMap<i64, usize>> {
+        &self.work
+    }
+
+    /// Returns the rank which owns the given base block, if it is one of the
+    /// base blocks of this decomposition.
+    pub fn rank_of(&self, block: &Rectangle<i64>) -> Option<usize> {
+        self.work().get((&block.0, &block.1)).cloned()
+    }
+
+    /// Returns the rank which owns the block with the given adjacency list
+    /// key (its high resolution rectangle and level), on any level.
+    pub fn rank_of_key(&self, key: &(Rectangle<i64>, u32)) -> Option<usize> {
+        let (rect, level) = key;
+        let rect = coarsen_inward(&IndexSpace::from(rect.clone()), 1 << level)?.to_rect();
+        self.work.get(level)?.get((&rect.0, &rect.1)).cloned()
+    }
+
+    /// Returns an iterator over the blocks owned by the given rank.
+    pub fn blocks_for_rank(&self, rank: usize) -> impl Iterator<Item = &Rectangle<i64>> + '_ {
+        self.blocks
+            .iter()
+            .filter(move |block| self.rank_of(block) == Some(rank))
+    }
+
+    /// Writes this decomposition to a compact binary stream: the extent,
+    /// resolution, mesh motion, guard zone stencil, periodic axes and rank
+    /// speeds, then every block (in order, with its level and rank), then the
+    /// adjacency list. Numbers are little-endian. A decomposition read back
+    /// with [`DomainDecomposition::load`] is identical to this one, so
+    /// restarts and post-processing tools do not need to re-derive it.
+    pub fn save<W: Write>(&self, mut writer: W) -> io::Result<()> {
+        let w = &mut writer;
+        let Extension { lower, upper } = self.extension;
+        w.write_all(DECOMPOSITION_MAGIC)?;
+        write_u64(w, DECOMPOSITION_VERSION)?;
+        write_f64s(w, &[self.extent.0.start, self.extent.0.end])?;
+        write_f64s(w, &[self.extent.1.start, self.extent.1.end])?;
+        write_u64(w, self.resolution.0 as u64)?;
+        write_u64(w, self.resolution.1 as u64)?;
+        write_u64(w, self.num_ranks as u64)?;
+        write_u64(w, self.base_level as u64)?;
+        write_i64s(w, &[lower.0, lower.1, upper.0, upper.1])?;
+        write_u64(w, self.corners as u64)?;
+        write_u64(w, self.periodic.0 as u64)?;
+        write_u64(w, self.periodic.1 as u64)?;
+        write_u64(w, self.rank_speeds.as_ref().map_or(0, |s| s.len()) as u64)?;
+        write_f64s(w, self.rank_speeds.as_deref().unwrap_or(&[]))?;
+        write_f64s(w, &[self.motion.velocity.0, self.motion.velocity.1])?;
+        write_f64s(
+            w,
+            &[self.motion.expansion_rate.0, self.motion.expansion_rate.1],
+        )?;
+
+        write_u64(w, self.blocks.len() as u64)?;
+
+        for block in &self.blocks {
+            write_rect(w, block)?;
+            write_u64(w, self.rank_of(block).unwrap() as u64)?;
+        }
+        let refined: Vec<_> = self.refined_blocks().collect();
+        write_u64(w, refined.len() as u64)?;
+
+        for (space, level) in refined {
+            let rank = self.work[&level].get(space.to_rect_ref()).unwrap();
+            write_u64(w, level as u64)?;
+            write_rect(w, &space.to_rect())?;
+            write_u64(w, *rank as u64)?;
+        }
+        write_u64(w, self.adjacency.len() as u64)?;
+
+        for (a, b) in self.adjacency.iter() {
+            for (rect, level) in [a, b] {
+                write_rect(w, rect)?;
+                write_u64(w, *level as u64)?;
+            }
+        }
+        w.flush()
+    }
+
+    /// Reads a decomposition written by [`DomainDecomposition::save`]. An
+    /// error of kind `InvalidData` is returned if the stream was not written
+    /// by `save`, or by an incompatible version of it.
+    pub fn load<R: Read>(mut reader: R) -> io::Result<Self> {
+        let r = &mut reader;
+        let mut magic = [0; 8];
+        r.read_exact(&mut magic)?;
+
+        let version = read_u64(r)?;
+
+        if &magic != DECOMPOSITION_MAGIC || !(1..=DECOMPOSITION_VERSION).contains(&version) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "not a gridiron decomposition file, or an unsupported version",
+            ));
+        }
+        let x = read_f64(r)?..read_f64(r)?;
+        let y = read_f64(r)?..read_f64(r)?;
+        let extent = (x, y);
+        let resolution = (read_u64(r)? as usize, read_u64(r)? as usize);
+        let num_ranks = read_u64(r)? as usize;
+        let base_level = read_u64(r)? as u32;
+        let extension = Extension {
+            lower: (read_i64(r)?, read_i64(r)?),
+            upper: (read_i64(r)?, read_i64(r)?),
+        };
+        let corners = version < 2 || read_u64(r)? != 0;
+        let periodic = if version < 3 {
+            (false, false)
+        } else {
+            (read_u64(r)? != 0, read_u64(r)? != 0)
+        };
+        let rank_speeds = match if version < 4 { 0 } else { read_u64(r)? } {
+            0 => None,
+            n => Some((0..n).map(|_| read_f64(r)).collect::<io::Result<Vec<_>>>()?),
+        };
+        let mut motion = MovingCartesian::stationary(Cartesian::new(extent.clone(), resolution));
+        motion.velocity = (read_f64(r)?, read_f64(r)?);
+        motion.expansion_rate = (read_f64(r)?, read_f64(r)?);
+
+        let mut blocks = Vec::new();
+        let mut work: BTreeMap<u32, RectangleMap<i64, usize>> = BTreeMap::new();
+        work.insert(base_level, RectangleMap::new());
+
+        for _ in 0..read_u64(r)? {
+            let block = read_rect(r)?;
+            let rank = read_u64(r)? as usize;
+            work.entry(base_level)
+                .or_default()
+                .insert(block.clone(), rank);
+            blocks.push(block);
+        }
+        for _ in 0..read_u64(r)? {
+            let level = read_u64(r)? as u32;
+            let block = read_rect(r)?;
+            let rank = read_u64(r)? as usize;
+            work.entry(level).or_default().insert(block, rank);
+        }
+        let mut adjacency = AdjacencyList::new();
+
+        for _ in 0..read_u64(r)? {
+            let a = (read_rect(r)?, read_u64(r)? as u32);
+            let b = (read_rect(r)?, read_u64(r)? as u32);
+            adjacency.insert(a, b);
+        }
+
+        Ok(Self {
+            extent,
+            resolution,
+            num_ranks,
+            blocks,
+            base_level,
+            extension,
+            corners,
+            periodic,
+            rank_speeds,
+            adjacency,
+            work,
+            motion,
+        })
+    }
+}
+
+const DECOMPOSITION_MAGIC: &[u8; 8] = b"GRIDDCMP";
+
+const DECOMPOSITION_VERSION: u64 = 4;
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index_space::range2d;
+    use crate::meshing::hilbert_partition_by_speed;
+
+    #[test]
+    fn domain_decomposition_assigns_every_block() {
+        let decomp =
+            DomainDecomposition::new((-1.0..1.0, -1.0..1.0), (100, 100), BlockLayout::Size(25), 4)
+                .unwrap();
+        assert_eq!(decomp.blocks().len(), 16);
+        assert_eq!(decomp.adjacency_list().len(), 2 * (24 + 18));
+        assert_eq!(decomp.cell_center((0, 99)), (-0.99, 0.99));
+        assert!((0..4).all(|rank| decomp.blocks_for_rank(rank).count() == 4));
+        assert_eq!(decomp.rank_of(&(0..25, 0..25)), Some(0));
+        assert_eq!(decomp.rank_of(&(0..10, 0..10)), None);
+
+        let decomp =
+            DomainDecomposition::new((0.0..1.0, 0.0..1.0), (10, 7), BlockLayout::Count(6), 6)
+                .unwrap()
+                .with_extension(Extension::per_axis(1, 0));
+        assert_eq!(decomp.blocks().len(), 6);
+        assert!(decomp.blocks().iter().all(|b| b.0.end - b.0.start <= 5));
+        assert_eq!(decomp.adjacency_list().len(), 2 * 3);
+
+        let new = |layout| DomainDecomposition::new((0.0..4.0, 0.0..1.0), (400, 100), layout, 4);
+        let square = new(BlockLayout::Count(4)).unwrap();
+        let shaped = new(BlockLayout::CountForShape(4)).unwrap();
+        assert!(square.blocks().iter().all(|b| b.0.end - b.0.start == 200 && b.1.end - b.1.start == 50));
+        assert!(shaped.blocks().iter().all(|b| b.0.end - b.0.start == 100 && b.1 == (0..100)));
+        assert_eq!(
+            new(BlockLayout::CountForShape(0)).err(),
+            Some(DecompositionError::Empty)
+        );
+    }
+
+    #[test]
+    fn domain_decomposition_rejects_bad_layouts() {
+        let extent = (0.0..1.0, 0.0..1.0);
+        let new = |layout, ranks| DomainDecomposition::new(extent.clone(), (8, 8), layout, ranks);
+        assert_eq!(
+            new(BlockLayout::Size(0), 1).err(),
+            Some(DecompositionError::Empty)
+        );
+        assert_eq!(
+            new(BlockLayout::Count(128), 1).err(),
+            Some(DecompositionError::TooManyBlocks(16, 8))
+        );
+        assert_eq!(
+            new(BlockLayout::Size(4), 8).err(),
+            Some(DecompositionError::TooFewBlocks(4, 8))
+        );
+    }
+
+    #[test]
+    fn domain_decomposition_honors_refined_regions() {
+        let decomp =
+            DomainDecomposition::new((0.0..1.0, 0.0..1.0), (16, 16), BlockLayout::Size(8), 4)
+                .unwrap()
+                .with_refinement(&[
+                    RefinedRegion {
+                        bounds: RegionBounds::Index(IndexSpace::new(0..8, 0..8)),
+                        depth: 1,
+                    },
+                    RefinedRegion {
+                        bounds: RegionBounds::Physical((0.1..0.2, 0.1..0.2)),
+                        depth: 2,
+                    },
+                ])
+                .unwrap();
+        let refined: Vec<_> = decomp.refined_blocks().collect();
+        assert_eq!(decomp.base_level(), 2);
+        assert_eq!(refined.len(), 8);
+        assert_eq!(refined[0], (IndexSpace::new(4..12, 4..12), 0));
+        assert!(refined[4..]
+            .iter()
+            .all(|(space, level)| *level == 1 && space.len() == 64));
+        assert_eq!(
+            decomp.rank_of_key(&((0..32, 0..32), 2)),
+            decomp.rank_of(&(0..8, 0..8))
+        );
+        assert!(decomp.rank_of_key(&((4..12, 4..12), 0)).is_some());
+        assert!(decomp
+            .adjacency_list()
+            .contains(&((0..32, 0..32), 2), &((4..12, 4..12), 0)));
+
+        let extent = (0.0..1.0, 0.0..1.0);
+        let outside = RefinedRegion {
+            bounds: RegionBounds::Physical((2.0..3.0, 0.0..1.0)),
+            depth: 1,
+        };
+        assert_eq!(
+            DomainDecomposition::new(extent, (8, 8), BlockLayout::Size(4), 1)
+                .unwrap()
+                .with_refinement(&[outside])
+                .err(),
+            Some(DecompositionError::RegionOutsideDomain(0))
+        );
+    }
+
+    #[test]
+    fn domain_decomposition_connects_blocks_across_periodic_axes() {
+        let decomp =
+            DomainDecomposition::new((0.0..1.0, 0.0..1.0), (12, 12), BlockLayout::Size(4), 1)
+                .unwrap()
+                .with_stencil(Stencil::star(1));
+        let left = ((0..4, 4..8), 0);
+        let right = ((8..12, 4..8), 0);
+        assert!(!decomp.adjacency_list().contains(&right, &left));
+
+        let decomp = decomp.with_periodic((true, false));
+        assert!(decomp.adjacency_list().contains(&right, &left));
+        assert!(decomp.adjacency_list().contains(&left, &right));
+        assert!(!decomp
+            .adjacency_list()
+            .contains(&((4..8, 8..12), 0), &((4..8, 0..4), 0)));
+        assert!(!decomp
+            .adjacency_list()
+            .contains(&((8..12, 8..12), 0), &((0..4, 4..8), 0)));
+    }
+
+    #[test]
+    fn domain_decomposition_save_load_round_trip_works() {
+        let region = RefinedRegion {
+            bounds: RegionBounds::Index(IndexSpace::new(4..12, 0..8)),
+            depth: 1,
+        };
+        let decomp =
+            DomainDecomposition::new((0.0..1.0, 0.0..2.0), (16, 16), BlockLayout::Count(8), 3)
+                .unwrap()
+                .with_motion((1.0, 0.0), (0.0, 0.5))
+                .with_refinement(std::slice::from_ref(&region))
+                .unwrap()
+                .with_extension(Extension::per_axis(2, 1))
+                .with_periodic((true, false));
+
+        let mut bytes = Vec::new();
+        decomp.save(&mut bytes).unwrap();
+        let loaded = DomainDecomposition::load(bytes.as_slice()).unwrap();
+
+        assert_eq!(loaded.extent(), decomp.extent());
+        assert_eq!(loaded.resolution(), decomp.resolution());
+        assert_eq!(loaded.num_ranks(), decomp.num_ranks());
+        assert_eq!(loaded.base_level(), decomp.base_level());
+        assert_eq!(loaded.blocks(), decomp.blocks());
+        assert_eq!(loaded.motion(), decomp.motion());
+        assert_eq!(loaded.periodic(), (true, false));
+        assert_eq!(loaded.rank_speeds(), None);
+        assert_eq!(
+            loaded.refined_blocks().collect::<Vec<_>>(),
+            decomp.refined_blocks().collect::<Vec<_>>()
+        );
+        assert_eq!(loaded.adjacency_list().len(), decomp.adjacency_list().len());
+        assert!(decomp
+            .adjacency_list()
+            .iter()
+            .all(|(a, b)| loaded.adjacency_list().contains(a, b)));
+        assert!(decomp
+            .adjacency_list()
+            .vertices()
+            .all(|key| loaded.rank_of_key(key) == decomp.rank_of_key(key)));
+
+        let decomp = decomp.with_rank_speeds(&[2.0, 1.0, 1.0]).unwrap();
+        let mut bytes = Vec::new();
+        decomp.save(&mut bytes).unwrap();
+        let loaded = DomainDecomposition::load(bytes.as_slice())
+            .unwrap()
+            .with_refinement(std::slice::from_ref(&region))
+            .unwrap();
+        let decomp = decomp.with_refinement(&[region]).unwrap();
+        assert_eq!(loaded.rank_speeds(), Some(&[2.0, 1.0, 1.0][..]));
+        assert!(decomp
+            .adjacency_list()
+            .vertices()
+            .all(|key| loaded.rank_of_key(key) == decomp.rank_of_key(key)));
+
+        bytes[0] = b'X';
+        assert_eq!(
+            DomainDecomposition::load(bytes.as_slice())
+                .err()
+                .map(|e| e.kind()),
+            Some(std::io::ErrorKind::InvalidData)
+        );
+    }
+
+    #[test]
+    fn hilbert_partition_by_speed_favors_fast_ranks() {
+        let blocks = range2d(0..4, 0..4)
+            .into_iter()
+            .map(|(i, j)| ((i..i + 1, j..j + 1), 1.0));
+        let work = hilbert_partition_by_speed(blocks, &[1.0, 3.0]);
+        assert_eq!(work.iter().filter(|(_, &rank)| rank == 0).count(), 4);
+        assert_eq!(work.iter().filter(|(_, &rank)| rank == 1).count(), 12);
+
+        let decomp =
+            DomainDecomposition::new((0.0..1.0, 0.0..1.0), (16, 16), BlockLayout::Size(4), 3)
+                .unwrap()
+                .with_rank_speeds(&[2.0, 1.0, 1.0])
+                .unwrap();
+        assert_eq!(decomp.blocks_for_rank(0).count(), 8);
+        assert_eq!(decomp.blocks_for_rank(1).count(), 4);
+        assert_eq!(
+            decomp.clone().with_rank_speeds(&[1.0, 0.0, 1.0]).err(),
+            Some(DecompositionError::InvalidSpeeds)
+        );
+        assert_eq!(
+            decomp.with_rank_speeds(&[1.0]).err(),
+            Some(DecompositionError::InvalidSpeeds)
+        );
+    }
+}
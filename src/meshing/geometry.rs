@@ -0,0 +1,481 @@
+//! Coordinate geometries mapping index space to physical space, and
+//! conservative remapping between meshes.
+
+use crate::index_space::{Axis, IndexSpace};
+use crate::patch::Patch;
+use crate::rect_map::Rectangle;
+use std::f64::consts::PI;
+use std::ops::Range;
+
+/// Maps zone indexes to physical space, supplying the zone centers, face
+/// areas, and volumes needed by finite-volume solvers. Grid lines are
+/// numbered by level 0 indexes: the zone `(i, j)` at level `l` is bounded by
+/// the grid lines `i * 2^l` and `(i + 1) * 2^l` on the `I` axis, and likewise
+/// on the `J` axis. The `I` and `J` axes are the first and second coordinates
+/// of the geometry (for example `r` and `z` in cylindrical coordinates).
+/// Curvilinear geometries are axisymmetric, and their areas and volumes
+/// include the full revolution about the symmetry axis.
+pub trait Geometry {
+    /// Returns the physical coordinate of the grid line `n` on the given
+    /// axis.
+    fn coordinate(&self, axis: Axis, n: i64) -> f64;
+
+    /// Returns the volume of the region bounded by the given coordinate
+    /// ranges.
+    fn volume(&self, x: &Range<f64>, y: &Range<f64>) -> f64;
+
+    /// Returns the area of the surface normal to `axis` at the coordinate
+    /// `position`, spanning the range `span` of the other coordinate.
+    fn area(&self, axis: Axis, position: f64, span: &Range<f64>) -> f64;
+
+    /// Returns the coordinate ranges bounding a zone.
+    fn cell_bounds(&self, level: u32, index: (i64, i64)) -> (Range<f64>, Range<f64>) {
+        let (i, j) = (index.0 << level, index.1 << level);
+        let (di, dj) = ((index.0 + 1) << level, (index.1 + 1) << level);
+        (
+            self.coordinate(Axis::I, i)..self.coordinate(Axis::I, di),
+            self.coordinate(Axis::J, j)..self.coordinate(Axis::J, dj),
+        )
+    }
+
+    /// Returns the coordinates of a zone center, the midpoint of its bounds.
+    fn cell_center(&self, level: u32, index: (i64, i64)) -> (f64, f64) {
+        let (x, y) = self.cell_bounds(level, index);
+        (0.5 * (x.start + x.end), 0.5 * (y.start + y.end))
+    }
+
+    /// Returns the volume of a zone.
+    fn cell_volume(&self, level: u32, index: (i64, i64)) -> f64 {
+        let (x, y) = self.cell_bounds(level, index);
+        self.volume(&x, &y)
+    }
+
+    /// Returns the area of the face on the lower side of a zone, normal to
+    /// the given axis. This is the face indexed by `index` in a face-centered
+    /// flux patch.
+    fn face_area(&self, level: u32, axis: Axis, index: (i64, i64)) -> f64 {
+        let (x, y) = self.cell_bounds(level, index);
+        match axis {
+            Axis::I => self.area(axis, x.start, &y),
+            Axis::J => self.area(axis, y.start, &x),
+        }
+    }
+
+    /// Returns a patch with the zone center coordinates (two fields) over an
+    /// index space.
+    fn cell_centers(&self, level: u32, space: &IndexSpace) -> Patch {
+        Patch::from_vector_function(level, space.clone(), |index| {
+            let (x, y) = self.cell_center(level, index);
+            [x, y]
+        })
+    }
+
+    /// Returns a patch with the zone volumes over an index space.
+    fn cell_volumes(&self, level: u32, space: &IndexSpace) -> Patch {
+        Patch::from_scalar_function(level, space.clone(), |index| self.cell_volume(level, index))
+    }
+
+    /// Returns a face-centered patch with the areas of the faces normal to
+    /// the given axis, for the zones of an index space. The patch covers the
+    /// space extended by one on the upper side of the axis.
+    fn face_areas(&self, level: u32, axis: Axis, space: &IndexSpace) -> Patch {
+        Patch::from_scalar_function(level, space.extend_upper(1, axis), |index| {
+            self.face_area(level, axis, index)
+        })
+    }
+}
+
+/// Uniformly spaced Cartesian coordinates `(x, y)`. Areas and volumes are per
+/// unit length in the third dimension.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cartesian {
+    pub origin: (f64, f64),
+    pub spacing: (f64, f64),
+}
+
+impl Cartesian {
+    /// Creates a geometry covering the given physical extent with the given
+    /// number of (level 0) zones on each axis.
+    pub fn new(extent: Rectangle<f64>, resolution: (usize, usize)) -> Self {
+        Self {
+            origin: (extent.0.start, extent.1.start),
+            spacing: uniform_spacing(&extent, resolution),
+        }
+    }
+}
+
+impl Geometry for Cartesian {
+    fn coordinate(&self, axis: Axis, n: i64) -> f64 {
+        uniform_coordinate(self.origin, self.spacing, axis, n)
+    }
+
+    fn volume(&self, x: &Range<f64>, y: &Range<f64>) -> f64 {
+        (x.end - x.start) * (y.end - y.start)
+    }
+
+    fn area(&self, _: Axis, _: f64, span: &Range<f64>) -> f64 {
+        span.end - span.start
+    }
+}
+
+/// Uniformly spaced axisymmetric cylindrical coordinates `(r, z)`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Cylindrical {
+    pub origin: (f64, f64),
+    pub spacing: (f64, f64),
+}
+
+impl Cylindrical {
+    /// Creates a geometry covering the given `(r, z)` extent with the given
+    /// number of (level 0) zones on each axis.
+    pub fn new(extent: Rectangle<f64>, resolution: (usize, usize)) -> Self {
+        Self {
+            origin: (extent.0.start, extent.1.start),
+            spacing: uniform_spacing(&extent, resolution),
+        }
+    }
+}
+
+impl Geometry for Cylindrical {
+    fn coordinate(&self, axis: Axis, n: i64) -> f64 {
+        uniform_coordinate(self.origin, self.spacing, axis, n)
+    }
+
+    fn volume(&self, r: &Range<f64>, z: &Range<f64>) -> f64 {
+        PI * (r.end * r.end - r.start * r.start) * (z.end - z.start)
+    }
+
+    fn area(&self, axis: Axis, position: f64, span: &Range<f64>) -> f64 {
+        match axis {
+            Axis::I => 2.0 * PI * position * (span.end - span.start),
+            Axis::J => PI * (span.end * span.end - span.start * span.start),
+        }
+    }
+}
+
+/// Uniformly spaced axisymmetric spherical-polar coordinates `(r, theta)`,
+/// where `theta` is the polar angle.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SphericalPolar {
+    pub origin: (f64, f64),
+    pub spacing: (f64, f64),
+}
+
+impl SphericalPolar {
+    /// Creates a geometry covering the given `(r, theta)` extent with the
+    /// given number of (level 0) zones on each axis.
+    pub fn new(extent: Rectangle<f64>, resolution: (usize, usize)) -> Self {
+        Self {
+            origin: (extent.0.start, extent.1.start),
+            spacing: uniform_spacing(&extent, resolution),
+        }
+    }
+}
+
+impl Geometry for SphericalPolar {
+    fn coordinate(&self, axis: Axis, n: i64) -> f64 {
+        uniform_coordinate(self.origin, self.spacing, axis, n)
+    }
+
+    fn volume(&self, r: &Range<f64>, q: &Range<f64>) -> f64 {
+        spherical_volume(r, q)
+    }
+
+    fn area(&self, axis: Axis, position: f64, span: &Range<f64>) -> f64 {
+        spherical_area(axis, position, span)
+    }
+}
+
+/// Axisymmetric spherical-polar coordinates `(r, theta)` with logarithmically
+/// spaced radii: grid line `n` on the `I` axis is at `r_inner * exp(n *
+/// dlogr)`. Zones have a fixed aspect ratio at all radii when `dlogr` is
+/// comparable to the angular spacing.
+#[derive(Debug, Clone, PartialEq)]
+pub struct LogRadial {
+    pub r_inner: f64,
+    pub dlogr: f64,
+    pub theta_start: f64,
+    pub dtheta: f64,
+}
+
+impl LogRadial {
+    /// Creates a geometry spanning the radii `r.start..r.end` with
+    /// logarithmic spacing, and the polar angles `theta` with uniform
+    /// spacing, with the given number of (level 0) zones on each axis.
+    pub fn new(r: Range<f64>, theta: Range<f64>, resolution: (usize, usize)) -> Self {
+        Self {
+            r_inner: r.start,
+            dlogr: (r.end / r.start).ln() / resolution.0 as f64,
+            theta_start: theta.start,
+            dtheta: (theta.end - theta.start) / resolution.1 as f64,
+        }
+    }
+}
+
+impl Geometry for LogRadial {
+    fn coordinate(&self, axis: Axis, n: i64) -> f64 {
+        match axis {
+            Axis::I => self.r_inner * (n as f64 * self.dlogr).exp(),
+            Axis::J => self.theta_start + n as f64 * self.dtheta,
+        }
+    }
+
+    fn volume(&self, r: &Range<f64>, q: &Range<f64>) -> f64 {
+        spherical_volume(r, q)
+    }
+
+    fn area(&self, axis: Axis, position: f64, span: &Range<f64>) -> f64 {
+        spherical_area(axis, position, span)
+    }
+}
+
+/// A Cartesian mapping from zone indexes to physical coordinates which moves
+/// and expands uniformly in time. At time `t` the origin is displaced by
+/// `velocity * t`, and the zone spacing is multiplied by `1 + expansion_rate
+/// * t` on each axis (homologous expansion). Data on a moving mesh is carried
+/// from one mapping to another with [`remap`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct MovingCartesian {
+    pub initial: Cartesian,
+    pub velocity: (f64, f64),
+    pub expansion_rate: (f64, f64),
+}
+
+impl MovingCartesian {
+    /// Creates a mapping which stays fixed at the given geometry.
+    pub fn stationary(initial: Cartesian) -> Self {
+        Self {
+            initial,
+            velocity: (0.0, 0.0),
+            expansion_rate: (0.0, 0.0),
+        }
+    }
+
+    /// Returns the geometry of the mesh at the given time.
+    pub fn at(&self, time: f64) -> Cartesian {
+        let Cartesian { origin, spacing } = self.initial;
+        Cartesian {
+            origin: (
+                origin.0 + self.velocity.0 * time,
+                origin.1 + self.velocity.1 * time,
+            ),
+            spacing: (
+                spacing.0 * (1.0 + self.expansion_rate.0 * time),
+                spacing.1 * (1.0 + self.expansion_rate.1 * time),
+            ),
+        }
+    }
+
+    /// Returns the velocity at the given time of the mesh point currently at
+    /// the given physical position. Solvers on a moving mesh use this to
+    /// correct face fluxes for the motion of the faces.
+    pub fn grid_velocity(&self, time: f64, position: (f64, f64)) -> (f64, f64) {
+        let current = self.at(time);
+        let n = (
+            (position.0 - current.origin.0) / current.spacing.0,
+            (position.1 - current.origin.1) / current.spacing.1,
+        );
+        (
+            self.velocity.0 + n.0 * self.initial.spacing.0 * self.expansion_rate.0,
+            self.velocity.1 + n.1 * self.initial.spacing.1 * self.expansion_rate.1,
+        )
+    }
+}
+
+/// Conservatively remaps a patch of volume-averaged data from one Cartesian
+/// mapping to another. The result covers the zones of `space` (at the
+/// patch's level) as positioned by the `to` mapping, and each of its zones is
+/// the overlap-weighted average of the source zones as positioned by the
+/// `from` mapping, so the volume integral of the data is preserved. The
+/// source patch (typically extended with guard zones) must cover every
+/// target zone; this function panics otherwise.
+pub fn remap(patch: &Patch, from: &Cartesian, to: &Cartesian, space: &IndexSpace) -> Patch {
+    let level = patch.level();
+    let source = patch.index_space();
+    let weights_i = overlap_weights(
+        from,
+        to,
+        level,
+        Axis::I,
+        space.to_rect().0,
+        source.to_rect().0,
+    );
+    let weights_j = overlap_weights(
+        from,
+        to,
+        level,
+        Axis::J,
+        space.to_rect().1,
+        source.to_rect().1,
+    );
+    let (i0, j0) = space.start();
+
+    Patch::from_slice_function(
+        level,
+        space.clone(),
+        patch.num_fields(),
+        |(i, j), result| {
+            result.iter_mut().for_each(|r| *r = 0.0);
+
+            for &(m, wi) in &weights_i[(i - i0) as usize] {
+                for &(n, wj) in &weights_j[(j - j0) as usize] {
+                    for (r, u) in result.iter_mut().zip(patch.get_slice((m, n))) {
+                        *r += wi * wj * u
+                    }
+                }
+            }
+        },
+    )
+}
+
+/// For each target zone on one axis, returns the source zones it overlaps,
+/// with the fraction of the target zone covered by each.
+fn overlap_weights(
+    from: &Cartesian,
+    to: &Cartesian,
+    level: u32,
+    axis: Axis,
+    target: Range<i64>,
+    source: Range<i64>,
+) -> Vec<Vec<(i64, f64)>> {
+    let edge = |geometry: &Cartesian, n: i64| geometry.coordinate(axis, n << level);
+    let (origin, spacing) = match axis {
+        Axis::I => (from.origin.0, from.spacing.0 * (1 << level) as f64),
+        Axis::J => (from.origin.1, from.spacing.1 * (1 << level) as f64),
+    };
+
+    target
+        .map(|n| {
+            let (x0, x1) = (edge(to, n), edge(to, n + 1));
+            let m0 = ((x0 - origin) / spacing).floor() as i64;
+            let m1 = ((x1 - origin) / spacing).ceil() as i64;
+
+            (m0..m1)
+                .filter_map(|m| {
+                    let overlap = x1.min(edge(from, m + 1)) - x0.max(edge(from, m));
+                    if overlap <= 0.0 {
+                        return None;
+                    }
+                    assert!(
+                        source.contains(&m),
+                        "source patch does not cover the target zones"
+                    );
+                    Some((m, overlap / (x1 - x0)))
+                })
+                .collect()
+        })
+        .collect()
+}
+
+fn uniform_spacing(extent: &Rectangle<f64>, resolution: (usize, usize)) -> (f64, f64) {
+    (
+        (extent.0.end - extent.0.start) / resolution.0 as f64,
+        (extent.1.end - extent.1.start) / resolution.1 as f64,
+    )
+}
+
+fn uniform_coordinate(origin: (f64, f64), spacing: (f64, f64), axis: Axis, n: i64) -> f64 {
+    match axis {
+        Axis::I => origin.0 + n as f64 * spacing.0,
+        Axis::J => origin.1 + n as f64 * spacing.1,
+    }
+}
+
+fn spherical_volume(r: &Range<f64>, q: &Range<f64>) -> f64 {
+    2.0 / 3.0 * PI * (r.end.powi(3) - r.start.powi(3)) * (q.start.cos() - q.end.cos())
+}
+
+fn spherical_area(axis: Axis, position: f64, span: &Range<f64>) -> f64 {
+    match axis {
+        Axis::I => 2.0 * PI * position * position * (span.start.cos() - span.end.cos()),
+        Axis::J => PI * (span.end * span.end - span.start * span.start) * position.sin(),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn geometry_volumes_are_consistent_across_levels() {
+        let cartesian = Cartesian::new((-1.0..1.0, 0.0..1.0), (20, 10));
+        assert_eq!(cartesian.cell_center(0, (0, 0)), (-0.95, 0.05));
+        assert!((cartesian.cell_volume(1, (0, 0)) - 0.04).abs() < 1e-12);
+        assert!((cartesian.face_area(0, Axis::J, (3, 3)) - 0.1).abs() < 1e-12);
+
+        let geometries: Vec<Box<dyn Geometry>> = vec![
+            Box::new(cartesian),
+            Box::new(Cylindrical::new((0.0..1.0, -1.0..1.0), (16, 16))),
+            Box::new(SphericalPolar::new((0.5..1.0, 0.0..PI), (16, 16))),
+            Box::new(LogRadial::new(0.1..10.0, 0.0..PI, (16, 16))),
+        ];
+        let space = IndexSpace::new(0..16, 0..8);
+
+        for geometry in &geometries {
+            let fine = geometry.cell_volumes(0, &space).sum(0);
+            let coarse = geometry.cell_volumes(1, &space.coarsen_by(2)).sum(0);
+            assert!((fine - coarse).abs() < 1e-12 * fine);
+        }
+    }
+
+    #[test]
+    fn spherical_geometry_satisfies_divergence_theorem() {
+        // The volume of a zone is the integral of r / 3 over its boundary,
+        // since the divergence of the radial vector r is 3.
+        let geometry = LogRadial::new(1.0..2.0, 0.0..PI, (8, 8));
+        let index = (3, 2);
+        let (r, _) = geometry.cell_bounds(0, index);
+        let flux = r.end * geometry.face_area(0, Axis::I, (4, 2))
+            - r.start * geometry.face_area(0, Axis::I, index);
+        assert!((flux / 3.0 - geometry.cell_volume(0, index)).abs() < 1e-12);
+    }
+
+    #[test]
+    fn moving_mesh_translates_and_expands() {
+        let motion = MovingCartesian {
+            initial: Cartesian::new((0.0..1.0, 0.0..1.0), (10, 10)),
+            velocity: (1.0, 0.0),
+            expansion_rate: (0.0, 1.0),
+        };
+        let geometry = motion.at(2.0);
+        assert_eq!(geometry.origin, (2.0, 0.0));
+        assert!((geometry.spacing.1 - 0.3).abs() < 1e-12);
+        assert!((motion.grid_velocity(2.0, (2.5, 0.6)).1 - 0.2).abs() < 1e-12);
+    }
+
+    #[test]
+    fn remap_is_conservative() {
+        let from = Cartesian::new((0.0..1.0, 0.0..1.0), (10, 10));
+        let to = Cartesian {
+            origin: (0.05, 0.0),
+            spacing: (0.1, 0.1),
+        };
+        let patch = Patch::from_scalar_function(0, (-1..11, 0..10), |(i, _)| i as f64);
+        let space = IndexSpace::new(0..10, 0..10);
+
+        let same = remap(&patch, &from, &from, &space);
+        assert_eq!(same.get_slice((3, 4)), &[3.0]);
+
+        let shifted = remap(&patch, &from, &to, &space);
+        assert!((shifted.get_slice((3, 4))[0] - 3.5).abs() < 1e-12);
+
+        let coarse = Patch::from_scalar_function(1, (0..4, 0..4), |(i, j)| (i * j) as f64);
+        let expanded = MovingCartesian {
+            initial: from.clone(),
+            velocity: (0.0, 0.0),
+            expansion_rate: (-0.5, 0.0),
+        }
+        .at(1.0);
+        let inner = IndexSpace::new(0..4, 0..4);
+        let result = remap(&coarse, &from, &expanded, &inner);
+        let mass = |p: &Patch, g: &Cartesian| {
+            p.index_space()
+                .iter()
+                .map(|index| p.get_slice(index)[0] * g.cell_volume(1, index))
+                .sum::<f64>()
+        };
+        let covered = IndexSpace::new(0..2, 0..4);
+        let source_mass = mass(&coarse.extract(covered), &from);
+        assert!((mass(&result, &expanded) - source_mass).abs() < 1e-12);
+    }
+}
@@ -0,0 +1,977 @@
+//! Exports the block-structured [`LevelHierarchy`] and the distributed
+//! regridding driver.
+
+use super::{
+    berger_rigoutsos, check_ratios, coarsen_inward, coarsen_outward, level_adjacency, level_scale,
+    partition_levels, read_i64, read_u64, write_i64s,
+};
+use crate::adjacency_list::AdjacencyList;
+use crate::automaton::{execute_comm, Automaton, Status};
+use crate::coder::Coder;
+use crate::index_space::{Extension, IndexSpace, Stencil};
+use crate::message::Communicator;
+use crate::patch::Patch;
+use crate::rect_map::{Rectangle, RectangleMap};
+use std::collections::{BTreeMap, HashMap};
+use std::{error, fmt};
+
+/// Error returned when an operation on a [`LevelHierarchy`] would violate
+/// proper nesting.
+#[derive(Debug, Clone, PartialEq)]
+pub enum HierarchyError {
+    /// The given index space (at the given level) is not covered by the
+    /// patches on the next coarser level.
+    NotNested(u32, IndexSpace),
+}
+
+impl fmt::Display for HierarchyError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        match self {
+            HierarchyError::NotNested(level, space) => write!(
+                fmt,
+                "space {:?} on level {} is not covered by the next coarser level",
+                space.to_rect(),
+                level
+            ),
+        }
+    }
+}
+
+impl error::Error for HierarchyError {}
+
+/// A collection of patches on multiple refinement levels. Recall that level 0
+/// is the finest; the coarsest level present is the base of the hierarchy,
+/// and is not subject to nesting. Each finer level must be properly nested:
+/// every patch must be covered by the patches on the next coarser level that
+/// is present. Patches on each level are keyed by their high resolution
+/// rectangle, and patches on the same level should not overlap.
+///
+/// Each level is refined from the one above it by a refinement ratio, and a
+/// zone on level `l` spans [`LevelHierarchy::scale`]`(l)` high resolution
+/// zones on each axis. The ratio is currently limited to two (see
+/// [`LevelHierarchy::with_ratios`]), so the scale is `2^l`, and the high
+/// resolution rectangles used as keys agree with
+/// [`Patch::high_resolution_rect`].
+#[derive(Clone, Default)]
+pub struct LevelHierarchy {
+    levels: BTreeMap<u32, RectangleMap<i64, Patch>>,
+    ratios: Vec<u32>,
+}
+
+impl LevelHierarchy {
+    /// Creates an empty hierarchy.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a hierarchy with the given patches on its base level.
+    pub fn from_base<I: IntoIterator<Item = Patch>>(patches: I) -> Self {
+        let mut result = Self::new();
+
+        for patch in patches {
+            result.insert_unchecked(patch);
+        }
+        result
+    }
+
+    /// Returns this hierarchy with the given refinement ratios: `ratios[l]`
+    /// is the ratio between levels `l + 1` and `l`. Levels beyond the end of
+    /// the list are refined by a factor of two. Patches already in the
+    /// hierarchy are re-keyed. The limit is a factor of two: patches,
+    /// geometries and the halo, transfer and flux register machinery all
+    /// assume that each level is twice as fine as the one above it, so this
+    /// method panics if any ratio is not two.
+    pub fn with_ratios(mut self, ratios: Vec<u32>) -> Self {
+        check_ratios(&ratios);
+        self.ratios = ratios;
+
+        for patch in std::mem::take(&mut self.levels)
+            .into_values()
+            .flat_map(|map| map.into_iter().map(|(_, p)| p))
+        {
+            self.insert_unchecked(patch);
+        }
+        self
+    }
+
+    /// Returns the refinement ratio between levels `level + 1` and `level`.
+    pub fn ratio(&self, level: u32) -> u32 {
+        self.ratios.get(level as usize).cloned().unwrap_or(2)
+    }
+
+    /// Returns the number of high resolution zones spanned by a zone on the
+    /// given level, on each axis.
+    pub fn scale(&self, level: u32) -> i64 {
+        level_scale(&self.ratios, level)
+    }
+
+    /// Returns the refinement ratios given with
+    /// [`LevelHierarchy::with_ratios`], for example to pass to [`regrid`].
+    pub fn ratios(&self) -> &[u32] {
+        &self.ratios
+    }
+
+    /// Returns the high resolution index space covered by a patch in this
+    /// hierarchy.
+    pub fn high_resolution_space(&self, patch: &Patch) -> IndexSpace {
+        patch
+            .index_space()
+            .refine_by(self.scale(patch.level()) as u32)
+    }
+
+    /// Returns the levels which have patches, from finest to coarsest.
+    pub fn levels(&self) -> impl Iterator<Item = u32> + '_ {
+        self.levels.keys().cloned()
+    }
+
+    /// Returns the coarsest level in the hierarchy, if it is not empty.
+    pub fn base_level(&self) -> Option<u32> {
+        self.levels.keys().next_back().cloned()
+    }
+
+    /// Returns the patches on the given level.
+    pub fn level(&self, level: u32) -> Option<&RectangleMap<i64, Patch>> {
+        self.levels.get(&level)
+    }
+
+    /// Returns the patches on the given level, mutably.
+    pub fn level_mut(&mut self, level: u32) -> Option<&mut RectangleMap<i64, Patch>> {
+        self.levels.get_mut(&level)
+    }
+
+    /// Returns an iterator over all the patches, from finest to coarsest.
+    pub fn patches(&self) -> impl Iterator<Item = &Patch> {
+        self.levels
+            .values()
+            .flat_map(|map| map.iter().map(|(_, p)| p))
+    }
+
+    /// Inserts a patch, returning an error if it is not nested in the next
+    /// coarser level.
+    pub fn insert(&mut self, patch: Patch) -> Result<(), HierarchyError> {
+        self.check_nested(patch.level(), &patch.index_space())?;
+        self.insert_unchecked(patch);
+        Ok(())
+    }
+
+    /// Determines whether every level is properly nested in the next coarser
+    /// level.
+    pub fn is_properly_nested(&self) -> bool {
+        self.levels.iter().all(|(&level, map)| {
+            map.iter()
+                .all(|(_, p)| self.check_nested(level, &p.index_space()).is_ok())
+        })
+    }
+
+    /// Replaces the patches on a level with new ones covering the given index
+    /// spaces (measured at that level). Data on the new patches is copied from
+    /// the old patches on the same level where they overlap, and is otherwise
+    /// prolonged (piecewise constant) from the next coarser level. Passing an
+    /// empty list of spaces removes the level. An error is returned, and the
+    /// hierarchy is unchanged, if any of the new spaces is not nested in the
+    /// next coarser level. Finer levels are not modified, so when regridding
+    /// several levels, proceed from coarse to fine.
+    pub fn regrid<I>(
+        &mut self,
+        level: u32,
+        spaces: I,
+        num_fields: usize,
+    ) -> Result<(), HierarchyError>
+    where
+        I: IntoIterator<Item = IndexSpace>,
+    {
+        let spaces: Vec<_> = spaces.into_iter().collect();
+
+        for space in &spaces {
+            self.check_nested(level, space)?;
+        }
+
+        let old = self.levels.remove(&level).unwrap_or_default();
+        let coarse = self.coarser_level(level);
+        let scale = self.scale(level);
+
+        let new: RectangleMap<i64, Patch> = spaces
+            .into_iter()
+            .map(|space| {
+                let patch = Patch::from_slice_function(level, space, num_fields, |index, slice| {
+                    let hr_index = (index.0 * scale, index.1 * scale);
+
+                    if let Some((_, p)) = old.query_point(hr_index).next() {
+                        slice.clone_from_slice(p.get_slice(index))
+                    } else if let Some(c) = coarse {
+                        let factor = self.scale(c) / scale;
+                        let coarse_index = (index.0.div_euclid(factor), index.1.div_euclid(factor));
+
+                        if let Some((_, p)) = self.levels[&c].query_point(hr_index).next() {
+                            slice.clone_from_slice(&p.get_slice(coarse_index)[..num_fields])
+                        }
+                    }
+                });
+                (self.high_resolution_space(&patch).to_rect(), patch)
+            })
+            .collect();
+
+        if !new.is_empty() {
+            self.levels.insert(level, new);
+        }
+        Ok(())
+    }
+
+    /// Restricts the data on the given level onto the next coarser level:
+    /// each coarse zone covered by a single patch on this level is replaced
+    /// by the average of the fine zones it contains, so that the coarse data
+    /// is conservatively consistent with the fine data. Other coarse zones
+    /// are left unchanged.
+    pub fn restrict(&mut self, level: u32) {
+        let coarse = match self.coarser_level(level) {
+            Some(coarse) if self.levels.contains_key(&level) => coarse,
+            _ => return,
+        };
+        let scale = self.scale(level);
+        let factor = self.scale(coarse) / scale;
+        let mut coarse_map = self.levels.remove(&coarse).unwrap();
+        let mut sum = Vec::new();
+
+        for (rect, patch) in coarse_map.iter_mut() {
+            for (fine_rect, p) in self.levels[&level].query_rect(IndexSpace::from(rect)) {
+                let fine_space = IndexSpace::from(fine_rect).coarsen_by(scale as u32);
+                let covered = match coarsen_inward(&fine_space, factor)
+                    .and_then(|c| c.intersect(&patch.index_space()))
+                    .filter(|c| !c.is_empty())
+                {
+                    Some(covered) => covered,
+                    None => continue,
+                };
+                for (i, j) in covered.iter() {
+                    sum.clear();
+                    sum.resize(patch.num_fields(), 0.0);
+
+                    for index in
+                        IndexSpace::new(i * factor..(i + 1) * factor, j * factor..(j + 1) * factor)
+                            .iter()
+                    {
+                        for (s, x) in sum.iter_mut().zip(p.get_slice(index)) {
+                            *s += x
+                        }
+                    }
+                    for (y, s) in patch.get_slice_mut((i, j)).iter_mut().zip(&sum) {
+                        *y = s / (factor * factor) as f64
+                    }
+                }
+            }
+        }
+        self.levels.insert(coarse, coarse_map);
+    }
+
+    /// Returns the adjacency list of all the patches in the hierarchy, for
+    /// the given guard zone extension (measured at the level of each
+    /// receiving patch). Keys are the high resolution rectangles (with this
+    /// hierarchy's scales) and levels of the patches, and patches on
+    /// different levels are connected where they overlap.
+    pub fn adjacency_list(&self, extension: Extension) -> AdjacencyList<(Rectangle<i64>, u32)> {
+        let mut edges = AdjacencyList::new();
+
+        for (&level_b, map_b) in &self.levels {
+            for (b, _) in map_b.iter() {
+                let key_b = (IndexSpace::from(b).to_rect(), level_b);
+                let b = IndexSpace::from(b).coarsen_by(self.scale(level_b) as u32);
+                let extended = b
+                    .extend_by(&extension)
+                    .refine_by(self.scale(level_b) as u32);
+
+                for (&level_a, map_a) in &self.levels {
+                    for (a, _) in map_a.query_rect(extended.clone()) {
+                        let key_a = (IndexSpace::from(a).to_rect(), level_a);
+
+                        if key_a != key_b {
+                            edges.insert(key_a, key_b.clone())
+                        }
+                    }
+                }
+            }
+        }
+        edges
+    }
+
+    /// Returns the patches on the next finer level present which overlap the
+    /// given patch.
+    pub fn children_of<'a>(&'a self, patch: &Patch) -> impl Iterator<Item = &'a Patch> + 'a {
+        let space = self.high_resolution_space(patch);
+        self.finer_level(patch.level())
+            .map(|l| &self.levels[&l])
+            .into_iter()
+            .flat_map(move |fine| fine.query_rect(space.clone()).map(|(_, p)| p))
+    }
+
+    /// Returns the patches on the next coarser level present which overlap
+    /// the given patch.
+    pub fn parents_of<'a>(&'a self, patch: &Patch) -> impl Iterator<Item = &'a Patch> + 'a {
+        let space = self.high_resolution_space(patch);
+        self.coarser_level(patch.level())
+            .map(|l| &self.levels[&l])
+            .into_iter()
+            .flat_map(move |coarse| coarse.query_rect(space.clone()).map(|(_, p)| p))
+    }
+
+    /// Returns the patch on the next coarser level present which contains
+    /// the given patch entirely, if there is one. A properly nested patch may
+    /// instead be covered by several coarser patches; see
+    /// [`LevelHierarchy::parents_of`].
+    pub fn parent_of(&self, patch: &Patch) -> Option<&Patch> {
+        let space = self.high_resolution_space(patch);
+        self.parents_of(patch)
+            .find(|p| self.high_resolution_space(p).contains_space(&space))
+    }
+
+    /// Returns the fraction of the given patch's zones which are covered by
+    /// patches on the next finer level present. This is zero if there is no
+    /// finer level.
+    pub fn covered_fraction(&self, patch: &Patch) -> f64 {
+        let space = self.high_resolution_space(patch);
+        let mut remaining = vec![space.clone()];
+
+        for child in self.children_of(patch) {
+            let cover = self.high_resolution_space(child);
+            remaining = remaining.iter().flat_map(|r| r.subtract(&cover)).collect();
+        }
+        let uncovered: usize = remaining.iter().map(IndexSpace::len).sum();
+        1.0 - uncovered as f64 / space.len() as f64
+    }
+
+    fn insert_unchecked(&mut self, patch: Patch) {
+        let rect = self.high_resolution_space(&patch).to_rect();
+        self.levels
+            .entry(patch.level())
+            .or_default()
+            .insert(rect, patch);
+    }
+
+    fn coarser_level(&self, level: u32) -> Option<u32> {
+        self.levels.range(level + 1..).next().map(|(&l, _)| l)
+    }
+
+    fn finer_level(&self, level: u32) -> Option<u32> {
+        self.levels.range(..level).next_back().map(|(&l, _)| l)
+    }
+
+    fn check_nested(&self, level: u32, space: &IndexSpace) -> Result<(), HierarchyError> {
+        let coarse = match self.coarser_level(level) {
+            Some(l) => &self.levels[&l],
+            None => return Ok(()),
+        };
+        let hr_space = space.refine_by(self.scale(level) as u32);
+        let mut remaining = vec![hr_space.clone()];
+
+        for (rect, _) in coarse.query_rect(hr_space.to_rect()) {
+            let cover = IndexSpace::from(rect);
+            remaining = remaining.iter().flat_map(|r| r.subtract(&cover)).collect();
+        }
+
+        if remaining.is_empty() {
+            Ok(())
+        } else {
+            Err(HierarchyError::NotNested(level, space.clone()))
+        }
+    }
+}
+
+/// The parameters of [`regrid`].
+#[derive(Clone, Copy)]
+pub struct RegridParameters<'a> {
+    /// The smallest fraction of flagged zones in each cluster, as for
+    /// [`berger_rigoutsos`].
+    pub min_efficiency: f64,
+
+    /// The stencil the new adjacency list is built for.
+    pub stencil: Stencil,
+
+    /// The refinement ratios between the levels, as for
+    /// [`LevelHierarchy::with_ratios`] (see [`LevelHierarchy::ratios`]). An
+    /// empty list refines every level by two, and [`regrid`] panics if any
+    /// ratio is not two.
+    pub ratios: &'a [u32],
+}
+
+/// The new patch layout produced by [`regrid`].
+pub struct RegridOutcome {
+    /// The new patches owned by this rank, with data transferred from the old
+    /// patches.
+    pub patches: Vec<Patch>,
+
+    /// The adjacency list of all the new patches, keyed by their high
+    /// resolution rectangles and levels.
+    pub adjacency: AdjacencyList<(Rectangle<i64>, u32)>,
+
+    /// The ranks owning each of the new patches, keyed by level and then by
+    /// the index rectangle of the patch at its level.
+    pub work: BTreeMap<u32, RectangleMap<i64, usize>>,
+}
+
+/// Replaces the refined levels of a distributed patch layout, based on the
+/// zones flagged for refinement on each rank.
+///
+/// The old layout (`work`) maps the index rectangles of the patches on every
+/// level to the ranks that own them, and `patches` are the old patches owned
+/// by this rank. Flags are zone indexes measured at the level given with
+/// them; a zone flagged on level `l` is covered by patches on level `l - 1`.
+/// The flags from every rank are first gathered with the communicator. Then,
+/// from coarse to fine, the flags on each level are clustered with
+/// [`berger_rigoutsos`], and the clusters are clipped to the new patches on
+/// that level, so the new levels are properly nested. Flags outside the new
+/// patches on their level, or on level zero, are ignored. The coarsest level
+/// of the old layout is kept as the base.
+///
+/// Every patch, including those on the base level, is then reassigned to a
+/// rank along a Hilbert curve, weighted by its number of zones. Data is
+/// transferred to the new patches as a group of [`Automaton`] tasks run with
+/// [`execute_comm`], so patches may move between ranks; the coder converts
+/// the target patch keys and the transferred data to bytes. Each new zone
+/// takes its data from an old patch on the same level if one covers it, and
+/// otherwise is prolonged (piecewise constant) from the finest coarser old
+/// patch covering it. The new adjacency list is built for the given stencil.
+///
+/// The high resolution rectangles in the keys of the adjacency list and the
+/// transfer are measured with the refinement ratios in the parameters, which
+/// must all be two.
+pub fn regrid<Comm, Code, I>(
+    comm: &mut Comm,
+    code: &Code,
+    work: &BTreeMap<u32, RectangleMap<i64, usize>>,
+    patches: Vec<Patch>,
+    flags: I,
+    parameters: RegridParameters,
+) -> RegridOutcome
+where
+    Comm: Communicator,
+    Code: Coder<Type = ((Rectangle<i64>, u32), Patch)>,
+    I: IntoIterator<Item = ((i64, i64), u32)>,
+{
+    let RegridParameters {
+        min_efficiency,
+        stencil,
+        ratios,
+    } = parameters;
+    check_ratios(ratios);
+    let flags = gather_flags(comm, flags);
+    let scale = |level| level_scale(ratios, level);
+    let base_level = *work.keys().next_back().expect("the old layout is empty");
+    let mut spaces: BTreeMap<u32, Vec<IndexSpace>> = BTreeMap::new();
+
+    spaces.insert(
+        base_level,
+        work[&base_level]
+            .iter()
+            .map(|(r, _)| IndexSpace::from(r))
+            .collect(),
+    );
+
+    for level in (0..base_level).rev() {
+        let coarse = &spaces[&(level + 1)];
+        let flagged = flags
+            .iter()
+            .filter(|(index, l)| *l == level + 1 && coarse.iter().any(|c| c.contains(*index)))
+            .map(|(index, _)| *index);
+        let clipped: Vec<_> = berger_rigoutsos(flagged, min_efficiency)
+            .iter()
+            .flat_map(|cluster| coarse.iter().filter_map(move |c| cluster.intersect(c)))
+            .filter(|space| !space.is_empty())
+            .map(|space| space.refine_by((scale(level + 1) / scale(level)) as u32))
+            .collect();
+        spaces.insert(level, clipped);
+    }
+
+    let blocks = spaces
+        .into_iter()
+        .flat_map(|(level, spaces)| spaces.into_iter().map(move |space| (space, level)))
+        .collect();
+    let new_work = partition_levels(blocks, &vec![1.0; comm.size()], ratios);
+    let adjacency = level_adjacency(&new_work, &stencil.extension(), stencil.corners, None, ratios);
+
+    let mut outgoing: HashMap<(Rectangle<i64>, u32), Vec<_>> = HashMap::new();
+    let mut tasks = Vec::new();
+
+    for (&level, map) in &new_work {
+        for (rect, &rank) in map.iter() {
+            let target = IndexSpace::from(rect);
+            let key = (target.refine_by(scale(level) as u32).to_rect(), level);
+            let sources = regrid_sources(work, &target, level, ratios);
+
+            for (source, region) in &sources {
+                outgoing
+                    .entry(source.clone())
+                    .or_default()
+                    .push((key.clone(), region.clone()));
+            }
+            if rank == comm.rank() {
+                tasks.push(RegridTask::Target {
+                    key,
+                    space: target,
+                    level,
+                    expected: sources.len(),
+                    received: Vec::new(),
+                    ratios: ratios.to_vec(),
+                });
+            }
+        }
+    }
+    for patch in patches {
+        let key = (
+            patch.index_space().refine_by(scale(patch.level()) as u32).to_rect(),
+            patch.level(),
+        );
+        let messages = outgoing
+            .remove(&key)
+            .unwrap_or_default()
+            .into_iter()
+            .map(|(target, region)| {
+                let space = coarsen_outward(&region, scale(patch.level()));
+                (target, patch.extract(space))
+            })
+            .collect();
+        tasks.push(RegridTask::Source { key, messages });
+    }
+
+    let owner = |key: &(Rectangle<i64>, u32)| {
+        let space = IndexSpace::from(key.0.clone()).coarsen_by(scale(key.1) as u32);
+        *new_work[&key.1].get(space.to_rect_ref()).unwrap()
+    };
+    let patches = execute_comm(comm, code, &owner, None, tasks)
+        .flatten()
+        .collect();
+
+    RegridOutcome {
+        patches,
+        adjacency,
+        work: new_work,
+    }
+}
+
+/// Gathers the flags from every rank, sorted and without duplicates. Each
+/// flag is encoded as two indexes and a level, in the little-endian format
+/// of [`DomainDecomposition::save`].
+fn gather_flags<Comm, I>(comm: &mut Comm, flags: I) -> Vec<((i64, i64), u32)>
+where
+    Comm: Communicator,
+    I: IntoIterator<Item = ((i64, i64), u32)>,
+{
+    let mut bytes = Vec::new();
+
+    for ((i, j), level) in flags {
+        write_i64s(&mut bytes, &[i, j, level as i64]).unwrap();
+    }
+    let bytes = comm.all_reduce(
+        |mut a, b| {
+            a.extend(b);
+            a
+        },
+        bytes,
+    );
+    comm.next_time_stamp();
+
+    let mut reader = bytes.as_slice();
+    let mut flags = Vec::new();
+
+    while !reader.is_empty() {
+        let i = read_i64(&mut reader).unwrap();
+        let j = read_i64(&mut reader).unwrap();
+        let level = read_u64(&mut reader).unwrap() as u32;
+        flags.push(((i, j), level));
+    }
+    flags.sort_unstable();
+    flags.dedup();
+    flags
+}
+
+/// Returns the old patches (keyed by high resolution rectangle and level)
+/// which send data to a new patch on the given level, and the high
+/// resolution region each of them sends. Old levels are visited from the
+/// target level upward, and a patch is a source if it overlaps the part of
+/// the target not covered by finer old patches.
+fn regrid_sources(
+    work: &BTreeMap<u32, RectangleMap<i64, usize>>,
+    target: &IndexSpace,
+    level: u32,
+    ratios: &[u32],
+) -> Vec<((Rectangle<i64>, u32), IndexSpace)> {
+    let scale = |level| level_scale(ratios, level);
+    let target = target.refine_by(scale(level) as u32);
+    let mut remaining = vec![target.clone()];
+    let mut sources = Vec::new();
+
+    for (&l, map) in work.range(level..) {
+        let covers: Vec<_> = map
+            .query_rect(coarsen_outward(&target, scale(l)))
+            .map(|(rect, _)| IndexSpace::from(rect).refine_by(scale(l) as u32))
+            .filter(|cover| {
+                remaining
+                    .iter()
+                    .any(|r| r.intersect(cover).is_some_and(|o| !o.is_empty()))
+            })
+            .collect();
+
+        for cover in covers {
+            remaining = remaining.iter().flat_map(|r| r.subtract(&cover)).collect();
+            let region = target.intersect(&cover).unwrap();
+            sources.push(((cover.to_rect(), l), region));
+        }
+        if remaining.is_empty() {
+            break;
+        }
+    }
+    sources
+}
+
+/// A task in the data transfer of [`regrid`]: either an old patch sending
+/// its data to the new patches, or a new patch receiving it.
+enum RegridTask {
+    Source {
+        key: (Rectangle<i64>, u32),
+        messages: Vec<((Rectangle<i64>, u32), Patch)>,
+    },
+    Target {
+        key: (Rectangle<i64>, u32),
+        space: IndexSpace,
+        level: u32,
+        expected: usize,
+        received: Vec<Patch>,
+        ratios: Vec<u32>,
+    },
+}
+
+impl Automaton for RegridTask {
+    type Key = (Rectangle<i64>, u32);
+
+    type Message = Patch;
+
+    type Value = Option<Patch>;
+
+    fn key(&self) -> Self::Key {
+        match self {
+            RegridTask::Source { key, .. } => key.clone(),
+            RegridTask::Target { key, .. } => key.clone(),
+        }
+    }
+
+    fn messages(&self) -> Vec<(Self::Key, Self::Message)> {
+        match self {
+            RegridTask::Source { messages, .. } => messages.clone(),
+            RegridTask::Target { .. } => Vec::new(),
+        }
+    }
+
+    fn receive(&mut self, message: Self::Message) -> Status {
+        match self {
+            RegridTask::Source { .. } => unreachable!("source tasks do not receive messages"),
+            RegridTask::Target {
+                expected, received, ..
+            } => {
+                received.push(message);
+                Status::eligible_if(received.len() == *expected)
+            }
+        }
+    }
+
+    fn value(self) -> Self::Value {
+        match self {
+            RegridTask::Source { .. } => None,
+            RegridTask::Target {
+                space,
+                level,
+                mut received,
+                ratios,
+                ..
+            } => {
+                received.sort_by_key(|p| std::cmp::Reverse(p.level()));
+                let num_fields = received.first().map_or(0, Patch::num_fields);
+                let mut patch = Patch::zeros(level, num_fields, space.clone());
+
+                for source in &received {
+                    let factor = level_scale(&ratios, source.level()) / level_scale(&ratios, level);
+                    let source_space = source.index_space();
+
+                    for index in space.iter() {
+                        let coarse = (index.0.div_euclid(factor), index.1.div_euclid(factor));
+
+                        if source_space.contains(coarse) {
+                            patch
+                                .get_slice_mut(index)
+                                .clone_from_slice(source.get_slice(coarse))
+                        }
+                    }
+                }
+                Some(patch)
+            }
+        }
+    }
+
+    fn independent(&self) -> bool {
+        match self {
+            RegridTask::Source { .. } => true,
+            RegridTask::Target { expected, .. } => *expected == 0,
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index_space::range2d;
+
+    #[test]
+    fn level_hierarchy_enforces_nesting() {
+        let base = vec![
+            Patch::zeros(1, 1, (0..4, 0..8)),
+            Patch::zeros(1, 1, (4..8, 0..8)),
+        ];
+        let mut hierarchy = LevelHierarchy::from_base(base);
+        assert_eq!(hierarchy.base_level(), Some(1));
+
+        assert!(hierarchy.insert(Patch::zeros(0, 1, (6..10, 0..4))).is_ok());
+        assert_eq!(
+            hierarchy.insert(Patch::zeros(0, 1, (14..18, 0..4))),
+            Err(HierarchyError::NotNested(0, IndexSpace::new(14..18, 0..4)))
+        );
+        assert!(hierarchy.is_properly_nested());
+        assert_eq!(hierarchy.levels().collect::<Vec<_>>(), vec![0, 1]);
+        assert_eq!(hierarchy.patches().count(), 3);
+    }
+
+    #[test]
+    fn level_hierarchy_regrid_transfers_data() {
+        let base = Patch::from_scalar_function(1, (0..8, 0..8), |(i, j)| (i * 8 + j) as f64);
+        let mut hierarchy = LevelHierarchy::from_base(vec![base]);
+        let fine = vec![IndexSpace::new(0..4, 0..4)];
+        hierarchy.regrid(0, fine, 1).unwrap();
+
+        let fine = hierarchy.level(0).unwrap().get((&(0..4), &(0..4))).unwrap();
+        assert_eq!(fine.get_slice((3, 2)), &[9.0]);
+
+        // Overwrite fine data, then regrid to a shifted region: the overlap
+        // retains the fine data, the rest is sampled from the base level.
+        hierarchy
+            .level_mut(0)
+            .unwrap()
+            .iter_mut()
+            .for_each(|(_, p)| p.data_mut().iter_mut().for_each(|x| *x = -1.0));
+        hierarchy
+            .regrid(0, vec![IndexSpace::new(2..6, 0..4)], 1)
+            .unwrap();
+
+        let fine = hierarchy.level(0).unwrap().get((&(2..6), &(0..4))).unwrap();
+        assert_eq!(fine.get_slice((3, 0)), &[-1.0]);
+        assert_eq!(fine.get_slice((5, 3)), &[17.0]);
+
+        assert!(hierarchy
+            .regrid(0, vec![IndexSpace::new(0..20, 0..4)], 1)
+            .is_err());
+        hierarchy.regrid(0, vec![], 1).unwrap();
+        assert_eq!(hierarchy.levels().count(), 1);
+    }
+
+    #[test]
+    fn level_hierarchy_respects_refinement_ratios() {
+        let base = Patch::from_scalar_function(2, (0..4, 0..4), |(i, j)| (i * 4 + j) as f64);
+        let mut hierarchy = LevelHierarchy::from_base(vec![base]).with_ratios(vec![2, 2]);
+        assert_eq!(hierarchy.scale(2), 4);
+        assert_eq!(hierarchy.ratio(2), 2);
+        assert!(hierarchy.level(2).unwrap().contains((&(0..16), &(0..16))));
+
+        hierarchy
+            .regrid(0, vec![IndexSpace::new(4..12, 0..8)], 1)
+            .unwrap();
+        assert!(hierarchy.is_properly_nested());
+        assert!(hierarchy
+            .regrid(0, vec![IndexSpace::new(0..20, 0..4)], 1)
+            .is_err());
+
+        let fine = hierarchy
+            .level(0)
+            .unwrap()
+            .get((&(4..12), &(0..8)))
+            .unwrap();
+        assert_eq!(fine.get_slice((7, 5)), &[5.0]);
+        assert_eq!(fine.get_slice((8, 3)), &[8.0]);
+
+        hierarchy
+            .level_mut(0)
+            .unwrap()
+            .iter_mut()
+            .for_each(|(_, p)| p.map_index_mut(|(i, _), s| s[0] = i as f64));
+        hierarchy.restrict(0);
+        let coarse = hierarchy
+            .level(2)
+            .unwrap()
+            .get((&(0..16), &(0..16)))
+            .unwrap();
+        assert_eq!(coarse.get_slice((1, 0)), &[5.5]);
+        assert_eq!(coarse.get_slice((1, 2)), &[6.0]);
+
+        let edges = hierarchy.adjacency_list(Extension::uniform(1));
+        assert!(edges.contains(&((0..16, 0..16), 2), &((4..12, 0..8), 0)));
+        assert!(edges.contains(&((4..12, 0..8), 0), &((0..16, 0..16), 2)));
+    }
+
+    #[test]
+    #[should_panic(expected = "only a refinement ratio of two is supported")]
+    fn level_hierarchy_rejects_refinement_ratios_other_than_two() {
+        let base = Patch::from_scalar_function(1, (0..4, 0..4), |(i, j)| (i * 4 + j) as f64);
+        LevelHierarchy::from_base(vec![base]).with_ratios(vec![4]);
+    }
+
+    #[test]
+    fn level_hierarchy_parent_and_child_queries_work() {
+        let base = vec![
+            Patch::zeros(1, 1, (0..4, 0..8)),
+            Patch::zeros(1, 1, (4..8, 0..8)),
+        ];
+        let mut hierarchy = LevelHierarchy::from_base(base);
+        hierarchy.insert(Patch::zeros(0, 1, (0..4, 0..8))).unwrap();
+        hierarchy.insert(Patch::zeros(0, 1, (6..10, 0..4))).unwrap();
+
+        let coarse = hierarchy
+            .level(1)
+            .unwrap()
+            .get((&(0..8), &(0..16)))
+            .unwrap();
+        let straddling = hierarchy
+            .level(0)
+            .unwrap()
+            .get((&(6..10), &(0..4)))
+            .unwrap();
+        let inside = hierarchy.level(0).unwrap().get((&(0..4), &(0..8))).unwrap();
+
+        assert_eq!(hierarchy.children_of(coarse).count(), 2);
+        assert_eq!(hierarchy.parents_of(straddling).count(), 2);
+        assert!(hierarchy.parent_of(straddling).is_none());
+        assert_eq!(
+            hierarchy.parent_of(inside).map(|p| p.index_space()),
+            Some(IndexSpace::new(0..4, 0..8))
+        );
+        assert_eq!(hierarchy.covered_fraction(coarse), 40.0 / 128.0);
+        assert_eq!(hierarchy.covered_fraction(inside), 0.0);
+    }
+
+    #[test]
+    fn regrid_transfers_data_to_new_levels() {
+        use crate::coder::NullCoder;
+        use crate::message::NullCommunicator;
+
+        let mut comm = NullCommunicator::new();
+        let code = NullCoder::new();
+        let f = |(i, j): (i64, i64)| (i * 100 + j) as f64;
+        let mut work = BTreeMap::new();
+        work.insert(
+            1,
+            vec![((0..8, 0..8), 0), ((8..16, 0..8), 0)]
+                .into_iter()
+                .collect::<RectangleMap<_, _>>(),
+        );
+        let patches = vec![
+            Patch::from_scalar_function(1, (0..8, 0..8), f),
+            Patch::from_scalar_function(1, (8..16, 0..8), f),
+        ];
+        let flags = range2d(2..6, 2..6)
+            .into_iter()
+            .map(|index| (index, 1))
+            .chain(std::iter::once(((0, 0), 0)));
+
+        let outcome = regrid(
+            &mut comm,
+            &code,
+            &work,
+            patches,
+            flags,
+            RegridParameters {
+                min_efficiency: 0.7,
+                stencil: Stencil::square(1),
+                ratios: &[],
+            },
+        );
+        let fine: Vec<_> = outcome.work[&0]
+            .iter()
+            .map(|(r, _)| IndexSpace::from(r))
+            .collect();
+
+        assert_eq!(fine, vec![IndexSpace::new(4..12, 4..12)]);
+        assert_eq!(outcome.patches.len(), 3);
+        assert!(outcome
+            .adjacency
+            .contains(&((0..16, 0..16), 1), &((4..12, 4..12), 0)));
+
+        let mut patches = outcome.patches;
+
+        for patch in &mut patches {
+            if patch.level() == 0 {
+                assert_eq!(patch.get_slice((5, 11)), &[f((2, 5))]);
+                patch.map_index_mut(|_, slice| slice[0] = -1.0);
+            }
+        }
+        let flags = range2d(2..6, 2..8).into_iter().map(|index| (index, 1));
+        let outcome = regrid(
+            &mut comm,
+            &code,
+            &outcome.work,
+            patches,
+            flags,
+            RegridParameters {
+                min_efficiency: 0.7,
+                stencil: Stencil::square(1),
+                ratios: &[],
+            },
+        );
+        let fine = outcome.patches.iter().find(|p| p.level() == 0).unwrap();
+
+        assert_eq!(fine.index_space(), IndexSpace::new(4..12, 4..16));
+        assert_eq!(fine.get_slice((11, 11)), &[-1.0]);
+        assert_eq!(fine.get_slice((11, 12)), &[f((5, 6))]);
+    }
+
+    #[test]
+    fn regrid_uses_the_given_refinement_ratios() {
+        use crate::coder::NullCoder;
+        use crate::message::NullCommunicator;
+
+        let mut comm = NullCommunicator::new();
+        let code = NullCoder::new();
+        let f = |(i, j): (i64, i64)| (i * 100 + j) as f64;
+        let mut work = BTreeMap::new();
+        work.insert(1, std::iter::once(((0..8, 0..8), 0)).collect::<RectangleMap<_, _>>());
+        let patches = vec![Patch::from_scalar_function(1, (0..8, 0..8), f)];
+        let flags = range2d(2..4, 2..4).into_iter().map(|index| (index, 1));
+
+        let parameters = RegridParameters {
+            min_efficiency: 0.7,
+            stencil: Stencil::square(1),
+            ratios: &[2],
+        };
+        let outcome = regrid(&mut comm, &code, &work, patches, flags, parameters);
+        let fine = outcome.patches.iter().find(|p| p.level() == 0).unwrap();
+
+        assert_eq!(fine.index_space(), IndexSpace::new(4..8, 4..8));
+        assert_eq!(fine.get_slice((7, 5)), &[f((3, 2))]);
+        assert!(outcome
+            .adjacency
+            .contains(&((0..16, 0..16), 1), &((4..8, 4..8), 0)));
+    }
+
+    #[test]
+    #[should_panic(expected = "only a refinement ratio of two is supported")]
+    fn regrid_rejects_refinement_ratios_other_than_two() {
+        use crate::coder::NullCoder;
+        use crate::message::NullCommunicator;
+
+        let mut comm = NullCommunicator::new();
+        let code = NullCoder::new();
+        let mut work = BTreeMap::new();
+        work.insert(1, std::iter::once(((0..8, 0..8), 0)).collect::<RectangleMap<_, _>>());
+        let patches = vec![Patch::zeros(1, 1, (0..8, 0..8))];
+        let flags = range2d(2..4, 2..4).into_iter().map(|index| (index, 1));
+
+        let parameters = RegridParameters {
+            min_efficiency: 0.7,
+            stencil: Stencil::square(1),
+            ratios: &[4],
+        };
+        regrid(&mut comm, &code, &work, patches, flags, parameters);
+    }
+}
@@ -0,0 +1,1868 @@
+//! Functions for filling guard zone regions and creating adjacency lists.
+//!
+//! Adjacency lists are used to establish the flow of data in parallel
+//! executions based on message-passing. The coordinate geometries, the graph
+//! partitioner, [`DomainDecomposition`] and [`LevelHierarchy`] live in
+//! submodules, and are re-exported here.
+
+use crate::adjacency_list::AdjacencyList;
+use crate::index_space::{self, Axis, Extension, IndexSpace, Stencil};
+use crate::patch::Patch;
+use crate::rect_map::{Brick, BrickMap, Rectangle, RectangleMap, RectangleRef};
+use std::borrow::Borrow;
+use std::collections::{BTreeMap, HashMap};
+use std::io::{self, Read, Write};
+use std::ops::Range;
+use std::{error, fmt};
+
+mod decomposition;
+mod geometry;
+mod hierarchy;
+mod partition;
+
+pub use decomposition::{
+    BlockLayout, DecompositionError, DomainDecomposition, RefinedRegion, RegionBounds,
+};
+pub use geometry::{
+    remap, Cartesian, Cylindrical, Geometry, LogRadial, MovingCartesian, SphericalPolar,
+};
+pub use hierarchy::{regrid, HierarchyError, LevelHierarchy, RegridOutcome, RegridParameters};
+pub use partition::{edge_cut, partition_graph, rebalance_incremental};
+
+/// A trait for a container that can respond to queries for a patch overlying
+/// a point.
+pub trait PatchQuery {
+    /// Return a patch containing the given point, if one exists.
+    fn patch_containing_point(&self, point: (i64, i64)) -> Option<&Patch>;
+}
+
+impl PatchQuery for Vec<Patch> {
+    fn patch_containing_point(&self, point: (i64, i64)) -> Option<&Patch> {
+        self.iter()
+            .find(|p| p.high_resolution_space().contains(point))
+    }
+}
+
+impl PatchQuery for RectangleMap<i64, Patch> {
+    fn patch_containing_point(&self, point: (i64, i64)) -> Option<&Patch> {
+        self.query_point(point).next().map(|(_, p)| p)
+    }
+}
+
+/// Describes how guard zones beyond one side of the domain are filled.
+#[derive(Debug, Clone, PartialEq)]
+pub enum AxisBoundary {
+    /// Guard zones are filled by the `boundary_value` closure given to
+    /// [`extend_patch_mut`].
+    Custom,
+
+    /// The axis wraps around: guard zones are filled from the opposite end
+    /// of the domain.
+    Periodic,
+
+    /// Guard zones mirror the zones across the boundary, with each field
+    /// multiplied by the given factor (for example `-1.0` for the velocity
+    /// component normal to the boundary, and `1.0` for the others).
+    Reflect(Vec<f64>),
+
+    /// Guard zones copy the nearest zone inside the domain (zero gradient).
+    Outflow,
+
+    /// Each field is filled according to its own rule, for example copying
+    /// the density while reflecting the normal velocity. Fields beyond the
+    /// end of the list are copied from the nearest zone inside the domain.
+    PerField(Vec<FieldBoundary>),
+
+    /// The lower and upper sides of the axis are filled differently. See
+    /// [`BoundaryTopology::with_face`]. A periodic axis must be periodic on
+    /// both sides.
+    Sides(Box<AxisBoundary>, Box<AxisBoundary>),
+}
+
+/// Describes how a single field is filled in the guard zones beyond an
+/// [`AxisBoundary::PerField`] boundary.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldBoundary {
+    /// Copy the nearest zone inside the domain (zero gradient).
+    Outflow,
+
+    /// Mirror the zones across the boundary, multiplied by the given factor.
+    Reflect(f64),
+
+    /// Use a fixed value.
+    Fixed(f64),
+}
+
+/// Identifies the lower or upper side of an axis.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Side {
+    Lower,
+    Upper,
+}
+
+/// A face of the domain: an axis and the side of it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BoundaryFace {
+    pub axis: Axis,
+    pub side: Side,
+}
+
+/// Describes the domain a patch lives in, and how guard zones outside the
+/// domain are filled on each axis. The domain is measured at the level of
+/// the patches being extended.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BoundaryTopology {
+    pub domain: IndexSpace,
+    pub i: AxisBoundary,
+    pub j: AxisBoundary,
+}
+
+/// Where the value of one field in a guard zone comes from, as determined by
+/// [`BoundaryTopology::field_source`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FieldSource {
+    /// The field at the given index inside the domain, multiplied by a
+    /// factor.
+    Zone((i64, i64), f64),
+
+    /// A fixed value.
+    Value(f64),
+}
+
+impl BoundaryTopology {
+    /// Creates a topology for the given domain, in which all the guard zones
+    /// outside the domain are filled by the `boundary_value` closure.
+    pub fn new(domain: IndexSpace) -> Self {
+        Self {
+            domain,
+            i: AxisBoundary::Custom,
+            j: AxisBoundary::Custom,
+        }
+    }
+
+    /// Returns this topology, with the given behavior on one axis.
+    pub fn with_axis(mut self, axis: Axis, boundary: AxisBoundary) -> Self {
+        match axis {
+            Axis::I => self.i = boundary,
+            Axis::J => self.j = boundary,
+        }
+        self
+    }
+
+    /// Returns this topology, with the given behavior on one side of an axis.
+    /// The other side keeps its current behavior.
+    pub fn with_face(mut self, face: BoundaryFace, boundary: AxisBoundary) -> Self {
+        let current = match face.axis {
+            Axis::I => &mut self.i,
+            Axis::J => &mut self.j,
+        };
+        let (mut lower, mut upper) = match current.clone() {
+            AxisBoundary::Sides(lower, upper) => (*lower, *upper),
+            other => (other.clone(), other),
+        };
+        match face.side {
+            Side::Lower => lower = boundary,
+            Side::Upper => upper = boundary,
+        }
+        *current = if lower == upper {
+            lower
+        } else {
+            AxisBoundary::Sides(Box::new(lower), Box::new(upper))
+        };
+        self
+    }
+
+    /// Maps an index outside the domain to the index inside the domain whose
+    /// data fills it, together with the reflection factors to apply (empty
+    /// if there is no reflection). Where both axes reflect (at corners), the
+    /// factors are the products of those of the two axes. Returns `None` if
+    /// the index falls beyond a `Custom` or `PerField` boundary. Indexes
+    /// inside the domain are returned unchanged.
+    pub fn source_index(&self, index: (i64, i64)) -> Option<((i64, i64), Vec<f64>)> {
+        let (i0, j0) = self.domain.start();
+        let (i1, j1) = self.domain.end();
+        let (i, fi) = map_axis_index(index.0, i0..i1, &self.i)?;
+        let (j, fj) = map_axis_index(index.1, j0..j1, &self.j)?;
+        let factors = (0..fi.len().max(fj.len()))
+            .map(|n| fi.get(n).unwrap_or(&1.0) * fj.get(n).unwrap_or(&1.0))
+            .collect();
+        Some(((i, j), factors))
+    }
+
+    /// Returns where the given field of the zone at `index` comes from. This
+    /// is like [`BoundaryTopology::source_index`], except that it also
+    /// resolves `PerField` boundaries. At corners where both axes reflect,
+    /// the factor is the product of those of the two axes. Returns `None` if
+    /// the index falls beyond a `Custom` boundary.
+    pub fn field_source(&self, index: (i64, i64), field: usize) -> Option<FieldSource> {
+        let (i0, j0) = self.domain.start();
+        let (i1, j1) = self.domain.end();
+        let i = map_axis_field(index.0, i0..i1, &self.i, field)?;
+        let j = map_axis_field(index.1, j0..j1, &self.j, field)?;
+
+        match (i, j) {
+            (AxisSource::Value(v), _) | (_, AxisSource::Value(v)) => Some(FieldSource::Value(v)),
+            (AxisSource::Index(i, fi), AxisSource::Index(j, fj)) => {
+                Some(FieldSource::Zone((i, j), fi.unwrap_or(1.0) * fj.unwrap_or(1.0)))
+            }
+        }
+    }
+
+    /// Returns the face of the domain beyond which the given index lies, if
+    /// it is outside the domain. The `I` axis is checked first, so at
+    /// corners the face on the `I` axis is returned.
+    pub fn face_of(&self, index: (i64, i64)) -> Option<BoundaryFace> {
+        let (i0, j0) = self.domain.start();
+        let (i1, j1) = self.domain.end();
+        let side = |n: i64, lower: i64, upper: i64| {
+            if n < lower {
+                Some(Side::Lower)
+            } else if n >= upper {
+                Some(Side::Upper)
+            } else {
+                None
+            }
+        };
+        side(index.0, i0, i1)
+            .map(|side| BoundaryFace {
+                axis: Axis::I,
+                side,
+            })
+            .or_else(|| {
+                side(index.1, j0, j1).map(|side| BoundaryFace {
+                    axis: Axis::J,
+                    side,
+                })
+            })
+    }
+}
+
+fn map_axis_index(
+    n: i64,
+    range: std::ops::Range<i64>,
+    boundary: &AxisBoundary,
+) -> Option<(i64, &[f64])> {
+    if range.contains(&n) {
+        return Some((n, &[]));
+    }
+    match boundary {
+        AxisBoundary::Sides(lower, upper) => map_axis_index(
+            n,
+            range.clone(),
+            if n < range.start { lower } else { upper },
+        ),
+        AxisBoundary::Custom | AxisBoundary::PerField(_) => None,
+        AxisBoundary::Periodic => Some((wrap_index(n, &range), &[])),
+        AxisBoundary::Outflow => Some((n.clamp(range.start, range.end - 1), &[])),
+        AxisBoundary::Reflect(factors) => Some((mirror_index(n, &range), factors.as_slice())),
+    }
+}
+
+enum AxisSource {
+    Index(i64, Option<f64>),
+    Value(f64),
+}
+
+fn map_axis_field(
+    n: i64,
+    range: std::ops::Range<i64>,
+    boundary: &AxisBoundary,
+    field: usize,
+) -> Option<AxisSource> {
+    if range.contains(&n) {
+        return Some(AxisSource::Index(n, None));
+    }
+    let clamped = n.clamp(range.start, range.end - 1);
+
+    match boundary {
+        AxisBoundary::Sides(lower, upper) => map_axis_field(
+            n,
+            range.clone(),
+            if n < range.start { lower } else { upper },
+            field,
+        ),
+        AxisBoundary::Custom => None,
+        AxisBoundary::Periodic => Some(AxisSource::Index(wrap_index(n, &range), None)),
+        AxisBoundary::Outflow => Some(AxisSource::Index(clamped, None)),
+        AxisBoundary::Reflect(factors) => Some(AxisSource::Index(
+            mirror_index(n, &range),
+            factors.get(field).cloned(),
+        )),
+        AxisBoundary::PerField(rules) => match rules.get(field) {
+            None | Some(FieldBoundary::Outflow) => Some(AxisSource::Index(clamped, None)),
+            Some(FieldBoundary::Reflect(f)) => {
+                Some(AxisSource::Index(mirror_index(n, &range), Some(*f)))
+            }
+            Some(FieldBoundary::Fixed(v)) => Some(AxisSource::Value(*v)),
+        },
+    }
+}
+
+fn wrap_index(n: i64, range: &Range<i64>) -> i64 {
+    (n - range.start).rem_euclid(range.end - range.start) + range.start
+}
+
+fn mirror_index(n: i64, range: &Range<i64>) -> i64 {
+    if n < range.start {
+        2 * range.start - 1 - n
+    } else {
+        2 * range.end - 1 - n
+    }
+}
+
+/// Fills guard zone values in a mutable patch by sampling data from other
+/// patches in `PatchQuery` object. Indexes contained in the
+/// `valid_index_space` are not touched. Guard zones at the patch corners are
+/// filled along with the others. Guard zones outside the domain are first
+/// mapped into it, field by field, according to the boundary `topology`
+/// (see [`BoundaryTopology::field_source`]), and filled from this patch's
+/// valid zones or the neighbors. The `boundary_value` closure is called with
+/// the face being filled, the original index, and the fields of the zone,
+/// for guard zones beyond a `Custom` boundary, or when no patch contains a
+/// mapped index. The face is `None` if the index is inside the domain (its
+/// neighbor is missing). Where both axes reflect (at corners), the products
+/// of the factors of the two axes are applied. The patch containing each
+/// source zone is looked up once per guard zone, however many fields come
+/// from it. The patch's guard zones are marked as filled afterwards.
+///
+/// Masked patches are respected (see [`Patch::set_mask`]): solid guard
+/// zones of this patch are not filled, and a mapped index which falls on a
+/// solid zone of this patch or a neighbor is treated as if no patch
+/// contained it, so the `boundary_value` closure supplies its data.
+///
+/// __WARNING__: this function is currently implemented only for patches at
+/// uniform refinement level.
+pub fn extend_patch_mut<P, G>(
+    patch: &mut Patch,
+    valid_index_space: &IndexSpace,
+    topology: &BoundaryTopology,
+    boundary_value: G,
+    neighbors: &P,
+) where
+    P: PatchQuery,
+    G: Fn(Option<BoundaryFace>, (i64, i64), &mut [f64]),
+{
+    let mut value = vec![0.0; patch.num_fields()];
+
+    for strip in patch.index_space().subtract(valid_index_space) {
+        for index in strip.iter() {
+            if patch.is_solid(index) {
+                continue;
+            }
+            let mut filled = true;
+
+            // The source zones of the fields, and the patches containing
+            // them. The fields share one source unless some axis has a
+            // PerField boundary.
+            let mut sources: Vec<((i64, i64), Option<&Patch>)> = Vec::new();
+
+            for (field, v) in value.iter_mut().enumerate() {
+                let source = match topology.field_source(index, field) {
+                    Some(FieldSource::Value(x)) => Some(x),
+                    Some(FieldSource::Zone(source, factor)) => {
+                        let p = match sources.iter().find(|(s, _)| *s == source) {
+                            Some((_, p)) => *p,
+                            None => {
+                                let p = if valid_index_space.contains(source) {
+                                    Some(&*patch)
+                                } else {
+                                    neighbors.patch_containing_point(source)
+                                }
+                                .filter(|p| !p.is_solid(source));
+                                sources.push((source, p));
+                                p
+                            }
+                        };
+                        p.map(|p| p.get_slice(source)[field] * factor)
+                    }
+                    None => None,
+                };
+                match source {
+                    Some(x) => *v = x,
+                    None => {
+                        filled = false;
+                        break;
+                    }
+                }
+            }
+            if filled {
+                patch.get_slice_mut(index).clone_from_slice(&value)
+            } else {
+                boundary_value(topology.face_of(index), index, patch.get_slice_mut(index))
+            }
+        }
+    }
+    patch.mark_guards_filled();
+}
+
+/// A trait for a container that can yield an adjacency list (the container
+/// items can form a topology). The intended use case is for a `RectangleMap`
+/// of patches, where adjacency means that two patches overlap when one is
+/// extended. More specifically, a graph edge pointing from patch `A` to patch
+/// `B` means that `A` is _upstream_ of `B`: guard zones from `A` are required
+/// to extend `B`. In parallel executions, messages are passed in the
+/// direction of the arrows, from `A` to `B` in this case.
+pub trait GraphTopology {
+    /// The type of key used to identify vertices
+    type Key;
+
+    /// An additional type parameter given to `Self::adjacency_list`. In
+    /// contect, this is probably the number of guard zones (possibly
+    /// different on each side of each axis), which in general will influence
+    /// which other patches are neighbors.
+    type Parameter;
+
+    /// Return an adjacency list derived from this container.
+    fn adjacency_list(&self, parameter: Self::Parameter) -> AdjacencyList<Self::Key>;
+}
+
+/// The parameter is the guard zone extension of the receiving patch. An edge
+/// `A -> B` is inserted if `A` overlaps `B` when `B` is extended. A uniform
+/// number of guard zones can be given as `Extension::uniform(n)` or `n.into()`.
+impl GraphTopology for RectangleMap<i64, Patch> {
+    type Key = (Rectangle<i64>, u32);
+
+    type Parameter = Extension;
+
+    fn adjacency_list(&self, extension: Self::Parameter) -> AdjacencyList<Self::Key> {
+        patch_adjacency_list(self, &extension)
+    }
+}
+
+/// A temporary `RectangleMap` is built from the patches, keyed by their high
+/// resolution rectangles.
+impl GraphTopology for [Patch] {
+    type Key = (Rectangle<i64>, u32);
+
+    type Parameter = Extension;
+
+    fn adjacency_list(&self, extension: Self::Parameter) -> AdjacencyList<Self::Key> {
+        let map: RectangleMap<i64, &Patch> =
+            self.iter().map(|p| (p.high_resolution_rect(), p)).collect();
+        patch_adjacency_list(&map, &extension)
+    }
+}
+
+impl GraphTopology for Vec<Patch> {
+    type Key = (Rectangle<i64>, u32);
+
+    type Parameter = Extension;
+
+    fn adjacency_list(&self, extension: Self::Parameter) -> AdjacencyList<Self::Key> {
+        self.as_slice().adjacency_list(extension)
+    }
+}
+
+/// Bricks in a 3d decomposition are vertices of the graph, and the parameter
+/// is the number of guard zones on each side of each axis. An edge `A -> B`
+/// is inserted if `A` overlaps `B` when `B` is extended, so with one or more
+/// guard zones an interior brick of a Cartesian decomposition has 26
+/// neighbors (faces, edges and corners).
+impl<V> GraphTopology for BrickMap<i64, V> {
+    type Key = Brick<i64>;
+
+    type Parameter = i64;
+
+    fn adjacency_list(&self, num_guard: Self::Parameter) -> AdjacencyList<Self::Key> {
+        let mut edges = AdjacencyList::new();
+
+        for (b, _) in self.iter() {
+            let extended = (
+                b.0.start - num_guard..b.0.end + num_guard,
+                b.1.start - num_guard..b.1.end + num_guard,
+                b.2.start - num_guard..b.2.end + num_guard,
+            );
+            for (a, _) in self.query_brick(extended) {
+                if a != b {
+                    let a = (a.0.clone(), a.1.clone(), a.2.clone());
+                    let b = (b.0.clone(), b.1.clone(), b.2.clone());
+                    edges.insert(a, b)
+                }
+            }
+        }
+        edges
+    }
+}
+
+/// Returns an adjacency list for a map of patches which tile a domain that is
+/// periodic on one or both axes. In addition to the edges returned by
+/// `GraphTopology::adjacency_list`, an edge `A -> B` is inserted if `A`
+/// overlaps a periodic image of `B` (when extended) on a periodic axis. The
+/// `periodic` flags refer to the `I` and `J` axes respectively. A patch is
+/// never made a neighbor of itself, even if it spans a periodic axis.
+pub fn periodic_adjacency_list<P: Borrow<Patch>>(
+    map: &RectangleMap<i64, P>,
+    extension: Extension,
+    domain: &IndexSpace,
+    periodic: (bool, bool),
+) -> AdjacencyList<(Rectangle<i64>, u32)> {
+    let mut edges = AdjacencyList::new();
+
+    for (b, q) in map.iter() {
+        let q = q.borrow();
+        let extended = q.index_space().extend_by(&extension);
+
+        for (image, (si, sj)) in extended.periodic_images(domain) {
+            if (si != 0 && !periodic.0) || (sj != 0 && !periodic.1) {
+                continue;
+            }
+            for (a, p) in map.query_rect(image) {
+                if a != b {
+                    let a = (IndexSpace::from(a).into(), p.borrow().level());
+                    let b = (IndexSpace::from(b).into(), q.level());
+
+                    if !edges.contains(&a, &b) {
+                        edges.insert(a, b)
+                    }
+                }
+            }
+        }
+    }
+    edges
+}
+
+/// Error returned by [`strict_adjacency_list`] when patches on the same level
+/// overlap one another.
+#[derive(Debug, Clone, PartialEq)]
+pub struct OverlapError {
+    /// The high resolution rectangles of each pair of overlapping patches,
+    /// and their level.
+    pub overlaps: Vec<(Rectangle<i64>, Rectangle<i64>, u32)>,
+}
+
+impl fmt::Display for OverlapError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            fmt,
+            "{} pairs of same-level patches overlap",
+            self.overlaps.len()
+        )?;
+
+        for (a, b, level) in &self.overlaps {
+            write!(fmt, "; {:?} and {:?} on level {}", a, b, level)?;
+        }
+        Ok(())
+    }
+}
+
+impl error::Error for OverlapError {}
+
+/// Like `GraphTopology::adjacency_list` for a map of patches, but checks that
+/// no two patches on the same level overlap. Such overlaps would make the
+/// guard zone fills ambiguous, and are otherwise silently turned into edges.
+/// If any are found, every overlapping pair is returned in the error (each
+/// pair once), rather than the adjacency list.
+pub fn strict_adjacency_list<P: Borrow<Patch>>(
+    map: &RectangleMap<i64, P>,
+    extension: Extension,
+) -> Result<AdjacencyList<(Rectangle<i64>, u32)>, OverlapError> {
+    let mut overlaps = Vec::new();
+
+    for (b, q) in map.iter() {
+        let level = q.borrow().level();
+
+        for (a, p) in map.query_rect(IndexSpace::from(b)) {
+            if a != b && p.borrow().level() == level {
+                let a: Rectangle<i64> = IndexSpace::from(a).into();
+                let b: Rectangle<i64> = IndexSpace::from(b).into();
+
+                if !overlaps.contains(&(b.clone(), a.clone(), level)) {
+                    overlaps.push((a, b, level))
+                }
+            }
+        }
+    }
+    if overlaps.is_empty() {
+        Ok(patch_adjacency_list(map, &extension))
+    } else {
+        Err(OverlapError { overlaps })
+    }
+}
+
+/// Returns an adjacency list for a map of patches, keyed by their high
+/// resolution rectangles, in which an edge `A -> B` is inserted if `A`
+/// overlaps one of the guard zone regions of `B` for the given stencil
+/// (measured at `B`'s level). With a stencil that has corners this is the
+/// same as `GraphTopology::adjacency_list` with the stencil's extension;
+/// without corners, patches which only touch `B` diagonally are left out.
+pub fn stencil_adjacency_list<P: Borrow<Patch>>(
+    map: &RectangleMap<i64, P>,
+    stencil: &Stencil,
+) -> AdjacencyList<(Rectangle<i64>, u32)> {
+    let mut edges = AdjacencyList::new();
+
+    for (b, q) in map.iter() {
+        let q = q.borrow();
+        let b = (IndexSpace::from(b).into(), q.level());
+
+        for region in stencil.guard_regions(&q.index_space()) {
+            for (a, p) in map.query_rect(region.refine_by(1 << q.level())) {
+                let a = (IndexSpace::from(a).into(), p.borrow().level());
+
+                if a != b && !edges.contains(&a, &b) {
+                    edges.insert(a, b.clone())
+                }
+            }
+        }
+    }
+    edges
+}
+
+fn patch_adjacency_list<P: Borrow<Patch>>(
+    map: &RectangleMap<i64, P>,
+    extension: &Extension,
+) -> AdjacencyList<(Rectangle<i64>, u32)> {
+    let mut edges = AdjacencyList::new();
+
+    for (b, q) in map.iter() {
+        let q = q.borrow();
+
+        for (a, p) in map.query_rect(q.index_space().extend_by(extension)) {
+            if a != b {
+                let a = (IndexSpace::from(a).into(), p.borrow().level());
+                let b = (IndexSpace::from(b).into(), q.level());
+                edges.insert(a, b)
+            }
+        }
+    }
+    edges
+}
+
+/// A single guard zone transfer in a [`HaloPlan`]: the region of the
+/// `source` patch to extract, and the region of the `target` patch's extended
+/// (guard zone) storage where it lands. Vertex keys are `(rectangle, level)`
+/// pairs as in [`GraphTopology::adjacency_list`]; `extract` is measured at the
+/// source level, and `place` at the target level.
+#[derive(Debug, Clone, PartialEq)]
+pub struct HaloTransfer {
+    pub source: (Rectangle<i64>, u32),
+    pub target: (Rectangle<i64>, u32),
+    pub extract: IndexSpace,
+    pub place: IndexSpace,
+}
+
+impl HaloTransfer {
+    /// Extracts the data to be sent from the source patch (or any patch on
+    /// the source level which covers the extracted region).
+    pub fn extract(&self, source: &Patch) -> Patch {
+        source.extract(self.extract.clone())
+    }
+
+    /// Writes data obtained from [`HaloTransfer::extract`] into the target's
+    /// extended patch. Data is averaged if the source is finer than the
+    /// target, and sampled piecewise constant if it is coarser.
+    pub fn place(&self, data: &Patch, target: &mut Patch) {
+        if data.level() == target.level() {
+            data.extract(self.place.clone()).copy_into(target)
+        } else {
+            data.sample_at(target.level(), self.place.clone())
+                .copy_into(target)
+        }
+    }
+}
+
+/// A precomputed plan for a halo (guard zone) exchange. For each edge
+/// `A -> B` of an adjacency list, the plan records the part of `A` which
+/// overlaps `B` extended by the guard zone `extension` (given in zones at
+/// `B`'s level), so that solvers need not recompute geometric overlaps at
+/// every stage. When the source is finer than the target, only the target
+/// zones which the source covers completely are included; when it is
+/// coarser, the extracted region is rounded outward to whole source zones.
+/// Edges whose patches do not overlap are left out of the plan.
+#[derive(Clone, Default)]
+pub struct HaloPlan {
+    outgoing: HashMap<(Rectangle<i64>, u32), Vec<HaloTransfer>>,
+    incoming: HashMap<(Rectangle<i64>, u32), Vec<HaloTransfer>>,
+}
+
+impl HaloPlan {
+    /// Builds a plan from an adjacency list of `(rectangle, level)` keys,
+    /// where rectangles are high resolution.
+    pub fn new(edges: &AdjacencyList<(Rectangle<i64>, u32)>, extension: &Extension) -> Self {
+        Self::from_regions(edges, |target| {
+            let scale = 1 << target.1;
+            let scaled = Extension {
+                lower: (extension.lower.0 * scale, extension.lower.1 * scale),
+                upper: (extension.upper.0 * scale, extension.upper.1 * scale),
+            };
+            vec![IndexSpace::from(target.0.clone()).extend_by(&scaled)]
+        })
+    }
+
+    /// Builds a plan which fills the guard zones of the given stencil. There
+    /// is one transfer for each guard zone region of the target (see
+    /// [`Stencil::guard_regions`]) that the source overlaps, so without
+    /// corners the diagonal guard zones are never sent.
+    pub fn with_stencil(edges: &AdjacencyList<(Rectangle<i64>, u32)>, stencil: &Stencil) -> Self {
+        Self::from_regions(edges, |target| {
+            let scale = 1 << target.1;
+            let scaled = Stencil {
+                width: (stencil.width.0 * scale, stencil.width.1 * scale),
+                corners: stencil.corners,
+            };
+            scaled.guard_regions(&IndexSpace::from(target.0.clone()))
+        })
+    }
+
+    fn from_regions<F>(edges: &AdjacencyList<(Rectangle<i64>, u32)>, regions: F) -> Self
+    where
+        F: Fn(&(Rectangle<i64>, u32)) -> Vec<IndexSpace>,
+    {
+        let mut plan = Self::default();
+
+        for (a, b) in edges.iter() {
+            for region in regions(b) {
+                if let Some(transfer) = plan_transfer(a, b, &region) {
+                    plan.outgoing
+                        .entry(a.clone())
+                        .or_default()
+                        .push(transfer.clone());
+                    plan.incoming.entry(b.clone()).or_default().push(transfer);
+                }
+            }
+        }
+        plan
+    }
+
+    /// Returns the number of transfers in the plan.
+    pub fn len(&self) -> usize {
+        self.incoming.values().map(Vec::len).sum()
+    }
+
+    /// Returns true if the plan has no transfers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns the transfers whose source is the given patch key.
+    pub fn outgoing(&self, key: &(Rectangle<i64>, u32)) -> &[HaloTransfer] {
+        self.outgoing.get(key).map_or(&[], Vec::as_slice)
+    }
+
+    /// Returns the transfers whose target is the given patch key.
+    pub fn incoming(&self, key: &(Rectangle<i64>, u32)) -> &[HaloTransfer] {
+        self.incoming.get(key).map_or(&[], Vec::as_slice)
+    }
+}
+
+/// Plans the transfer from `source` into the given high resolution `region`
+/// of the target's extended storage.
+fn plan_transfer(
+    source: &(Rectangle<i64>, u32),
+    target: &(Rectangle<i64>, u32),
+    region: &IndexSpace,
+) -> Option<HaloTransfer> {
+    let (ls, lt) = (source.1, target.1);
+    let overlap = region
+        .intersect(&IndexSpace::from(source.0.clone()))
+        .filter(|overlap| !overlap.is_empty())?;
+
+    let (extract, place) = if ls < lt {
+        let place = coarsen_inward(&overlap, 1 << lt)?;
+        let extract = place.refine_by(1 << lt).coarsen_by(1 << ls);
+        (extract, place)
+    } else {
+        (
+            coarsen_outward(&overlap, 1 << ls),
+            overlap.coarsen_by(1 << lt),
+        )
+    };
+
+    Some(HaloTransfer {
+        source: source.clone(),
+        target: target.clone(),
+        extract,
+        place,
+    })
+}
+
+/// Assigns blocks on several levels to ranks, along a Hilbert curve through
+/// their high resolution rectangles, weighted by their number of zones and
+/// the given rank shares. The result is keyed by level, and then by the index
+/// rectangle of each block at its level. The refinement ratios are as for
+/// [`level_scale`].
+fn partition_levels(
+    blocks: Vec<(IndexSpace, u32)>,
+    shares: &[f64],
+    ratios: &[u32],
+) -> BTreeMap<u32, RectangleMap<i64, usize>> {
+    let mut work: BTreeMap<u32, RectangleMap<i64, usize>> = BTreeMap::new();
+    let sorted = hilbert_sorted(blocks.into_iter().map(|(space, level)| {
+        let scale = level_scale(ratios, level) as u32;
+        (space.refine_by(scale).to_rect(), (space, level))
+    }));
+    let costs: Vec<_> = sorted
+        .iter()
+        .map(|(_, (space, _))| space.len() as f64)
+        .collect();
+    let counts = index_space::partition_proportional(&costs, shares);
+    let mut sorted = sorted.into_iter();
+
+    for (rank, count) in counts.into_iter().enumerate() {
+        for (_, (space, level)) in sorted.by_ref().take(count) {
+            work.entry(level).or_default().insert(space, rank);
+        }
+    }
+    work
+}
+
+/// Builds the adjacency list of blocks on several levels, keyed by index
+/// rectangles at each level. Keys in the result are high resolution
+/// rectangles and levels. Without corners, only the face-adjacent guard
+/// regions of each receiving block are considered. If the high resolution
+/// domain and its periodic axes are given, the guard regions are also
+/// wrapped around the periodic axes. The refinement ratios are as for
+/// [`level_scale`].
+fn level_adjacency(
+    work: &BTreeMap<u32, RectangleMap<i64, usize>>,
+    extension: &Extension,
+    corners: bool,
+    periodic: Option<(&IndexSpace, (bool, bool))>,
+    ratios: &[u32],
+) -> AdjacencyList<(Rectangle<i64>, u32)> {
+    let mut adjacency = AdjacencyList::new();
+    let scale = |level| level_scale(ratios, level);
+
+    for (&level_b, blocks_b) in work {
+        for (b, _) in blocks_b.iter() {
+            let b = IndexSpace::from(b);
+            let key_b = (b.refine_by(scale(level_b) as u32).to_rect(), level_b);
+            let regions = if corners {
+                vec![b.extend_by(extension)]
+            } else {
+                face_regions(&b, extension).to_vec()
+            };
+
+            let images = regions.into_iter().flat_map(|region| {
+                let region = region.refine_by(scale(level_b) as u32);
+
+                match periodic {
+                    Some((domain, (pi, pj))) => region
+                        .periodic_images(domain)
+                        .filter(|(_, (si, sj))| (*si == 0 || pi) && (*sj == 0 || pj))
+                        .map(|(image, _)| image)
+                        .collect(),
+                    None => vec![region],
+                }
+            });
+
+            for region in images {
+                for (&level_a, blocks_a) in work {
+                    for (a, _) in blocks_a.query_rect(coarsen_outward(&region, scale(level_a))) {
+                        let key_a = (
+                            IndexSpace::from(a).refine_by(scale(level_a) as u32).to_rect(),
+                            level_a,
+                        );
+
+                        if key_a != key_b && !adjacency.contains(&key_a, &key_b) {
+                            adjacency.insert(key_a, key_b.clone())
+                        }
+                    }
+                }
+            }
+        }
+    }
+    adjacency
+}
+
+/// Returns the given space extended on the `I` axis only, and on the `J` axis
+/// only. Together these cover the guard zones of a stencil without corners.
+fn face_regions(space: &IndexSpace, extension: &Extension) -> [IndexSpace; 2] {
+    [
+        space
+            .extend_lower(extension.lower.0, Axis::I)
+            .extend_upper(extension.upper.0, Axis::I),
+        space
+            .extend_lower(extension.lower.1, Axis::J)
+            .extend_upper(extension.upper.1, Axis::J),
+    ]
+}
+
+/// Panics unless every refinement ratio in the list is two. Patches,
+/// geometries, [`remap`], [`DomainDecomposition`], [`HaloPlan`] and
+/// [`FluxRegister`](crate::flux_register::FluxRegister) measure a zone on
+/// level `l` as `2^l` high resolution zones, so other ratios are not yet
+/// supported.
+fn check_ratios(ratios: &[u32]) {
+    assert!(
+        ratios.iter().all(|&r| r == 2),
+        "only a refinement ratio of two is supported, got {:?}",
+        ratios
+    );
+}
+
+/// Returns the number of high resolution zones spanned by a zone on the
+/// given level, on each axis, where `ratios[l]` is the refinement ratio
+/// between levels `l + 1` and `l`, and levels beyond the end of the list are
+/// refined by a factor of two.
+fn level_scale(ratios: &[u32], level: u32) -> i64 {
+    (0..level)
+        .map(|l| ratios.get(l as usize).map_or(2, |&r| r as i64))
+        .product()
+}
+
+fn coarsen_outward(space: &IndexSpace, factor: i64) -> IndexSpace {
+    let (i0, j0) = space.start();
+    let (i1, j1) = space.end();
+    IndexSpace::new(
+        i0.div_euclid(factor)..(i1 + factor - 1).div_euclid(factor),
+        j0.div_euclid(factor)..(j1 + factor - 1).div_euclid(factor),
+    )
+}
+
+fn coarsen_inward(space: &IndexSpace, factor: i64) -> Option<IndexSpace> {
+    let (i0, j0) = space.start();
+    let (i1, j1) = space.end();
+    let di = (i0 + factor - 1).div_euclid(factor)..i1.div_euclid(factor);
+    let dj = (j0 + factor - 1).div_euclid(factor)..j1.div_euclid(factor);
+    Some(IndexSpace::new(di, dj)).filter(|space| !space.is_empty())
+}
+
+/// Returns the distance along a Hilbert curve of the point `(x, y)`, in a
+/// square grid of side `2^order`. The coordinates must be non-negative and
+/// less than `2^order`.
+pub fn hilbert_index(order: u32, point: (u64, u64)) -> u64 {
+    let n = 1u64 << order;
+    let (mut x, mut y) = point;
+    let mut d = 0;
+    let mut s = n / 2;
+
+    while s > 0 {
+        let rx = (x & s > 0) as u64;
+        let ry = (y & s > 0) as u64;
+        d += s * s * ((3 * rx) ^ ry);
+
+        if ry == 0 {
+            if rx == 1 {
+                x = n - 1 - x;
+                y = n - 1 - y;
+            }
+            std::mem::swap(&mut x, &mut y);
+        }
+        s /= 2;
+    }
+    d
+}
+
+/// Divides `total` elements into `pieces` contiguous parts whose sizes differ
+/// by at most one, and returns the `pieces + 1` edges of the parts: part `n`
+/// spans `edges[n]..edges[n + 1]`. The first edge is zero and the last is
+/// `total`. The number of pieces must be positive and no larger than
+/// `total`.
+pub fn partition_edges(total: usize, pieces: usize) -> Vec<usize> {
+    assert!(pieces > 0, "the number of pieces must be positive");
+    sizes_to_edges(index_space::partition(total, pieces))
+}
+
+/// Divides a sequence of elements with the given (non-negative) weights into
+/// `pieces` contiguous parts whose total weights are as nearly equal as
+/// possible, and returns the `pieces + 1` edges of the parts, as in
+/// [`partition_edges`]. Every part gets at least one element.
+pub fn partition_edges_weighted(weights: &[f64], pieces: usize) -> Vec<usize> {
+    assert!(pieces > 0, "the number of pieces must be positive");
+    sizes_to_edges(index_space::partition_weighted(weights, pieces))
+}
+
+/// Maps an edge vector to the index ranges of its parts, offset by `start`.
+pub fn edges_to_ranges(edges: &[usize], start: i64) -> Vec<Range<i64>> {
+    edges
+        .windows(2)
+        .map(|w| start + w[0] as i64..start + w[1] as i64)
+        .collect()
+}
+
+/// Maps edge vectors on the `i` and `j` axes to the rectangles of the block
+/// grid they define, offset by `start`. Rectangles are returned in row-major
+/// order (`j` varies fastest).
+pub fn edges_to_rectangles(
+    edges_i: &[usize],
+    edges_j: &[usize],
+    start: (i64, i64),
+) -> Vec<Rectangle<i64>> {
+    let ranges_i = edges_to_ranges(edges_i, start.0);
+    let ranges_j = edges_to_ranges(edges_j, start.1);
+
+    ranges_i
+        .iter()
+        .flat_map(|di| ranges_j.iter().map(move |dj| (di.clone(), dj.clone())))
+        .collect()
+}
+
+/// Decomposes a 3d box into `count` bricks arranged on a Cartesian grid,
+/// whose dimensions are given by [`block_dims`](index_space::block_dims).
+/// Brick extents on each axis differ by at most one zone. Bricks are returned
+/// in row-major order (`k` varies fastest). Each axis of the box must be at
+/// least as long as the number of bricks along it.
+pub fn decompose_brick(domain: Brick<i64>, count: usize) -> Vec<Brick<i64>> {
+    let dims = index_space::block_dims(count, 3);
+    let axes = [&domain.0, &domain.1, &domain.2];
+    let ranges: Vec<_> = axes
+        .iter()
+        .zip(dims)
+        .map(|(range, n)| {
+            edges_to_ranges(
+                &partition_edges((range.end - range.start) as usize, n),
+                range.start,
+            )
+        })
+        .collect();
+
+    let mut bricks = Vec::new();
+
+    for di in &ranges[0] {
+        for dj in &ranges[1] {
+            for dk in &ranges[2] {
+                bricks.push((di.clone(), dj.clone(), dk.clone()))
+            }
+        }
+    }
+    bricks
+}
+
+fn sizes_to_edges(sizes: Vec<usize>) -> Vec<usize> {
+    std::iter::once(0)
+        .chain(sizes.into_iter().scan(0, |edge, size| {
+            *edge += size;
+            Some(*edge)
+        }))
+        .collect()
+}
+
+/// Assigns blocks to ranks by ordering them along a Hilbert curve and
+/// splitting the sequence into `num_ranks` contiguous chunks of nearly equal
+/// length. Compared with a row-by-row enumeration, this yields compact
+/// per-rank regions with a small surface-to-volume ratio. Block positions on
+/// the curve are determined by the rank of their start coordinate among all
+/// the distinct start coordinates on each axis, so the blocks need not be
+/// uniformly sized. The number of blocks must be at least the number of
+/// ranks.
+pub fn hilbert_partition<I>(blocks: I, num_ranks: usize) -> RectangleMap<i64, usize>
+where
+    I: IntoIterator<Item = Rectangle<i64>>,
+{
+    let blocks = hilbert_sorted(blocks.into_iter().map(|rect| (rect, ())));
+    let counts = index_space::partition(blocks.len(), num_ranks);
+    assign_contiguous(blocks, counts)
+}
+
+/// Like [`hilbert_partition`], but each block carries a cost (for example the
+/// wall time it took to advance in the last step), and the curve is split so
+/// that the total cost on each rank is as nearly equal as possible, rather
+/// than the number of blocks. Costs must be non-negative.
+pub fn hilbert_partition_weighted<I>(blocks: I, num_ranks: usize) -> RectangleMap<i64, usize>
+where
+    I: IntoIterator<Item = (Rectangle<i64>, f64)>,
+{
+    let blocks = hilbert_sorted(blocks);
+    let costs: Vec<_> = blocks.iter().map(|(_, cost)| *cost).collect();
+    let counts = index_space::partition_weighted(&costs, num_ranks);
+    assign_contiguous(blocks, counts)
+}
+
+/// Like [`hilbert_partition_weighted`], but the ranks need not be equally
+/// fast: the curve is split so that the total cost on each rank is as nearly
+/// proportional to its (positive) speed as possible. This keeps ranks on
+/// slower nodes from holding back the others in clusters of mixed hardware.
+/// The number of ranks is the length of `speeds`.
+pub fn hilbert_partition_by_speed<I>(blocks: I, speeds: &[f64]) -> RectangleMap<i64, usize>
+where
+    I: IntoIterator<Item = (Rectangle<i64>, f64)>,
+{
+    let blocks = hilbert_sorted(blocks);
+    let costs: Vec<_> = blocks.iter().map(|(_, cost)| *cost).collect();
+    let counts = index_space::partition_proportional(&costs, speeds);
+    assign_contiguous(blocks, counts)
+}
+
+fn hilbert_sorted<I, T>(blocks: I) -> Vec<(Rectangle<i64>, T)>
+where
+    I: IntoIterator<Item = (Rectangle<i64>, T)>,
+{
+    let blocks: Vec<_> = blocks.into_iter().collect();
+    let (coordinates, side) = block_grid_coordinates(blocks.iter().map(|(r, _)| (&r.0, &r.1)));
+    let order = 64 - side.saturating_sub(1).leading_zeros();
+
+    let mut keyed: Vec<_> = coordinates
+        .into_iter()
+        .zip(blocks)
+        .map(|(point, block)| (hilbert_index(order, point), block))
+        .collect();
+    keyed.sort_by_key(|(d, _)| *d);
+    keyed.into_iter().map(|(_, block)| block).collect()
+}
+
+fn assign_contiguous<T>(
+    blocks: Vec<(Rectangle<i64>, T)>,
+    counts: Vec<usize>,
+) -> RectangleMap<i64, usize> {
+    let mut blocks = blocks.into_iter();
+
+    counts
+        .into_iter()
+        .enumerate()
+        .flat_map(|(rank, count)| {
+            blocks
+                .by_ref()
+                .take(count)
+                .map(move |(rect, _)| (rect, rank))
+                .collect::<Vec<_>>()
+        })
+        .collect()
+}
+
+/// Interleaves the bits of a 2d block coordinate into its Morton (Z-order)
+/// code. The `i` bits occupy the odd positions, so that codes sort first by
+/// the most significant bits of `i`.
+pub fn morton_encode(index: (u32, u32)) -> u64 {
+    spread_bits_2d(index.0) << 1 | spread_bits_2d(index.1)
+}
+
+/// Recovers a 2d block coordinate from its Morton code.
+pub fn morton_decode(code: u64) -> (u32, u32) {
+    (compact_bits_2d(code >> 1), compact_bits_2d(code))
+}
+
+/// Interleaves the bits of a 3d block coordinate into its Morton code. Each
+/// coordinate must fit in 21 bits.
+pub fn morton_encode_3d(index: (u32, u32, u32)) -> u64 {
+    spread_bits_3d(index.0) << 2 | spread_bits_3d(index.1) << 1 | spread_bits_3d(index.2)
+}
+
+/// Recovers a 3d block coordinate from its Morton code.
+pub fn morton_decode_3d(code: u64) -> (u32, u32, u32) {
+    (
+        compact_bits_3d(code >> 2),
+        compact_bits_3d(code >> 1),
+        compact_bits_3d(code),
+    )
+}
+
+/// Returns the entries of a map of blocks, sorted by the Morton code of their
+/// block coordinates. Block coordinates are determined as in
+/// [`hilbert_partition`]. Traversing blocks in this order keeps spatially
+/// nearby blocks close in memory and time.
+pub fn morton_order<V>(map: &RectangleMap<i64, V>) -> Vec<(RectangleRef<'_, i64>, &V)> {
+    let (coordinates, _) = block_grid_coordinates(map.keys());
+    let mut entries: Vec<_> = coordinates
+        .into_iter()
+        .map(|(x, y)| morton_encode((x as u32, y as u32)))
+        .zip(map.iter())
+        .collect();
+    entries.sort_by_key(|(code, _)| *code);
+    entries.into_iter().map(|(_, entry)| entry).collect()
+}
+
+/// Returns the position of each block on a logical grid, determined by the
+/// rank of its start coordinate among the distinct start coordinates on each
+/// axis, along with the number of grid points on the longest axis.
+fn block_grid_coordinates<'a, I>(blocks: I) -> (Vec<(u64, u64)>, u64)
+where
+    I: IntoIterator<Item = RectangleRef<'a, i64>>,
+{
+    let starts: Vec<_> = blocks
+        .into_iter()
+        .map(|(di, dj)| (di.start, dj.start))
+        .collect();
+    let distinct = |axis: fn(&(i64, i64)) -> i64| {
+        let mut s: Vec<_> = starts.iter().map(axis).collect();
+        s.sort_unstable();
+        s.dedup();
+        s
+    };
+    let si = distinct(|s| s.0);
+    let sj = distinct(|s| s.1);
+    let coordinates = starts
+        .iter()
+        .map(|(i, j)| {
+            let x = si.binary_search(i).unwrap() as u64;
+            let y = sj.binary_search(j).unwrap() as u64;
+            (x, y)
+        })
+        .collect();
+    (coordinates, si.len().max(sj.len()) as u64)
+}
+
+fn spread_bits_2d(x: u32) -> u64 {
+    let mut x = x as u64;
+    x = (x | x << 16) & 0x0000_ffff_0000_ffff;
+    x = (x | x << 8) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x << 4) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x << 2) & 0x3333_3333_3333_3333;
+    x = (x | x << 1) & 0x5555_5555_5555_5555;
+    x
+}
+
+fn compact_bits_2d(x: u64) -> u32 {
+    let mut x = x & 0x5555_5555_5555_5555;
+    x = (x | x >> 1) & 0x3333_3333_3333_3333;
+    x = (x | x >> 2) & 0x0f0f_0f0f_0f0f_0f0f;
+    x = (x | x >> 4) & 0x00ff_00ff_00ff_00ff;
+    x = (x | x >> 8) & 0x0000_ffff_0000_ffff;
+    x = (x | x >> 16) & 0x0000_0000_ffff_ffff;
+    x as u32
+}
+
+fn spread_bits_3d(x: u32) -> u64 {
+    let mut x = x as u64 & 0x1f_ffff;
+    x = (x | x << 32) & 0x001f_0000_0000_ffff;
+    x = (x | x << 16) & 0x001f_0000_ff00_00ff;
+    x = (x | x << 8) & 0x100f_00f0_0f00_f00f;
+    x = (x | x << 4) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x << 2) & 0x1249_2492_4924_9249;
+    x
+}
+
+fn compact_bits_3d(x: u64) -> u32 {
+    let mut x = x & 0x1249_2492_4924_9249;
+    x = (x | x >> 2) & 0x10c3_0c30_c30c_30c3;
+    x = (x | x >> 4) & 0x100f_00f0_0f00_f00f;
+    x = (x | x >> 8) & 0x001f_0000_ff00_00ff;
+    x = (x | x >> 16) & 0x001f_0000_0000_ffff;
+    x = (x | x >> 32) & 0x1f_ffff;
+    x as u32
+}
+
+/// Clusters a set of flagged indexes into a list of disjoint rectangles
+/// covering them, using the Berger-Rigoutsos algorithm. A rectangle is
+/// accepted once the fraction of its zones which are flagged is at least
+/// `min_efficiency`. Otherwise it is split at a hole in the flag signature if
+/// one exists, then at the strongest inflection of the signature, and
+/// otherwise in half along its longest axis. Duplicate indexes are ignored.
+pub fn berger_rigoutsos<I>(flagged: I, min_efficiency: f64) -> Vec<IndexSpace>
+where
+    I: IntoIterator<Item = (i64, i64)>,
+{
+    let mut points: Vec<_> = flagged.into_iter().collect();
+    points.sort_unstable();
+    points.dedup();
+
+    let mut result = Vec::new();
+    let mut stack = vec![points];
+
+    while let Some(points) = stack.pop() {
+        if points.is_empty() {
+            continue;
+        }
+        let bounds = bounding_space(&points);
+        let efficiency = points.len() as f64 / bounds.len() as f64;
+
+        if efficiency >= min_efficiency || bounds.len() == 1 {
+            result.push(bounds);
+            continue;
+        }
+        let (lower, upper): (Vec<_>, Vec<_>) = match choose_cut(&points, &bounds) {
+            (Axis::I, cut) => points.into_iter().partition(|p| p.0 < cut),
+            (Axis::J, cut) => points.into_iter().partition(|p| p.1 < cut),
+        };
+        stack.push(upper);
+        stack.push(lower);
+    }
+    result
+}
+
+fn bounding_space(points: &[(i64, i64)]) -> IndexSpace {
+    let (i0, j0, i1, j1) = points.iter().fold(
+        (i64::MAX, i64::MAX, i64::MIN, i64::MIN),
+        |(i0, j0, i1, j1), &(i, j)| (i0.min(i), j0.min(j), i1.max(i + 1), j1.max(j + 1)),
+    );
+    IndexSpace::new(i0..i1, j0..j1)
+}
+
+/// Returns the axis and index at which to split a rectangle of flagged
+/// points, such that points with a coordinate less than the index go to one
+/// side. The cut always leaves points on both sides.
+fn choose_cut(points: &[(i64, i64)], bounds: &IndexSpace) -> (Axis, i64) {
+    let (ni, nj) = bounds.dim();
+    let (i0, j0) = bounds.start();
+    let mut sig_i = vec![0i64; ni];
+    let mut sig_j = vec![0i64; nj];
+
+    for &(i, j) in points {
+        sig_i[(i - i0) as usize] += 1;
+        sig_j[(j - j0) as usize] += 1;
+    }
+
+    let hole = |sig: &[i64]| {
+        let mid = sig.len() / 2;
+        (1..sig.len())
+            .filter(|&k| sig[k] == 0)
+            .min_by_key(|&k| (k as i64 - mid as i64).abs())
+    };
+    let inflection = |sig: &[i64]| {
+        let lap: Vec<_> = (1..sig.len().saturating_sub(1))
+            .map(|k| sig[k - 1] - 2 * sig[k] + sig[k + 1])
+            .collect();
+        let mid = sig.len() as i64 / 2;
+        (1..lap.len())
+            .filter(|&k| lap[k - 1].signum() * lap[k].signum() < 0)
+            .map(|k| {
+                (
+                    (lap[k] - lap[k - 1]).abs(),
+                    -(k as i64 + 1 - mid).abs(),
+                    k + 1,
+                )
+            })
+            .max()
+    };
+
+    let axis_cut = |axis: Axis, cut: usize| match axis {
+        Axis::I => (Axis::I, i0 + cut as i64),
+        Axis::J => (Axis::J, j0 + cut as i64),
+    };
+
+    let holes = [(Axis::I, hole(&sig_i)), (Axis::J, hole(&sig_j))];
+    let mid_i = ni as i64 / 2;
+    let mid_j = nj as i64 / 2;
+
+    if let Some((axis, k)) = holes
+        .iter()
+        .filter_map(|(axis, k)| k.map(|k| (*axis, k)))
+        .min_by_key(|(axis, k)| match axis {
+            Axis::I => (*k as i64 - mid_i).abs(),
+            Axis::J => (*k as i64 - mid_j).abs(),
+        })
+    {
+        // Cut at the hole, so it goes with the upper side; the bounding box
+        // of that side will shrink to exclude it.
+        return axis_cut(axis, k);
+    }
+
+    match (inflection(&sig_i), inflection(&sig_j)) {
+        (Some(a), Some(b)) if b.0 > a.0 => axis_cut(Axis::J, b.2),
+        (Some(a), _) => axis_cut(Axis::I, a.2),
+        (None, Some(b)) => axis_cut(Axis::J, b.2),
+        (None, None) if ni >= nj => axis_cut(Axis::I, ni / 2),
+        (None, None) => axis_cut(Axis::J, nj / 2),
+    }
+}
+
+fn write_u64<W: Write>(writer: &mut W, value: u64) -> io::Result<()> {
+    writer.write_all(&value.to_le_bytes())
+}
+
+fn write_f64s<W: Write>(writer: &mut W, values: &[f64]) -> io::Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?
+    }
+    Ok(())
+}
+
+fn write_i64s<W: Write>(writer: &mut W, values: &[i64]) -> io::Result<()> {
+    for value in values {
+        writer.write_all(&value.to_le_bytes())?
+    }
+    Ok(())
+}
+
+fn write_rect<W: Write>(writer: &mut W, rect: &Rectangle<i64>) -> io::Result<()> {
+    write_i64s(
+        writer,
+        &[rect.0.start, rect.0.end, rect.1.start, rect.1.end],
+    )
+}
+
+fn read_u64<R: Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+fn read_i64<R: Read>(reader: &mut R) -> io::Result<i64> {
+    Ok(read_u64(reader)? as i64)
+}
+
+fn read_f64<R: Read>(reader: &mut R) -> io::Result<f64> {
+    Ok(f64::from_bits(read_u64(reader)?))
+}
+
+fn read_rect<R: Read>(reader: &mut R) -> io::Result<Rectangle<i64>> {
+    Ok((
+        read_i64(reader)?..read_i64(reader)?,
+        read_i64(reader)?..read_i64(reader)?,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index_space::{range2d, Extension, IndexSpace, Stencil};
+    use crate::patch::Patch;
+    use crate::rect_map::RectangleMap;
+
+    fn quilt() -> RectangleMap<i64, Patch> {
+        range2d(0..3, 0..3)
+            .iter()
+            .map(|(i, j)| {
+                let rect = (i * 10..(i + 1) * 10, j * 10..(j + 1) * 10);
+                (rect.clone(), Patch::zeros(0, 1, rect))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn adjacency_list_with_uniform_extension_works() {
+        let edges = quilt().adjacency_list(Extension::uniform(1));
+        let center = ((10..20, 10..20), 0);
+        assert_eq!(edges.incoming_edges(&center).count(), 8);
+        assert_eq!(edges.outgoing_edges(&center).count(), 8);
+        assert_eq!(edges.len(), 2 * (12 + 8));
+    }
+
+    #[test]
+    fn periodic_adjacency_list_wraps_edges() {
+        let domain = IndexSpace::new(0..30, 0..30);
+        let corner = ((0..10, 0..10), 0);
+
+        let edges = periodic_adjacency_list(&quilt(), Extension::uniform(1), &domain, (true, true));
+        assert_eq!(edges.len(), 9 * 8);
+        assert_eq!(edges.incoming_edges(&corner).count(), 8);
+        assert!(edges.contains(&((20..30, 20..30), 0), &corner));
+
+        let edges =
+            periodic_adjacency_list(&quilt(), Extension::uniform(1), &domain, (true, false));
+        assert_eq!(edges.incoming_edges(&corner).count(), 5);
+        assert!(!edges.contains(&((0..10, 20..30), 0), &corner));
+    }
+
+    #[test]
+    fn strict_adjacency_list_reports_overlaps() {
+        let mut patches = quilt();
+        assert_eq!(
+            strict_adjacency_list(&patches, Extension::uniform(1)).map(|edges| edges.len()),
+            Ok(2 * (12 + 8))
+        );
+
+        patches.insert((5..15, 5..15), Patch::zeros(0, 1, (5..15, 5..15)));
+        patches.insert((0..8, 0..8), Patch::zeros(1, 1, (0..4, 0..4)));
+        let error = strict_adjacency_list(&patches, Extension::uniform(1))
+            .err()
+            .unwrap();
+        assert_eq!(error.overlaps.len(), 4);
+        assert!(error
+            .overlaps
+            .iter()
+            .all(|(a, b, _)| *a == (5..15, 5..15) || *b == (5..15, 5..15)));
+    }
+
+    #[test]
+    fn stencil_adjacency_list_skips_corners() {
+        let center = ((10..20, 10..20), 0);
+        let star = stencil_adjacency_list(&quilt(), &Stencil::star(1));
+        assert_eq!(star.incoming_edges(&center).count(), 4);
+        assert_eq!(star.len(), 2 * 12);
+
+        let square = stencil_adjacency_list(&quilt(), &Stencil::square(1));
+        assert_eq!(
+            square.len(),
+            quilt().adjacency_list(Extension::uniform(1)).len()
+        );
+
+        let plan = HaloPlan::with_stencil(&square, &Stencil::star(1));
+        assert_eq!(plan.incoming(&center).len(), 4);
+        assert!(plan
+            .incoming(&center)
+            .iter()
+            .all(|transfer| transfer.place.len() == 10));
+
+        let decomp =
+            DomainDecomposition::new((0.0..1.0, 0.0..1.0), (30, 30), BlockLayout::Size(10), 1)
+                .unwrap()
+                .with_stencil(Stencil::star(2));
+        assert_eq!(decomp.adjacency_list().len(), 2 * 12);
+    }
+
+    #[test]
+    fn partition_returns_edges() {
+        assert_eq!(partition_edges(100, 8), vec![0, 13, 26, 39, 52, 64, 76, 88, 100]);
+        assert_eq!(partition_edges(3, 3), vec![0, 1, 2, 3]);
+        assert_eq!(
+            partition_edges_weighted(&[0.0, 0.0, 0.0, 9.0], 3),
+            vec![0, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn edges_map_to_rectangles() {
+        assert_eq!(edges_to_ranges(&[0, 2, 5], 10), vec![10..12, 12..15]);
+
+        let rects = edges_to_rectangles(&partition_edges(10, 2), &partition_edges(6, 3), (0, -3));
+        assert_eq!(rects.len(), 6);
+        assert_eq!(rects[0], (0..5, -3..-1));
+        assert_eq!(rects[5], (5..10, 1..3));
+    }
+
+    #[test]
+    fn brick_decomposition_has_26_neighbors() {
+        let bricks = decompose_brick((0..30, 0..30, -15..15), 27);
+        assert_eq!(bricks.len(), 27);
+        assert_eq!(bricks[0], (0..10, 0..10, -15..-5));
+
+        let map: BrickMap<i64, ()> = bricks.into_iter().map(|b| (b, ())).collect();
+        let edges = map.adjacency_list(1);
+        let center = (10..20, 10..20, -5..5);
+        let corner = (0..10, 0..10, -15..-5);
+        assert_eq!(edges.incoming_edges(&center).count(), 26);
+        assert_eq!(edges.incoming_edges(&corner).count(), 7);
+        assert_eq!(map.adjacency_list(0).len(), 0);
+
+        let bricks = decompose_brick((0..20, 0..10, 0..7), 12);
+        assert_eq!(bricks.len(), 12);
+        assert_eq!(
+            bricks
+                .iter()
+                .map(|b| (b.0.end - b.0.start) * (b.1.end - b.1.start) * (b.2.end - b.2.start))
+                .sum::<i64>(),
+            1400
+        );
+    }
+
+    #[test]
+    fn halo_plan_matches_uniform_overlaps() {
+        let extension = Extension::uniform(1);
+        let plan = HaloPlan::new(&quilt().adjacency_list(extension), &extension);
+        let center = ((10..20, 10..20), 0);
+        assert_eq!(plan.len(), 2 * (12 + 8));
+        assert_eq!(plan.incoming(&center).len(), 8);
+        assert_eq!(plan.outgoing(&((0..1, 0..1), 0)).len(), 0);
+
+        for transfer in plan.incoming(&center) {
+            assert_eq!(transfer.extract, transfer.place);
+            assert!(transfer.extract.len() == 1 || transfer.extract.len() == 10);
+        }
+    }
+
+    #[test]
+    fn halo_plan_transfers_across_levels() {
+        let coarse_key = ((0..20, 0..20), 1);
+        let fine_key = ((20..30, 0..10), 0);
+        let mut edges = AdjacencyList::new();
+        edges.insert(coarse_key.clone(), fine_key.clone());
+        edges.insert(fine_key.clone(), coarse_key.clone());
+
+        let plan = HaloPlan::new(&edges, &Extension::uniform(2));
+        let to_fine = &plan.incoming(&fine_key)[0];
+        let to_coarse = &plan.incoming(&coarse_key)[0];
+        assert_eq!(to_fine.place, IndexSpace::new(18..20, 0..12));
+        assert_eq!(to_fine.extract, IndexSpace::new(9..10, 0..6));
+        assert_eq!(to_coarse.place, IndexSpace::new(10..12, 0..5));
+        assert_eq!(to_coarse.extract, IndexSpace::new(20..24, 0..10));
+
+        let coarse = Patch::from_scalar_function(1, (0..10, 0..10), |(i, j)| (i + j) as f64);
+        let mut fine = Patch::zeros(0, 1, (18..32, -2..12));
+        to_fine.place(&to_fine.extract(&coarse), &mut fine);
+        assert_eq!(fine.get_slice((18, 0)), &[9.0]);
+        assert_eq!(fine.get_slice((19, 11)), &[14.0]);
+        assert_eq!(fine.get_slice((20, 0)), &[0.0]);
+
+        let fine = Patch::from_scalar_function(0, (20..30, 0..10), |(i, _)| i as f64);
+        let mut coarse = Patch::zeros(1, 1, (-2..12, -2..12));
+        to_coarse.place(&to_coarse.extract(&fine), &mut coarse);
+        assert_eq!(coarse.get_slice((10, 0)), &[20.5]);
+        assert_eq!(coarse.get_slice((11, 4)), &[22.5]);
+    }
+
+    #[test]
+    fn extend_patch_applies_boundary_topology() {
+        let domain = IndexSpace::new(0..4, 0..4);
+        let valid = domain.clone();
+        let mut patch = Patch::from_slice_function(0, valid.extend_all(1), 2, |(i, j), p| {
+            p[0] = (10 * i + j) as f64;
+            p[1] = 1.0;
+        });
+        let topology = BoundaryTopology::new(domain)
+            .with_axis(Axis::I, AxisBoundary::Periodic)
+            .with_axis(Axis::J, AxisBoundary::Reflect(vec![1.0, -1.0]));
+
+        extend_patch_mut(
+            &mut patch,
+            &valid,
+            &topology,
+            |_, _, p| p[0] = -99.0,
+            &Vec::new(),
+        );
+        assert!(patch.guards_filled());
+        assert_eq!(patch.get_slice((-1, 2)), &[32.0, 1.0]);
+        assert_eq!(patch.get_slice((4, 1)), &[1.0, 1.0]);
+        assert_eq!(patch.get_slice((2, -1)), &[20.0, -1.0]);
+        assert_eq!(patch.get_slice((2, 4)), &[23.0, -1.0]);
+        assert_eq!(patch.get_slice((-1, -1)), &[30.0, -1.0]);
+
+        let topology = BoundaryTopology::new(IndexSpace::new(0..4, 0..4))
+            .with_axis(Axis::J, AxisBoundary::Outflow);
+        extend_patch_mut(
+            &mut patch,
+            &valid,
+            &topology,
+            |_, _, p| p[0] = -99.0,
+            &Vec::new(),
+        );
+        assert_eq!(patch.get_slice((1, 4)), &[13.0, 1.0]);
+        assert_eq!(patch.get_slice((-1, 1))[0], -99.0);
+    }
+
+    #[test]
+    fn extend_patch_applies_per_field_boundaries() {
+        let domain = IndexSpace::new(0..4, 0..4);
+        let valid = domain.clone();
+        let mut patch = Patch::from_slice_function(0, valid.extend_all(1), 3, |(i, j), p| {
+            p[0] = (10 * i + j) as f64;
+            p[1] = 1.0;
+            p[2] = 2.0;
+        });
+        let topology = BoundaryTopology::new(domain).with_axis(
+            Axis::J,
+            AxisBoundary::PerField(vec![
+                FieldBoundary::Outflow,
+                FieldBoundary::Reflect(-1.0),
+                FieldBoundary::Fixed(5.0),
+            ]),
+        );
+        let faces = std::cell::RefCell::new(Vec::new());
+
+        extend_patch_mut(
+            &mut patch,
+            &valid,
+            &topology,
+            |face, _, p| {
+                faces.borrow_mut().push(face);
+                p[0] = -99.0
+            },
+            &Vec::new(),
+        );
+        assert_eq!(patch.get_slice((2, -1)), &[20.0, -1.0, 5.0]);
+        assert_eq!(patch.get_slice((1, 4)), &[13.0, -1.0, 5.0]);
+        assert_eq!(patch.get_slice((-1, 2))[0], -99.0);
+        assert!(faces.borrow().contains(&Some(BoundaryFace {
+            axis: Axis::I,
+            side: Side::Lower,
+        })));
+        assert_eq!(
+            topology.face_of((2, 4)),
+            Some(BoundaryFace {
+                axis: Axis::J,
+                side: Side::Upper,
+            })
+        );
+        assert_eq!(topology.face_of((2, 2)), None);
+    }
+
+    #[test]
+    fn boundary_topology_faces_can_differ() {
+        let domain = IndexSpace::new(0..4, 0..4);
+        let valid = domain.clone();
+        let mut patch = Patch::from_slice_function(0, valid.extend_all(1), 2, |(i, j), p| {
+            p[0] = (10 * i + j) as f64;
+            p[1] = 1.0;
+        });
+        let lower_i = BoundaryFace {
+            axis: Axis::I,
+            side: Side::Lower,
+        };
+        let upper_i = BoundaryFace {
+            axis: Axis::I,
+            side: Side::Upper,
+        };
+        let topology = BoundaryTopology::new(domain)
+            .with_axis(Axis::J, AxisBoundary::Outflow)
+            .with_face(lower_i, AxisBoundary::Reflect(vec![1.0, -1.0]));
+        assert_eq!(
+            topology.i,
+            AxisBoundary::Sides(
+                Box::new(AxisBoundary::Reflect(vec![1.0, -1.0])),
+                Box::new(AxisBoundary::Custom)
+            )
+        );
+
+        extend_patch_mut(
+            &mut patch,
+            &valid,
+            &topology,
+            |_, _, p| p[0] = -99.0,
+            &Vec::new(),
+        );
+        assert_eq!(patch.get_slice((-1, 2)), &[2.0, -1.0]);
+        assert_eq!(patch.get_slice((4, 2))[0], -99.0);
+        assert_eq!(patch.get_slice((-1, 4)), &[3.0, -1.0]);
+        assert_eq!(
+            topology.source_index((-1, 4)),
+            Some(((0, 3), vec![1.0, -1.0]))
+        );
+
+        let topology = topology.with_face(upper_i, AxisBoundary::Reflect(vec![1.0, -1.0]));
+        assert_eq!(topology.i, AxisBoundary::Reflect(vec![1.0, -1.0]));
+    }
+
+    #[test]
+    fn corners_between_reflecting_axes_apply_both_factors() {
+        let domain = IndexSpace::new(0..4, 0..4);
+        let valid = domain.clone();
+        let mut patch = Patch::from_vector_function(0, domain.extend_all(1), |(i, j)| {
+            [(i * 10 + j) as f64, 1.0, 1.0]
+        });
+        let topology = BoundaryTopology::new(domain)
+            .with_axis(Axis::I, AxisBoundary::Reflect(vec![1.0, -1.0, 1.0]))
+            .with_axis(Axis::J, AxisBoundary::Reflect(vec![1.0, 1.0, -1.0]));
+
+        extend_patch_mut(&mut patch, &valid, &topology, |_, _, _| unreachable!(), &Vec::new());
+        assert_eq!(patch.get_slice((-1, -1)), &[0.0, -1.0, -1.0]);
+        assert_eq!(patch.get_slice((4, 2)), &[32.0, -1.0, 1.0]);
+        assert_eq!(topology.source_index((4, -1)), Some(((3, 0), vec![1.0, -1.0, -1.0])));
+    }
+
+    #[test]
+    fn hilbert_index_visits_neighbors_consecutively() {
+        let mut points: Vec<_> = (0..8).flat_map(|x| (0..8).map(move |y| (x, y))).collect();
+        points.sort_by_key(|&p| hilbert_index(3, p));
+
+        assert_eq!(points[0], (0, 0));
+        assert_eq!(points[63], (7, 0));
+        assert!(points.windows(2).all(|w| {
+            let dx = (w[0].0 as i64 - w[1].0 as i64).abs();
+            let dy = (w[0].1 as i64 - w[1].1 as i64).abs();
+            dx + dy == 1
+        }));
+    }
+
+    #[test]
+    fn hilbert_partition_assigns_compact_regions() {
+        let grid = range2d(0..8, 0..8);
+        let blocks = grid
+            .iter()
+            .map(|(i, j)| (i * 4..i * 4 + 4, j * 4..j * 4 + 4));
+        let work = hilbert_partition(blocks, 4);
+        assert_eq!(work.len(), 64);
+
+        for rank in 0..4 {
+            let owned: Vec<_> = work.iter().filter(|(_, &r)| r == rank).collect();
+            assert_eq!(owned.len(), 16);
+        }
+        for (i, j) in range2d(0..2, 0..2).iter() {
+            let quadrant = (i * 16..i * 16 + 16, j * 16..j * 16 + 16);
+            let mut ranks: Vec<_> = work.query_rect(quadrant).map(|(_, &r)| r).collect();
+            ranks.dedup();
+            assert_eq!(ranks.len(), 1);
+        }
+    }
+
+    #[test]
+    fn morton_codes_round_trip() {
+        assert_eq!(morton_encode((0, 0)), 0);
+        assert_eq!(morton_encode((0, 1)), 1);
+        assert_eq!(morton_encode((1, 0)), 2);
+        assert_eq!(morton_encode((1, 1)), 3);
+        assert_eq!(morton_encode((2, 0)), 8);
+        assert_eq!(morton_encode_3d((1, 0, 0)), 4);
+
+        for &(i, j, k) in &[
+            (0, 0, 0),
+            (5, 17, 123),
+            (0x1f_ffff, 0, 77),
+            (1000, 999, 0x1f_ffff),
+        ] {
+            assert_eq!(morton_decode(morton_encode((i, j))), (i, j));
+            assert_eq!(morton_decode_3d(morton_encode_3d((i, j, k))), (i, j, k));
+        }
+        assert_eq!(morton_decode(morton_encode((u32::MAX, 7))), (u32::MAX, 7));
+    }
+
+    #[test]
+    fn morton_order_sorts_blocks_in_z_order() {
+        let map: RectangleMap<i64, (i64, i64)> = range2d(0..4, 0..4)
+            .iter()
+            .map(|(i, j)| ((i * 10..i * 10 + 10, j * 10..j * 10 + 10), (i, j)))
+            .collect();
+        let order: Vec<_> = morton_order(&map).into_iter().map(|(_, &v)| v).collect();
+        assert_eq!(&order[..5], &[(0, 0), (0, 1), (1, 0), (1, 1), (0, 2)]);
+        assert_eq!(order[15], (3, 3));
+    }
+
+    #[test]
+    fn weighted_hilbert_partition_balances_cost() {
+        let grid = range2d(0..4, 0..4);
+        let blocks = grid.iter().map(|(i, j)| {
+            let cost = if (i, j) == (0, 0) { 15.0 } else { 1.0 };
+            ((i * 4..i * 4 + 4, j * 4..j * 4 + 4), cost)
+        });
+        let work = hilbert_partition_weighted(blocks, 2);
+        let owned = |rank| work.iter().filter(|(_, &r)| r == rank).count();
+
+        assert_eq!(work.get((&(0..4), &(0..4))), Some(&0));
+        assert_eq!(owned(0), 1);
+        assert_eq!(owned(1), 15);
+    }
+
+    #[test]
+    fn berger_rigoutsos_separates_distant_clusters() {
+        let a = IndexSpace::new(0..4, 0..4);
+        let b = IndexSpace::new(10..12, 20..26);
+        let flags: Vec<_> = a.iter().chain(b.iter()).collect();
+        let mut boxes = berger_rigoutsos(flags, 0.8);
+        boxes.sort_by_key(|s| s.start());
+        assert_eq!(boxes, vec![a, b]);
+    }
+
+    #[test]
+    fn berger_rigoutsos_boxes_are_efficient_and_cover_flags() {
+        let flags: Vec<_> = IndexSpace::new(0..32, 0..32)
+            .iter()
+            .filter(|&(i, j)| ((i - 16) * (i - 16) + (j - 16) * (j - 16) - 100).abs() < 20)
+            .collect();
+        let boxes = berger_rigoutsos(flags.clone(), 0.7);
+
+        for (n, a) in boxes.iter().enumerate() {
+            let count = flags.iter().filter(|&&f| a.contains(f)).count();
+            assert!(count as f64 / a.len() as f64 >= 0.7 || a.len() == 1);
+
+            for b in &boxes[n + 1..] {
+                assert!(a.intersect(b).is_none_or(|o| o.is_empty()));
+            }
+        }
+        assert!(flags.iter().all(|&f| boxes.iter().any(|b| b.contains(f))));
+    }
+
+    #[test]
+    fn extend_patch_respects_masks() {
+        let mut neighbor = Patch::from_scalar_function(0, (4..8, 0..4), |(i, _)| i as f64);
+        neighbor.set_mask(|(i, j)| i == 4 && j == 1);
+        let neighbors = vec![neighbor];
+        let topology = BoundaryTopology::new(IndexSpace::new(0..8, 0..4));
+        let valid = IndexSpace::new(0..4, 0..4);
+        let mut patch = Patch::zeros(0, 1, (0..5, 0..4));
+        patch.set_mask(|(i, j)| i == 4 && j == 3);
+
+        extend_patch_mut(
+            &mut patch,
+            &valid,
+            &topology,
+            |_, _, p| p[0] = -1.0,
+            &neighbors,
+        );
+        assert_eq!(patch.get_slice((4, 0)), &[4.0]);
+        assert_eq!(patch.get_slice((4, 1)), &[-1.0]);
+        assert_eq!(patch.get_slice((4, 3)), &[0.0]);
+    }
+
+    #[test]
+    fn adjacency_list_of_patch_vec_matches_rect_map() {
+        let patches: Vec<Patch> = quilt().into_iter().map(|(_, p)| p).collect();
+        let a = patches.adjacency_list(Extension::uniform(1));
+        let b = quilt().adjacency_list(Extension::uniform(1));
+        assert_eq!(a.len(), b.len());
+        assert!(b.iter().all(|(x, y)| a.outgoing_edges(x).any(|z| z == y)));
+    }
+
+    #[test]
+    fn adjacency_list_with_one_sided_extension_works() {
+        let extension = Extension {
+            lower: (1, 0),
+            upper: (0, 0),
+        };
+        let edges = quilt().adjacency_list(extension);
+        let center = ((10..20, 10..20), 0);
+        assert_eq!(edges.len(), 6);
+        assert_eq!(
+            edges.incoming_edges(&center).collect::<Vec<_>>(),
+            [&((0..10, 10..20), 0)]
+        );
+    }
+}
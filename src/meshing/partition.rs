@@ -0,0 +1,618 @@
+//! Multilevel partitioning of irregular graphs of patches, and incremental
+//! rebalancing of an existing partition.
+
+use crate::adjacency_list::AdjacencyList;
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+use std::hash::Hash;
+
+/// Partitions the vertices of an irregular graph (for example the patches of
+/// an AMR hierarchy after regridding) into `num_parts` parts, minimizing the
+/// number of edges between parts subject to a balance constraint on the
+/// vertex weights. Returns the part of each vertex, in the order of
+/// `vertices`.
+///
+/// The graph is coarsened by repeated heavy-edge matching, the coarsest graph
+/// is split by recursive bisection (growing each half breadth-first from
+/// the starting vertex which yields the smallest cut), and the
+/// partition is projected back through the levels with a refinement at each
+/// one in the manner of the Kernighan-Lin and Fiduccia-Mattheyses
+/// heuristics. Edge directions are ignored, and edges
+/// touching vertices not in `vertices` are skipped. The total weight of each
+/// part is kept below `imbalance` times the average (but never below the
+/// heaviest vertex) when possible; a typical value is `1.05`. The result is
+/// deterministic given the order of `vertices` and of the edges. There must
+/// be at least as many vertices as parts.
+pub fn partition_graph<K>(
+    vertices: &[(K, f64)],
+    edges: &AdjacencyList<K>,
+    num_parts: usize,
+    imbalance: f64,
+) -> Vec<usize>
+where
+    K: Hash + Eq + Clone,
+{
+    assert!(num_parts > 0 && vertices.len() >= num_parts);
+
+    let graph = WeightedGraph::new(vertices, edges);
+    let total: f64 = graph.weights.iter().sum();
+    let heaviest = graph.weights.iter().cloned().fold(0.0, f64::max);
+    let max_weight = (imbalance * total / num_parts as f64).max(heaviest);
+
+    let mut levels = vec![graph];
+    let mut maps = Vec::new();
+
+    while levels.last().unwrap().len() > 8 * num_parts {
+        let (coarse, map) = levels.last().unwrap().coarsen(0.5 * max_weight);
+
+        if coarse.len() as f64 > 0.95 * levels.last().unwrap().len() as f64 {
+            break;
+        }
+        levels.push(coarse);
+        maps.push(map);
+    }
+    let mut parts = levels
+        .last()
+        .unwrap()
+        .initial_partition(num_parts, max_weight);
+
+    while let Some(graph) = levels.pop() {
+        graph.refine(&mut parts, num_parts, max_weight);
+
+        if let Some(map) = maps.pop() {
+            parts = map.iter().map(|&c| parts[c]).collect();
+        }
+    }
+    parts
+}
+
+/// Returns the number of edges in the graph whose endpoints are assigned to
+/// different parts by [`partition_graph`]. Edges touching vertices not in
+/// `vertices` are not counted.
+pub fn edge_cut<K>(vertices: &[(K, f64)], edges: &AdjacencyList<K>, parts: &[usize]) -> usize
+where
+    K: Hash + Eq + Clone,
+{
+    let part: HashMap<&K, usize> = vertices
+        .iter()
+        .map(|(k, _)| k)
+        .zip(parts.iter().cloned())
+        .collect();
+    edges
+        .iter()
+        .filter(|(a, b)| match (part.get(a), part.get(b)) {
+            (Some(p), Some(q)) => p != q,
+            _ => false,
+        })
+        .count()
+}
+
+/// Adjusts an existing partition (for example from [`partition_graph`] or
+/// [`hilbert_partition`](super::hilbert_partition), before the vertex
+/// weights changed) to restore balance, while migrating at most
+/// `max_migrations` vertices. Vertices flow diffusively from the most loaded
+/// part to a lighter part they are connected to, choosing at each step the
+/// move which least increases the edge cut, and which leaves both parts
+/// lighter than the source was. A vertex is moved to a lighter part it is not
+/// connected to only if no connected move exists. Moving stops once every
+/// part weighs at most `imbalance` times the average, or the migration budget
+/// is spent. Returns the new part of each vertex, in the order of `vertices`;
+/// `parts` gives the current ones.
+pub fn rebalance_incremental<K>(
+    vertices: &[(K, f64)],
+    edges: &AdjacencyList<K>,
+    parts: &[usize],
+    num_parts: usize,
+    imbalance: f64,
+    max_migrations: usize,
+) -> Vec<usize>
+where
+    K: Hash + Eq + Clone,
+{
+    assert_eq!(vertices.len(), parts.len());
+
+    let graph = WeightedGraph::new(vertices, edges);
+    let mut parts = parts.to_vec();
+    let mut load = vec![0.0; num_parts];
+    let mut moved = vec![false; parts.len()];
+
+    for (v, &p) in parts.iter().enumerate() {
+        load[p] += graph.weights[v];
+    }
+    let max_weight = imbalance * load.iter().sum::<f64>() / num_parts as f64;
+
+    for _ in 0..max_migrations {
+        let p = (0..num_parts)
+            .max_by(|&a, &b| load[a].partial_cmp(&load[b]).unwrap())
+            .unwrap();
+
+        if load[p] <= max_weight {
+            break;
+        }
+        let candidates = (0..parts.len()).filter(|&v| parts[v] == p && !moved[v]);
+        let mut best: Option<(bool, f64, usize, usize)> = None;
+
+        for v in candidates {
+            let w = graph.weights[v];
+            let mut connection: BTreeMap<usize, f64> = BTreeMap::new();
+
+            for &(u, e) in &graph.neighbors[v] {
+                *connection.entry(parts[u]).or_insert(0.0) += e;
+            }
+            let internal = connection.get(&p).cloned().unwrap_or(0.0);
+
+            for q in (0..num_parts).filter(|&q| q != p && load[q] + w < load[p]) {
+                let external = connection.get(&q).cloned();
+                let candidate = (external.is_some(), external.unwrap_or(0.0) - internal, v, q);
+
+                let better = match best {
+                    None => true,
+                    Some(b) => (candidate.0, candidate.1, -load[q]) > (b.0, b.1, -load[b.3]),
+                };
+                if better {
+                    best = Some(candidate)
+                }
+            }
+        }
+        match best {
+            Some((_, _, v, q)) => {
+                load[p] -= graph.weights[v];
+                load[q] += graph.weights[v];
+                parts[v] = q;
+                moved[v] = true;
+            }
+            None => break,
+        }
+    }
+    parts
+}
+
+/// An undirected graph with weighted vertices and edges, used by
+/// `partition_graph` and `rebalance_incremental`. Neighbor lists are sorted
+/// by vertex index.
+struct WeightedGraph {
+    weights: Vec<f64>,
+    neighbors: Vec<Vec<(usize, f64)>>,
+}
+
+impl WeightedGraph {
+    /// Builds the graph from a list of weighted vertices and an adjacency
+    /// list. Each directed edge contributes a weight of one, and edges
+    /// touching unknown vertices are skipped.
+    fn new<K: Hash + Eq + Clone>(vertices: &[(K, f64)], edges: &AdjacencyList<K>) -> Self {
+        let index: HashMap<&K, usize> = vertices
+            .iter()
+            .enumerate()
+            .map(|(n, (k, _))| (k, n))
+            .collect();
+        let mut neighbors = vec![BTreeMap::new(); vertices.len()];
+
+        for (a, b) in edges.iter() {
+            if let (Some(&a), Some(&b)) = (index.get(a), index.get(b)) {
+                if a != b {
+                    *neighbors[a].entry(b).or_insert(0.0) += 1.0;
+                    *neighbors[b].entry(a).or_insert(0.0) += 1.0;
+                }
+            }
+        }
+        Self {
+            weights: vertices.iter().map(|(_, w)| *w).collect(),
+            neighbors: neighbors
+                .into_iter()
+                .map(|n| n.into_iter().collect())
+                .collect(),
+        }
+    }
+
+    fn len(&self) -> usize {
+        self.weights.len()
+    }
+
+    /// Contracts a heavy-edge matching, where matched pairs may not exceed
+    /// the given weight. Returns the coarse graph, and the coarse vertex of
+    /// each vertex.
+    fn coarsen(&self, max_pair_weight: f64) -> (Self, Vec<usize>) {
+        let mut map = vec![usize::MAX; self.len()];
+        let mut count = 0;
+
+        let mut order: Vec<_> = (0..self.len()).collect();
+        order.sort_by_key(|&v| self.neighbors[v].len());
+
+        for v in order {
+            if map[v] != usize::MAX {
+                continue;
+            }
+            let partner = self.neighbors[v]
+                .iter()
+                .filter(|&&(u, _)| {
+                    map[u] == usize::MAX && self.weights[u] + self.weights[v] <= max_pair_weight
+                })
+                .min_by(|&&(u, w), &&(x, y)| {
+                    let lighter = |a: usize, b: usize| {
+                        (self.weights[a], self.neighbors[a].len())
+                            .partial_cmp(&(self.weights[b], self.neighbors[b].len()))
+                            .unwrap()
+                    };
+                    y.partial_cmp(&w).unwrap().then(lighter(u, x))
+                });
+            map[v] = count;
+
+            if let Some(&(u, _)) = partner {
+                map[u] = count;
+            }
+            count += 1;
+        }
+
+        let mut weights = vec![0.0; count];
+        let mut neighbors = vec![BTreeMap::new(); count];
+
+        for v in 0..self.len() {
+            weights[map[v]] += self.weights[v];
+
+            for &(u, w) in &self.neighbors[v] {
+                if map[u] != map[v] {
+                    *neighbors[map[v]].entry(map[u]).or_insert(0.0) += w;
+                }
+            }
+        }
+        let coarse = Self {
+            weights,
+            neighbors: neighbors
+                .into_iter()
+                .map(|n| n.into_iter().collect())
+                .collect(),
+        };
+        (coarse, map)
+    }
+
+    /// Partitions the graph by recursive bisection. Each bisection grows a
+    /// region breadth-first from every possible starting vertex until it
+    /// holds its share of the weight, and keeps the region with the smallest
+    /// cut.
+    fn initial_partition(&self, num_parts: usize, max_weight: f64) -> Vec<usize> {
+        let mut parts = vec![0; self.len()];
+        let all: Vec<_> = (0..self.len()).collect();
+        self.bisect(&all, 0, num_parts, &mut parts);
+        self.refine(&mut parts, num_parts, max_weight);
+        parts
+    }
+
+    fn bisect(&self, subset: &[usize], first_part: usize, num_parts: usize, parts: &mut [usize]) {
+        if num_parts == 1 {
+            subset.iter().for_each(|&v| parts[v] = first_part);
+            return;
+        }
+        let lower_parts = num_parts / 2;
+        let total: f64 = subset.iter().map(|&v| self.weights[v]).sum();
+        let target = total * lower_parts as f64 / num_parts as f64;
+        let mut inside = vec![false; self.len()];
+        subset.iter().for_each(|&v| inside[v] = true);
+
+        let (lower, upper) = subset
+            .iter()
+            .map(|&start| self.grow_region(start, subset, &inside, target, lower_parts, num_parts))
+            .fold(None, |best: Option<(f64, Vec<usize>)>, region| {
+                let cut = self.region_cut(&region, &inside);
+                match best {
+                    Some((c, _)) if c <= cut => best,
+                    _ => Some((cut, region)),
+                }
+            })
+            .map(|(_, region)| {
+                let mut in_region = vec![false; self.len()];
+                region.iter().for_each(|&v| in_region[v] = true);
+                subset.iter().partition::<Vec<usize>, _>(|&&v| in_region[v])
+            })
+            .unwrap();
+
+        self.bisect(&lower, first_part, lower_parts, parts);
+        self.bisect(
+            &upper,
+            first_part + lower_parts,
+            num_parts - lower_parts,
+            parts,
+        );
+    }
+
+    /// Grows a region breadth-first from `start` within the subset (jumping
+    /// to the next unvisited vertex of the subset if a component is
+    /// exhausted) until its weight is nearest the target, leaving enough
+    /// vertices for the parts on each side.
+    fn grow_region(
+        &self,
+        start: usize,
+        subset: &[usize],
+        inside: &[bool],
+        target: f64,
+        lower_parts: usize,
+        num_parts: usize,
+    ) -> Vec<usize> {
+        let mut seen = vec![false; self.len()];
+        let mut region = Vec::new();
+        let mut weight = 0.0;
+
+        for first in std::iter::once(start).chain(subset.iter().cloned()) {
+            if seen[first] {
+                continue;
+            }
+            seen[first] = true;
+            let mut queue = std::collections::VecDeque::from(vec![first]);
+
+            while let Some(v) = queue.pop_front() {
+                let w = self.weights[v];
+                let enough = region.len() >= lower_parts
+                    && (weight + w - target).abs() > (weight - target).abs();
+
+                if enough || subset.len() - region.len() <= num_parts - lower_parts {
+                    return region;
+                }
+                region.push(v);
+                weight += w;
+
+                for &(u, _) in &self.neighbors[v] {
+                    if inside[u] && !seen[u] {
+                        seen[u] = true;
+                        queue.push_back(u)
+                    }
+                }
+            }
+        }
+        region
+    }
+
+    fn region_cut(&self, region: &[usize], inside: &[bool]) -> f64 {
+        let mut in_region = vec![false; self.len()];
+        region.iter().for_each(|&v| in_region[v] = true);
+        region
+            .iter()
+            .flat_map(|&v| self.neighbors[v].iter())
+            .filter(|&&(u, _)| inside[u] && !in_region[u])
+            .map(|&(_, w)| w)
+            .sum()
+    }
+
+    /// Refines a partition with passes of single-vertex moves, in the manner
+    /// of Fiduccia and Mattheyses: each pass repeatedly makes the best
+    /// allowed move of an unmoved boundary vertex (even if it increases the
+    /// cut), and then rolls back to the point in the sequence with the
+    /// smallest cut. A move may not overload its target part or empty its
+    /// source part. Candidate moves are kept in a priority queue; when a
+    /// vertex moves, only the moves of its neighbors are re-evaluated, and
+    /// moves which are not allowed wait until their target part loses a
+    /// vertex or their source part gains one.
+    fn refine(&self, parts: &mut [usize], num_parts: usize, max_weight: f64) {
+        let mut load = vec![0.0; num_parts];
+        let mut size = vec![0; num_parts];
+        let mut connection = vec![0.0; num_parts];
+
+        for (v, &p) in parts.iter().enumerate() {
+            load[p] += self.weights[v];
+            size[p] += 1;
+        }
+
+        for _ in 0..8 {
+            let mut locked = vec![false; self.len()];
+            let mut stamp = vec![0; self.len()];
+            let mut queue = BinaryHeap::new();
+            let mut full: Vec<Vec<Move>> = vec![Vec::new(); num_parts];
+            let mut last: Vec<Vec<Move>> = vec![Vec::new(); num_parts];
+            let mut moves = Vec::new();
+            let mut best = (0.0, self.overload(&load, max_weight), 0);
+            let mut gain = 0.0;
+
+            for v in 0..self.len() {
+                self.push_moves(v, 0, parts, &mut connection, &mut queue);
+            }
+
+            while moves.len() < best.2 + 64 {
+                let candidate = match queue.pop() {
+                    Some(m) => m,
+                    None => break,
+                };
+                let Move { gain: g, vertex: v, part: q, stamp: s } = candidate;
+                let p = parts[v];
+
+                if locked[v] || s != stamp[v] {
+                    continue;
+                }
+                if load[q] + self.weights[v] > max_weight {
+                    full[q].push(candidate);
+                    continue;
+                }
+                if size[p] == 1 {
+                    last[p].push(candidate);
+                    continue;
+                }
+                parts[v] = q;
+                load[p] -= self.weights[v];
+                load[q] += self.weights[v];
+                size[p] -= 1;
+                size[q] += 1;
+                locked[v] = true;
+                moves.push((v, p));
+                gain += g;
+
+                queue.extend(full[p].drain(..));
+                queue.extend(last[q].drain(..));
+
+                for &(u, _) in &self.neighbors[v] {
+                    if !locked[u] {
+                        stamp[u] += 1;
+                        self.push_moves(u, stamp[u], parts, &mut connection, &mut queue);
+                    }
+                }
+                let overload = self.overload(&load, max_weight);
+
+                if overload < best.1 || (overload == best.1 && gain > best.0) {
+                    best = (gain, overload, moves.len());
+                }
+            }
+            for &(v, p) in moves[best.2..].iter().rev() {
+                let q = parts[v];
+                parts[v] = p;
+                load[q] -= self.weights[v];
+                load[p] += self.weights[v];
+                size[q] -= 1;
+                size[p] += 1;
+            }
+            if best.2 == 0 {
+                break;
+            }
+        }
+    }
+
+    /// Queues a move of vertex `v` to each neighboring part, with the
+    /// reduction of the cut it would make. The connection buffer must hold
+    /// zero for every part, and is left that way.
+    fn push_moves(
+        &self,
+        v: usize,
+        stamp: usize,
+        parts: &[usize],
+        connection: &mut [f64],
+        queue: &mut BinaryHeap<Move>,
+    ) {
+        let p = parts[v];
+
+        for &(u, e) in &self.neighbors[v] {
+            connection[parts[u]] += e;
+        }
+        let internal = std::mem::replace(&mut connection[p], 0.0);
+
+        for &(u, _) in &self.neighbors[v] {
+            let q = parts[u];
+            let external = std::mem::replace(&mut connection[q], 0.0);
+
+            if q != p && external > 0.0 {
+                queue.push(Move {
+                    gain: external - internal,
+                    vertex: v,
+                    part: q,
+                    stamp,
+                });
+            }
+        }
+    }
+
+    fn overload(&self, load: &[f64], max_weight: f64) -> f64 {
+        load.iter().map(|l| (l - max_weight).max(0.0)).sum()
+    }
+}
+
+/// A candidate move of a vertex to another part in
+/// `WeightedGraph::refine`. Moves are ordered by gain, and then by lowest
+/// vertex and lowest part, so the queue yields the same sequence of moves as
+/// a scan of every vertex would. A move is stale once the stamp of its
+/// vertex has changed.
+#[derive(Clone, Copy)]
+struct Move {
+    gain: f64,
+    vertex: usize,
+    part: usize,
+    stamp: usize,
+}
+
+impl PartialEq for Move {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for Move {}
+
+impl PartialOrd for Move {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Move {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.gain
+            .total_cmp(&other.gain)
+            .then(other.vertex.cmp(&self.vertex))
+            .then(other.part.cmp(&self.part))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index_space::{range2d, Extension, IndexSpace};
+    use crate::meshing::GraphTopology;
+    use crate::patch::Patch;
+    use crate::rect_map::RectangleMap;
+
+    #[test]
+    fn graph_partition_balances_and_cuts_few_edges() {
+        let blocks: RectangleMap<i64, Patch> = range2d(0..16, 0..16)
+            .iter()
+            .map(|(i, j)| {
+                let rect = (i * 4..i * 4 + 4, j * 4..j * 4 + 4);
+                (rect.clone(), Patch::zeros(0, 1, rect))
+            })
+            .collect();
+        let edges = blocks.adjacency_list(Extension::uniform(1));
+        let vertices: Vec<_> = blocks
+            .keys()
+            .map(|rect| ((IndexSpace::from(rect).to_rect(), 0), 1.0))
+            .collect();
+
+        let parts = partition_graph(&vertices, &edges, 4, 1.05);
+        assert!((0..4).all(|p| parts.iter().filter(|&&q| q == p).count() <= 67));
+
+        // Four quadrants cut 90 neighbor pairs (each is two directed edges).
+        assert_eq!(edge_cut(&vertices, &edges, &parts), 2 * 90);
+    }
+
+    #[test]
+    fn graph_partition_separates_clusters() {
+        let mut edges = AdjacencyList::new();
+
+        for cluster in &[0, 10] {
+            for a in 0..5 {
+                for b in 0..5 {
+                    if a != b {
+                        edges.insert(cluster + a, cluster + b)
+                    }
+                }
+            }
+        }
+        edges.insert(4, 10);
+        let vertices: Vec<_> = (0..5).chain(10..15).map(|k| (k, 1.0)).collect();
+        let parts = partition_graph(&vertices, &edges, 2, 1.0);
+        assert_eq!(edge_cut(&vertices, &edges, &parts), 1);
+        assert!(parts[..5].iter().all(|&p| p == parts[0]));
+        assert_ne!(parts[0], parts[5]);
+    }
+
+    #[test]
+    fn incremental_rebalance_bounds_migrations() {
+        let mut edges = AdjacencyList::new();
+
+        for k in 0..11 {
+            edges.insert(k, k + 1);
+            edges.insert(k + 1, k);
+        }
+        let parts: Vec<_> = (0..12).map(|k| k / 4).collect();
+        let vertices: Vec<_> = (0..12)
+            .map(|k| (k, if k < 4 { 2.0 } else { 1.0 }))
+            .collect();
+
+        let limited = rebalance_incremental(&vertices, &edges, &parts, 3, 1.0, 1);
+        let migrated = |new: &[usize]| parts.iter().zip(new).filter(|(a, b)| a != b).count();
+        assert_eq!(migrated(&limited), 1);
+        assert_eq!(limited[3], 1);
+
+        let balanced = rebalance_incremental(&vertices, &edges, &parts, 3, 1.15, 12);
+        let load = |p| {
+            (0..12)
+                .filter(|&k| balanced[k] == p)
+                .map(|k| vertices[k].1)
+                .sum::<f64>()
+        };
+        assert!((0..3).all(|p| load(p) <= 1.15 * 16.0 / 3.0));
+        assert!(migrated(&balanced) <= 3);
+        assert_eq!(edge_cut(&vertices, &edges, &balanced), 4);
+    }
+}
@@ -51,7 +51,7 @@ pub trait Communicator {
             let one = 1 << level;
             let two = 1 << (level + 1);
 
-            if r % two == 0 && r + one <= p {
+            if r % two == 0 && r + one < p {
                 self.send(r + one, value.clone())
             }
         }
@@ -67,12 +67,14 @@ pub trait Communicator {
         let r = self.rank();
         let p = self.size();
 
-        for level in (0..util::ceil_log2(p)).rev() {
+        for level in 0..util::ceil_log2(p) {
             let one = 1 << level;
             let two = 1 << (level + 1);
 
             if r % two == 0 {
-                value = f(value, self.recv())
+                if r + one < p {
+                    value = f(value, self.recv())
+                }
             } else {
                 self.send(r - one, value);
                 return None;
@@ -89,4 +91,88 @@ pub trait Communicator {
     {
         self.broadcast(self.reduce(f, value))
     }
+
+    /// Returns the minimum of a floating point value over all ranks, for
+    /// example the largest stable time step of a distributed simulation.
+    /// The communicator time stamp is not advanced.
+    fn all_reduce_min_f64(&self, value: f64) -> f64 {
+        util::decode_f64(self.all_reduce(
+            |a, b| util::encode_f64(util::decode_f64(a).min(util::decode_f64(b))),
+            util::encode_f64(value),
+        ))
+    }
+
+    /// Returns the maximum of a floating point value over all ranks. The
+    /// communicator time stamp is not advanced.
+    fn all_reduce_max_f64(&self, value: f64) -> f64 {
+        util::decode_f64(self.all_reduce(
+            |a, b| util::encode_f64(util::decode_f64(a).max(util::decode_f64(b))),
+            util::encode_f64(value),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Communicator;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+    use std::thread;
+
+    struct ChannelCommunicator {
+        rank: usize,
+        peers: Vec<Sender<Vec<u8>>>,
+        receiver: Receiver<Vec<u8>>,
+    }
+
+    impl Communicator for ChannelCommunicator {
+        fn rank(&self) -> usize {
+            self.rank
+        }
+
+        fn size(&self) -> usize {
+            self.peers.len()
+        }
+
+        fn send(&self, rank: usize, message: Vec<u8>) {
+            self.peers[rank].send(message).unwrap()
+        }
+
+        fn recv(&self) -> Vec<u8> {
+            self.receiver.recv().unwrap()
+        }
+
+        fn next_time_stamp(&mut self) {}
+    }
+
+    fn communicators(size: usize) -> Vec<ChannelCommunicator> {
+        let (peers, receivers): (Vec<_>, Vec<_>) = (0..size).map(|_| channel()).unzip();
+        receivers
+            .into_iter()
+            .enumerate()
+            .map(|(rank, receiver)| ChannelCommunicator {
+                rank,
+                peers: peers.clone(),
+                receiver,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn all_reduce_works_for_any_number_of_ranks() {
+        for size in 1..9 {
+            let threads: Vec<_> = communicators(size)
+                .into_iter()
+                .map(|comm| {
+                    thread::spawn(move || {
+                        let x = comm.rank() as f64;
+                        (comm.all_reduce_min_f64(x), comm.all_reduce_max_f64(x))
+                    })
+                })
+                .collect();
+
+            for thread in threads {
+                assert_eq!(thread.join().unwrap(), (0.0, (size - 1) as f64));
+            }
+        }
+    }
 }
@@ -74,6 +74,18 @@ impl comm::Communicator for MpiCommunicator {
     fn next_time_stamp(&mut self) {
         self.time_stamp += 1;
     }
+
+    fn all_reduce_min_f64(&self, value: f64) -> f64 {
+        unsafe {
+            mpi::all_reduce_min_f64(value)
+        }
+    }
+
+    fn all_reduce_max_f64(&self, value: f64) -> f64 {
+        unsafe {
+            mpi::all_reduce_max_f64(value)
+        }
+    }
 }
 
 impl Drop for MpiCommunicator {
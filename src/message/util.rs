@@ -11,6 +11,18 @@ pub fn ceil_log2(x: usize) -> usize {
     n
 }
 
+/// Encode an `f64` as a message buffer.
+pub fn encode_f64(value: f64) -> Vec<u8> {
+    value.to_le_bytes().to_vec()
+}
+
+/// Decode an `f64` from a message buffer created with [`encode_f64`].
+pub fn decode_f64(buffer: Vec<u8>) -> f64 {
+    let mut bytes = [0; 8];
+    bytes.copy_from_slice(&buffer);
+    f64::from_le_bytes(bytes)
+}
+
 /// Read a `usize` out of the given stream.
 pub fn read_usize<R: Read>(stream: &mut R) -> usize {
     usize::from_le_bytes(read_bytes_array(stream))
@@ -11,6 +11,7 @@
 //!   threads)
 //! - interaction with PBS or other job scheduler at HPC sites (discovering
 //!   the process group)
+//! - native all-reduce of scalars, such as the global time step
 //!
 //! [1]: http://rsmpi.github.io/rsmpi/mpi/index.html
 
@@ -40,4 +41,8 @@ extern "C" {
     pub fn recv(buf: *mut u8, count: i32, source: i32, tag: i32);
     #[link_name = "gridiron_mpi_probe_tag"]
     pub fn probe_tag(tag: i32) -> Status;
+    #[link_name = "gridiron_mpi_all_reduce_min_f64"]
+    pub fn all_reduce_min_f64(value: f64) -> f64;
+    #[link_name = "gridiron_mpi_all_reduce_max_f64"]
+    pub fn all_reduce_max_f64(value: f64) -> f64;
 }
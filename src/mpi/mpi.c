@@ -50,3 +50,15 @@ struct Status gridiron_mpi_probe_tag(int tag) {
     result.tag = tag;
     return result;
 }
+
+double gridiron_mpi_all_reduce_min_f64(double value) {
+    double result;
+    MPI_Allreduce(&value, &result, 1, MPI_DOUBLE, MPI_MIN, MPI_COMM_WORLD);
+    return result;
+}
+
+double gridiron_mpi_all_reduce_max_f64(double value) {
+    double result;
+    MPI_Allreduce(&value, &result, 1, MPI_DOUBLE, MPI_MAX, MPI_COMM_WORLD);
+    return result;
+}
@@ -2,11 +2,99 @@ use core::ops;
 
 /// A statically-sized numeric vector over a generic scalar data type T, which
 /// supports arithmetic operations also supported by T.
-#[derive(Clone, Copy)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Vector<T, const DIM: usize> {
     data: [T; DIM],
 }
 
+impl<T, const DIM: usize> Vector<T, DIM> {
+    /// Creates a vector from an array of components.
+    pub fn new(data: [T; DIM]) -> Self {
+        Self { data }
+    }
+
+    /// Returns the components of this vector as an array.
+    pub fn into_array(self) -> [T; DIM] {
+        self.data
+    }
+
+    /// Returns the components of this vector as a slice.
+    pub fn as_slice(&self) -> &[T] {
+        &self.data
+    }
+
+    /// Returns an iterator over the components of this vector.
+    pub fn iter(&self) -> core::slice::Iter<'_, T> {
+        self.data.iter()
+    }
+}
+
+impl<T, const DIM: usize> Vector<T, DIM>
+where
+    T: Copy + Default + ops::Add<Output = T> + ops::Mul<Output = T>,
+{
+    /// Returns the inner product of this vector with another one.
+    pub fn dot(&self, other: &Self) -> T {
+        self.iter()
+            .zip(other.iter())
+            .fold(T::default(), |sum, (&a, &b)| sum + a * b)
+    }
+}
+
+impl<const DIM: usize> Vector<f64, DIM> {
+    /// Returns the Euclidean length of this vector.
+    pub fn norm(&self) -> f64 {
+        self.dot(self).sqrt()
+    }
+}
+
+impl<T> Vector<T, 3>
+where
+    T: Copy + ops::Mul<Output = T> + ops::Sub<Output = T>,
+{
+    /// Returns the cross product of this vector with another one.
+    pub fn cross(&self, other: &Self) -> Self {
+        let (a, b) = (self, other);
+        Self::new([
+            a[1] * b[2] - a[2] * b[1],
+            a[2] * b[0] - a[0] * b[2],
+            a[0] * b[1] - a[1] * b[0],
+        ])
+    }
+}
+
+impl<T: Copy + Default, const DIM: usize> Default for Vector<T, DIM> {
+    fn default() -> Self {
+        Self {
+            data: [T::default(); DIM],
+        }
+    }
+}
+
+impl<T, const DIM: usize> From<[T; DIM]> for Vector<T, DIM> {
+    fn from(data: [T; DIM]) -> Self {
+        Self { data }
+    }
+}
+
+impl<T, const DIM: usize> IntoIterator for Vector<T, DIM> {
+    type Item = T;
+    type IntoIter = core::array::IntoIter<T, DIM>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIterator::into_iter(self.data)
+    }
+}
+
+impl<'a, T, const DIM: usize> IntoIterator for &'a Vector<T, DIM> {
+    type Item = &'a T;
+    type IntoIter = core::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.data.iter()
+    }
+}
+
 impl<T, U, V, const DIM: usize> ops::Add<Vector<U, DIM>> for Vector<T, DIM>
 where
     T: Copy + ops::Add<U, Output = V>,
@@ -43,6 +131,47 @@ where
     }
 }
 
+impl<T, U, const DIM: usize> ops::AddAssign<Vector<U, DIM>> for Vector<T, DIM>
+where
+    T: ops::AddAssign<U>,
+    U: Copy,
+{
+    fn add_assign(&mut self, other: Vector<U, DIM>) {
+        for (x, y) in self.data.iter_mut().zip(other.data.iter()) {
+            *x += *y
+        }
+    }
+}
+
+impl<T, U, const DIM: usize> ops::SubAssign<Vector<U, DIM>> for Vector<T, DIM>
+where
+    T: ops::SubAssign<U>,
+    U: Copy,
+{
+    fn sub_assign(&mut self, other: Vector<U, DIM>) {
+        for (x, y) in self.data.iter_mut().zip(other.data.iter()) {
+            *x -= *y
+        }
+    }
+}
+
+impl<T, V, const DIM: usize> ops::Neg for Vector<T, DIM>
+where
+    T: Copy + ops::Neg<Output = V>,
+    V: Copy + Default,
+{
+    type Output = Vector<V, DIM>;
+
+    fn neg(self) -> Self::Output {
+        let mut data = [V::default(); DIM];
+
+        for (i, x) in data.iter_mut().enumerate() {
+            *x = self[i].neg()
+        }
+        Self::Output { data }
+    }
+}
+
 impl<T, U, V, const DIM: usize> ops::Mul<U> for Vector<T, DIM>
 where
     T: Copy + ops::Mul<U, Output = V>,
@@ -87,38 +216,94 @@ impl<T, const DIM: usize> ops::Index<usize> for Vector<T, DIM> {
     }
 }
 
-// #[cfg(test)]
-// mod test {
-// extern crate test;
-// use test::Bencher;
-// use super::Vector;
-
-// const COUNT: usize = 160000;
-
-// #[bench]
-// fn bench_add_raw_floats_in_vec(b: &mut Bencher) {
-//     b.iter(|| {
-//         let x: Vec<_> = (0..COUNT).map(|_| 1.0).collect();
-//         let y: Vec<_> = (0..COUNT).map(|_| 1.0).collect();
-//         let _: Vec<_> = x.into_iter().zip(y).map(|(x, y)| x + y).collect();
-//     })
-// }
-
-// #[bench]
-// fn bench_add_numeric_vectors4_floats_in_vec(b: &mut Bencher) {
-//     b.iter(|| {
-//         let x: Vec<_> = (0..COUNT/4).map(|_| Vector { data: [0.0, 1.0, 2.0, 3.0] }).collect();
-//         let y: Vec<_> = (0..COUNT/4).map(|_| Vector { data: [0.0, 1.0, 2.0, 3.0] }).collect();
-//         let _: Vec<_> = x.into_iter().zip(y).map(|(x, y)| x + y).collect();
-//     })
-// }
-
-// #[bench]
-// fn bench_add_numeric_vectors8_floats_in_vec(b: &mut Bencher) {
-//     b.iter(|| {
-//         let x: Vec<_> = (0..COUNT/8).map(|_| Vector { data: [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0] }).collect();
-//         let y: Vec<_> = (0..COUNT/8).map(|_| Vector { data: [0.0, 1.0, 2.0, 3.0, 4.0, 5.0, 6.0, 7.0] }).collect();
-//         let _: Vec<_> = x.into_iter().zip(y).map(|(x, y)| x + y).collect();
-//     })
-// }
-// }
+impl<T, const DIM: usize> ops::IndexMut<usize> for Vector<T, DIM> {
+    fn index_mut(&mut self, index: usize) -> &mut Self::Output {
+        &mut self.data[index]
+    }
+}
+
+/// The vector is serialized as a fixed-length tuple of its components.
+#[cfg(feature = "serde")]
+impl<T, const DIM: usize> serde::Serialize for Vector<T, DIM>
+where
+    T: serde::Serialize,
+{
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeTuple;
+
+        let mut tuple = serializer.serialize_tuple(DIM)?;
+
+        for x in &self.data {
+            tuple.serialize_element(x)?;
+        }
+        tuple.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de, T, const DIM: usize> serde::Deserialize<'de> for Vector<T, DIM>
+where
+    T: Copy + Default + serde::Deserialize<'de>,
+{
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct VectorVisitor<T, const DIM: usize>(core::marker::PhantomData<T>);
+
+        impl<'de, T, const DIM: usize> serde::de::Visitor<'de> for VectorVisitor<T, DIM>
+        where
+            T: Copy + Default + serde::Deserialize<'de>,
+        {
+            type Value = Vector<T, DIM>;
+
+            fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+                write!(formatter, "a sequence of {} components", DIM)
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut data = [T::default(); DIM];
+
+                for (i, x) in data.iter_mut().enumerate() {
+                    *x = seq
+                        .next_element()?
+                        .ok_or_else(|| serde::de::Error::invalid_length(i, &self))?;
+                }
+                Ok(Vector { data })
+            }
+        }
+        deserializer.deserialize_tuple(DIM, VectorVisitor(core::marker::PhantomData))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Vector;
+
+    #[test]
+    fn vector_arithmetic_works() {
+        let mut a = Vector::new([1.0, 2.0, 3.0]);
+        let b = Vector::from([3.0, 2.0, 1.0]);
+
+        assert_eq!(a + b, Vector::new([4.0, 4.0, 4.0]));
+        assert_eq!(-(a - b) * 2.0, Vector::new([4.0, 0.0, -4.0]));
+
+        a += b;
+        a -= Vector::new([1.0, 1.0, 1.0]);
+        a[0] = 0.0;
+        assert_eq!(a.into_array(), [0.0, 3.0, 3.0]);
+        assert_eq!(Vector::<f64, 3>::default(), Vector::new([0.0; 3]));
+    }
+
+    #[test]
+    fn vector_products_work() {
+        let x = Vector::new([1.0, 0.0, 0.0]);
+        let y = Vector::new([0.0, 1.0, 0.0]);
+
+        assert_eq!(x.cross(&y), Vector::new([0.0, 0.0, 1.0]));
+        assert_eq!(x.dot(&y), 0.0);
+        assert_eq!(Vector::new([3.0, 4.0]).norm(), 5.0);
+        assert_eq!(Vector::new([1, 2, 3]).into_iter().sum::<i32>(), 6);
+        assert_eq!((&Vector::new([1, 2, 3])).into_iter().count(), 3);
+    }
+}
@@ -1,6 +1,8 @@
-use crate::index_space::IndexSpace;
+use crate::index_space::{IndexSpace, MemoryLayout};
 use crate::rect_map::Rectangle;
 use std::cmp::Ordering::*;
+use std::collections::HashMap;
+use std::{error, fmt};
 
 /// Identifies the part of the mesh where patch data resides. An
 /// `n`-dimensional cartesian array has `n` of these parameters, one per axis.
@@ -56,8 +58,27 @@ pub struct Patch {
     /// The number of fields stored at each zone.
     num_fields: usize,
 
-    /// The backing array of data on this patch.
+    /// The backing array of data on this patch. This is empty while the
+    /// patch is compressed.
     data: Vec<f64>,
+
+    /// The compressed backing array, if the patch is dormant.
+    #[cfg_attr(feature = "serde", serde(default))]
+    compressed: Option<Vec<u8>>,
+
+    /// The valid interior region of this patch, if it has guard zones. The
+    /// guard zones are the part of the patch outside this region.
+    #[cfg_attr(feature = "serde", serde(default))]
+    valid_rect: Option<Rectangle<i64>>,
+
+    /// Whether the guard zones of this patch have been filled.
+    #[cfg_attr(feature = "serde", serde(default))]
+    guards_filled: bool,
+
+    /// Flags marking the solid zones of this patch, in row-major order, if
+    /// it has an embedded boundary.
+    #[cfg_attr(feature = "serde", serde(default))]
+    mask: Option<Vec<bool>>,
 }
 
 impl Patch {
@@ -68,6 +89,10 @@ impl Patch {
             rect: (0..0, 0..0),
             num_fields: 0,
             data: Vec::new(),
+            compressed: None,
+            valid_rect: None,
+            guards_filled: false,
+            mask: None,
         }
     }
 
@@ -81,6 +106,10 @@ impl Patch {
             level,
             num_fields,
             data,
+            compressed: None,
+            valid_rect: None,
+            guards_filled: false,
+            mask: None,
         }
     }
 
@@ -114,8 +143,41 @@ impl Patch {
         F: Fn((i64, i64), &mut [f64]),
     {
         let space: IndexSpace = space.into();
-        let mut data = vec![0.0; space.len() * num_fields];
+        let data = vec![0.0; space.len() * num_fields];
+        Self::from_buffer(level, space, num_fields, data, f)
+    }
+
+    /// Generates a patch at a given level, covering the given space, with
+    /// values defined from a closure which operates on mutable slices. The
+    /// backing array is taken from the given pool if a buffer of the right
+    /// size is available. The patch can be returned to the pool with
+    /// [`PatchPool::reclaim`] when it is no longer needed.
+    pub fn from_pool<I, F>(
+        pool: &mut PatchPool,
+        level: u32,
+        space: I,
+        num_fields: usize,
+        f: F,
+    ) -> Self
+    where
+        I: Into<IndexSpace>,
+        F: Fn((i64, i64), &mut [f64]),
+    {
+        let space: IndexSpace = space.into();
+        let data = pool.take(space.len() * num_fields);
+        Self::from_buffer(level, space, num_fields, data, f)
+    }
 
+    fn from_buffer<F>(
+        level: u32,
+        space: IndexSpace,
+        num_fields: usize,
+        mut data: Vec<f64>,
+        f: F,
+    ) -> Self
+    where
+        F: Fn((i64, i64), &mut [f64]),
+    {
         for (index, slice) in space.iter().zip(data.chunks_exact_mut(num_fields)) {
             f(index, slice)
         }
@@ -124,11 +186,49 @@ impl Patch {
             data,
             rect: space.into(),
             num_fields,
+            compressed: None,
+            valid_rect: None,
+            guards_filled: false,
+            mask: None,
         }
     }
 
+    /// Generates a patch from a buffer of data with the given memory layout.
+    /// The data is re-ordered into the patch's native layout,
+    /// [`MemoryLayout::RowMajor`]. This function panics if the buffer size
+    /// does not match the index space and number of fields.
+    pub fn from_layout<I>(
+        level: u32,
+        space: I,
+        num_fields: usize,
+        layout: MemoryLayout,
+        data: &[f64],
+    ) -> Self
+    where
+        I: Into<IndexSpace>,
+    {
+        let space: IndexSpace = space.into();
+
+        assert! {
+            data.len() == space.len() * num_fields,
+            "buffer of size {} does not match patch of {} zones and {} fields",
+            data.len(),
+            space.len(),
+            num_fields
+        };
+
+        Self::from_slice_function(level, space.clone(), num_fields, |index, slice| {
+            for (field, x) in slice.iter_mut().enumerate() {
+                *x = data[layout.offset(&space, index, field, num_fields)]
+            }
+        })
+    }
+
+    /// Creates a patch over the given selection, copying the data (and the
+    /// mask, if the source has one) from the overlapping part of the source
+    /// patch. The remaining zones are zero-filled and fluid.
     pub fn extract_from(source: &Patch, selection: IndexSpace) -> Self {
-        Self::from_slice_function(
+        let mut result = Self::from_slice_function(
             source.level,
             selection,
             source.num_fields,
@@ -137,7 +237,12 @@ impl Patch {
                     slice.clone_from_slice(source.get_slice(index))
                 }
             },
-        )
+        );
+
+        if source.has_mask() {
+            result.set_mask(|index| source.is_solid(index))
+        }
+        result
     }
 
     pub fn level(&self) -> u32 {
@@ -148,28 +253,199 @@ impl Patch {
         self.num_fields
     }
 
+    /// Returns the backing array of data on this patch. The data is in the
+    /// [`MemoryLayout::RowMajor`] layout.
     pub fn data(&self) -> &Vec<f64> {
+        self.resident()
+    }
+
+    /// Compresses the backing array of this patch, to reduce the memory
+    /// footprint of patches which are not expected to be accessed for a
+    /// while. The accessors taking `&mut self` decompress the data on
+    /// access; those taking `&self` panic while the patch is compressed, so
+    /// it must first be made resident again with [`Patch::ensure_resident`].
+    /// This method does nothing if the patch is already compressed. It
+    /// requires the `zstd` feature.
+    #[cfg(feature = "zstd")]
+    pub fn compress(&mut self) {
+        if self.compressed.is_none() {
+            let bytes: Vec<u8> = self.data.iter().flat_map(|x| x.to_le_bytes()).collect();
+            let compressed = zstd::bulk::compress(&bytes, 0).expect("patch compression failed");
+            self.compressed = Some(compressed);
+            self.data = Vec::new();
+        }
+    }
+
+    /// Decompresses the backing array of this patch if it is compressed.
+    pub fn ensure_resident(&mut self) {
+        #[cfg(feature = "zstd")]
+        if let Some(compressed) = self.compressed.take() {
+            let size = self.index_space().len() * self.num_fields * std::mem::size_of::<f64>();
+            let bytes =
+                zstd::bulk::decompress(&compressed, size).expect("patch decompression failed");
+            self.data = bytes
+                .chunks_exact(std::mem::size_of::<f64>())
+                .map(|b| f64::from_le_bytes(std::convert::TryInto::try_into(b).unwrap()))
+                .collect();
+        }
+    }
+
+    /// Returns the backing array, which must not be compressed.
+    fn resident(&self) -> &Vec<f64> {
+        assert!(
+            self.compressed.is_none(),
+            "patch data accessed while compressed; call Patch::ensure_resident first"
+        );
         &self.data
     }
 
-    pub fn data_mut(&mut self) -> &mut [f64] {
+    /// Returns the backing array, decompressing it first if needed.
+    fn resident_mut(&mut self) -> &mut Vec<f64> {
+        self.ensure_resident();
         &mut self.data
     }
 
+    /// Returns true if the backing array of this patch is compressed.
+    pub fn is_compressed(&self) -> bool {
+        self.compressed.is_some()
+    }
+
+    /// Returns a copy of this patch's data, re-ordered into the given memory
+    /// layout. This is useful for interfacing with Fortran-ordered kernels or
+    /// kernels that expect a structure-of-arrays.
+    pub fn to_layout(&self, layout: MemoryLayout) -> Vec<f64> {
+        let space = self.index_space();
+        let mut result = vec![0.0; self.resident().len()];
+
+        for (index, slice) in space.iter().zip(self.resident().chunks_exact(self.num_fields)) {
+            for (field, x) in slice.iter().enumerate() {
+                result[layout.offset(&space, index, field, self.num_fields)] = *x
+            }
+        }
+        result
+    }
+
+    pub fn data_mut(&mut self) -> &mut [f64] {
+        self.resident_mut()
+    }
+
     pub fn iter_data_mut(&mut self) -> impl Iterator<Item = &mut [f64]> {
-        self.data.chunks_exact_mut(self.num_fields)
+        let num_fields = self.num_fields;
+        self.resident_mut().chunks_exact_mut(num_fields)
     }
 
     pub fn select(&self, subspace: IndexSpace) -> impl Iterator<Item = &'_ [f64]> {
         subspace
             .memory_region_in(&self.index_space())
-            .iter_slice(&self.data, self.num_fields)
+            .iter_slice(self.resident(), self.num_fields)
     }
 
     pub fn select_mut(&mut self, subspace: IndexSpace) -> impl Iterator<Item = &'_ mut [f64]> {
+        let (num_fields, space) = (self.num_fields, self.index_space());
         subspace
-            .memory_region_in(&self.index_space())
-            .iter_slice_mut(&mut self.data, self.num_fields)
+            .memory_region_in(&space)
+            .iter_slice_mut(self.resident_mut(), num_fields)
+    }
+
+    /// Declares the valid interior region of this patch. The remainder of the
+    /// patch is considered to be guard zones, which are marked as not yet
+    /// filled. In debug builds, reading data from a guard zone with
+    /// [`Patch::get_slice`] or [`Patch::sample`] panics until the guard zones
+    /// are marked as filled. This method panics if the valid region is not
+    /// contained in this patch.
+    pub fn set_valid_space<I: Into<IndexSpace>>(&mut self, valid: I) {
+        let valid: IndexSpace = valid.into();
+
+        assert! {
+            self.index_space().contains_space(&valid),
+            "the valid space is not contained in the patch"
+        };
+        self.valid_rect = Some(valid.into());
+        self.guards_filled = false;
+    }
+
+    /// Returns the valid interior region of this patch. If no valid region
+    /// was declared, this is the patch's whole index space.
+    pub fn valid_index_space(&self) -> IndexSpace {
+        match &self.valid_rect {
+            Some(rect) => IndexSpace::from(rect.clone()),
+            None => self.index_space(),
+        }
+    }
+
+    /// Marks the guard zones of this patch as filled. This is done by
+    /// [`crate::meshing::extend_patch_mut`].
+    pub fn mark_guards_filled(&mut self) {
+        self.guards_filled = true;
+    }
+
+    /// Marks the guard zones of this patch as stale, for example after the
+    /// valid region was updated.
+    pub fn mark_guards_stale(&mut self) {
+        self.guards_filled = false;
+    }
+
+    /// Returns true if the patch has no guard zones, or if its guard zones
+    /// have been filled.
+    pub fn guards_filled(&self) -> bool {
+        self.valid_rect.is_none() || self.guards_filled
+    }
+
+    /// Marks the zones for which the given closure returns true as solid, and
+    /// the remaining zones as fluid. Solid zones represent embedded (or
+    /// immersed) boundaries inside the patch: they are skipped by the
+    /// reductions, such as [`Patch::sum`] and [`Patch::reduce`], and are
+    /// neither filled nor used as sources by
+    /// [`crate::meshing::extend_patch_mut`]. The data in solid zones is left
+    /// unchanged.
+    pub fn set_mask<F: Fn((i64, i64)) -> bool>(&mut self, solid: F) {
+        self.mask = Some(self.index_space().iter().map(solid).collect());
+    }
+
+    /// Removes the mask from this patch, so that all of its zones are fluid.
+    pub fn clear_mask(&mut self) {
+        self.mask = None;
+    }
+
+    /// Returns true if this patch has a mask.
+    pub fn has_mask(&self) -> bool {
+        self.mask.is_some()
+    }
+
+    /// Returns true if the zone at the given index is solid. Zones outside
+    /// the patch, and all zones of a patch without a mask, are fluid.
+    pub fn is_solid(&self, index: (i64, i64)) -> bool {
+        match &self.mask {
+            Some(mask) if self.index_space().contains(index) => {
+                mask[self.index_space().row_major_offset(index)]
+            }
+            _ => false,
+        }
+    }
+
+    /// Returns true if the zone at the given level and index is solid. The
+    /// index measures ticks at the target level, as in [`Patch::sample`]. A
+    /// finer zone is solid if the zone containing it is solid, and a coarser
+    /// zone is solid only if all the zones it covers are solid.
+    pub fn is_solid_at(&self, level: u32, index: (i64, i64)) -> bool {
+        match level.cmp(&self.level) {
+            Equal => self.is_solid(index),
+            Less => self.is_solid_at(level + 1, (index.0 / 2, index.1 / 2)),
+            Greater => {
+                self.is_solid_at(level - 1, (index.0 * 2, index.1 * 2))
+                    && self.is_solid_at(level - 1, (index.0 * 2, index.1 * 2 + 1))
+                    && self.is_solid_at(level - 1, (index.0 * 2 + 1, index.1 * 2))
+                    && self.is_solid_at(level - 1, (index.0 * 2 + 1, index.1 * 2 + 1))
+            }
+        }
+    }
+
+    /// Returns the number of fluid zones in the valid region of this patch.
+    pub fn num_fluid_zones(&self) -> usize {
+        self.valid_index_space()
+            .iter()
+            .filter(|&index| !self.is_solid(index))
+            .count()
     }
 
     /// Returns this patch's rectangle.
@@ -204,7 +480,7 @@ impl Patch {
                 let j = (index.1 - j0) as usize;
 
                 let (_m, n) = self.index_space().dim();
-                self.data[(i * n + j) * self.num_fields + field]
+                self.resident()[(i * n + j) * self.num_fields + field]
             }
             Less => self.sample(level + 1, (index.0 / 2, index.1 / 2), field),
             Greater => {
@@ -217,6 +493,35 @@ impl Patch {
         }
     }
 
+    /// Resamples this patch onto a new patch at the given level, covering
+    /// the given space. The space is measured in ticks at the target level.
+    /// Data is averaged if the target level is coarser than this patch, and
+    /// sampled piecewise constant if it is finer. This method panics if the
+    /// target space is not covered by this patch. If this patch has a mask,
+    /// the result is masked according to [`Patch::is_solid_at`]. Note that
+    /// averaged zones include the data in solid zones.
+    pub fn sample_at<I: Into<IndexSpace>>(&self, level: u32, space: I) -> Self {
+        let mut result =
+            Self::from_slice_function(level, space, self.num_fields, |index, slice| {
+                self.sample_slice(level, index, slice)
+            });
+
+        if self.has_mask() {
+            result.set_mask(|index| self.is_solid_at(level, index))
+        }
+        result
+    }
+
+    /// Returns a copy of this patch coarsened by `2^k` on each axis, for
+    /// example to write lightweight movie frames. Coarse zones are obtained by
+    /// averaging (not striding) so the result is conservative. A stitched
+    /// coarse snapshot of many patches is obtained by passing a coarser level
+    /// to [`stitch`]. This method panics if the patch's index space is not
+    /// divisible by `2^k`.
+    pub fn downsample(&self, k: u32) -> Self {
+        self.sample_at(self.level + k, self.index_space().coarsen_by(1 << k))
+    }
+
     /// Samples all the fields in this patch at the given index and returns
     /// the result as a fixed-length array. The array size must be less than
     /// or equal to the number of fields.
@@ -246,21 +551,79 @@ impl Patch {
         }
     }
 
+    /// Returns the minimum value of a field over the fluid zones in the valid
+    /// region of this patch.
+    pub fn min(&self, field: usize) -> f64 {
+        self.fold_field(field, f64::INFINITY, f64::min)
+    }
+
+    /// Returns the maximum value of a field over the fluid zones in the valid
+    /// region of this patch.
+    pub fn max(&self, field: usize) -> f64 {
+        self.fold_field(field, f64::NEG_INFINITY, f64::max)
+    }
+
+    /// Returns the sum of a field over the fluid zones in the valid region of
+    /// this patch.
+    pub fn sum(&self, field: usize) -> f64 {
+        self.fold_field(field, 0.0, |a, b| a + b)
+    }
+
+    /// Returns the mean value of a field over the fluid zones in the valid
+    /// region of this patch. The mean is zero if there are no fluid zones,
+    /// for example if every zone is solid.
+    pub fn mean(&self, field: usize) -> f64 {
+        match self.num_fluid_zones() {
+            0 => 0.0,
+            n => self.sum(field) / n as f64,
+        }
+    }
+
+    /// Reduces each of the first `NUM_FIELDS` fields over the fluid zones in
+    /// the valid region of this patch, using the given binary operation and
+    /// initial values, and returns the result as a fixed-length array. The
+    /// array size must be less than or equal to the number of fields.
+    pub fn reduce<F, const NUM_FIELDS: usize>(
+        &self,
+        init: [f64; NUM_FIELDS],
+        f: F,
+    ) -> [f64; NUM_FIELDS]
+    where
+        F: Fn(f64, f64) -> f64,
+    {
+        assert! {
+            NUM_FIELDS <= self.num_fields,
+            "attempt to reduce {} fields from a patch with {} fields",
+            NUM_FIELDS,
+            self.num_fields
+        };
+
+        self.select_fluid()
+            .fold(init, |mut result, slice| {
+                for (r, x) in result.iter_mut().zip(slice) {
+                    *r = f(*r, *x)
+                }
+                result
+            })
+    }
+
     /// Returns a slice of all data fields at the given index. This method
     /// does not check if the index is logically in bounds, but will panic if
     /// a memory location would have been out of bounds.
     pub fn get_slice(&self, index: (i64, i64)) -> &[f64] {
+        self.debug_validate_guard(index);
         let s = self.index_space().row_major_offset(index);
-        &self.data[s * self.num_fields..(s + 1) * self.num_fields]
+        &self.resident()[s * self.num_fields..(s + 1) * self.num_fields]
     }
 
     pub fn get_slice_mut(&mut self, index: (i64, i64)) -> &mut [f64] {
+        let n = self.num_fields;
         let s = self.index_space().row_major_offset(index);
-        &mut self.data[s * self.num_fields..(s + 1) * self.num_fields]
+        &mut self.resident_mut()[s * n..(s + 1) * n]
     }
 
-    /// Extracts a subset of this patch and return it. This method panics if
-    /// the slice is out of bounds.
+    /// Extracts a subset of this patch and return it, along with its mask if
+    /// it has one. This method panics if the slice is out of bounds.
     pub fn extract<I: Into<IndexSpace>>(&self, subset: I) -> Self {
         let subset: IndexSpace = subset.into();
 
@@ -269,9 +632,27 @@ impl Patch {
             "the index space is out of bounds"
         }
 
-        Self::from_slice_function(self.level, subset, self.num_fields, |index, slice| {
-            slice.clone_from_slice(self.get_slice(index))
-        })
+        let mut result =
+            Self::from_slice_function(self.level, subset, self.num_fields, |index, slice| {
+                slice.clone_from_slice(self.get_slice(index))
+            });
+
+        if self.has_mask() {
+            result.set_mask(|index| self.is_solid(index))
+        }
+        result
+    }
+
+    /// Extracts the part of this patch which overlaps the given index space.
+    /// The result covers the intersection of the two spaces, so it is
+    /// suitable as a message payload for a neighbor whose (extended) index
+    /// space is given. This method panics if the spaces do not overlap.
+    pub fn extract_overlap(&self, with: &IndexSpace) -> Self {
+        let overlap = self
+            .index_space()
+            .intersect(with)
+            .expect("patches do not overlap");
+        self.extract(overlap)
     }
 
     pub fn map_index_mut<F>(&mut self, f: F)
@@ -284,7 +665,7 @@ impl Patch {
 
         index_space
             .iter()
-            .zip(memory_region.iter_slice_mut(&mut self.data, num_fields))
+            .zip(memory_region.iter_slice_mut(self.resident_mut(), num_fields))
             .for_each(|(index, slice)| f(index, slice))
     }
 
@@ -292,8 +673,10 @@ impl Patch {
     /// on the same level and have the same number of fields, but they do not
     /// need to have the same index space. Only the elements at the
     /// overlapping part of the index spaces are mapped; the remaining part of
-    /// the target patch is unchanged. This method panics if the target space
-    /// does not overlap this one.
+    /// the target patch is unchanged. Only data is mapped: the target keeps
+    /// its own mask (or lack of one), so solid zones of this patch are mapped
+    /// like fluid ones. This method panics if the target space does not
+    /// overlap this one.
     pub fn map_into<F>(&self, target: &mut Self, f: F)
     where
         F: Fn(&[f64], &mut [f64]),
@@ -309,8 +692,8 @@ impl Patch {
         let target_region = overlap_space.memory_region_in(&target.index_space());
 
         source_region
-            .iter_slice(&self.data, self.num_fields)
-            .zip(target_region.iter_slice_mut(&mut target.data, self.num_fields))
+            .iter_slice(self.resident(), self.num_fields)
+            .zip(target_region.iter_slice_mut(target.resident_mut(), self.num_fields))
             .for_each(|x| f(x.0, x.1))
     }
 
@@ -318,7 +701,8 @@ impl Patch {
     /// be on the same level and have the same number of fields, but they do
     /// not need to have the same index space. Only the elements at the
     /// overlapping part of the index spaces are mapped; the remaining part of
-    /// the target patch is unchanged.
+    /// the target patch is unchanged. As with [`Patch::map_into`], the mask
+    /// of the target is not changed.
     pub fn copy_into(&self, target: &mut Self) {
         self.map_into(target, |src, dst| {
             for (s, d) in src.iter().zip(dst) {
@@ -331,8 +715,8 @@ impl Patch {
     where
         F: Fn(&[f64], &mut [f64]),
     {
-        let mut data = vec![0.0; self.data.len()];
-        self.data
+        let mut data = vec![0.0; self.resident().len()];
+        self.resident()
             .chunks_exact(self.num_fields)
             .zip(data.chunks_exact_mut(self.num_fields))
             .for_each(|x| f(x.0, x.1));
@@ -341,10 +725,51 @@ impl Patch {
             rect: self.rect.clone(),
             num_fields: self.num_fields,
             data,
+            compressed: None,
+            valid_rect: self.valid_rect.clone(),
+            guards_filled: self.guards_filled,
+            mask: self.mask.clone(),
         }
     }
 
+    fn fold_field<F>(&self, field: usize, init: f64, f: F) -> f64
+    where
+        F: Fn(f64, f64) -> f64,
+    {
+        assert! {
+            field < self.num_fields,
+            "field index {} out of range on patch with {} fields",
+            field,
+            self.num_fields
+        };
+
+        self.select_fluid()
+            .fold(init, |a, slice| f(a, slice[field]))
+    }
+
+    fn select_fluid(&self) -> impl Iterator<Item = &'_ [f64]> {
+        let valid = self.valid_index_space();
+
+        valid
+            .clone()
+            .into_iter()
+            .zip(self.select(valid))
+            .filter(move |&(index, _)| !self.is_solid(index))
+            .map(|(_, slice)| slice)
+    }
+
+    fn debug_validate_guard(&self, index: (i64, i64)) {
+        debug_assert! {
+            self.guards_filled() || self.valid_index_space().contains(index),
+            "index ({} {}) is in a guard zone which has not been filled",
+            index.0,
+            index.1
+        };
+    }
+
     fn validate_index(&self, index: (i64, i64), field: usize) {
+        self.debug_validate_guard(index);
+
         let space = self.index_space();
 
         assert! {
@@ -373,11 +798,213 @@ impl Default for Patch {
     }
 }
 
+/// Error returned from [`stitch`] when the patches do not tile their
+/// bounding index space.
+#[derive(Debug, Clone, PartialEq)]
+pub enum StitchError {
+    /// No patches were given.
+    NoPatches,
+
+    /// The patches do not all have the same number of fields.
+    FieldCountMismatch(usize, usize),
+
+    /// The given index (at the target level) is not covered by any patch.
+    Gap((i64, i64)),
+
+    /// The given index (at the target level) is covered by more than one
+    /// patch.
+    Overlap((i64, i64)),
+}
+
+impl fmt::Display for StitchError {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        use StitchError::*;
+
+        match self {
+            NoPatches => write!(fmt, "no patches to stitch"),
+            FieldCountMismatch(a, b) => write!(fmt, "patches have {} and {} fields", a, b),
+            Gap(i) => write!(fmt, "index ({} {}) is not covered by any patch", i.0, i.1),
+            Overlap(i) => write!(
+                fmt,
+                "index ({} {}) is covered by multiple patches",
+                i.0, i.1
+            ),
+        }
+    }
+}
+
+impl error::Error for StitchError {}
+
+/// Combines a collection of patches into a single patch at the given level,
+/// covering their bounding index space. Patches at other levels are sampled
+/// at the target level. If any of the patches has a mask, the result is masked
+/// with the solid zones of all the patches, as sampled by
+/// [`Patch::sample_at`]. An error is returned if the patches leave gaps in, or
+/// overlap within, the bounding space.
+pub fn stitch(patches: &[Patch], level: u32) -> Result<Patch, StitchError> {
+    let first = patches.first().ok_or(StitchError::NoPatches)?;
+    let num_fields = first.num_fields;
+
+    let spaces: Vec<_> = patches
+        .iter()
+        .map(|p| p.high_resolution_space().coarsen_by(1 << level))
+        .collect();
+
+    let (i0, j0) = spaces.iter().fold(spaces[0].start(), |(i0, j0), s| {
+        (i0.min(s.start().0), j0.min(s.start().1))
+    });
+    let (i1, j1) = spaces.iter().fold(spaces[0].end(), |(i1, j1), s| {
+        (i1.max(s.end().0), j1.max(s.end().1))
+    });
+    let bounds = IndexSpace::new(i0..i1, j0..j1);
+    let mut result = Patch::zeros(level, num_fields, bounds.clone());
+    let mut covered = vec![false; bounds.len()];
+    let mut solid = vec![false; bounds.len()];
+
+    for (patch, space) in patches.iter().zip(spaces) {
+        if patch.num_fields != num_fields {
+            return Err(StitchError::FieldCountMismatch(
+                num_fields,
+                patch.num_fields,
+            ));
+        }
+        let sampled;
+        let source = if patch.level == level {
+            patch
+        } else {
+            sampled = patch.sample_at(level, space.clone());
+            &sampled
+        };
+        for index in space.iter() {
+            let n = bounds.row_major_offset(index);
+
+            if covered[n] {
+                return Err(StitchError::Overlap(index));
+            }
+            covered[n] = true;
+            solid[n] = source.is_solid(index);
+            result
+                .get_slice_mut(index)
+                .clone_from_slice(source.get_slice(index))
+        }
+    }
+
+    let gap = bounds.iter().zip(covered).find(|(_, c)| !c);
+
+    if let Some((index, _)) = gap {
+        return Err(StitchError::Gap(index));
+    }
+    if patches.iter().any(Patch::has_mask) {
+        result.set_mask(|index| solid[bounds.row_major_offset(index)])
+    }
+    Ok(result)
+}
+
+/// Norms of the difference between two patches for a single field, as
+/// returned from [`compare`]. The `l1` and `l2` norms are normalized by the
+/// number of zones compared.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct DiffNorms {
+    /// The largest absolute difference.
+    pub max: f64,
+
+    /// The mean absolute difference.
+    pub l1: f64,
+
+    /// The root-mean-square difference.
+    pub l2: f64,
+}
+
+/// Compares two patches and returns the norms of their difference for each
+/// field, restricted to the intersection of their index spaces. The patches
+/// must be on the same level and have the same number of fields. This
+/// function panics if the patches do not overlap.
+pub fn compare(a: &Patch, b: &Patch) -> Vec<DiffNorms> {
+    assert!(a.level == b.level);
+    assert!(a.num_fields == b.num_fields);
+
+    let overlap = a
+        .index_space()
+        .intersect(&b.index_space())
+        .expect("patches do not overlap");
+    let mut norms = vec![DiffNorms::default(); a.num_fields];
+
+    for (x, y) in a.select(overlap.clone()).zip(b.select(overlap.clone())) {
+        for (n, (x, y)) in norms.iter_mut().zip(x.iter().zip(y)) {
+            let d = (x - y).abs();
+            n.max = n.max.max(d);
+            n.l1 += d;
+            n.l2 += d * d;
+        }
+    }
+
+    let count = overlap.len().max(1) as f64;
+
+    for n in &mut norms {
+        n.l1 /= count;
+        n.l2 = (n.l2 / count).sqrt();
+    }
+    norms
+}
+
+/// A pool of patch data buffers which can be reused, to reduce allocator
+/// pressure when many short-lived patches of the same shapes are created
+/// every time step. Buffers are binned by their size.
+#[derive(Debug, Default)]
+pub struct PatchPool {
+    buffers: HashMap<usize, Vec<Vec<f64>>>,
+}
+
+impl PatchPool {
+    /// Creates a new empty pool.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Takes a zero-filled buffer of the given size from the pool, or
+    /// allocates a new one if none is available.
+    pub fn take(&mut self, size: usize) -> Vec<f64> {
+        match self.buffers.get_mut(&size).and_then(|bin| bin.pop()) {
+            Some(mut buffer) => {
+                buffer.iter_mut().for_each(|x| *x = 0.0);
+                buffer
+            }
+            None => vec![0.0; size],
+        }
+    }
+
+    /// Returns a patch's backing array to the pool. Compressed patches have
+    /// no backing array, so nothing is reclaimed from them.
+    pub fn reclaim(&mut self, patch: Patch) {
+        if !patch.data.is_empty() {
+            self.buffers
+                .entry(patch.data.len())
+                .or_default()
+                .push(patch.data)
+        }
+    }
+
+    /// Returns the number of buffers held in the pool.
+    pub fn len(&self) -> usize {
+        self.buffers.values().map(|bin| bin.len()).sum()
+    }
+
+    /// Returns true if the pool holds no buffers.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Releases all buffers held in the pool.
+    pub fn clear(&mut self) {
+        self.buffers.clear()
+    }
+}
+
 #[cfg(test)]
 mod test {
 
-    use super::Patch;
-    use crate::index_space::{range2d, IndexSpace};
+    use super::{compare, stitch, DiffNorms, Patch, PatchPool, StitchError};
+    use crate::index_space::{range2d, IndexSpace, MemoryLayout};
     use crate::rect_map::{Rectangle, RectangleMap, RectangleRef};
 
     fn finest_patch<'a>(
@@ -413,6 +1040,54 @@ mod test {
         )
     }
 
+    #[test]
+    fn masked_zones_are_skipped_by_reductions() {
+        let mut patch = Patch::from_scalar_function(0, (0..4, 0..4), |(i, j)| (i + j) as f64);
+        patch.set_mask(|(i, j)| i + j >= 5);
+
+        assert_eq!(patch.num_fluid_zones(), 13);
+        assert_eq!(patch.max(0), 4.0);
+        assert_eq!(patch.sum(0), 32.0);
+        assert_eq!(patch.reduce([0.0], f64::max), [4.0]);
+        assert!(patch.extract((2..4, 2..4)).is_solid((3, 3)));
+        assert!(!patch.extract((0..2, 0..2)).is_solid((1, 1)));
+
+        patch.clear_mask();
+        assert_eq!(patch.num_fluid_zones(), 16);
+    }
+
+    #[test]
+    fn mean_of_all_solid_patch_is_zero() {
+        let mut patch = Patch::from_scalar_function(0, (0..4, 0..4), |(i, j)| (i + j) as f64);
+        patch.set_mask(|_| true);
+
+        assert_eq!(patch.num_fluid_zones(), 0);
+        assert_eq!(patch.mean(0), 0.0);
+    }
+
+    #[test]
+    fn mask_is_kept_by_sampling_and_stitching() {
+        let mut patch = Patch::from_scalar_function(0, (0..4, 0..4), |(i, j)| (i + j) as f64);
+        patch.set_mask(|(i, j)| i < 2 && (j < 2 || i == 0));
+
+        let coarse = patch.downsample(1);
+        assert!(coarse.is_solid((0, 0)));
+        assert!(!coarse.is_solid((0, 1)));
+        assert!(!coarse.is_solid((1, 0)));
+
+        let fine = coarse.sample_at(0, (0..4, 0..4));
+        assert!(fine.is_solid((1, 1)));
+        assert!(!fine.is_solid((0, 2)));
+
+        let left = Patch::extract_from(&patch, IndexSpace::new(0..2, 0..4));
+        let right = patch.extract((2..4, 0..4));
+        let stitched = stitch(&[left, right], 0).unwrap();
+        assert!(stitched
+            .index_space()
+            .iter()
+            .all(|index| stitched.is_solid(index) == patch.is_solid(index)));
+    }
+
     #[test]
     fn patch_sampling_works() {
         let patch = Patch::from_scalar_function(1, (4..10, 4..10), |(i, j)| i as f64 + j as f64);
@@ -428,6 +1103,207 @@ mod test {
         assert_eq!(patch.sample(0, (10, 10), 0), 10.0);
     }
 
+    #[test]
+    fn patch_resampling_works() {
+        let patch = Patch::from_vector_function(1, (4..10, 4..10), |(i, j)| [i as f64, j as f64]);
+
+        let coarse = patch.sample_at(2, (2..5, 3..5));
+        assert_eq!(coarse.level(), 2);
+        assert_eq!(coarse.index_space(), IndexSpace::new(2..5, 3..5));
+        assert_eq!(coarse.get_slice((3, 4)), &[6.5, 8.5]);
+
+        let fine = patch.sample_at(0, (8..20, 8..20));
+        assert_eq!(fine.high_resolution_space(), patch.high_resolution_space());
+        assert_eq!(fine.get_slice((13, 18)), &[6.0, 9.0]);
+
+        let same = patch.sample_at(1, patch.index_space());
+        assert_eq!(same.data(), patch.data());
+    }
+
+    #[test]
+    #[cfg(feature = "zstd")]
+    fn patch_compression_round_trip_works() {
+        let patch = Patch::from_vector_function(0, (0..64, 0..64), |(i, j)| [i as f64, j as f64]);
+        let mut dormant = patch.clone();
+
+        dormant.compress();
+        assert!(dormant.is_compressed());
+
+        dormant.ensure_resident();
+        assert!(!dormant.is_compressed());
+        assert_eq!(dormant.data(), patch.data());
+
+        dormant.compress();
+        assert_eq!(dormant.get_slice_mut((3, 5)), &[3.0, 5.0]);
+        assert!(!dormant.is_compressed());
+
+        dormant.compress();
+        let read = std::panic::catch_unwind(|| dormant.get_slice((3, 5)).to_vec());
+        assert!(read.is_err());
+    }
+
+    #[test]
+    fn patch_overlap_extraction_works() {
+        let patch = Patch::from_scalar_function(0, (0..10, 0..10), |(i, j)| (i * 10 + j) as f64);
+        let neighbor = IndexSpace::new(10..20, 5..15).extend_all(2);
+        let slab = patch.extract_overlap(&neighbor);
+        assert_eq!(slab.index_space(), IndexSpace::new(8..10, 3..10));
+        assert_eq!(slab.get_slice((9, 3)), &[93.0]);
+    }
+
+    #[test]
+    #[should_panic]
+    fn patch_overlap_extraction_panics_if_disjoint() {
+        let patch = Patch::zeros(0, 1, (0..10, 0..10));
+        patch.extract_overlap(&IndexSpace::new(12..20, 0..10));
+    }
+
+    #[test]
+    fn patch_guard_tracking_works() {
+        let mut patch = Patch::zeros(0, 1, (0..10, 0..10));
+        assert!(patch.guards_filled());
+
+        patch.set_valid_space((2..8, 2..8));
+        assert!(!patch.guards_filled());
+        assert_eq!(patch.valid_index_space(), IndexSpace::new(2..8, 2..8));
+        assert_eq!(patch.get_slice((2, 2)), &[0.0]);
+
+        patch.mark_guards_filled();
+        assert!(patch.guards_filled());
+        assert_eq!(patch.sample(0, (0, 0), 0), 0.0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic]
+    fn patch_guard_read_panics_before_filled() {
+        let mut patch = Patch::zeros(0, 1, (0..10, 0..10));
+        patch.set_valid_space((2..8, 2..8));
+        patch.get_slice((0, 0));
+    }
+
+    #[test]
+    fn patch_reductions_work() {
+        let mut patch =
+            Patch::from_vector_function(0, (0..4, 0..4), |(i, j)| [i as f64, -(j as f64)]);
+        assert_eq!(patch.min(0), 0.0);
+        assert_eq!(patch.max(0), 3.0);
+        assert_eq!(patch.sum(1), -24.0);
+        assert_eq!(patch.mean(1), -1.5);
+        assert_eq!(patch.reduce([0.0, 0.0], f64::min), [0.0, -3.0]);
+        assert_eq!(patch.reduce([0.0], |a, b| a + b), [24.0]);
+
+        patch.set_valid_space((1..3, 1..3));
+        assert_eq!(patch.max(0), 2.0);
+        assert_eq!(patch.mean(1), -1.5);
+    }
+
+    #[test]
+    fn patch_pool_reuses_buffers() {
+        let mut pool = PatchPool::new();
+        let a = Patch::from_pool(&mut pool, 0, (0..4, 0..4), 2, |_, s| s.fill(1.0));
+        let ptr = a.data().as_ptr();
+        pool.reclaim(a);
+        assert_eq!(pool.len(), 1);
+
+        let b = Patch::from_pool(&mut pool, 0, (4..8, 0..4), 2, |(i, _), s| s[0] = i as f64);
+        assert!(pool.is_empty());
+        assert_eq!(b.data().as_ptr(), ptr);
+        assert_eq!(b.get_slice((5, 0)), &[5.0, 0.0]);
+
+        let c = Patch::from_pool(&mut pool, 0, (0..2, 0..2), 2, |_, _| {});
+        assert_eq!(c.data().len(), 8);
+    }
+
+    #[test]
+    fn patch_downsampling_conserves_sums() {
+        let patch = Patch::from_scalar_function(1, (4..12, 0..16), |(i, j)| (i * j) as f64);
+        let coarse = patch.downsample(2);
+        assert_eq!(coarse.level(), 3);
+        assert_eq!(coarse.index_space(), IndexSpace::new(1..3, 0..4));
+        assert_eq!(
+            coarse.high_resolution_space(),
+            patch.high_resolution_space()
+        );
+        assert_eq!(coarse.sum(0) * 16.0, patch.sum(0));
+    }
+
+    #[test]
+    fn patch_comparison_works() {
+        let a = Patch::from_vector_function(0, (0..4, 0..4), |(i, _)| [i as f64, 1.0]);
+        let b = Patch::from_vector_function(0, (2..6, 0..4), |_| [0.0, 1.0]);
+        let norms = compare(&a, &b);
+
+        assert_eq!(norms[0].max, 3.0);
+        assert_eq!(norms[0].l1, 2.5);
+        assert_eq!(norms[0].l2, 6.5f64.sqrt());
+        assert_eq!(norms[1], DiffNorms::default());
+    }
+
+    #[test]
+    fn stitching_patches_works() {
+        let f = |(i, j): (i64, i64)| (i * 100 + j) as f64;
+        let patches: Vec<_> = range2d(0..2, 0..3)
+            .iter()
+            .map(|(i, j)| Patch::from_scalar_function(0, (i * 4..i * 4 + 4, j * 5..j * 5 + 5), f))
+            .collect();
+
+        let global = stitch(&patches, 0).unwrap();
+        assert_eq!(global.index_space(), IndexSpace::new(0..8, 0..15));
+        assert_eq!(
+            global.data(),
+            Patch::from_scalar_function(0, (0..8, 0..15), f).data()
+        );
+
+        let fine = [
+            Patch::from_scalar_function(0, (0..4, 0..4), f),
+            Patch::from_scalar_function(0, (0..4, 4..8), f),
+        ];
+        let coarse = stitch(&fine, 1).unwrap();
+        let sample = 0.25 * (f((2, 2)) + f((2, 3)) + f((3, 2)) + f((3, 3)));
+        assert_eq!(coarse.index_space(), IndexSpace::new(0..2, 0..4));
+        assert_eq!(coarse.get_slice((1, 1)), &[sample]);
+    }
+
+    #[test]
+    fn stitching_reports_gaps_and_overlaps() {
+        let a = Patch::zeros(0, 1, (0..4, 0..4));
+        let b = Patch::zeros(0, 1, (4..8, 1..4));
+        let c = Patch::zeros(0, 1, (3..8, 0..4));
+        assert_eq!(stitch(&[], 0).unwrap_err(), StitchError::NoPatches);
+        assert_eq!(
+            stitch(&[a.clone(), b], 0).unwrap_err(),
+            StitchError::Gap((4, 0))
+        );
+        assert_eq!(
+            stitch(&[a, c], 0).unwrap_err(),
+            StitchError::Overlap((3, 0))
+        );
+    }
+
+    #[test]
+    fn patch_relayout_works() {
+        let patch =
+            Patch::from_vector_function(0, (0..2, 0..3), |(i, j)| [(i * 3 + j) as f64, -1.0]);
+
+        assert_eq!(patch.to_layout(MemoryLayout::RowMajor), *patch.data());
+        assert_eq!(
+            patch.to_layout(MemoryLayout::ColumnMajorPlanar),
+            vec![0.0, 3.0, 1.0, 4.0, 2.0, 5.0, -1.0, -1.0, -1.0, -1.0, -1.0, -1.0]
+        );
+
+        for layout in [
+            MemoryLayout::RowMajor,
+            MemoryLayout::ColumnMajor,
+            MemoryLayout::RowMajorPlanar,
+            MemoryLayout::ColumnMajorPlanar,
+        ] {
+            let data = patch.to_layout(layout);
+            let round_trip = Patch::from_layout(0, (0..2, 0..3), 2, layout, &data);
+            assert_eq!(round_trip.data(), patch.data());
+        }
+    }
+
     #[test]
     fn can_extend_patch() {
         let mut quilt = RectangleMap::new();
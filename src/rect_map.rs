@@ -1,6 +1,6 @@
 use crate::interval_map::IntervalMap;
 use core::iter::FromIterator;
-use core::ops::{Range, RangeBounds};
+use core::ops::{Add, Range, RangeBounds, Sub};
 
 /// Type alias for a 2d range
 pub type Rectangle<T> = (Range<T>, Range<T>);
@@ -8,10 +8,20 @@ pub type Rectangle<T> = (Range<T>, Range<T>);
 /// Type alias for a 2d range, by-reference
 pub type RectangleRef<'a, T> = (&'a Range<T>, &'a Range<T>);
 
+/// Type alias for a key-value pair in a `RectangleMap`, by-reference
+pub type RectangleEntry<'a, T, V> = (RectangleRef<'a, T>, &'a V);
+
+/// Type alias for a 3d range
+pub type Brick<T> = (Range<T>, Range<T>, Range<T>);
+
+/// Type alias for a 3d range, by-reference
+pub type BrickRef<'a, T> = (&'a Range<T>, &'a Range<T>, &'a Range<T>);
+
 /// An associative map where the keys are `Rectangle` objects. Supports point,
 /// rectangle, generic 2d range-based queries to iterate over key-value pairs.
 ///
 #[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct RectangleMap<T: Ord + Copy, V> {
     map: IntervalMap<T, IntervalMap<T, V>>,
 }
@@ -59,13 +69,14 @@ impl<T: Ord + Copy, V> RectangleMap<T, V> {
         self.map.require(di).require(dj)
     }
 
-    pub fn remove(&mut self, key: RectangleRef<T>) {
-        if let Some(m) = self.map.get_mut(key.0) {
-            m.remove(key.1);
-            if m.is_empty() {
-                self.map.remove(key.0)
-            }
+    pub fn remove(&mut self, key: RectangleRef<T>) -> Option<V> {
+        let m = self.map.get_mut(key.0)?;
+        let removed = m.remove(key.1);
+
+        if m.is_empty() {
+            self.map.remove(key.0);
         }
+        removed
     }
 
     pub fn into_balanced(self) -> Self {
@@ -132,6 +143,68 @@ impl<T: Ord + Copy, V> RectangleMap<T, V> {
             .map(move |(di, l)| l.query_range(s.clone()).map(move |(dj, m)| ((di, dj), m)))
             .flatten()
     }
+
+    pub fn query_point_mut(
+        &mut self,
+        point: (T, T),
+    ) -> impl Iterator<Item = (RectangleRef<'_, T>, &mut V)> {
+        self.map
+            .query_point_mut(point.0)
+            .flat_map(move |(di, l)| l.query_point_mut(point.1).map(move |(dj, m)| ((di, dj), m)))
+    }
+
+    pub fn query_rect_mut<I>(
+        &mut self,
+        space: I,
+    ) -> impl Iterator<Item = (RectangleRef<'_, T>, &mut V)>
+    where
+        I: Into<Rectangle<T>>,
+    {
+        let rect = space.into();
+        self.query_bounds_mut(rect.0, rect.1)
+    }
+
+    pub fn query_bounds_mut<R, S>(
+        &mut self,
+        r: R,
+        s: S,
+    ) -> impl Iterator<Item = (RectangleRef<'_, T>, &mut V)>
+    where
+        R: RangeBounds<T> + Clone,
+        S: RangeBounds<T> + Clone,
+    {
+        self.map
+            .query_mut(r)
+            .flat_map(move |(di, l)| l.query_mut(s.clone()).map(move |(dj, m)| ((di, dj), m)))
+    }
+}
+
+impl<T, V> RectangleMap<T, V>
+where
+    T: Ord + Copy + Add<Output = T> + Sub<Output = T>,
+{
+    /// Returns an iterator over all pairs of distinct rectangles `(A, B)`
+    /// which overlap when either one of them is extended by `extension` on
+    /// all sides. Each pair is yielded exactly once, with `A` ordered before
+    /// `B`.
+    pub fn overlapping_pairs(
+        &self,
+        extension: T,
+    ) -> impl Iterator<Item = (RectangleEntry<'_, T, V>, RectangleEntry<'_, T, V>)> {
+        self.iter().flat_map(move |(a, p)| {
+            let extended = (
+                a.0.start - extension..a.0.end + extension,
+                a.1.start - extension..a.1.end + extension,
+            );
+            self.query_rect(extended)
+                .filter(move |(b, _)| rect_key(a) < rect_key(*b))
+                .map(move |(b, q)| ((a, p), (b, q)))
+        })
+    }
+}
+
+fn rect_key<T: Copy>(rect: RectangleRef<T>) -> (T, T, T, T) {
+    (rect.0.start, rect.0.end, rect.1.start, rect.1.end)
 }
 
 // ============================================================================
@@ -163,6 +236,108 @@ impl<T: Ord + Copy, V> FromIterator<(Rectangle<T>, V)> for RectangleMap<T, V> {
     }
 }
 
+/// An associative map where the keys are `Brick` objects: the 3d analog of
+/// `RectangleMap`. Supports point and brick-based queries to iterate over
+/// key-value pairs.
+#[derive(Clone)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct BrickMap<T: Ord + Copy, V> {
+    map: IntervalMap<T, RectangleMap<T, V>>,
+}
+
+impl<T: Ord + Copy, V> BrickMap<T, V> {
+    pub fn new() -> Self {
+        Self {
+            map: IntervalMap::new(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.map.is_empty()
+    }
+
+    pub fn len(&self) -> usize {
+        self.map.iter().map(|(_, m)| m.len()).sum()
+    }
+
+    pub fn contains(&self, key: BrickRef<T>) -> bool {
+        self.get(key).is_some()
+    }
+
+    pub fn get(&self, key: BrickRef<T>) -> Option<&V> {
+        self.map.get(key.0).and_then(|m| m.get((key.1, key.2)))
+    }
+
+    pub fn get_mut(&mut self, key: BrickRef<T>) -> Option<&mut V> {
+        self.map
+            .get_mut(key.0)
+            .and_then(|m| m.get_mut((key.1, key.2)))
+    }
+
+    pub fn insert<I>(&mut self, space: I, value: V) -> &mut V
+    where
+        I: Into<Brick<T>>,
+    {
+        let (di, dj, dk) = space.into();
+        self.map.require(di).insert((dj, dk), value)
+    }
+
+    pub fn remove(&mut self, key: BrickRef<T>) -> Option<V> {
+        let m = self.map.get_mut(key.0)?;
+        let removed = m.remove((key.1, key.2));
+
+        if m.is_empty() {
+            self.map.remove(key.0);
+        }
+        removed
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (BrickRef<'_, T>, &V)> {
+        self.map
+            .iter()
+            .flat_map(|(di, l)| l.iter().map(move |((dj, dk), m)| ((di, dj, dk), m)))
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = BrickRef<'_, T>> {
+        self.iter().map(|(key, _)| key)
+    }
+
+    pub fn query_point(&self, point: (T, T, T)) -> impl Iterator<Item = (BrickRef<'_, T>, &V)> {
+        self.map.query_point(point.0).flat_map(move |(di, l)| {
+            l.query_point((point.1, point.2))
+                .map(move |((dj, dk), m)| ((di, dj, dk), m))
+        })
+    }
+
+    pub fn query_brick<I>(&self, space: I) -> impl Iterator<Item = (BrickRef<'_, T>, &V)>
+    where
+        I: Into<Brick<T>>,
+    {
+        let (di, dj, dk) = space.into();
+        self.map.query_range(di).flat_map(move |(di, l)| {
+            l.query_bounds(dj.clone(), dk.clone())
+                .map(move |((dj, dk), m)| ((di, dj, dk), m))
+        })
+    }
+}
+
+impl<T: Ord + Copy, V> Default for BrickMap<T, V> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: Ord + Copy, V> FromIterator<(Brick<T>, V)> for BrickMap<T, V> {
+    fn from_iter<I: IntoIterator<Item = (Brick<T>, V)>>(iter: I) -> Self {
+        let mut result = Self::new();
+
+        for (brick, item) in iter {
+            result.insert(brick, item);
+        }
+        result
+    }
+}
+
 // The impl's below enable syntactic sugar for iteration, but since the
 // iterators use combinators and closures, the iterator type cannt be written
 // explicitly for the `IntoIter` associated type. The
@@ -200,7 +375,7 @@ impl<T: Ord + Copy, V> FromIterator<(Rectangle<T>, V)> for RectangleMap<T, V> {
 
 #[cfg(test)]
 mod test {
-    use super::RectangleMap;
+    use super::{BrickMap, RectangleMap};
 
     #[test]
     fn can_query_points() {
@@ -215,4 +390,64 @@ mod test {
         assert_eq!(rect_map.query_point((2, 2)).count(), 1);
         assert_eq!(rect_map.query_point((12, 12)).count(), 1);
     }
+
+    #[test]
+    fn can_mutate_query_results() {
+        let mut rect_map = RectangleMap::new();
+
+        rect_map.insert((0..10, 0..10), 1);
+        rect_map.insert((20..30, 20..30), 2);
+        rect_map.insert((9..21, 9..21), 3);
+
+        for (_, value) in rect_map.query_point_mut((9, 9)) {
+            *value *= 10
+        }
+        for (_, value) in rect_map.query_rect_mut((15..25, 15..25)) {
+            *value += 1
+        }
+        assert_eq!(rect_map.get((&(0..10), &(0..10))), Some(&10));
+        assert_eq!(rect_map.get((&(20..30), &(20..30))), Some(&3));
+        assert_eq!(rect_map.get((&(9..21), &(9..21))), Some(&31));
+    }
+
+    #[test]
+    fn overlapping_pairs_are_yielded_once() {
+        let mut rect_map = RectangleMap::new();
+
+        for i in 0..3 {
+            for j in 0..3 {
+                rect_map.insert((i * 10..(i + 1) * 10, j * 10..(j + 1) * 10), (i, j));
+            }
+        }
+        assert_eq!(rect_map.overlapping_pairs(0).count(), 0);
+        assert_eq!(rect_map.overlapping_pairs(1).count(), 12 + 8);
+
+        let mut pairs: Vec<_> = rect_map
+            .overlapping_pairs(1)
+            .map(|((_, &a), (_, &b))| (a, b))
+            .collect();
+        pairs.sort_unstable();
+        pairs.dedup();
+        assert_eq!(pairs.len(), 20);
+        assert!(pairs.contains(&((0, 0), (1, 1))));
+        assert!(!pairs.contains(&((1, 1), (0, 0))));
+    }
+
+    #[test]
+    fn can_query_bricks() {
+        let mut brick_map = BrickMap::new();
+
+        brick_map.insert((0..10, 0..10, 0..10), 1);
+        brick_map.insert((0..10, 0..10, 10..20), 2);
+        brick_map.insert((10..20, 0..10, 0..10), 3);
+
+        assert_eq!(brick_map.len(), 3);
+        assert_eq!(brick_map.query_point((5, 5, 5)).count(), 1);
+        assert_eq!(brick_map.query_point((5, 5, 25)).count(), 0);
+        assert_eq!(brick_map.query_brick((9..11, 5..6, 9..11)).count(), 3);
+        assert_eq!(brick_map.query_brick((11..12, 5..6, 11..12)).count(), 0);
+        assert_eq!(brick_map.remove((&(0..10), &(0..10), &(10..20))), Some(2));
+        assert!(!brick_map.contains((&(0..10), &(0..10), &(10..20))));
+        assert_eq!(brick_map.len(), 2);
+    }
 }
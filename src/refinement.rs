@@ -0,0 +1,169 @@
+//! Criteria for flagging zones that need refinement.
+//!
+//! The flag sets produced by [`flag_zones`] are consumed by the clustering
+//! algorithm [`crate::meshing::berger_rigoutsos`] to generate refined
+//! patches.
+
+use crate::index_space::Axis;
+use crate::patch::Patch;
+use std::collections::BTreeMap;
+
+/// A rule which decides whether a zone of a patch needs refinement. Criteria
+/// that use a stencil read the patch's neighboring zones, and ignore the
+/// neighbors which fall outside the patch. Patches are best given with filled
+/// guard zones, so that flags near patch boundaries see the neighboring data.
+pub trait RefinementCriterion {
+    /// Returns true if the zone at the given index (measured at the patch's
+    /// level) should be refined.
+    fn flag(&self, patch: &Patch, index: (i64, i64)) -> bool;
+}
+
+/// Any closure taking a patch and an index can be used as a criterion.
+impl<F> RefinementCriterion for F
+where
+    F: Fn(&Patch, (i64, i64)) -> bool,
+{
+    fn flag(&self, patch: &Patch, index: (i64, i64)) -> bool {
+        self(patch, index)
+    }
+}
+
+/// Flags zones where the difference of a field with any adjacent zone exceeds
+/// a threshold. If `relative` is true, the difference is divided by the
+/// magnitude of the field at the zone.
+#[derive(Clone, Copy, Debug)]
+pub struct GradientThreshold {
+    pub field: usize,
+    pub threshold: f64,
+    pub relative: bool,
+}
+
+impl RefinementCriterion for GradientThreshold {
+    fn flag(&self, patch: &Patch, index: (i64, i64)) -> bool {
+        [Axis::I, Axis::J].iter().any(|&axis| {
+            let (l, c, r) = stencil(patch, index, axis, self.field);
+            let d = l
+                .into_iter()
+                .chain(r)
+                .fold(0.0, |d: f64, n| d.max((n - c).abs()));
+
+            if self.relative {
+                d > self.threshold * c.abs()
+            } else {
+                d > self.threshold
+            }
+        })
+    }
+}
+
+/// Flags zones using the normalized second-derivative error estimator of
+/// Löhner (1987), as used in e.g. the FLASH and PLUTO codes. The `filter`
+/// parameter (typically around 0.01) suppresses flagging of small ripples.
+/// An axis is only considered if both neighbors are inside the patch.
+#[derive(Clone, Copy, Debug)]
+pub struct SecondDerivative {
+    pub field: usize,
+    pub threshold: f64,
+    pub filter: f64,
+}
+
+impl RefinementCriterion for SecondDerivative {
+    fn flag(&self, patch: &Patch, index: (i64, i64)) -> bool {
+        [Axis::I, Axis::J].iter().any(|&axis| {
+            let (l, c, r) = match stencil(patch, index, axis, self.field) {
+                (Some(l), c, Some(r)) => (l, c, r),
+                _ => return false,
+            };
+            let numerator = (r - 2.0 * c + l).abs();
+            let denominator =
+                (r - c).abs() + (c - l).abs() + self.filter * (r.abs() + 2.0 * c.abs() + l.abs());
+
+            denominator > 0.0 && numerator > self.threshold * denominator
+        })
+    }
+}
+
+/// Evaluates a refinement criterion over the valid zones of each patch, and
+/// returns the flagged indexes grouped by the patches' levels. Indexes are
+/// measured at their patch's level.
+pub fn flag_zones<C>(patches: &[Patch], criterion: &C) -> BTreeMap<u32, Vec<(i64, i64)>>
+where
+    C: RefinementCriterion,
+{
+    let mut flags: BTreeMap<u32, Vec<(i64, i64)>> = BTreeMap::new();
+
+    for patch in patches {
+        let flagged = patch
+            .valid_index_space()
+            .iter()
+            .filter(|&index| criterion.flag(patch, index))
+            .collect::<Vec<_>>();
+
+        if !flagged.is_empty() {
+            flags.entry(patch.level()).or_default().extend(flagged)
+        }
+    }
+    flags
+}
+
+fn stencil(
+    patch: &Patch,
+    index: (i64, i64),
+    axis: Axis,
+    field: usize,
+) -> (Option<f64>, f64, Option<f64>) {
+    let space = patch.index_space();
+    let (i, j) = index;
+    let (l, r) = match axis {
+        Axis::I => ((i - 1, j), (i + 1, j)),
+        Axis::J => ((i, j - 1), (i, j + 1)),
+    };
+    let value = |n| {
+        Some(n)
+            .filter(|&n| space.contains(n))
+            .map(|n| patch.get_slice(n)[field])
+    };
+    (value(l), patch.get_slice(index)[field], value(r))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::index_space::IndexSpace;
+
+    fn step() -> Patch {
+        Patch::from_scalar_function(0, (0..8, 0..8), |(i, _)| if i < 4 { 1.0 } else { 0.1 })
+    }
+
+    #[test]
+    fn gradient_threshold_flags_discontinuity() {
+        let criterion = GradientThreshold {
+            field: 0,
+            threshold: 0.1,
+            relative: false,
+        };
+        let flags = flag_zones(&[step()], &criterion);
+        assert_eq!(flags.len(), 1);
+        assert!(flags[&0].iter().all(|&(i, _)| i == 3 || i == 4));
+        assert_eq!(flags[&0].len(), 16);
+    }
+
+    #[test]
+    fn second_derivative_ignores_linear_data() {
+        let criterion = SecondDerivative {
+            field: 0,
+            threshold: 0.5,
+            filter: 0.01,
+        };
+        let ramp = Patch::from_scalar_function(0, (0..8, 0..8), |(i, j)| (i + 2 * j) as f64);
+        assert!(flag_zones(&[ramp], &criterion).is_empty());
+        assert_eq!(flag_zones(&[step()], &criterion)[&0].len(), 16);
+    }
+
+    #[test]
+    fn closure_criterion_works() {
+        let region = IndexSpace::new(2..4, 2..4);
+        let flags = flag_zones(&[step()], &|_: &Patch, index| region.contains(index));
+        assert_eq!(flags[&0].len(), 4);
+    }
+}
@@ -0,0 +1,231 @@
+//! Local (per-level) time stepping, or subcycling.
+//!
+//! A patch on level `l` is stable with a time step as many times as long as
+//! patches on level 0 (the finest) as its zones are wide, which is the
+//! product of the refinement ratios below it (`2^l` with ratios of two). The
+//! executors in [`crate::automaton`] still process the whole task group at
+//! every _tick_, which is one step of the finest level; a task on a coarser
+//! level only does work on the ticks where [`Schedule::is_due`] is true, and
+//! otherwise passes through unchanged. Every task keeps sending its messages
+//! at every tick, so the number of messages a task waits for is the same
+//! from one tick to the next.
+//!
+//! Finer patches need guard zone data from coarser neighbors at times in
+//! between the coarse steps. Tasks send their neighbors a [`TimeBracket`],
+//! holding their data from before and after their latest step, which the
+//! recipient interpolates linearly to its own time. Finer data sent to a
+//! coarser patch is always at the coarse patch's time, since both levels
+//! are synchronized on the ticks where the coarse patch is due.
+//!
+//! The coarse fluxes through coarse-fine interfaces are not corrected here;
+//! to keep the scheme conservative, see [`crate::flux_register`].
+
+use crate::index_space::IndexSpace;
+use crate::meshing::LevelHierarchy;
+use crate::patch::Patch;
+
+/// The order in which the patches on each level are stepped, through one
+/// cycle of ticks which advances every level by one step of the coarsest
+/// (base) level.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Schedule {
+    base_level: u32,
+    ratios: Vec<u32>,
+}
+
+impl Schedule {
+    /// Creates a schedule for levels `0` through `base_level`, with every
+    /// level refined by a factor of two.
+    pub fn new(base_level: u32) -> Self {
+        Self {
+            base_level,
+            ratios: Vec::new(),
+        }
+    }
+
+    /// Creates a schedule for the levels of the given hierarchy, from level
+    /// 0 through its base level, with the hierarchy's refinement ratios (see
+    /// [`LevelHierarchy::with_ratios`]). An empty hierarchy has only level 0.
+    pub fn for_hierarchy(hierarchy: &LevelHierarchy) -> Self {
+        Self {
+            base_level: hierarchy.base_level().unwrap_or(0),
+            ratios: hierarchy.ratios().to_vec(),
+        }
+    }
+
+    /// Returns the coarsest level in the schedule.
+    pub fn base_level(&self) -> u32 {
+        self.base_level
+    }
+
+    /// Returns the number of ticks in one cycle, which is the number of steps
+    /// taken on level 0 for each step on the base level.
+    pub fn ticks_per_cycle(&self) -> u64 {
+        self.ticks_per_step(self.base_level)
+    }
+
+    /// Returns the number of ticks spanned by one step on the given level,
+    /// which is the product of the refinement ratios between it and level 0.
+    pub fn ticks_per_step(&self, level: u32) -> u64 {
+        (0..level)
+            .map(|l| self.ratios.get(l as usize).map_or(2, |&r| r as u64))
+            .product()
+    }
+
+    /// Returns true if patches on the given level take a step at the given
+    /// tick. Ticks may be counted from the start of the run, or from the
+    /// start of the cycle.
+    pub fn is_due(&self, level: u32, tick: u64) -> bool {
+        tick.is_multiple_of(self.ticks_per_step(level))
+    }
+
+    /// Returns the size of a step on the given level, if one tick has the
+    /// given size.
+    pub fn step_size(&self, level: u32, tick_size: f64) -> f64 {
+        tick_size * self.ticks_per_step(level) as f64
+    }
+}
+
+/// A patch's data at the start and at the end of its latest step. The two
+/// patches must have the same level, index space, and number of fields.
+#[derive(Clone, Debug)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+pub struct TimeBracket {
+    earlier: (f64, Patch),
+    later: (f64, Patch),
+}
+
+impl TimeBracket {
+    /// Creates a bracket from the data at two times. This function panics if
+    /// the patches do not have the same layout, or if the times are out of
+    /// order.
+    pub fn new(earlier: (f64, Patch), later: (f64, Patch)) -> Self {
+        assert! {
+            earlier.1.level() == later.1.level() &&
+            earlier.1.index_space() == later.1.index_space() &&
+            earlier.1.num_fields() == later.1.num_fields(),
+            "bracketing patches must have the same layout"
+        };
+        assert!(earlier.0 <= later.0, "bracketing times are out of order");
+        Self { earlier, later }
+    }
+
+    /// Creates a bracket with the data at a single time, for example before
+    /// the first step.
+    pub fn at(time: f64, patch: Patch) -> Self {
+        Self {
+            earlier: (time, patch.clone()),
+            later: (time, patch),
+        }
+    }
+
+    /// Returns the time at the start of the step.
+    pub fn start_time(&self) -> f64 {
+        self.earlier.0
+    }
+
+    /// Returns the time at the end of the step.
+    pub fn end_time(&self) -> f64 {
+        self.later.0
+    }
+
+    /// Returns the level of the bracketing patches.
+    pub fn level(&self) -> u32 {
+        self.later.1.level()
+    }
+
+    /// Returns a bracket of the given subset of these patches.
+    pub fn extract(&self, subset: IndexSpace) -> Self {
+        Self {
+            earlier: (self.earlier.0, self.earlier.1.extract(subset.clone())),
+            later: (self.later.0, self.later.1.extract(subset)),
+        }
+    }
+
+    /// Applies the same function (for example a resampling) to both
+    /// patches.
+    pub fn map<F: Fn(&Patch) -> Patch>(&self, f: F) -> Self {
+        Self::new(
+            (self.earlier.0, f(&self.earlier.1)),
+            (self.later.0, f(&self.later.1)),
+        )
+    }
+
+    /// Returns the data linearly interpolated to the given time. Times
+    /// outside the bracket are clamped to it, so that round-off in the
+    /// recipient's time does not lead to extrapolation.
+    pub fn interpolate(&self, time: f64) -> Patch {
+        let (t0, p0) = &self.earlier;
+        let (t1, p1) = &self.later;
+
+        if t1 <= t0 || time >= *t1 {
+            return p1.clone();
+        }
+        if time <= *t0 {
+            return p0.clone();
+        }
+        let w = (time - t0) / (t1 - t0);
+        let mut result = p0.clone();
+
+        for (a, b) in result.data_mut().iter_mut().zip(p1.data()) {
+            *a += w * (b - *a)
+        }
+        result
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn schedule_steps_coarse_levels_less_often() {
+        let schedule = Schedule::new(2);
+        assert_eq!(schedule.ticks_per_cycle(), 4);
+
+        let due: Vec<Vec<u32>> = (0..4)
+            .map(|tick| (0..=2).filter(|&l| schedule.is_due(l, tick)).collect())
+            .collect();
+        assert_eq!(due, vec![vec![0, 1, 2], vec![0], vec![0, 1], vec![0]]);
+        assert_eq!(schedule.step_size(2, 0.25), 1.0);
+        assert!(Schedule::new(0).is_due(0, 7));
+    }
+
+    #[test]
+    fn schedule_follows_the_hierarchy_ratios() {
+        let base = Patch::zeros(2, 1, (0..4, 0..4));
+        let hierarchy = LevelHierarchy::from_base(vec![base]).with_ratios(vec![2, 2]);
+        let schedule = Schedule::for_hierarchy(&hierarchy);
+
+        assert_eq!(schedule.base_level(), 2);
+        assert_eq!(schedule.ticks_per_step(1), hierarchy.scale(1) as u64);
+        assert_eq!(schedule.ticks_per_cycle(), hierarchy.scale(2) as u64);
+        assert_eq!(
+            Schedule::for_hierarchy(&LevelHierarchy::new()).ticks_per_cycle(),
+            1
+        );
+    }
+
+    #[test]
+    fn time_bracket_interpolates_linearly() {
+        let space = IndexSpace::new(0..2, 0..3);
+        let p0 = Patch::from_scalar_function(1, space.clone(), |_| 1.0);
+        let p1 = Patch::from_scalar_function(1, space.clone(), |(i, j)| (i + j) as f64);
+        let bracket = TimeBracket::new((2.0, p0), (4.0, p1));
+
+        let p = bracket.interpolate(3.0);
+        assert_eq!(p.sample(1, (1, 2), 0), 2.0);
+        assert_eq!(bracket.interpolate(1.0).sample(1, (1, 2), 0), 1.0);
+        assert_eq!(bracket.interpolate(5.0).sample(1, (1, 2), 0), 3.0);
+
+        let sub = bracket.extract(IndexSpace::new(1..2, 0..3));
+        assert_eq!(sub.interpolate(3.5).sample(1, (1, 0), 0), 1.0);
+
+        let fine = bracket.map(|p| p.sample_at(0, space.refine_by(2)));
+        assert_eq!(fine.level(), 0);
+        assert_eq!(fine.interpolate(3.0).sample(0, (3, 5), 0), 2.0);
+
+        let single = TimeBracket::at(2.0, Patch::from_scalar_function(1, space, |_| 5.0));
+        assert_eq!(single.interpolate(10.0).sample(1, (0, 0), 0), 5.0);
+    }
+}
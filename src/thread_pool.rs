@@ -1,24 +1,523 @@
+use crate::index_space::IndexSpace;
+use std::any::Any;
 use std::cell;
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::marker::PhantomData;
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
+use std::time::Instant;
 
 type Job = Box<dyn FnOnce() + Send + 'static>;
 
-#[cfg(feature = "crossbeam_channel")]
-type JobSender = crossbeam_channel::Sender<Job>;
+/// A panic caught in a job run by a [`ThreadPool`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct JobPanic {
+    /// The index of the worker which ran the job.
+    pub worker_id: usize,
 
-#[cfg(not(feature = "crossbeam_channel"))]
-type JobSender = std::sync::mpsc::Sender<Job>;
+    /// The panic message, if the payload was a string.
+    pub message: String,
+}
+
+impl fmt::Display for JobPanic {
+    fn fmt(&self, fmt: &mut fmt::Formatter<'_>) -> Result<(), fmt::Error> {
+        write!(
+            fmt,
+            "a job panicked on worker {}: {}",
+            self.worker_id, self.message
+        )
+    }
+}
+
+/// Returns the message of a panic payload, as caught by
+/// [`std::panic::catch_unwind`], if it is a string.
+pub fn panic_message(payload: &(dyn Any + Send)) -> String {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message.to_string()
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message.clone()
+    } else {
+        "(non-string panic payload)".to_string()
+    }
+}
+
+/// The priority class of a job. A worker runs every job of a higher class,
+/// from its own queue or stolen from another worker, before any job of a
+/// lower class. Jobs of the same class run in the order they were spawned
+/// onto a worker's queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl Priority {
+    /// The number of priority classes.
+    pub const COUNT: usize = 3;
+}
+
+/// Statistics of one worker of a [`ThreadPool`], since the pool was
+/// created, returned by [`ThreadPool::stats`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct WorkerStats {
+    /// The number of jobs this worker has run, including stolen ones.
+    pub jobs_executed: u64,
+
+    /// The number of jobs this worker took from another worker's queue.
+    pub jobs_stolen: u64,
+
+    /// The number of jobs waiting in this worker's queue.
+    pub queue_depth: usize,
+
+    /// The time this worker has spent running jobs, in seconds.
+    pub busy_seconds: f64,
+
+    /// The fraction of the pool's lifetime this worker has spent running
+    /// jobs.
+    pub busy_fraction: f64,
+}
+
+/// How the workers of a [`ThreadPool`] are pinned to cores, with the
+/// `core_affinity` feature. Without it the workers are not pinned, and are
+/// not associated with NUMA nodes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Placement {
+    /// The workers are pinned to the first cores, in the order the system
+    /// lists them.
+    #[default]
+    Compact,
+
+    /// The workers are spread over the NUMA nodes: successive workers are
+    /// pinned to cores of successive nodes, so worker `n` is on node `n %
+    /// num_nodes` while every node has cores left. Workers steal from others
+    /// on their own node before those on other nodes.
+    Numa,
+}
+
+/// Returns the CPUs of each NUMA node, as listed by Linux in
+/// `/sys/devices/system/node`. It is empty if the topology is not
+/// available.
+pub fn numa_nodes() -> Vec<Vec<usize>> {
+    let mut nodes = Vec::new();
 
-struct Worker {
-    handle: Option<thread::JoinHandle<()>>,
-    sender: Option<JobSender>,
+    while let Ok(list) = std::fs::read_to_string(format!(
+        "/sys/devices/system/node/node{}/cpulist",
+        nodes.len()
+    )) {
+        nodes.push(parse_cpu_list(&list))
+    }
+    nodes
+}
+
+/// Parses a Linux CPU list, such as `0-3,8-11`, which is also the format
+/// of [`CoreMask::cores`] on the command line.
+pub fn parse_cpu_list(list: &str) -> Vec<usize> {
+    list.trim()
+        .split(',')
+        .filter(|range| !range.is_empty())
+        .flat_map(|range| {
+            let mut ends = range
+                .split('-')
+                .map(|n| n.trim().parse::<usize>().unwrap_or(0));
+            let start = ends.next().unwrap_or(0);
+            let end = ends.next().unwrap_or(start);
+            start..=end
+        })
+        .collect()
 }
 
-/// A minimal thread pool implementation with core affinity. No effort is made
-/// to schedule jobs intelligently, it just goes round-robin. Jobs must be
-/// `'static`.
+/// Restricts the cores the workers of a [`ThreadPool`] may be pinned to,
+/// with the `core_affinity` feature, which otherwise uses every core the
+/// system lists. The [`Placement`] then chooses among the remaining cores.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CoreMask {
+    /// If given, only these core ids are used, in this order. Ids the
+    /// system does not list are ignored.
+    pub cores: Option<Vec<usize>>,
+
+    /// Use only the first hardware thread of each physical core, leaving
+    /// its hyperthread siblings idle.
+    pub exclude_siblings: bool,
+
+    /// Leave the first this many of the remaining cores unused, for example
+    /// for the communicator's threads.
+    pub reserved: usize,
+}
+
+impl CoreMask {
+    /// Returns the ids of the cores to use among those available, given the
+    /// hyperthread siblings of a core (including itself).
+    #[cfg_attr(not(feature = "core_affinity"), allow(dead_code))]
+    fn select<S: Fn(usize) -> Vec<usize>>(&self, available: &[usize], siblings: S) -> Vec<usize> {
+        let mut selected: Vec<_> = match &self.cores {
+            Some(cores) => cores
+                .iter()
+                .copied()
+                .filter(|id| available.contains(id))
+                .collect(),
+            None => available.to_vec(),
+        };
+        if self.exclude_siblings {
+            let mut kept = Vec::new();
+
+            for id in selected {
+                if !siblings(id).iter().any(|other| kept.contains(other)) {
+                    kept.push(id)
+                }
+            }
+            selected = kept;
+        }
+        selected.into_iter().skip(self.reserved).collect()
+    }
+}
+
+/// Returns the hyperthread siblings of a CPU (including itself), as listed
+/// by Linux in `/sys/devices/system/cpu`, or just the CPU if they are not
+/// available.
+#[cfg_attr(not(feature = "core_affinity"), allow(dead_code))]
+fn thread_siblings(id: usize) -> Vec<usize> {
+    std::fs::read_to_string(format!(
+        "/sys/devices/system/cpu/cpu{}/topology/thread_siblings_list",
+        id
+    ))
+    .map(|list| parse_cpu_list(&list))
+    .unwrap_or_else(|_| vec![id])
+}
+
+/// Chooses the cores of the workers, given the ids of the available cores
+/// and the CPUs of each NUMA node, and returns the position in `cores` of
+/// each worker's core, and its node if known.
+#[cfg_attr(not(feature = "core_affinity"), allow(dead_code))]
+fn place_workers(
+    cores: &[usize],
+    nodes: &[Vec<usize>],
+    num_threads: usize,
+    placement: Placement,
+) -> Vec<(usize, Option<usize>)> {
+    let node_of = |id: usize| nodes.iter().position(|cpus| cpus.contains(&id));
+
+    match placement {
+        Placement::Compact => (0..cores.len().min(num_threads))
+            .map(|n| (n, node_of(cores[n])))
+            .collect(),
+        Placement::Numa => {
+            let mut groups: Vec<(Option<usize>, VecDeque<usize>)> = Vec::new();
+
+            for (n, &id) in cores.iter().enumerate() {
+                let node = node_of(id);
+
+                match groups.iter_mut().find(|(group, _)| *group == node) {
+                    Some((_, members)) => members.push_back(n),
+                    None => groups.push((node, VecDeque::from(vec![n]))),
+                }
+            }
+            let mut placed = Vec::new();
+
+            while placed.len() < num_threads.min(cores.len()) {
+                for (node, members) in &mut groups {
+                    if let (true, Some(n)) = (placed.len() < num_threads, members.pop_front()) {
+                        placed.push((n, *node))
+                    }
+                }
+            }
+            placed
+        }
+    }
+}
+
+#[cfg(feature = "core_affinity")]
+type Core = core_affinity::CoreId;
+
+#[cfg(not(feature = "core_affinity"))]
+type Core = ();
+
+/// The counters behind [`WorkerStats`], updated by each worker.
+#[derive(Default)]
+struct Counters {
+    jobs_executed: AtomicU64,
+    jobs_stolen: AtomicU64,
+    busy_nanos: AtomicU64,
+}
+
+/// The job queues of the workers, one for each priority class, and the
+/// count of jobs not yet taken from them, shared by the pool and its worker
+/// threads. Each worker also has a queue of pinned jobs, which no other
+/// worker takes. Workers wait on the condition variable while no jobs they
+/// could take are pending.
+struct Shared {
+    queues: Vec<Mutex<[VecDeque<Job>; Priority::COUNT]>>,
+    pinned: Vec<Mutex<VecDeque<Job>>>,
+    nodes: Vec<Option<usize>>,
+    steal_order: Vec<Vec<usize>>,
+    work_stealing: bool,
+    state: Mutex<State>,
+    wakeup: Condvar,
+    idle: Condvar,
+    panics: Mutex<Vec<JobPanic>>,
+    counters: Vec<Counters>,
+    created: Instant,
+}
+
+struct State {
+    pending: Vec<usize>,
+    pinned: Vec<usize>,
+    unfinished: usize,
+    shutdown: bool,
+}
+
+impl Shared {
+    fn push(&self, worker_id: usize, priority: Priority, job: Job) {
+        if self.queues.is_empty() {
+            return self.run_inline(job);
+        }
+        // The job is counted before it is published, so that a worker which
+        // takes it never finds the count at zero.
+        {
+            let mut state = self.state.lock().unwrap();
+            state.unfinished += 1;
+            state.pending[worker_id] += 1;
+        }
+        self.queues[worker_id].lock().unwrap()[priority as usize].push_back(job);
+        // Any worker can take the job if they steal; otherwise only its
+        // owner can, and a single wakeup might go to another worker.
+        if self.work_stealing {
+            self.wakeup.notify_one();
+        } else {
+            self.wakeup.notify_all();
+        }
+    }
+
+    /// Runs a job on the calling thread, for a pool with no workers. A panic
+    /// is recorded as if worker zero had run the job.
+    fn run_inline(&self, job: Job) {
+        if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+            self.panics.lock().unwrap().push(JobPanic {
+                worker_id: 0,
+                message: panic_message(payload.as_ref()),
+            })
+        }
+    }
+
+    fn push_pinned(&self, worker_id: usize, job: Job) {
+        {
+            let mut state = self.state.lock().unwrap();
+            state.unfinished += 1;
+            state.pinned[worker_id] += 1;
+        }
+        self.pinned[worker_id].lock().unwrap().push_back(job);
+        self.wakeup.notify_all();
+    }
+
+    /// Takes the oldest job pinned to the given worker, or else the oldest
+    /// job of the highest priority class which has one from the worker's
+    /// own queue, or else steals the newest job of that class from the
+    /// first other worker in its steal order which has one. Returns the job
+    /// and whether it was stolen.
+    fn take(&self, worker_id: usize) -> Option<(Job, bool)> {
+        if let Some(job) = self.pinned[worker_id].lock().unwrap().pop_front() {
+            self.state.lock().unwrap().pinned[worker_id] -= 1;
+            return Some((job, false));
+        }
+        let job = (0..Priority::COUNT).find_map(|class| {
+            let own = self.queues[worker_id].lock().unwrap()[class].pop_front();
+            own.map(|job| (job, worker_id)).or_else(|| {
+                self.steal_order[worker_id].iter().find_map(|&k| {
+                    self.queues[k].lock().unwrap()[class]
+                        .pop_back()
+                        .map(|job| (job, k))
+                })
+            })
+        });
+        let (job, owner) = job?;
+        self.state.lock().unwrap().pending[owner] -= 1;
+        Some((job, owner != worker_id))
+    }
+
+    /// Returns whether the given worker has a job it could take: one pinned
+    /// to it, or one in its own queue or that of a worker it steals from.
+    fn has_work(&self, state: &State, worker_id: usize) -> bool {
+        state.pinned[worker_id] > 0
+            || state.pending[worker_id] > 0
+            || self.steal_order[worker_id]
+                .iter()
+                .any(|&k| state.pending[k] > 0)
+    }
+
+    /// Runs jobs until the pool is shut down and no jobs are left. A job
+    /// which panics is recorded, and the worker carries on.
+    fn run(&self, worker_id: usize) {
+        loop {
+            if let Some((job, stolen)) = self.take(worker_id) {
+                #[cfg(feature = "tracing")]
+                let _span = tracing::trace_span!("job", worker = worker_id, stolen).entered();
+                let start = Instant::now();
+                let result = panic::catch_unwind(AssertUnwindSafe(job));
+                let counters = &self.counters[worker_id];
+                counters
+                    .busy_nanos
+                    .fetch_add(start.elapsed().as_nanos() as u64, Ordering::Relaxed);
+                counters.jobs_executed.fetch_add(1, Ordering::Relaxed);
+                counters
+                    .jobs_stolen
+                    .fetch_add(stolen as u64, Ordering::Relaxed);
+
+                if let Err(payload) = result {
+                    self.panics.lock().unwrap().push(JobPanic {
+                        worker_id,
+                        message: panic_message(payload.as_ref()),
+                    })
+                }
+                let mut state = self.state.lock().unwrap();
+                state.unfinished -= 1;
+
+                if state.unfinished == 0 {
+                    self.idle.notify_all();
+                }
+                continue;
+            }
+            let mut state = self.state.lock().unwrap();
+
+            while !self.has_work(&state, worker_id) && !state.shutdown {
+                state = self.wakeup.wait(state).unwrap();
+            }
+            if !self.has_work(&state, worker_id) {
+                return;
+            }
+        }
+    }
+}
+
+/// A function called on each worker thread when it starts or stops, with
+/// the worker's id.
+type WorkerHook = Arc<dyn Fn(usize) + Send + Sync>;
+
+/// Configures and creates a [`ThreadPool`]. By default the worker threads
+/// are named `gridiron-worker-N`, have the standard library's stack size,
+/// and steal jobs from each other. With the `tracing` feature each job runs
+/// in a `job` span recording its worker and whether it was stolen.
+pub struct ThreadPoolBuilder {
+    num_threads: usize,
+    placement: Placement,
+    core_mask: CoreMask,
+    thread_name_prefix: String,
+    stack_size: Option<usize>,
+    work_stealing: bool,
+    start_hook: Option<WorkerHook>,
+    stop_hook: Option<WorkerHook>,
+}
+
+impl ThreadPoolBuilder {
+    /// Starts configuring a pool of at most the given number of threads, as
+    /// for [`ThreadPool::new`].
+    pub fn new(num_threads: usize) -> Self {
+        Self {
+            num_threads,
+            placement: Placement::default(),
+            core_mask: CoreMask::default(),
+            thread_name_prefix: "gridiron-worker".to_string(),
+            stack_size: None,
+            work_stealing: true,
+            start_hook: None,
+            stop_hook: None,
+        }
+    }
+
+    /// Places the workers on the cores as given.
+    pub fn placement(mut self, placement: Placement) -> Self {
+        self.placement = placement;
+        self
+    }
+
+    /// Restricts the cores the workers may be pinned to. If fewer cores
+    /// remain than the requested number of threads, the pool has one worker
+    /// per remaining core (and one which is not pinned if none remain,
+    /// unless zero threads were requested).
+    pub fn core_mask(mut self, core_mask: CoreMask) -> Self {
+        self.core_mask = core_mask;
+        self
+    }
+
+    /// Names the worker threads `<prefix>-N`.
+    pub fn thread_name_prefix(mut self, prefix: &str) -> Self {
+        self.thread_name_prefix = prefix.to_string();
+        self
+    }
+
+    /// Gives each worker thread a stack of the given size in bytes, for
+    /// jobs which recurse deeply.
+    pub fn stack_size(mut self, bytes: usize) -> Self {
+        self.stack_size = Some(bytes);
+        self
+    }
+
+    /// Sets whether idle workers steal jobs from the others. Without
+    /// stealing, each worker runs only the jobs assigned to it, as a pool
+    /// with a channel per worker would; this keeps a task on the worker its
+    /// [`crate::automaton::Automaton::worker_hint`] names, at the cost of
+    /// idle cores when the load is uneven.
+    pub fn work_stealing(mut self, work_stealing: bool) -> Self {
+        self.work_stealing = work_stealing;
+        self
+    }
+
+    /// Calls the given function on each worker thread when it starts, after
+    /// it is pinned and before it runs any jobs, for example to register the
+    /// thread with a profiler.
+    pub fn start_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.start_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Calls the given function on each worker thread when it stops, after
+    /// it has run its last job, as the pool is dropped.
+    pub fn stop_hook<F>(mut self, hook: F) -> Self
+    where
+        F: Fn(usize) + Send + Sync + 'static,
+    {
+        self.stop_hook = Some(Arc::new(hook));
+        self
+    }
+
+    /// Creates the pool and starts its workers.
+    pub fn build(self) -> ThreadPool {
+        ThreadPool::build(self)
+    }
+}
+
+/// The number of jobs spawned in a [`Scope`] which have not yet completed,
+/// and the message of the first of them to panic.
+struct Remaining {
+    count: Mutex<usize>,
+    done: Condvar,
+    panicked: Mutex<Option<String>>,
+}
+
+/// A minimal thread pool implementation with core affinity and work
+/// stealing. Each worker has its own queue of jobs, which go round-robin
+/// unless a worker is requested; a worker whose queue is empty steals jobs
+/// from the others, so the assignment is a preference and no core sits idle
+/// while jobs are waiting. Jobs may be given a [`Priority`], and are
+/// otherwise of normal priority. Jobs spawned directly must be `'static`; jobs
+/// spawned in a [`ThreadPool::scope`] may borrow from the caller. Dropping
+/// the pool waits for all the jobs spawned into it to complete.
+///
+/// A job which panics does not take its worker down: the panic is caught,
+/// and is available from [`ThreadPool::take_panics`]. Panics in scoped jobs
+/// are instead propagated by [`ThreadPool::scope`], and the executors in
+/// [`crate::automaton`] resume a task's panic on the thread collecting the
+/// results.
 pub struct ThreadPool {
-    workers: Vec<Worker>,
+    shared: Arc<Shared>,
+    handles: Vec<thread::JoinHandle<()>>,
+    requested_threads: usize,
     current_worker_id: cell::Cell<usize>,
 }
 
@@ -27,15 +526,120 @@ impl ThreadPool {
     /// the system has fewer physical CPU cores than the requested number of
     /// threads, then the number of cores is unsed instead.
     pub fn new(num_threads: usize) -> Self {
+        Self::with_placement(num_threads, Placement::Compact)
+    }
+
+    /// Starts configuring a thread pool of at most the given number of
+    /// threads; see [`ThreadPoolBuilder`].
+    pub fn builder(num_threads: usize) -> ThreadPoolBuilder {
+        ThreadPoolBuilder::new(num_threads)
+    }
+
+    /// Creates a new thread pool as [`ThreadPool::new`] does, with the
+    /// workers placed on the cores as given.
+    pub fn with_placement(num_threads: usize, placement: Placement) -> Self {
+        ThreadPoolBuilder::new(num_threads)
+            .placement(placement)
+            .build()
+    }
+
+    fn build(builder: ThreadPoolBuilder) -> Self {
+        let cores = Self::place(builder.num_threads, builder.placement, &builder.core_mask);
+        let num_threads = cores.len();
+        let nodes: Vec<_> = cores.iter().map(|(_, node)| *node).collect();
+        let steal_order = (0..num_threads)
+            .map(|w| {
+                if !builder.work_stealing {
+                    return Vec::new();
+                }
+                let mut others: Vec<_> = (1..num_threads).map(|k| (w + k) % num_threads).collect();
+                others.sort_by_key(|&k| nodes[k] != nodes[w]);
+                others
+            })
+            .collect();
+        let shared = Arc::new(Shared {
+            queues: (0..num_threads)
+                .map(|_| Mutex::new(Default::default()))
+                .collect(),
+            pinned: (0..num_threads)
+                .map(|_| Mutex::new(VecDeque::new()))
+                .collect(),
+            nodes,
+            steal_order,
+            work_stealing: builder.work_stealing,
+            state: Mutex::new(State {
+                pending: vec![0; num_threads],
+                pinned: vec![0; num_threads],
+                unfinished: 0,
+                shutdown: false,
+            }),
+            wakeup: Condvar::new(),
+            idle: Condvar::new(),
+            panics: Mutex::new(Vec::new()),
+            counters: (0..num_threads).map(|_| Counters::default()).collect(),
+            created: Instant::now(),
+        });
         ThreadPool {
-            workers: Self::make_workers(num_threads),
+            handles: Self::make_workers(&shared, cores, &builder),
+            requested_threads: builder.num_threads,
+            shared,
             current_worker_id: cell::Cell::new(0),
         }
     }
 
-    /// Returns the number of worker threads in the pool.
+    /// Returns the number of worker threads in the pool. It is at most the
+    /// number requested, and may be less with the `core_affinity` feature,
+    /// which starts at most one worker per available core (see
+    /// [`CoreMask`]). A pool of zero threads is valid: its jobs run on the
+    /// thread spawning them, before the spawn call returns.
     pub fn num_threads(&self) -> usize {
-        self.workers.len()
+        self.handles.len()
+    }
+
+    /// Returns the number of worker threads requested when the pool was
+    /// created, which callers can compare with [`ThreadPool::num_threads`]
+    /// to report a pool clamped to the available cores.
+    pub fn requested_threads(&self) -> usize {
+        self.requested_threads
+    }
+
+    /// Returns the NUMA node of the core the given worker is pinned to, if
+    /// it is pinned and the topology is known.
+    pub fn numa_node(&self, worker_id: usize) -> Option<usize> {
+        self.shared.nodes[worker_id]
+    }
+
+    /// Runs a closure on the given worker (modulo the number of workers),
+    /// and returns its result. The job is not stolen by other workers, and
+    /// runs before the worker's other queued jobs. Memory the closure
+    /// allocates and writes first, such as the buffers of a task built on
+    /// the worker its [`crate::automaton::Automaton::worker_hint`] names, is
+    /// then placed on that worker's NUMA node by the operating system's
+    /// first-touch policy. The closure may borrow from the caller, and a
+    /// panic in it is resumed on the caller. This must not be called from a
+    /// job running on the same pool.
+    pub fn run_pinned<F, T>(&self, worker_id: usize, f: F) -> T
+    where
+        F: FnOnce() -> T + Send,
+        T: Send,
+    {
+        if self.num_threads() == 0 {
+            return f();
+        }
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let job: Box<dyn FnOnce() + Send + '_> = Box::new(move || {
+            sender.send(panic::catch_unwind(AssertUnwindSafe(f))).ok();
+        });
+
+        // SAFETY: this function does not return until the job has sent its
+        // result, after which the job touches nothing it borrows.
+        let job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + '_>, Job>(job) };
+        self.shared.push_pinned(worker_id % self.num_threads(), job);
+
+        match receiver.recv().unwrap() {
+            Ok(result) => result,
+            Err(payload) => panic::resume_unwind(payload),
+        }
     }
 
     /// Spawnd a new job into the pool. Job submissions go cyclically to the
@@ -48,87 +652,855 @@ impl ThreadPool {
         self.spawn_on(None, job)
     }
 
-    /// Spawns a job onto the worker thread with the given index, if it is
-    /// `Some`. The current worker index is not incremented. If the worker
-    /// index is `None`, then the job is run on the current worker index,
-    /// which is then incremented.
+    /// Spawns a job onto the queue of the worker thread with the given index
+    /// (modulo the number of workers), if it is `Some`. The current worker
+    /// index is not incremented. If the worker index is `None`, then the job
+    /// is queued on the current worker index, which is then incremented. The
+    /// job runs on another worker if that one is busy when another is idle.
     pub fn spawn_on<F>(&self, worker_id: Option<usize>, job: F)
     where
         F: FnOnce() + Send + 'static,
     {
-        let worker_id = if let Some(worker_id) = worker_id {
-            worker_id
+        self.spawn_on_with_priority(worker_id, Priority::Normal, job)
+    }
+
+    /// Spawns a job with the given priority, going to the workers
+    /// cyclically as [`ThreadPool::spawn`] does.
+    pub fn spawn_with_priority<F>(&self, priority: Priority, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.spawn_on_with_priority(None, priority, job)
+    }
+
+    /// Spawns a job with the given priority onto the queue of a worker
+    /// chosen as [`ThreadPool::spawn_on`] does.
+    pub fn spawn_on_with_priority<F>(&self, worker_id: Option<usize>, priority: Priority, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.shared
+            .push(self.next_worker(worker_id), priority, Box::new(job))
+    }
+
+    /// Returns the statistics of each worker since the pool was created. The
+    /// busy time of a job is counted when it completes.
+    pub fn stats(&self) -> Vec<WorkerStats> {
+        let lifetime = self.shared.created.elapsed().as_secs_f64();
+        self.shared
+            .counters
+            .iter()
+            .zip(self.shared.queues.iter().zip(&self.shared.pinned))
+            .map(|(counters, (queues, pinned))| {
+                let busy_seconds = counters.busy_nanos.load(Ordering::Relaxed) as f64 * 1e-9;
+                WorkerStats {
+                    jobs_executed: counters.jobs_executed.load(Ordering::Relaxed),
+                    jobs_stolen: counters.jobs_stolen.load(Ordering::Relaxed),
+                    queue_depth: queues
+                        .lock()
+                        .unwrap()
+                        .iter()
+                        .map(VecDeque::len)
+                        .sum::<usize>()
+                        + pinned.lock().unwrap().len(),
+                    busy_seconds,
+                    busy_fraction: if lifetime > 0.0 {
+                        busy_seconds / lifetime
+                    } else {
+                        0.0
+                    },
+                }
+            })
+            .collect()
+    }
+
+    /// Returns the panics caught in jobs since the last call, in the order
+    /// they happened.
+    pub fn take_panics(&self) -> Vec<JobPanic> {
+        std::mem::take(&mut *self.shared.panics.lock().unwrap())
+    }
+
+    /// Blocks until every job spawned into the pool so far, including those
+    /// spawned by other jobs meanwhile, has completed (or panicked). The
+    /// pool remains usable afterwards. This must not be called from a job
+    /// running on the same pool, which would wait for itself.
+    pub fn wait_idle(&self) {
+        let mut state = self.shared.state.lock().unwrap();
+
+        while state.unfinished > 0 {
+            state = self.shared.idle.wait(state).unwrap();
+        }
+    }
+
+    /// Creates a scope in which jobs borrowing data from the caller's stack
+    /// can be spawned, and calls the given closure with it. This function
+    /// returns only once all the jobs spawned in the scope have completed.
+    /// If the closure or any of the jobs panics, the panic is propagated
+    /// after the other jobs have completed. This must not be called from a
+    /// job running on the same pool, since the worker would block waiting
+    /// for jobs which may be queued behind it.
+    pub fn scope<'env, F, T>(&self, f: F) -> T
+    where
+        F: for<'scope> FnOnce(&'scope Scope<'scope, 'env>) -> T,
+    {
+        let scope = Scope {
+            pool: self,
+            remaining: Arc::new(Remaining {
+                count: Mutex::new(0),
+                done: Condvar::new(),
+                panicked: Mutex::new(None),
+            }),
+            scope: PhantomData,
+            env: PhantomData,
+        };
+        let result = panic::catch_unwind(AssertUnwindSafe(|| f(&scope)));
+        let remaining = &scope.remaining;
+        let mut count = remaining.count.lock().unwrap();
+
+        while *count > 0 {
+            count = remaining.done.wait(count).unwrap();
+        }
+        drop(count);
+        let panicked = remaining.panicked.lock().unwrap().take();
+
+        match (result, panicked) {
+            (Err(e), _) => panic::resume_unwind(e),
+            (Ok(_), Some(message)) => panic!("a scoped job panicked: {}", message),
+            (Ok(result), None) => result,
+        }
+    }
+
+    fn next_worker(&self, worker_id: Option<usize>) -> usize {
+        if self.num_threads() == 0 {
+            0
+        } else if let Some(worker_id) = worker_id {
+            worker_id % self.num_threads()
         } else {
             let worker_id = self.current_worker_id.get();
             self.current_worker_id
                 .set((worker_id + 1) % self.num_threads());
             worker_id
+        }
+    }
+}
+
+/// A scope in which jobs can be spawned onto a [`ThreadPool`] which borrow
+/// data living at least as long as the scope, created with
+/// [`ThreadPool::scope`].
+pub struct Scope<'scope, 'env: 'scope> {
+    pool: &'scope ThreadPool,
+    remaining: Arc<Remaining>,
+    scope: PhantomData<&'scope mut &'scope ()>,
+    env: PhantomData<&'env mut &'env ()>,
+}
+
+impl<'scope, 'env> Scope<'scope, 'env> {
+    /// Spawns a job into the pool, as [`ThreadPool::spawn`] does.
+    pub fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        self.spawn_on(None, job)
+    }
+
+    /// Spawns a job onto the queue of the worker thread with the given
+    /// index, as [`ThreadPool::spawn_on`] does.
+    pub fn spawn_on<F>(&self, worker_id: Option<usize>, job: F)
+    where
+        F: FnOnce() + Send + 'scope,
+    {
+        let remaining = self.remaining.clone();
+        *remaining.count.lock().unwrap() += 1;
+
+        let job: Box<dyn FnOnce() + Send + 'scope> = Box::new(move || {
+            if let Err(payload) = panic::catch_unwind(AssertUnwindSafe(job)) {
+                let message = panic_message(payload.as_ref());
+                remaining.panicked.lock().unwrap().get_or_insert(message);
+            }
+            let mut count = remaining.count.lock().unwrap();
+            *count -= 1;
+
+            if *count == 0 {
+                remaining.done.notify_all()
+            }
+        });
+
+        // SAFETY: ThreadPool::scope does not return (even by unwinding)
+        // until the count of remaining jobs is zero, which happens only
+        // after this job has run, so nothing it borrows is dropped while
+        // the job can still use it.
+        let job = unsafe { std::mem::transmute::<Box<dyn FnOnce() + Send + 'scope>, Job>(job) };
+        self.pool
+            .shared
+            .push(self.pool.next_worker(worker_id), Priority::Normal, job)
+    }
+}
+
+thread_local! {
+    static SCRATCH: cell::RefCell<HashMap<usize, Box<dyn Any>>> = cell::RefCell::new(HashMap::new());
+}
+
+/// The source of the keys of [`Scratch`] objects.
+static NEXT_SCRATCH_KEY: AtomicUsize = AtomicUsize::new(0);
+
+/// A token for a per-thread scratch object, such as preallocated flux
+/// buffers, which jobs can borrow mutably on the thread running them.
+/// Each thread gets its own object, created by the initializer the first
+/// time the thread uses the token, and reused by every later job on that
+/// thread, so a kernel need not allocate its buffers, nor carry them in a
+/// task sent between ranks. A [`ThreadPool`] worker keeps its objects until
+/// the pool is dropped, and the serial executor's thread until it exits.
+///
+/// The token is cheap to clone, and tasks can hold one. The contents of the
+/// object are whatever the last job on the thread left in it.
+pub struct Scratch<T> {
+    key: usize,
+    init: Arc<dyn Fn() -> T + Send + Sync>,
+}
+
+impl<T> Clone for Scratch<T> {
+    fn clone(&self) -> Self {
+        Self {
+            key: self.key,
+            init: self.init.clone(),
+        }
+    }
+}
+
+impl<T: 'static> Scratch<T> {
+    /// Registers a new kind of scratch object, created on each thread by
+    /// the given function.
+    pub fn new<F>(init: F) -> Self
+    where
+        F: Fn() -> T + Send + Sync + 'static,
+    {
+        Self {
+            key: NEXT_SCRATCH_KEY.fetch_add(1, Ordering::Relaxed),
+            init: Arc::new(init),
+        }
+    }
+
+    /// Calls the closure with this thread's scratch object. While the
+    /// closure runs the object is out of the thread's storage, so a nested
+    /// call with the same token gets a fresh object; if the closure panics,
+    /// the object is dropped and recreated on the next call.
+    pub fn with<R, F: FnOnce(&mut T) -> R>(&self, f: F) -> R {
+        let stored = SCRATCH.with(|scratch| scratch.borrow_mut().remove(&self.key));
+        let mut object = match stored {
+            Some(object) => object.downcast::<T>().unwrap(),
+            None => Box::new((self.init)()),
         };
-        self.workers[worker_id]
-            .sender
-            .as_ref()
-            .unwrap()
-            .send(Box::new(job))
-            .unwrap();
+        let result = f(&mut object);
+        SCRATCH.with(|scratch| scratch.borrow_mut().insert(self.key, object));
+        result
     }
 }
 
+/// Covers an index space with tiles of the given shape (see
+/// [`IndexSpace::tiles`]), runs the closure on each tile in parallel on the
+/// pool, and returns the results in the order of the tiles once every tile
+/// is done. The closure may borrow from the caller, and a panic in it is
+/// propagated as by [`ThreadPool::scope`]. This lets single-patch kernels,
+/// such as generating initial data, use all the workers; each tile writes
+/// its own result, for example a patch covering the tile.
+pub fn parallel_for<F, T>(
+    pool: &ThreadPool,
+    space: &IndexSpace,
+    chunk: (usize, usize),
+    f: F,
+) -> Vec<T>
+where
+    F: Fn(IndexSpace) -> T + Sync,
+    T: Send,
+{
+    let tiles: Vec<_> = space.tiles(chunk).collect();
+    let mut results: Vec<Option<T>> = tiles.iter().map(|_| None).collect();
+    let f = &f;
+
+    pool.scope(|s| {
+        for (tile, result) in tiles.into_iter().zip(results.iter_mut()) {
+            s.spawn(move || *result = Some(f(tile)))
+        }
+    });
+    results.into_iter().map(Option::unwrap).collect()
+}
+
 impl ThreadPool {
-    #[cfg(feature = "crossbeam_channel")]
-    fn make_channels() -> (crossbeam_channel::Sender<Job>, crossbeam_channel::Receiver<Job>) {
-        crossbeam_channel::unbounded()
+    #[cfg(feature = "core_affinity")]
+    fn place(
+        num_threads: usize,
+        placement: Placement,
+        core_mask: &CoreMask,
+    ) -> Vec<(Option<Core>, Option<usize>)> {
+        let available = core_affinity::get_core_ids().unwrap_or_default();
+
+        if available.is_empty() {
+            return vec![(None, None); num_threads];
+        }
+        let ids: Vec<_> = available.iter().map(|core| core.id).collect();
+        let ids = core_mask.select(&ids, thread_siblings);
+        let cores: Vec<_> = ids
+            .iter()
+            .map(|&id| available.iter().find(|core| core.id == id).unwrap())
+            .collect();
+
+        if cores.is_empty() {
+            return vec![(None, None); num_threads.min(1)];
+        }
+        place_workers(&ids, &numa_nodes(), num_threads, placement)
+            .into_iter()
+            .map(|(n, node)| (Some(*cores[n]), node))
+            .collect()
     }
 
-    #[cfg(not(feature = "crossbeam_channel"))]
-    fn make_channels() -> (std::sync::mpsc::Sender<Job>, std::sync::mpsc::Receiver<Job>) {
-        std::sync::mpsc::channel()
+    #[cfg(not(feature = "core_affinity"))]
+    fn place(num_threads: usize, _: Placement, _: &CoreMask) -> Vec<(Option<Core>, Option<usize>)> {
+        vec![(None, None); num_threads]
     }
 
     #[cfg(feature = "core_affinity")]
-    fn make_workers(num_threads: usize) -> Vec<Worker> {
-        use core_affinity::{get_core_ids, set_for_current};
-        get_core_ids()
-            .unwrap()
+    fn pin(core: Core) {
+        core_affinity::set_for_current(core);
+    }
+
+    #[cfg(not(feature = "core_affinity"))]
+    fn pin(_: Core) {}
+
+    fn make_workers(
+        shared: &Arc<Shared>,
+        cores: Vec<(Option<Core>, Option<usize>)>,
+        builder: &ThreadPoolBuilder,
+    ) -> Vec<thread::JoinHandle<()>> {
+        cores
             .into_iter()
-            .take(num_threads)
-            .map(|core_id| {
-                let (sender, receiver) = Self::make_channels();
-                let handle = thread::spawn(move || {
-                    set_for_current(core_id);
-                    for job in receiver {
-                        job()
-                    }
-                });
-                Worker {
-                    handle: Some(handle),
-                    sender: Some(sender),
-                }
+            .enumerate()
+            .map(|(worker_id, (core, _))| {
+                let shared = shared.clone();
+                let start_hook = builder.start_hook.clone();
+                let stop_hook = builder.stop_hook.clone();
+                let thread = thread::Builder::new()
+                    .name(format!("{}-{}", builder.thread_name_prefix, worker_id));
+                let thread = match builder.stack_size {
+                    Some(bytes) => thread.stack_size(bytes),
+                    None => thread,
+                };
+                thread
+                    .spawn(move || {
+                        if let Some(core) = core {
+                            Self::pin(core)
+                        }
+                        if let Some(start_hook) = start_hook {
+                            start_hook(worker_id)
+                        }
+                        shared.run(worker_id);
+
+                        if let Some(stop_hook) = stop_hook {
+                            stop_hook(worker_id)
+                        }
+                    })
+                    .expect("failed to spawn a worker thread")
             })
             .collect()
     }
+}
 
-    #[cfg(not(feature = "core_affinity"))]
-    fn make_workers(num_threads: usize) -> Vec<Worker> {
-        (0..num_threads)
-            .map(|_| {
-                let (sender, receiver) = Self::make_channels();
-                let handle = thread::spawn(move || {
-                    for job in receiver {
-                        job()
+impl Drop for ThreadPool {
+    fn drop(&mut self) {
+        self.shared.state.lock().unwrap().shutdown = true;
+        self.shared.wakeup.notify_all();
+
+        for handle in self.handles.drain(..) {
+            handle.join().unwrap();
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::sync::atomic::AtomicUsize;
+    use std::sync::mpsc;
+    use std::time::Duration;
+
+    #[test]
+    fn all_jobs_complete_before_the_pool_is_dropped() {
+        let count = Arc::new(AtomicUsize::new(0));
+        let pool = ThreadPool::new(3);
+
+        for n in 0..100 {
+            let count = count.clone();
+            let hint = if n % 2 == 0 { Some(n) } else { None };
+            pool.spawn_on(hint, move || {
+                count.fetch_add(1, Ordering::SeqCst);
+            })
+        }
+        drop(pool);
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+    }
+
+    #[test]
+    fn idle_workers_steal_jobs_from_a_busy_one() {
+        let pool = ThreadPool::new(2);
+
+        if pool.num_threads() < 2 {
+            return;
+        }
+        let (release, blocked) = mpsc::channel::<()>();
+        let (done, finished) = mpsc::channel();
+        pool.spawn_on(Some(0), move || blocked.recv().unwrap());
+
+        for n in 0..10 {
+            let done = done.clone();
+            pool.spawn_on(Some(0), move || done.send(n).unwrap());
+        }
+        let mut received: Vec<_> = (0..10)
+            .map(|_| finished.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect();
+        received.sort_unstable();
+        assert_eq!(received, (0..10).collect::<Vec<_>>());
+        release.send(()).unwrap();
+    }
+
+    #[test]
+    fn scoped_jobs_can_borrow_from_the_stack() {
+        let pool = ThreadPool::new(4);
+        let input: Vec<_> = (0..1000).map(|n| n as f64).collect();
+        let mut output = vec![0.0; input.len()];
+
+        let total = pool.scope(|s| {
+            for (x, y) in input.chunks(100).zip(output.chunks_mut(100)) {
+                s.spawn(move || {
+                    for (x, y) in x.iter().zip(y) {
+                        *y = 2.0 * x
                     }
+                })
+            }
+            input.len()
+        });
+        assert_eq!(total, 1000);
+        assert!(output.iter().zip(&input).all(|(y, x)| *y == 2.0 * x));
+    }
+
+    #[test]
+    #[should_panic(expected = "a scoped job panicked: job failed")]
+    fn a_panic_in_a_scoped_job_is_propagated() {
+        let pool = ThreadPool::new(2);
+        let count = AtomicUsize::new(0);
+
+        pool.scope(|s| {
+            s.spawn(|| panic!("job failed"));
+            s.spawn(|| {
+                count.fetch_add(1, Ordering::SeqCst);
+            });
+        });
+    }
+
+    #[test]
+    fn a_panicking_job_does_not_take_its_worker_down() {
+        let pool = ThreadPool::new(1);
+        let (done, finished) = mpsc::channel();
+        pool.spawn(|| panic!("job failed"));
+        pool.spawn(move || done.send(()).unwrap());
+        finished.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        let panics = pool.take_panics();
+        assert_eq!(panics.len(), 1);
+        assert_eq!(panics[0].message, "job failed");
+        assert_eq!(
+            panics[0].to_string(),
+            "a job panicked on worker 0: job failed"
+        );
+        assert!(pool.take_panics().is_empty());
+    }
+
+    #[test]
+    fn higher_priority_jobs_run_first() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        let (done, finished) = mpsc::channel();
+        pool.spawn(move || blocked.recv().unwrap());
+
+        for (n, priority) in [
+            Priority::Low,
+            Priority::Normal,
+            Priority::High,
+            Priority::Normal,
+        ]
+        .iter()
+        .enumerate()
+        {
+            let done = done.clone();
+            pool.spawn_with_priority(*priority, move || done.send(n).unwrap());
+        }
+        release.send(()).unwrap();
+        let order: Vec<_> = (0..4)
+            .map(|_| finished.recv_timeout(Duration::from_secs(10)).unwrap())
+            .collect();
+        assert_eq!(order, vec![2, 1, 3, 0]);
+    }
+
+    #[test]
+    fn stats_count_the_jobs_of_each_worker() {
+        let pool = ThreadPool::new(1);
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.spawn(move || {
+            blocked.recv().unwrap();
+            thread::sleep(Duration::from_millis(10));
+        });
+        pool.spawn(|| {});
+        pool.spawn(|| {});
+
+        while pool.stats()[0].queue_depth != 2 {
+            thread::yield_now()
+        }
+        release.send(()).unwrap();
+
+        while pool.stats()[0].jobs_executed != 3 {
+            thread::yield_now()
+        }
+        let stats = &pool.stats()[0];
+        assert_eq!(stats.queue_depth, 0);
+        assert_eq!(stats.jobs_stolen, 0);
+        assert!(stats.busy_seconds >= 0.01);
+        assert!(stats.busy_fraction > 0.0 && stats.busy_fraction <= 1.0);
+    }
+
+    #[test]
+    fn parallel_for_covers_the_index_space_once() {
+        let pool = ThreadPool::new(3);
+        let space = IndexSpace::new(-3..10, 2..9);
+        let tiles = parallel_for(&pool, &space, (4, 3), |tile| {
+            crate::patch::Patch::from_scalar_function(0, tile, |(i, j)| (i * 100 + j) as f64)
+        });
+        assert_eq!(tiles.len(), 4 * 3);
+        assert_eq!(
+            tiles
+                .iter()
+                .map(|tile| tile.index_space().len())
+                .sum::<usize>(),
+            space.len()
+        );
+
+        let stitched = crate::patch::stitch(&tiles, 0).unwrap();
+        assert_eq!(stitched.index_space(), space);
+        assert!(space
+            .iter()
+            .all(|(i, j)| stitched.get_slice((i, j))[0] == (i * 100 + j) as f64));
+    }
+
+    #[test]
+    fn cpu_lists_are_parsed_and_workers_spread_over_nodes() {
+        assert_eq!(parse_cpu_list("0-3,8,10-11\n"), vec![0, 1, 2, 3, 8, 10, 11]);
+        assert!(parse_cpu_list("\n").is_empty());
+
+        let cores: Vec<_> = (0..8).collect();
+        let nodes = vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7]];
+        assert_eq!(
+            place_workers(&cores, &nodes, 4, Placement::Compact),
+            vec![(0, Some(0)), (1, Some(0)), (2, Some(0)), (3, Some(0))]
+        );
+        assert_eq!(
+            place_workers(&cores, &nodes, 5, Placement::Numa),
+            vec![
+                (0, Some(0)),
+                (4, Some(1)),
+                (1, Some(0)),
+                (5, Some(1)),
+                (2, Some(0))
+            ]
+        );
+        assert_eq!(place_workers(&cores, &[], 16, Placement::Numa).len(), 8);
+    }
+
+    #[test]
+    fn core_masks_select_listed_cores_one_per_physical_core() {
+        let available: Vec<_> = (0..8).collect();
+        let siblings = |id: usize| vec![id % 4, id % 4 + 4];
+        let mask = |cores: Option<Vec<usize>>, exclude_siblings, reserved| CoreMask {
+            cores,
+            exclude_siblings,
+            reserved,
+        };
+        assert_eq!(mask(None, false, 0).select(&available, siblings), available);
+        assert_eq!(
+            mask(None, true, 0).select(&available, siblings),
+            vec![0, 1, 2, 3]
+        );
+        assert_eq!(
+            mask(None, true, 1).select(&available, siblings),
+            vec![1, 2, 3]
+        );
+        assert_eq!(
+            mask(Some(vec![6, 2, 5, 9]), false, 0).select(&available, siblings),
+            vec![6, 2, 5]
+        );
+        assert_eq!(
+            mask(Some(vec![6, 2, 5]), true, 0).select(&available, siblings),
+            vec![6, 5]
+        );
+        assert!(mask(None, false, 8).select(&available, siblings).is_empty());
+    }
+
+    #[test]
+    fn pinned_jobs_run_on_their_worker() {
+        let pool = ThreadPool::new(2);
+        let data = [1.0, 2.0, 3.0];
+        let threads: Vec<_> = (0..2 * pool.num_threads())
+            .map(|n| pool.run_pinned(n, || (thread::current().id(), data.iter().sum::<f64>())))
+            .collect();
+        assert!(threads.iter().all(|(_, sum)| *sum == 6.0));
+
+        for n in 0..pool.num_threads() {
+            assert_eq!(threads[n].0, threads[n + pool.num_threads()].0);
+        }
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            pool.run_pinned(0, || panic!("pinned job failed"))
+        }));
+        assert_eq!(
+            panic_message(result.unwrap_err().as_ref()),
+            "pinned job failed"
+        );
+        assert_eq!(pool.run_pinned(0, || 1), 1);
+    }
+
+    #[test]
+    fn workers_are_named_and_run_the_start_hook() {
+        let started = Arc::new(Mutex::new(Vec::new()));
+        let pool = {
+            let started = started.clone();
+            ThreadPoolBuilder::new(2)
+                .start_hook(move |worker_id| started.lock().unwrap().push(worker_id))
+                .build()
+        };
+        let names: Vec<_> = (0..pool.num_threads())
+            .map(|n| pool.run_pinned(n, || thread::current().name().map(String::from)))
+            .collect();
+
+        for (n, name) in names.iter().enumerate() {
+            assert_eq!(
+                name.as_deref(),
+                Some(format!("gridiron-worker-{}", n).as_str())
+            );
+        }
+        let mut started = started.lock().unwrap().clone();
+        started.sort_unstable();
+        assert_eq!(started, (0..pool.num_threads()).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn wait_idle_returns_once_all_jobs_have_completed() {
+        let pool = ThreadPool::new(2);
+        let count = Arc::new(AtomicU64::new(0));
+
+        for round in 1..=3 {
+            for _ in 0..20 {
+                let count = count.clone();
+                pool.spawn(move || {
+                    thread::sleep(std::time::Duration::from_millis(1));
+                    count.fetch_add(1, Ordering::SeqCst);
                 });
-                Worker {
-                    handle: Some(handle),
-                    sender: Some(sender),
-                }
+            }
+            pool.spawn(|| panic!("a failing job is also waited for"));
+            pool.wait_idle();
+            assert_eq!(count.load(Ordering::SeqCst), 20 * round);
+        }
+        assert_eq!(pool.take_panics().len(), 3);
+        pool.wait_idle();
+    }
+
+    #[test]
+    fn scratch_objects_are_reused_by_jobs_on_the_same_worker() {
+        let pool = ThreadPool::new(2);
+        let created = Arc::new(AtomicU64::new(0));
+        let scratch = {
+            let created = created.clone();
+            Scratch::new(move || {
+                created.fetch_add(1, Ordering::SeqCst);
+                Vec::<f64>::with_capacity(64)
             })
-            .collect()
+        };
+        let other = Scratch::new(|| 0usize);
+
+        for n in 0..4 * pool.num_threads() {
+            let lengths = pool.run_pinned(n, || {
+                other.with(|count| *count += 1);
+                scratch.with(|buffer| {
+                    buffer.push(n as f64);
+                    assert!(buffer.capacity() >= 64);
+                    buffer.len()
+                })
+            });
+            assert_eq!(lengths, n / pool.num_threads() + 1);
+        }
+        assert_eq!(created.load(Ordering::SeqCst), pool.num_threads() as u64);
+        assert_eq!(
+            scratch.with(|outer| scratch.with(|inner| inner.len() + outer.len())),
+            0
+        );
     }
-}
 
-impl Drop for Worker {
-    fn drop(&mut self) {
-        self.sender.take().unwrap();
-        self.handle.take().unwrap().join().unwrap();
+    #[test]
+    fn the_builder_sets_names_stack_size_and_stop_hooks() {
+        fn depth(n: u64) -> u64 {
+            let frame = [n; 512];
+            if n == 0 {
+                0
+            } else {
+                1 + depth(n - 1) + frame[(n % 512) as usize] - n
+            }
+        }
+        let stopped = Arc::new(AtomicU64::new(0));
+        let pool = {
+            let stopped = stopped.clone();
+            ThreadPool::builder(2)
+                .thread_name_prefix("solver")
+                .stack_size(64 << 20)
+                .work_stealing(false)
+                .stop_hook(move |_| {
+                    stopped.fetch_add(1, Ordering::SeqCst);
+                })
+                .build()
+        };
+        let (name, d) = pool.run_pinned(1, || {
+            (thread::current().name().map(String::from), depth(8000))
+        });
+        assert_eq!(
+            name.as_deref(),
+            Some(format!("solver-{}", 1 % pool.num_threads()).as_str())
+        );
+        assert_eq!(d, 8000);
+        assert_eq!(stopped.load(Ordering::SeqCst), 0);
+
+        let num_threads = pool.num_threads() as u64;
+        drop(pool);
+        assert_eq!(stopped.load(Ordering::SeqCst), num_threads);
+    }
+
+    #[test]
+    fn a_pool_of_zero_threads_runs_jobs_on_the_caller() {
+        let pool = ThreadPool::new(0);
+        assert_eq!((pool.num_threads(), pool.requested_threads()), (0, 0));
+
+        let caller = thread::current().id();
+        let (sender, receiver) = mpsc::channel();
+        pool.spawn_on(Some(3), move || {
+            sender.send(thread::current().id()).unwrap()
+        });
+        assert_eq!(receiver.try_recv(), Ok(caller));
+        assert_eq!(pool.run_pinned(1, || thread::current().id()), caller);
+
+        let space = IndexSpace::new(0..4, 0..4);
+        assert_eq!(
+            parallel_for(&pool, &space, (2, 2), |tile| tile.len()),
+            vec![4; 4]
+        );
+        pool.spawn(|| panic!("inline job failed"));
+        pool.wait_idle();
+        assert_eq!(pool.take_panics()[0].message, "inline job failed");
+        assert!(pool.stats().is_empty());
+
+        let squares: Vec<_> =
+            crate::automaton::execute_thread_pool(&pool, (0..3).map(Square)).collect();
+        assert_eq!(squares.len(), 3);
+    }
+
+    #[test]
+    fn jobs_are_counted_before_workers_can_take_them() {
+        let pool = ThreadPool::new(4);
+        let count = Arc::new(AtomicU64::new(0));
+
+        for round in 1..=100 {
+            for _ in 0..200 {
+                let count = count.clone();
+                pool.spawn(move || {
+                    count.fetch_add(1, Ordering::Relaxed);
+                });
+            }
+            pool.wait_idle();
+            assert_eq!(count.load(Ordering::Relaxed), 200 * round);
+        }
+    }
+
+    #[test]
+    fn pinned_jobs_are_counted_before_workers_can_take_them() {
+        let pool = ThreadPool::new(4);
+
+        for round in 0..100 {
+            for n in 0..20 {
+                assert_eq!(pool.run_pinned(n, || n * round), n * round);
+            }
+            pool.wait_idle();
+        }
+    }
+
+    #[test]
+    fn workers_without_stealing_only_run_their_own_jobs() {
+        let pool = ThreadPool::builder(2).work_stealing(false).build();
+
+        if pool.num_threads() < 2 {
+            return;
+        }
+        let (release, blocked) = mpsc::channel::<()>();
+        pool.spawn_on(Some(0), move || blocked.recv().unwrap());
+        pool.spawn_on(Some(0), || ());
+        thread::sleep(std::time::Duration::from_millis(50));
+
+        assert_eq!(pool.stats()[0].queue_depth, 1);
+        release.send(()).unwrap();
+        pool.wait_idle();
+
+        let stats = pool.stats();
+        assert_eq!((stats[0].jobs_executed, stats[1].jobs_executed), (2, 0));
+    }
+
+    struct Square(usize);
+
+    impl crate::automaton::Automaton for Square {
+        type Key = usize;
+        type Message = ();
+        type Value = usize;
+
+        fn key(&self) -> usize {
+            self.0
+        }
+
+        fn messages(&self) -> Vec<(usize, ())> {
+            Vec::new()
+        }
+
+        fn receive(&mut self, _: ()) -> crate::automaton::Status {
+            unreachable!()
+        }
+
+        fn value(self) -> usize {
+            assert!(self.0 != 3, "cannot square three");
+            self.0 * self.0
+        }
+
+        fn independent(&self) -> bool {
+            true
+        }
+    }
+
+    #[test]
+    fn the_executor_resumes_a_task_panic_on_the_caller() {
+        let pool = ThreadPool::new(2);
+        let squares: Vec<_> =
+            crate::automaton::execute_thread_pool(&pool, (0..3).map(Square)).collect();
+        assert_eq!(squares.len(), 3);
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            crate::automaton::execute_thread_pool(&pool, (0..5).map(Square)).count()
+        }));
+        let message = panic_message(result.unwrap_err().as_ref());
+        assert_eq!(
+            message,
+            "a task panicked on a pool worker: cannot square three"
+        );
+        assert!(pool.take_panics().is_empty());
+
+        let squares: Vec<_> =
+            crate::automaton::execute_thread_pool(&pool, (0..3).map(Square)).collect();
+        assert_eq!(squares.len(), 3);
     }
 }